@@ -0,0 +1,96 @@
+//! `OptimizedVarInt` と `utils::read_varint` の比較
+//!
+//! `cargo bench --bench varint` (SIMD 版は `--features simd`)
+
+use bytes::BytesMut;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use testServer::utils::config::VarIntConfig;
+use testServer::varint::{utils, OptimizedVarInt, VarIntEncoder};
+
+/// 1〜5バイトの値を満遍なく含むデータ
+fn mixed_values(count: usize) -> Vec<i32> {
+    (0..count as u32).map(|i| (i.wrapping_mul(0x9E37_79B9) >> (i % 5 * 7)) as i32).collect()
+}
+
+/// チャンクのパレットのような小さな値の列
+fn palette_values(count: usize) -> Vec<i32> {
+    (0..count as i32).map(|i| i % 64).collect()
+}
+
+fn encode(values: &[i32]) -> BytesMut {
+    let mut buf = BytesMut::new();
+    for &value in values {
+        utils::write_varint(&mut buf, value).unwrap();
+    }
+    buf
+}
+
+fn bench_decode(c: &mut Criterion) {
+    let optimized = OptimizedVarInt::new(VarIntConfig::default());
+    let mut group = c.benchmark_group("decode");
+
+    for (name, values) in [("mixed", mixed_values(4096)), ("palette", palette_values(4096))] {
+        let encoded = encode(&values);
+        group.throughput(Throughput::Elements(values.len() as u64));
+
+        group.bench_with_input(BenchmarkId::new("utils::read_varint", name), &encoded, |b, encoded| {
+            b.iter(|| {
+                let mut buf = &encoded[..];
+                while !buf.is_empty() {
+                    black_box(utils::read_varint(&mut buf).unwrap());
+                }
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("OptimizedVarInt::read_varint", name), &encoded, |b, encoded| {
+            b.iter(|| {
+                let mut buf = &encoded[..];
+                while !buf.is_empty() {
+                    black_box(optimized.read_varint(&mut buf).unwrap());
+                }
+            })
+        });
+        group.bench_with_input(BenchmarkId::new("OptimizedVarInt::read_varint_batch", name), &encoded, |b, encoded| {
+            let mut out = Vec::with_capacity(values.len());
+            b.iter(|| {
+                out.clear();
+                let mut buf = &encoded[..];
+                optimized.read_varint_batch(&mut buf, values.len(), &mut out).unwrap();
+                black_box(&out);
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_encode(c: &mut Criterion) {
+    let optimized = OptimizedVarInt::new(VarIntConfig::default());
+    let values = mixed_values(4096);
+    let mut group = c.benchmark_group("encode");
+    group.throughput(Throughput::Elements(values.len() as u64));
+
+    group.bench_function("utils::write_varint", |b| {
+        let mut buf = BytesMut::with_capacity(values.len() * 5);
+        b.iter(|| {
+            buf.clear();
+            for &value in &values {
+                utils::write_varint(&mut buf, value).unwrap();
+            }
+            black_box(&buf);
+        })
+    });
+    group.bench_function("OptimizedVarInt::write_varint", |b| {
+        let mut buf = BytesMut::with_capacity(values.len() * 5);
+        b.iter(|| {
+            buf.clear();
+            for &value in &values {
+                optimized.write_varint(&mut buf, value).unwrap();
+            }
+            black_box(&buf);
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_decode, bench_encode);
+criterion_main!(benches);
@@ -0,0 +1,71 @@
+//! パケットキャプチャ (.mccap) の表示ツール
+//!
+//! 使い方: `capdump [--hex] <file.mccap>...`
+
+use std::path::Path;
+use std::process::ExitCode;
+
+use testServer::net::capture::{CaptureReader, CaptureRecord};
+use testServer::net::protocol::Direction;
+
+fn hex_dump(data: &[u8]) -> String {
+    let mut out = String::new();
+    for (offset, chunk) in data.chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = chunk.iter()
+            .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+            .collect();
+        out.push_str(&format!("    {:08x}  {:<48} |{}|\n", offset * 16, hex.join(" "), ascii));
+    }
+    out
+}
+
+fn print_record(record: &CaptureRecord, hex: bool) {
+    let time = chrono::DateTime::from_timestamp_micros(record.timestamp_micros)
+        .map(|t| t.format("%H:%M:%S%.6f").to_string())
+        .unwrap_or_else(|| record.timestamp_micros.to_string());
+    let arrow = match record.direction {
+        Direction::Serverbound => "C->S",
+        Direction::Clientbound => "S->C",
+    };
+    let name = record.name().unwrap_or("Unknown");
+
+    println!(
+        "{} {} {:?} 0x{:02X} {} ({} bytes)",
+        time, arrow, record.state, record.packet_id, name, record.data.len()
+    );
+    if hex {
+        print!("{}", hex_dump(&record.data));
+    } else if let Some(description) = record.describe() {
+        println!("    {}", description);
+    }
+}
+
+fn dump(path: &Path, hex: bool) -> std::io::Result<()> {
+    let mut reader = CaptureReader::open(path)?;
+    println!("# {} (peer {})", path.display(), reader.peer);
+    while let Some(record) = reader.next_record()? {
+        print_record(&record, hex);
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let hex = args.iter().any(|arg| arg == "--hex");
+    let files: Vec<&String> = args.iter().filter(|arg| !arg.starts_with("--")).collect();
+
+    if files.is_empty() {
+        eprintln!("Usage: capdump [--hex] <file.mccap>...");
+        return ExitCode::from(2);
+    }
+
+    let mut status = ExitCode::SUCCESS;
+    for file in files {
+        if let Err(e) = dump(Path::new(file), hex) {
+            eprintln!("{}: {}", file, e);
+            status = ExitCode::FAILURE;
+        }
+    }
+    status
+}
@@ -0,0 +1,290 @@
+//! 模擬プレイヤーを大量に接続する負荷試験ツール
+//!
+//! 使い方:
+//!
+//! ```text
+//! loadtest [--address 127.0.0.1:25565] [--bots 100] [--join-rate 10] [--duration 60]
+//!          [--movement none|random|circle] [--chat-interval 0] [--name-prefix bot]
+//!          [--rcon 127.0.0.1:25575 --rcon-password <pw>]
+//! ```
+//!
+//! 結果は JSON で標準出力に書き出す。サーバー側の TPS と keep-alive 往復時間は
+//! RCON の `stats` コマンドで取得する。
+
+use std::collections::HashMap;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::BytesMut;
+use parking_lot::Mutex;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use testServer::client::{Client, ClientConfig};
+use testServer::net::play::movement::SYNCHRONIZE_PLAYER_POSITION_ID;
+use testServer::net::rcon::{RconPacket, AUTH_FAILED_ID, TYPE_COMMAND, TYPE_LOGIN};
+use testServer::net::stats::latency_summary;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Movement {
+    None,
+    Random,
+    Circle,
+}
+
+#[derive(Debug, Clone)]
+struct Options {
+    address: String,
+    bots: usize,
+    join_rate: f64,
+    duration: Duration,
+    movement: Movement,
+    chat_interval: Option<Duration>,
+    name_prefix: String,
+    rcon: Option<(String, String)>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            address: "127.0.0.1:25565".to_string(),
+            bots: 100,
+            join_rate: 10.0,
+            duration: Duration::from_secs(60),
+            movement: Movement::Random,
+            chat_interval: None,
+            name_prefix: "bot".to_string(),
+            rcon: None,
+        }
+    }
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut rcon_address = None;
+    let mut rcon_password = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} requires a value", arg));
+        match arg.as_str() {
+            "--address" => options.address = value()?,
+            "--bots" => options.bots = value()?.parse().map_err(|_| "invalid --bots")?,
+            "--join-rate" => options.join_rate = value()?.parse().map_err(|_| "invalid --join-rate")?,
+            "--duration" => {
+                options.duration = Duration::from_secs(value()?.parse().map_err(|_| "invalid --duration")?)
+            }
+            "--movement" => {
+                options.movement = match value()?.as_str() {
+                    "none" => Movement::None,
+                    "random" => Movement::Random,
+                    "circle" => Movement::Circle,
+                    other => return Err(format!("unknown movement pattern: {}", other)),
+                }
+            }
+            "--chat-interval" => {
+                let secs: f64 = value()?.parse().map_err(|_| "invalid --chat-interval")?;
+                if !secs.is_finite() {
+                    return Err("invalid --chat-interval".to_string());
+                }
+                options.chat_interval = (secs > 0.0).then(|| Duration::from_secs_f64(secs));
+            }
+            "--name-prefix" => options.name_prefix = value()?,
+            "--rcon" => rcon_address = Some(value()?),
+            "--rcon-password" => rcon_password = Some(value()?),
+            other => return Err(format!("unknown argument: {}", other)),
+        }
+    }
+
+    if !options.join_rate.is_finite() || options.join_rate <= 0.0 {
+        return Err("--join-rate must be positive".to_string());
+    }
+    if let Some(address) = rcon_address {
+        options.rcon = Some((address, rcon_password.unwrap_or_default()));
+    }
+    Ok(options)
+}
+
+/// 全ボットで共有する集計結果
+#[derive(Default)]
+struct Results {
+    connected: usize,
+    failed: usize,
+    disconnected_early: usize,
+    login_latencies: Vec<Duration>,
+    errors: HashMap<String, usize>,
+}
+
+impl Results {
+    fn record_error(&mut self, error: &testServer::ServerError) {
+        *self.errors.entry(error.to_string()).or_default() += 1;
+    }
+}
+
+/// 移動パターンに従って次の座標を返す
+fn next_position(movement: Movement, tick: u64, origin: (f64, f64, f64), current: (f64, f64, f64)) -> Option<(f64, f64, f64)> {
+    match movement {
+        Movement::None => None,
+        Movement::Random => {
+            let dx = rand::random::<f64>() * 0.4 - 0.2;
+            let dz = rand::random::<f64>() * 0.4 - 0.2;
+            Some((current.0 + dx, current.1, current.2 + dz))
+        }
+        Movement::Circle => {
+            let angle = tick as f64 * 0.05;
+            Some((origin.0 + angle.cos() * 4.0, origin.1, origin.2 + angle.sin() * 4.0))
+        }
+    }
+}
+
+async fn run_bot(index: usize, options: Arc<Options>, deadline: Instant, results: Arc<Mutex<Results>>) {
+    let username = format!("{}{}", options.name_prefix, index);
+    let started = Instant::now();
+
+    let mut bot = match Client::connect(ClientConfig::offline(&options.address, &username)).await {
+        Ok(bot) => bot,
+        Err(e) => {
+            let mut results = results.lock();
+            results.failed += 1;
+            results.record_error(&e);
+            return;
+        }
+    };
+    {
+        let mut results = results.lock();
+        results.connected += 1;
+        results.login_latencies.push(started.elapsed());
+    }
+
+    let mut origin = ((index % 100) as f64, 64.0, (index / 100) as f64);
+    let mut position = origin;
+    let mut movement = tokio::time::interval(Duration::from_millis(50));
+    let mut chat = options.chat_interval.map(tokio::time::interval);
+    let mut tick = 0u64;
+
+    let outcome: testServer::Result<()> = async {
+        loop {
+            if Instant::now() >= deadline {
+                return Ok(());
+            }
+            // next_packet はキャンセルしても読みかけのフレームや応答を失わない
+            tokio::select! {
+                frame = bot.next_packet() => {
+                    // サーバーに位置を戻されたら、そこから動き直す
+                    if frame?.id == SYNCHRONIZE_PLAYER_POSITION_ID {
+                        if let Some(synced) = bot.position {
+                            position = synced;
+                            origin = synced;
+                        }
+                    }
+                }
+                _ = movement.tick() => {
+                    tick += 1;
+                    if let Some(next) = next_position(options.movement, tick, origin, position) {
+                        position = next;
+                        bot.move_to(position.0, position.1, position.2, true).await?;
+                    }
+                }
+                _ = async { chat.as_mut().unwrap().tick().await }, if chat.is_some() => {
+                    bot.send_chat(&format!("hello from {}", username)).await?;
+                }
+                _ = tokio::time::sleep_until(deadline.into()) => return Ok(()),
+            }
+        }
+    }.await;
+
+    if let Err(e) = outcome {
+        let mut results = results.lock();
+        results.disconnected_early += 1;
+        results.record_error(&e);
+    }
+}
+
+/// RCON で1つのコマンドを実行し、出力を返す
+async fn rcon_command(address: &str, password: &str, command: &str) -> Result<String, String> {
+    let mut stream = TcpStream::connect(address).await.map_err(|e| e.to_string())?;
+    let mut buf = BytesMut::new();
+
+    async fn exchange(stream: &mut TcpStream, buf: &mut BytesMut, packet: RconPacket) -> Result<RconPacket, String> {
+        let mut out = BytesMut::new();
+        packet.encode(&mut out);
+        stream.write_all(&out).await.map_err(|e| e.to_string())?;
+        loop {
+            if let Some(packet) = RconPacket::decode(buf).map_err(|e| e.to_string())? {
+                return Ok(packet);
+            }
+            if stream.read_buf(buf).await.map_err(|e| e.to_string())? == 0 {
+                return Err("RCON connection closed".to_string());
+            }
+        }
+    }
+
+    let auth = exchange(&mut stream, &mut buf, RconPacket::new(1, TYPE_LOGIN, password)).await?;
+    if auth.request_id == AUTH_FAILED_ID {
+        return Err("RCON authentication failed".to_string());
+    }
+    let response = exchange(&mut stream, &mut buf, RconPacket::new(2, TYPE_COMMAND, command)).await?;
+    Ok(response.payload)
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let options = match parse_args() {
+        Ok(options) => Arc::new(options),
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::from(2);
+        }
+    };
+
+    let results = Arc::new(Mutex::new(Results::default()));
+    let started = Instant::now();
+    let join_interval = Duration::from_secs_f64(1.0 / options.join_rate);
+    let ramp_up = join_interval * options.bots as u32;
+    let deadline = started + ramp_up + options.duration;
+
+    let mut bots = Vec::with_capacity(options.bots);
+    let mut ramp = tokio::time::interval(join_interval);
+    for index in 0..options.bots {
+        ramp.tick().await;
+        bots.push(tokio::spawn(run_bot(index, options.clone(), deadline, results.clone())));
+    }
+
+    // 全員が接続している間にサーバー側の統計を取る
+    tokio::time::sleep_until((started + ramp_up + options.duration / 2).into()).await;
+    let server = match &options.rcon {
+        Some((address, password)) => match rcon_command(address, password, "stats").await {
+            Ok(output) => serde_json::from_str(&output).unwrap_or(serde_json::Value::String(output)),
+            Err(e) => serde_json::json!({ "error": e }),
+        },
+        None => serde_json::Value::Null,
+    };
+
+    for bot in bots {
+        let _ = bot.await;
+    }
+
+    let results = results.lock();
+    let report = serde_json::json!({
+        "address": options.address,
+        "bots": options.bots,
+        "join_rate": options.join_rate,
+        "duration_secs": options.duration.as_secs(),
+        "connected": results.connected,
+        "failed": results.failed,
+        "disconnected_early": results.disconnected_early,
+        "success_rate": results.connected as f64 / options.bots.max(1) as f64,
+        "login_latency_ms": latency_summary(&results.login_latencies),
+        "errors": results.errors,
+        "server": server,
+        "elapsed_secs": started.elapsed().as_secs_f64(),
+    });
+    println!("{}", serde_json::to_string_pretty(&report).unwrap());
+
+    if results.failed > 0 {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
@@ -0,0 +1,100 @@
+use bytes::BytesMut;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::net::connection::Frame;
+use crate::net::error::{Result, ServerError};
+use crate::net::login::encryption::PacketCipher;
+use crate::net::protocol::codec::PacketCodec;
+use crate::net::protocol::{Packet, PacketState};
+
+/// クライアント側の長さ付きフレームの読み書き
+pub struct ClientConnection {
+    stream: TcpStream,
+    buf: BytesMut,
+    /// 送信用バッファ。エンコードと暗号化をこの中で済ませる
+    out: BytesMut,
+    codec: PacketCodec,
+    cipher: Option<PacketCipher>,
+    state: PacketState,
+}
+
+impl ClientConnection {
+    pub fn new(stream: TcpStream) -> Self {
+        Self {
+            stream,
+            buf: BytesMut::with_capacity(4096),
+            out: BytesMut::with_capacity(4096),
+            codec: PacketCodec::default(),
+            cipher: None,
+            state: PacketState::Handshake,
+        }
+    }
+
+    pub fn state(&self) -> PacketState {
+        self.state
+    }
+
+    pub fn set_state(&mut self, state: PacketState) {
+        self.state = state;
+    }
+
+    /// 以降の送受信を共有鍵で暗号化する
+    pub fn enable_encryption(&mut self, shared_secret: &[u8]) -> Result<()> {
+        self.cipher = Some(PacketCipher::new(shared_secret)?);
+        Ok(())
+    }
+
+    /// 次のフレームを読む。接続が閉じられた場合は `None`。キャンセルしても読みかけのデータは失われない
+    pub async fn read_frame(&mut self) -> Result<Option<Frame>> {
+        loop {
+            if let Some((id, body)) = self.codec.decode_frame(&mut self.buf)? {
+                return Ok(Some(Frame { id, body }));
+            }
+
+            let start = self.buf.len();
+            if self.stream.read_buf(&mut self.buf).await? == 0 {
+                if self.buf.is_empty() {
+                    return Ok(None);
+                }
+                return Err(ServerError::Protocol("Connection closed mid-packet".to_string()));
+            }
+            if let Some(cipher) = &mut self.cipher {
+                cipher.decrypt(&mut self.buf[start..]);
+            }
+        }
+    }
+
+    pub async fn write_frame(&mut self, id: i32, body: &[u8]) -> Result<()> {
+        let start = self.out.len();
+        self.codec.encode_frame(id, body, &mut self.out)?;
+        self.encrypt_from(start);
+        self.flush().await
+    }
+
+    pub async fn write_packet<P: Packet>(&mut self, packet: &P) -> Result<()> {
+        self.queue_packet(packet)?;
+        self.flush().await
+    }
+
+    /// 送らずに送信バッファに積む。次の [`flush`](Self::flush) でまとめて送る
+    pub fn queue_packet<P: Packet>(&mut self, packet: &P) -> Result<()> {
+        let start = self.out.len();
+        self.codec.encode_packet(packet, &mut self.out)?;
+        self.encrypt_from(start);
+        Ok(())
+    }
+
+    /// 積んだ分だけ暗号化する。暗号は連続したストリームなので、積んだ順に1度だけ通す
+    fn encrypt_from(&mut self, start: usize) {
+        if let Some(cipher) = &mut self.cipher {
+            cipher.encrypt(&mut self.out[start..]);
+        }
+    }
+
+    /// 送信バッファを送る。キャンセルされても、送っていない分はバッファに残って次に送られる
+    pub async fn flush(&mut self) -> Result<()> {
+        self.stream.write_all_buf(&mut self.out).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,331 @@
+//! ヘッドレスのボットクライアント
+//!
+//! サーバー側と同じパケット定義とコーデックを使って接続し、ステータス取得・ログイン・
+//! Play 状態での操作を行う。結合テストや負荷試験から実サーバーを操作するためのもの。
+
+pub mod connection;
+pub mod ping;
+
+use std::time::{Duration, Instant};
+
+use bytes::BytesMut;
+use log::debug;
+use rand::RngCore;
+use rsa::pkcs8::DecodePublicKey;
+use rsa::{Pkcs1v15Encrypt, RsaPublicKey};
+use tokio::net::TcpStream;
+use uuid::Uuid;
+
+pub use connection::ClientConnection;
+pub use ping::{ping_legacy, ping_server, ServerStatus};
+
+use crate::net::connection::Frame;
+use crate::net::context::PROTOCOL_VERSION;
+use crate::net::error::{Result, ServerError};
+use crate::net::login::disconnect::LoginDisconnect;
+use crate::net::login::encryption::request::EncryptionRequest;
+use crate::net::login::encryption::response::EncryptionResponse;
+use crate::net::login::encryption::server_hash;
+use crate::net::login::start::LoginStart;
+use crate::net::login::success::LoginSuccess;
+use crate::net::play::chat::{ChatCommand, ChatMessage};
+use crate::net::play::client_information::ClientInformation;
+use crate::net::play::disconnect::PlayDisconnect;
+use crate::net::play::keep_alive::{KeepAlive, CLIENTBOUND_KEEP_ALIVE_ID};
+use crate::net::play::movement::{
+    ConfirmTeleportation, SetPlayerPosition, SetPlayerRotation, SynchronizePlayerPosition, SYNCHRONIZE_PLAYER_POSITION_ID,
+};
+use crate::net::protocol::handshake::HandshakePacket;
+use crate::net::protocol::types::ProtocolRead;
+use crate::net::protocol::{Packet, PacketState};
+use crate::varint::utils::{read_string, write_varint};
+
+pub const DEFAULT_SESSION_SERVER: &str = "https://sessionserver.mojang.com";
+
+/// ログイン時の認証方法
+#[derive(Debug, Clone)]
+pub enum ClientAuth {
+    /// オフラインモード（暗号化なし）
+    Offline,
+    /// セッションサーバーに参加してから暗号化を有効にする
+    Online {
+        access_token: String,
+        profile_id: Uuid,
+        /// `DEFAULT_SESSION_SERVER` かテスト用のモックサーバー
+        session_server: String,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    /// 接続先 (`host:port`)
+    pub address: String,
+    pub username: String,
+    pub protocol_version: i32,
+    pub auth: ClientAuth,
+    pub timeout: Duration,
+}
+
+impl ClientConfig {
+    pub fn offline(address: &str, username: &str) -> Self {
+        Self {
+            address: address.to_string(),
+            username: username.to_string(),
+            protocol_version: PROTOCOL_VERSION,
+            auth: ClientAuth::Offline,
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+fn timed_out() -> ServerError {
+    ServerError::Io(std::io::Error::new(std::io::ErrorKind::TimedOut, "timed out"))
+}
+
+/// `future` をタイムアウト付きで待つ
+async fn with_timeout<T>(timeout: Duration, future: impl std::future::Future<Output = Result<T>>) -> Result<T> {
+    tokio::time::timeout(timeout, future).await.map_err(|_| timed_out())?
+}
+
+fn split_address(address: &str) -> Result<(String, u16)> {
+    match address.rsplit_once(':') {
+        Some((host, port)) => {
+            let port = port.parse()
+                .map_err(|_| ServerError::Config(format!("不正なポート番号: {}", address)))?;
+            Ok((host.trim_matches(|c| c == '[' || c == ']').to_string(), port))
+        }
+        None => Ok((address.to_string(), 25565)),
+    }
+}
+
+/// 接続してハンドシェイクを送る
+async fn open(address: &str, protocol_version: i32, next_state: PacketState) -> Result<ClientConnection> {
+    let (host, port) = split_address(address)?;
+    let stream = TcpStream::connect(address).await?;
+    stream.set_nodelay(true)?;
+    let mut conn = ClientConnection::new(stream);
+
+    conn.write_packet(&HandshakePacket {
+        protocol_version,
+        server_address: host,
+        server_port: port,
+        next_state,
+    }).await?;
+    conn.set_state(next_state);
+    Ok(conn)
+}
+
+async fn expect_frame(conn: &mut ClientConnection) -> Result<Frame> {
+    conn.read_frame().await?
+        .ok_or_else(|| ServerError::Disconnected("サーバーが接続を閉じました".into()))
+}
+
+/// Status Request → Ping を行い、ステータスJSONと往復時間を返す
+pub async fn status(address: &str, timeout: Duration) -> Result<(serde_json::Value, Duration)> {
+    with_timeout(timeout, async {
+        let mut conn = open(address, PROTOCOL_VERSION, PacketState::Status).await?;
+
+        conn.write_frame(0x00, &[]).await?;
+        let mut frame = expect_frame(&mut conn).await?;
+        if frame.id != 0x00 {
+            return Err(ServerError::Protocol(format!("予期しないパケット: 0x{:02X}", frame.id)));
+        }
+        let json = read_string(&mut frame.body)?;
+        let status = serde_json::from_str(&json)
+            .map_err(|e| ServerError::Protocol(format!("不正なステータスJSON: {}", e)))?;
+
+        let started = Instant::now();
+        let payload = chrono::Utc::now().timestamp_millis();
+        conn.write_frame(0x01, &payload.to_be_bytes()).await?;
+        let mut frame = expect_frame(&mut conn).await?;
+        let latency = started.elapsed();
+        if frame.id != 0x01 {
+            return Err(ServerError::Protocol(format!("予期しないパケット: 0x{:02X}", frame.id)));
+        }
+        if i64::read(&mut frame.body)? != payload {
+            return Err(ServerError::Protocol("Pong のペイロードが一致しません".into()));
+        }
+        Ok((status, latency))
+    }).await
+}
+
+/// Play 状態に入ったボット
+pub struct Client {
+    conn: ClientConnection,
+    pub uuid: Uuid,
+    pub username: String,
+    pub position: Option<(f64, f64, f64)>,
+}
+
+impl Client {
+    /// 接続してログインし、Play 状態まで進める
+    pub async fn connect(config: ClientConfig) -> Result<Self> {
+        let timeout = config.timeout;
+        with_timeout(timeout, Self::login(config)).await
+    }
+
+    async fn login(config: ClientConfig) -> Result<Self> {
+        let mut conn = open(&config.address, config.protocol_version, PacketState::Login).await?;
+
+        let player_uuid = match &config.auth {
+            ClientAuth::Online { profile_id, .. } => Some(*profile_id),
+            ClientAuth::Offline => None,
+        };
+        conn.write_packet(&LoginStart { username: config.username.clone(), player_uuid }).await?;
+
+        loop {
+            let mut frame = expect_frame(&mut conn).await?;
+            match frame.id {
+                0x00 => {
+                    let disconnect = LoginDisconnect::decode(&mut frame.body)?;
+                    return Err(ServerError::Disconnected(disconnect.reason_json));
+                }
+                0x01 => {
+                    let request = EncryptionRequest::decode(&mut frame.body)?;
+                    Self::encrypt(&mut conn, &config.auth, request).await?;
+                }
+                0x02 => {
+                    let success = LoginSuccess::decode(&mut frame.body)?;
+                    conn.set_state(PacketState::Play);
+                    debug!("Logged in as {} ({})", success.username, success.uuid);
+                    return Ok(Self {
+                        conn,
+                        uuid: success.uuid,
+                        username: success.username,
+                        position: None,
+                    });
+                }
+                0x03 => {
+                    return Err(ServerError::Protocol("圧縮には対応していません".into()));
+                }
+                0x04 => {
+                    // Login Plugin Request には「未対応」と返す
+                    let message_id = crate::varint::utils::read_varint(&mut frame.body)?;
+                    let mut body = BytesMut::new();
+                    write_varint(&mut body, message_id)?;
+                    body.extend_from_slice(&[0]);
+                    conn.write_frame(0x02, &body).await?;
+                }
+                id => {
+                    return Err(ServerError::Protocol(format!("予期しないログインパケット: 0x{:02X}", id)));
+                }
+            }
+        }
+    }
+
+    /// セッションサーバーに参加し、Encryption Response を送って暗号化を有効にする
+    async fn encrypt(conn: &mut ClientConnection, auth: &ClientAuth, request: EncryptionRequest) -> Result<()> {
+        let ClientAuth::Online { access_token, profile_id, session_server } = auth else {
+            return Err(ServerError::Protocol("オンラインモードのサーバーにはオフラインで参加できません".into()));
+        };
+
+        let mut shared_secret = [0u8; 16];
+        rand::rng().fill_bytes(&mut shared_secret);
+
+        let server_id = server_hash(&request.server_id, &shared_secret, &request.public_key);
+        let response = reqwest::Client::new()
+            .post(format!("{}/session/minecraft/join", session_server.trim_end_matches('/')))
+            .json(&serde_json::json!({
+                "accessToken": access_token,
+                "selectedProfile": profile_id.simple().to_string(),
+                "serverId": server_id,
+            }))
+            .send()
+            .await
+            .map_err(|e| ServerError::Protocol(format!("セッションサーバーへの接続に失敗: {}", e)))?;
+        if !response.status().is_success() {
+            return Err(ServerError::Protocol(format!("セッションサーバーへの参加に失敗: {}", response.status())));
+        }
+
+        let public_key = RsaPublicKey::from_public_key_der(&request.public_key)
+            .map_err(|_| ServerError::Protocol("サーバーの公開鍵が不正です".into()))?;
+        let (encrypted_secret, encrypted_token) = {
+            let mut rng = rand::rng();
+            let mut encrypt = |data: &[u8]| public_key.encrypt(&mut rng, Pkcs1v15Encrypt, data)
+                .map_err(|_| ServerError::Protocol("RSA暗号化に失敗".into()));
+            (encrypt(&shared_secret)?, encrypt(&request.verify_token)?)
+        };
+
+        conn.write_packet(&EncryptionResponse {
+            shared_secret: encrypted_secret,
+            verify_token: encrypted_token,
+        }).await?;
+        conn.enable_encryption(&shared_secret)
+    }
+
+    pub async fn send_packet<P: Packet>(&mut self, packet: &P) -> Result<()> {
+        self.conn.write_packet(packet).await
+    }
+
+    pub async fn send_chat(&mut self, message: &str) -> Result<()> {
+        match message.strip_prefix('/') {
+            Some(command) => self.send_packet(&ChatCommand::new(command)).await,
+            None => self.send_packet(&ChatMessage::new(message)).await,
+        }
+    }
+
+    pub async fn move_to(&mut self, x: f64, y: f64, z: f64, on_ground: bool) -> Result<()> {
+        self.position = Some((x, y, z));
+        self.send_packet(&SetPlayerPosition { x, y, z, on_ground }).await
+    }
+
+    pub async fn look(&mut self, yaw: f32, pitch: f32, on_ground: bool) -> Result<()> {
+        self.send_packet(&SetPlayerRotation { yaw, pitch, on_ground }).await
+    }
+
+    /// 次のパケットを読む。Keep Alive とテレポートには自動で応答し、切断は `Disconnected` エラーになる。
+    ///
+    /// キャンセルしても安全なので `select!` で他の処理と並べられる。フレームを読んだ後は待たないので、
+    /// 応答は送信バッファに積んでおき、次の読み込みか送信の前に送る
+    pub async fn next_packet(&mut self) -> Result<Frame> {
+        self.conn.flush().await?;
+        let mut frame = expect_frame(&mut self.conn).await?;
+        match frame.id {
+            CLIENTBOUND_KEEP_ALIVE_ID => {
+                let id = KeepAlive::decode(&mut frame.body.clone())?.id;
+                self.conn.queue_packet(&KeepAlive { id, serverbound: true })?;
+            }
+            SYNCHRONIZE_PLAYER_POSITION_ID => {
+                let sync = SynchronizePlayerPosition::decode(&mut frame.body.clone())?;
+                // 向きは覚えていないので、相対指定なら 0 からとする
+                let (position, _) = sync.apply(self.position.unwrap_or_default(), (0.0, 0.0));
+                self.position = Some(position);
+                self.conn.queue_packet(&ConfirmTeleportation { teleport_id: sync.teleport_id })?;
+            }
+            0x1A => {
+                let disconnect = PlayDisconnect::decode(&mut frame.body)?;
+                return Err(ServerError::Disconnected(disconnect.reason_json));
+            }
+            _ => {}
+        }
+        Ok(frame)
+    }
+
+    /// 指定したIDのパケットが届くまで待つ。それ以外のパケットは読み捨てる
+    pub async fn wait_for(&mut self, packet_id: i32, timeout: Duration) -> Result<Frame> {
+        with_timeout(timeout, async {
+            loop {
+                let frame = self.next_packet().await?;
+                if frame.id == packet_id {
+                    return Ok(frame);
+                }
+            }
+        }).await
+    }
+
+    /// 指定したIDのパケットを待ってデコードする
+    pub async fn wait_for_packet<P: Packet>(&mut self, packet_id: i32, timeout: Duration) -> Result<P> {
+        let mut frame = self.wait_for(packet_id, timeout).await?;
+        P::decode(&mut frame.body)
+    }
+
+    /// Play 状態の生フレームを送る
+    pub async fn send_raw(&mut self, packet_id: i32, body: &[u8]) -> Result<()> {
+        self.conn.write_frame(packet_id, body).await
+    }
+
+    /// クライアント情報 (言語・描画距離など) を送る
+    pub async fn send_client_information(&mut self, locale: &str, view_distance: i8) -> Result<()> {
+        self.send_packet(&ClientInformation { locale: locale.to_string(), view_distance, ..ClientInformation::default() }).await
+    }
+}
@@ -0,0 +1,343 @@
+//! Server List Ping
+//!
+//! 1.7 以降の Status (ハンドシェイク → Status Request → Ping) と、
+//! それ以前のサーバーが応答する旧形式 (0xFE → 0xFF キック) の両方を型付きの [`ServerStatus`] で返す。
+
+use std::time::{Duration, Instant};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Serialize, Serializer};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use super::{split_address, status, with_timeout};
+use crate::net::error::{Result, ServerError};
+
+/// favicon の data URI の接頭辞
+const FAVICON_PREFIX: &str = "data:image/png;base64,";
+/// 旧形式の Ping で名乗るプロトコル番号 (1.6.4)
+const LEGACY_PROTOCOL: u8 = 78;
+/// 旧形式の応答の最大文字数
+const MAX_LEGACY_LENGTH: usize = 256;
+
+/// ステータス応答
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ServerStatus {
+    pub version: StatusVersion,
+    pub players: StatusPlayers,
+    pub description: TextComponent,
+    /// 64x64 の PNG
+    #[serde(serialize_with = "serialize_favicon", skip_serializing_if = "Option::is_none")]
+    pub favicon: Option<Vec<u8>>,
+    #[serde(rename = "enforcesSecureChat", skip_serializing_if = "Option::is_none")]
+    pub enforces_secure_chat: Option<bool>,
+    /// 旧形式の Ping で得た応答かどうか
+    #[serde(skip)]
+    pub legacy: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatusVersion {
+    pub name: String,
+    pub protocol: i32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatusPlayers {
+    pub max: i64,
+    pub online: i64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sample: Vec<PlayerSample>,
+}
+
+/// ホバー表示用に公開されているプレイヤー
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlayerSample {
+    pub name: String,
+    pub id: String,
+}
+
+/// チャットコンポーネント。文字列だけの形式も受け付ける
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(from = "RawComponent")]
+pub struct TextComponent {
+    pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bold: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub italic: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub underlined: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strikethrough: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub obfuscated: Option<bool>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub extra: Vec<TextComponent>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawComponent {
+    Text(String),
+    Component {
+        #[serde(default)]
+        text: String,
+        color: Option<String>,
+        bold: Option<bool>,
+        italic: Option<bool>,
+        underlined: Option<bool>,
+        strikethrough: Option<bool>,
+        obfuscated: Option<bool>,
+        #[serde(default)]
+        extra: Vec<TextComponent>,
+    },
+}
+
+impl From<RawComponent> for TextComponent {
+    fn from(raw: RawComponent) -> Self {
+        match raw {
+            RawComponent::Text(text) => Self { text, ..Self::default() },
+            RawComponent::Component { text, color, bold, italic, underlined, strikethrough, obfuscated, extra } => {
+                Self { text, color, bold, italic, underlined, strikethrough, obfuscated, extra }
+            }
+        }
+    }
+}
+
+impl TextComponent {
+    /// 装飾を除いた本文 (`extra` を含む)
+    pub fn plain_text(&self) -> String {
+        let mut text = self.text.clone();
+        for child in &self.extra {
+            text.push_str(&child.plain_text());
+        }
+        text
+    }
+}
+
+fn serialize_favicon<S: Serializer>(favicon: &Option<Vec<u8>>, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    match favicon {
+        Some(png) => serializer.serialize_str(&format!("{}{}", FAVICON_PREFIX, BASE64.encode(png))),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Status Response のJSONそのままの形
+#[derive(Deserialize)]
+struct RawStatus {
+    version: StatusVersion,
+    players: Option<StatusPlayers>,
+    #[serde(default)]
+    description: TextComponent,
+    favicon: Option<String>,
+    #[serde(rename = "enforcesSecureChat")]
+    enforces_secure_chat: Option<bool>,
+}
+
+impl ServerStatus {
+    /// Status Response のJSONを解釈する
+    pub fn from_json(json: serde_json::Value) -> Result<Self> {
+        let raw: RawStatus = serde_json::from_value(json)
+            .map_err(|e| ServerError::Protocol(format!("不正なステータスJSON: {}", e)))?;
+
+        let favicon = match raw.favicon {
+            Some(uri) => {
+                let data = uri.strip_prefix(FAVICON_PREFIX)
+                    .ok_or_else(|| ServerError::Protocol("favicon が PNG の data URI ではありません".into()))?;
+                // 改行を含めて送ってくるサーバーがある
+                let data: String = data.chars().filter(|c| !c.is_whitespace()).collect();
+                Some(BASE64.decode(data)
+                    .map_err(|e| ServerError::Protocol(format!("favicon を復号できません: {}", e)))?)
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            version: raw.version,
+            // プレイヤー数を隠すサーバーは players を省略する
+            players: raw.players.unwrap_or(StatusPlayers { max: 0, online: 0, sample: Vec::new() }),
+            description: raw.description,
+            favicon,
+            enforces_secure_chat: raw.enforces_secure_chat,
+            legacy: false,
+        })
+    }
+
+    /// 旧形式の応答 (0xFF キックの本文) を解釈する
+    pub fn from_legacy(text: &str) -> Result<Self> {
+        let invalid = || ServerError::Protocol(format!("不正な旧形式の応答: {:?}", text));
+
+        // 1.4 以降: §1\0プロトコル\0バージョン\0MOTD\0人数\0最大人数
+        if let Some(fields) = text.strip_prefix("\u{a7}1\0") {
+            let fields: Vec<&str> = fields.split('\0').collect();
+            let [protocol, name, motd, online, max] = fields[..] else {
+                return Err(invalid());
+            };
+            return Ok(Self::legacy(
+                StatusVersion { name: name.to_string(), protocol: protocol.parse().map_err(|_| invalid())? },
+                motd,
+                online.parse().map_err(|_| invalid())?,
+                max.parse().map_err(|_| invalid())?,
+            ));
+        }
+
+        // beta 1.8 〜 1.3: MOTD§人数§最大人数
+        let mut fields = text.rsplitn(3, '\u{a7}');
+        let (Some(max), Some(online), Some(motd)) = (fields.next(), fields.next(), fields.next()) else {
+            return Err(invalid());
+        };
+        Ok(Self::legacy(
+            StatusVersion { name: String::new(), protocol: -1 },
+            motd,
+            online.parse().map_err(|_| invalid())?,
+            max.parse().map_err(|_| invalid())?,
+        ))
+    }
+
+    fn legacy(version: StatusVersion, motd: &str, online: i64, max: i64) -> Self {
+        Self {
+            version,
+            players: StatusPlayers { max, online, sample: Vec::new() },
+            description: TextComponent { text: motd.to_string(), ..TextComponent::default() },
+            favicon: None,
+            enforces_secure_chat: None,
+            legacy: true,
+        }
+    }
+}
+
+/// ハンドシェイク → Status Request → Ping を行い、ステータスと往復時間を返す
+pub async fn ping_server(address: &str, timeout: Duration) -> Result<(ServerStatus, Duration)> {
+    let (json, latency) = status(address, timeout).await?;
+    Ok((ServerStatus::from_json(json)?, latency))
+}
+
+/// 1.6 以前の形式 (0xFE 0x01 + MC|PingHost) で問い合わせる。
+/// 往復時間は要求を送ってから応答が届くまで
+pub async fn ping_legacy(address: &str, timeout: Duration) -> Result<(ServerStatus, Duration)> {
+    with_timeout(timeout, async {
+        let (host, port) = split_address(address)?;
+        let mut stream = TcpStream::connect(address).await?;
+
+        let started = Instant::now();
+        stream.write_all(&legacy_request(&host, port)).await?;
+
+        let id = stream.read_u8().await?;
+        let latency = started.elapsed();
+        if id != 0xFF {
+            return Err(ServerError::Protocol(format!("旧形式の応答ではありません: 0x{:02X}", id)));
+        }
+        let length = stream.read_u16().await? as usize;
+        if length > MAX_LEGACY_LENGTH {
+            return Err(ServerError::Protocol(format!("旧形式の応答が長すぎます: {}", length)));
+        }
+        let mut units = vec![0u16; length];
+        for unit in units.iter_mut() {
+            *unit = stream.read_u16().await?;
+        }
+        let text = String::from_utf16(&units)
+            .map_err(|_| ServerError::Protocol("旧形式の応答が UTF-16 ではありません".into()))?;
+        Ok((ServerStatus::from_legacy(&text)?, latency))
+    }).await
+}
+
+/// UTF-16BE の文字列を文字数付きで書く
+fn put_legacy_string(buf: &mut Vec<u8>, value: &str) {
+    let units: Vec<u16> = value.encode_utf16().collect();
+    buf.extend_from_slice(&(units.len() as u16).to_be_bytes());
+    for unit in units {
+        buf.extend_from_slice(&unit.to_be_bytes());
+    }
+}
+
+fn legacy_request(host: &str, port: u16) -> Vec<u8> {
+    let mut payload = vec![LEGACY_PROTOCOL];
+    put_legacy_string(&mut payload, host);
+    payload.extend_from_slice(&(port as i32).to_be_bytes());
+
+    let mut request = vec![0xFE, 0x01, 0xFA];
+    put_legacy_string(&mut request, "MC|PingHost");
+    request.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    request.extend_from_slice(&payload);
+    request
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn test_status_json() {
+        let png = vec![0x89, b'P', b'N', b'G'];
+        let json = serde_json::json!({
+            "version": { "name": "1.20.1", "protocol": 763 },
+            "players": { "max": 20, "online": 1, "sample": [{ "name": "Steve", "id": "069a79f4-44e9-4726-a5be-fca90e38aaf5" }] },
+            "description": { "text": "Hello ", "color": "gold", "extra": ["World", { "text": "!", "bold": true }] },
+            "favicon": format!("{}{}", FAVICON_PREFIX, BASE64.encode(&png)),
+        });
+        let status = ServerStatus::from_json(json).unwrap();
+        assert_eq!(status.version.protocol, 763);
+        assert_eq!(status.players.sample[0].name, "Steve");
+        assert_eq!(status.description.color.as_deref(), Some("gold"));
+        assert_eq!(status.description.plain_text(), "Hello World!");
+        assert_eq!(status.favicon.as_deref(), Some(&png[..]));
+
+        // 書き戻したJSONも同じように読める
+        let again = ServerStatus::from_json(serde_json::to_value(&status).unwrap()).unwrap();
+        assert_eq!(again, status);
+
+        let minimal = ServerStatus::from_json(serde_json::json!({
+            "version": { "name": "x", "protocol": 5 },
+            "description": "plain",
+        })).unwrap();
+        assert_eq!(minimal.description.text, "plain");
+        assert_eq!(minimal.players.online, 0);
+
+        assert!(ServerStatus::from_json(serde_json::json!({ "description": "no version" })).is_err());
+        assert!(ServerStatus::from_json(serde_json::json!({
+            "version": { "name": "x", "protocol": 5 },
+            "favicon": "http://example.com/icon.png",
+        })).is_err());
+    }
+
+    #[test]
+    fn test_legacy_response() {
+        let status = ServerStatus::from_legacy("\u{a7}1\u{0}78\u{0}1.6.4\u{0}A Minecraft Server\u{0}3\u{0}20").unwrap();
+        assert!(status.legacy);
+        assert_eq!(status.version, StatusVersion { name: "1.6.4".to_string(), protocol: 78 });
+        assert_eq!(status.description.text, "A Minecraft Server");
+        assert_eq!((status.players.online, status.players.max), (3, 20));
+
+        let beta = ServerStatus::from_legacy("Old \u{a7} Server\u{a7}1\u{a7}8").unwrap();
+        assert_eq!(beta.description.text, "Old \u{a7} Server");
+        assert_eq!((beta.players.online, beta.players.max), (1, 8));
+
+        assert!(ServerStatus::from_legacy("garbage").is_err());
+        assert!(ServerStatus::from_legacy("\u{a7}1\u{0}78\u{0}1.6.4").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_ping_legacy() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = [0u8; 3];
+            stream.read_exact(&mut request).await.unwrap();
+            assert_eq!(request, [0xFE, 0x01, 0xFA]);
+
+            let mut response = vec![0xFF];
+            put_legacy_string(&mut response, "\u{a7}1\u{0}78\u{0}1.6.4\u{0}Legacy\u{0}0\u{0}10");
+            stream.write_all(&response).await.unwrap();
+        });
+
+        let (status, _latency) = ping_legacy(&address, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(status.description.text, "Legacy");
+        assert_eq!(status.players.max, 10);
+    }
+}
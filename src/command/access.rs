@@ -0,0 +1,267 @@
+use chrono::{Duration, Local};
+
+use super::{CommandContext, CommandDispatcher, CommandSource};
+use crate::net::access::{Ban, IpBan, OpEntry, PlayerBan, UserEntry, BANNED_IP_HEADLINE, BANNED_PLAYER_HEADLINE, DEFAULT_OP_LEVEL};
+use crate::net::login::offline::offline_uuid;
+use crate::utils::config::{AuthMethod, ServerConfig};
+
+/// ホワイトリスト・BAN・OP を操作するコマンドを登録する
+pub(super) fn register(dispatcher: &mut CommandDispatcher) {
+    dispatcher.register("whitelist", "ホワイトリストを操作します: whitelist <on|off|list|reload> / whitelist <add|remove> <名前>", whitelist_command);
+    dispatcher.register("op", "プレイヤーを OP にします: op <名前>", |ctx, args| match args {
+        [name] => {
+            let Some(player) = resolve_player(ctx, name) else {
+                return UNKNOWN_PLAYER_MESSAGE.to_string();
+            };
+            let entry = OpEntry { uuid: player.uuid, name: player.name.clone(), level: DEFAULT_OP_LEVEL, bypasses_player_limit: false };
+            saved(ctx.server.access.op(entry), format!("Made {} a server operator", player.name), "Nothing changed. The player already is an operator")
+        }
+        _ => "Usage: op <player>".to_string(),
+    });
+    dispatcher.register("deop", "OP を外します: deop <名前>", |ctx, args| match args {
+        [name] => saved(ctx.server.access.deop(name), format!("Made {} no longer a server operator", name), "Nothing changed. The player is not an operator"),
+        _ => "Usage: deop <player>".to_string(),
+    });
+    dispatcher.register("ban", "プレイヤーを BAN します: ban <名前> [理由]", |ctx, args| match args {
+        [name, reason @ ..] => ban_player(ctx, name, None, &reason.join(" ")),
+        _ => "Usage: ban <player> [reason]".to_string(),
+    });
+    dispatcher.register("tempban", "期限付きで BAN します: tempban <名前> <期間 (30m, 12h, 7d など)> [理由]", |ctx, args| match args {
+        [name, duration, reason @ ..] => match parse_duration(duration) {
+            Some(duration) => ban_player(ctx, name, Some(duration), &reason.join(" ")),
+            None => format!("Invalid duration: {}", duration),
+        },
+        _ => "Usage: tempban <player> <duration> [reason]".to_string(),
+    });
+    dispatcher.register("pardon", "BAN を解除します: pardon <名前>", |ctx, args| match args {
+        [name] => saved(ctx.server.access.pardon(name), format!("Unbanned {}", name), "Nothing changed. The player isn't banned"),
+        _ => "Usage: pardon <player>".to_string(),
+    });
+    dispatcher.register("ban-ip", "IP アドレスを BAN します: ban-ip <IP> [理由]", |ctx, args| match args {
+        [ip, reason @ ..] => match ip.parse() {
+            Ok(ip) => {
+                let entry = IpBan { ip, ban: Ban::new(ban_source(&ctx.source), &reason.join(" "), None) };
+                let message = entry.ban.kick_message(BANNED_IP_HEADLINE);
+                match ctx.server.access.ban_ip(entry) {
+                    Ok(()) => {
+                        // vanilla と同じく、その IP から接続しているプレイヤーを全員切断する
+                        let online = ctx.server.players.sessions().into_iter().filter(|session| session.remote.ip() == ip);
+                        let kicked = online.filter(|session| session.kick(&message)).count();
+                        format!("Banned IP {} ({} players disconnected)", ip, kicked)
+                    }
+                    Err(e) => format!("Failed to save: {}", e),
+                }
+            }
+            Err(_) => format!("Invalid IP address: {}", ip),
+        },
+        _ => "Usage: ban-ip <address> [reason]".to_string(),
+    });
+    dispatcher.register("pardon-ip", "IP アドレスの BAN を解除します: pardon-ip <IP>", |ctx, args| match args {
+        [ip] => match ip.parse() {
+            Ok(ip) => saved(ctx.server.access.pardon_ip(ip), format!("Unbanned IP {}", ip), "Nothing changed. That IP isn't banned"),
+            Err(_) => format!("Invalid IP address: {}", ip),
+        },
+        _ => "Usage: pardon-ip <address>".to_string(),
+    });
+    dispatcher.register("banlist", "BAN の一覧を表示します: banlist [players|ips]", banlist_command);
+}
+
+/// 名前から UUID を決められなかったときの出力
+const UNKNOWN_PLAYER_MESSAGE: &str = "That player does not exist";
+
+/// コマンドで指定されたプレイヤーの UUID を決める。vanilla と同じく一覧には UUID で載せる。
+/// オンラインならそのセッション、オンラインモードなら過去にログインしたときの UUID を使う。
+/// オフラインで入れる設定なら、まだ来ていないプレイヤーはオフラインの UUID にする
+fn resolve_player(ctx: &CommandContext, name: &str) -> Option<UserEntry> {
+    if let Some(session) = ctx.server.players.get_by_name(name) {
+        return Some(UserEntry { uuid: session.uuid(), name: session.name().to_string() });
+    }
+    if ctx.server.access.online_mode() {
+        if let Some(cached) = ctx.server.access.cached_player(name) {
+            return Some(cached);
+        }
+        let offline_allowed = ctx.server.config().auth.chain.iter().any(|method| matches!(method, AuthMethod::Offline { .. }));
+        if !offline_allowed {
+            return None;
+        }
+    }
+    Some(UserEntry { uuid: offline_uuid(name), name: name.to_string() })
+}
+
+/// 保存の結果をコマンドの出力にする
+fn saved(result: crate::Result<bool>, changed: String, unchanged: &str) -> String {
+    match result {
+        Ok(true) => changed,
+        Ok(false) => unchanged.to_string(),
+        Err(e) => format!("Failed to save: {}", e),
+    }
+}
+
+/// vanilla と同じく、コンソールからの BAN は "Server" と記録する
+fn ban_source(source: &CommandSource) -> &'static str {
+    match source {
+        CommandSource::Console => "Server",
+        CommandSource::Rcon(_) => "Rcon",
+    }
+}
+
+fn ban_player(ctx: &CommandContext, name: &str, duration: Option<Duration>, reason: &str) -> String {
+    let Some(player) = resolve_player(ctx, name) else {
+        return UNKNOWN_PLAYER_MESSAGE.to_string();
+    };
+    let name = player.name.as_str();
+    let expires = duration.map(|duration| Local::now().fixed_offset() + duration);
+    let entry = PlayerBan {
+        uuid: player.uuid,
+        name: name.to_string(),
+        ban: Ban::new(ban_source(&ctx.source), reason, expires),
+    };
+    let reason = entry.ban.reason.clone();
+    let message = entry.ban.kick_message(BANNED_PLAYER_HEADLINE);
+    match ctx.server.access.ban(entry) {
+        Ok(()) => {
+            if let Some(session) = ctx.server.players.get(&player.uuid) {
+                session.kick(&message);
+            }
+            match expires {
+                Some(expires) => format!("Banned {} until {}: {}", name, expires.format("%Y-%m-%d %H:%M:%S %z"), reason),
+                None => format!("Banned {}: {}", name, reason),
+            }
+        }
+        Err(e) => format!("Failed to save: {}", e),
+    }
+}
+
+/// `30s`、`10m`、`12h`、`7d`、`2w` の形式
+fn parse_duration(text: &str) -> Option<Duration> {
+    // 単位が複数バイトの文字でも文字の境界で分ける
+    let (split, _) = text.char_indices().last()?;
+    let (amount, unit) = text.split_at(split);
+    let amount: i64 = amount.parse().ok().filter(|amount| *amount > 0)?;
+    match unit {
+        "s" => Duration::try_seconds(amount),
+        "m" => Duration::try_minutes(amount),
+        "h" => Duration::try_hours(amount),
+        "d" => Duration::try_days(amount),
+        "w" => Duration::try_weeks(amount),
+        _ => None,
+    }
+}
+
+fn whitelist_command(ctx: &CommandContext, args: &[&str]) -> String {
+    let access = &ctx.server.access;
+    match args {
+        [toggle @ ("on" | "off")] => {
+            let enabled = *toggle == "on";
+            let current = ctx.server.config();
+            if current.whitelist == enabled {
+                return format!("Whitelist is already turned {}", toggle);
+            }
+            // 再起動や reload で元に戻らないよう、設定ファイルにも書き込む
+            let saved = match ctx.server.config_path() {
+                Some(path) => match ServerConfig::save_whitelist(path, enabled) {
+                    Ok(()) => true,
+                    Err(e) => return format!("Failed to save: {}", e),
+                },
+                None => false,
+            };
+            ctx.server.apply_config(ServerConfig { whitelist: enabled, ..(*current).clone() });
+            if saved {
+                format!("Whitelist is now turned {}", toggle)
+            } else {
+                format!("Whitelist is now turned {} (not saved: the server was started without a configuration file)", toggle)
+            }
+        }
+        ["list"] => {
+            let names: Vec<String> = access.whitelist().into_iter().map(|entry| entry.name).collect();
+            format!("There are {} whitelisted players: {}", names.len(), names.join(", "))
+        }
+        ["reload"] => match access.reload() {
+            Ok(()) => "Reloaded the whitelist".to_string(),
+            Err(e) => format!("Failed to reload: {}", e),
+        },
+        ["add", name] => {
+            let Some(entry) = resolve_player(ctx, name) else {
+                return UNKNOWN_PLAYER_MESSAGE.to_string();
+            };
+            let added = format!("Added {} to the whitelist", entry.name);
+            saved(access.add_to_whitelist(entry), added, "Player is already whitelisted")
+        }
+        ["remove", name] => saved(access.remove_from_whitelist(name), format!("Removed {} from the whitelist", name), "Player is not whitelisted"),
+        _ => "Usage: whitelist <on|off|list|reload> | whitelist <add|remove> <player>".to_string(),
+    }
+}
+
+fn banlist_command(ctx: &CommandContext, args: &[&str]) -> String {
+    if !matches!(args, [] | ["players"] | ["ips"]) {
+        return "Usage: banlist [players|ips]".to_string();
+    }
+    let access = &ctx.server.access;
+    let describe = |target: String, ban: &Ban| match ban.expires {
+        Some(expires) => format!("{} was banned by {} until {}: {}", target, ban.source, expires.format("%Y-%m-%d %H:%M:%S %z"), ban.reason),
+        None => format!("{} was banned by {}: {}", target, ban.source, ban.reason),
+    };
+
+    let mut lines = Vec::new();
+    if matches!(args, [] | ["players"]) {
+        lines.extend(access.banned_players().iter().map(|entry| describe(entry.name.clone(), &entry.ban)));
+    }
+    if matches!(args, [] | ["ips"]) {
+        lines.extend(access.banned_ips().iter().map(|entry| describe(entry.ip.to_string(), &entry.ban)));
+    }
+    if lines.is_empty() {
+        return "There are no bans".to_string();
+    }
+    lines.insert(0, format!("There are {} bans:", lines.len()));
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::context::ServerContext;
+    use crate::net::login::profile::GameProfile;
+    use crate::utils::AuthConfig;
+    use std::sync::Arc;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_online_mode_uses_real_uuids() {
+        let dir = std::env::temp_dir().join(format!("command-access-test-{}", Uuid::new_v4()));
+        let config = ServerConfig {
+            data_directory: dir.to_string_lossy().into_owned(),
+            auth: AuthConfig { chain: vec![AuthMethod::Mojang], ..AuthConfig::default() },
+            ..ServerConfig::default()
+        };
+        let ctx = Arc::new(ServerContext::new(config));
+        let dispatch = |line: &str| ctx.commands.dispatch(&ctx, CommandSource::Console, line);
+
+        // オンラインのプレイヤーはセッションの UUID で載せる
+        let notch = Uuid::new_v4();
+        let _online = ctx.players.register(GameProfile::new(notch, "Notch"), "127.0.0.1:1".parse().unwrap(), 763).unwrap();
+        dispatch("ban notch");
+        assert_eq!(ctx.access.banned_players().iter().map(|entry| entry.uuid).collect::<Vec<_>>(), vec![notch]);
+
+        // 来たことのないプレイヤーは UUID が分からないので載せない
+        assert_eq!(dispatch("op jeb_"), UNKNOWN_PLAYER_MESSAGE);
+        assert_eq!(dispatch("whitelist add jeb_"), UNKNOWN_PLAYER_MESSAGE);
+        let jeb = Uuid::new_v4();
+        ctx.access.remember(jeb, "jeb_").unwrap();
+        assert_eq!(dispatch("op JEB_"), "Made jeb_ a server operator");
+        assert_eq!(ctx.access.ops()[0].uuid, jeb);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("30m"), Some(Duration::minutes(30)));
+        assert_eq!(parse_duration("7d"), Some(Duration::days(7)));
+        assert_eq!(parse_duration("2w"), Some(Duration::weeks(2)));
+        assert_eq!(parse_duration("0h"), None);
+        assert_eq!(parse_duration("10"), None);
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("10分"), None);
+        assert_eq!(parse_duration("分"), None);
+    }
+}
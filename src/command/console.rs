@@ -0,0 +1,35 @@
+use std::io::BufRead;
+use std::sync::Arc;
+
+use log::warn;
+
+use super::CommandSource;
+use crate::net::context::ServerContext;
+
+/// 標準入力からコマンドを読み取り、ディスパッチャーに渡す
+///
+/// tokio の stdin はブロッキングスレッドを使うため、ランタイムの終了を妨げる。
+/// 専用のスレッドで読み取り、プロセス終了時にはそのまま破棄されるようにする。
+pub fn spawn_console(ctx: Arc<ServerContext>) {
+    let spawned = std::thread::Builder::new()
+        .name("console".to_string())
+        .spawn(move || {
+            for line in std::io::stdin().lock().lines() {
+                match line {
+                    Ok(line) => {
+                        let output = ctx.commands.dispatch(&ctx, CommandSource::Console, &line);
+                        if !output.is_empty() {
+                            println!("{}", output);
+                        }
+                    }
+                    Err(e) => {
+                        warn!("Failed to read console input: {}", e);
+                        break;
+                    }
+                }
+            }
+        });
+    if let Err(e) = spawned {
+        warn!("Failed to start console thread: {}", e);
+    }
+}
@@ -0,0 +1,218 @@
+pub mod console;
+mod access;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::net::context::ServerContext;
+
+/// コマンドの実行元
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandSource {
+    Console,
+    Rcon(SocketAddr),
+}
+
+impl fmt::Display for CommandSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandSource::Console => write!(f, "Console"),
+            CommandSource::Rcon(addr) => write!(f, "Rcon({})", addr),
+        }
+    }
+}
+
+/// コマンド実行時に渡されるコンテキスト
+pub struct CommandContext<'a> {
+    pub server: &'a Arc<ServerContext>,
+    pub source: CommandSource,
+}
+
+pub type CommandHandler = Box<dyn Fn(&CommandContext, &[&str]) -> String + Send + Sync>;
+
+struct RegisteredCommand {
+    description: String,
+    handler: CommandHandler,
+}
+
+/// コンソールとRCONで共有されるコマンドディスパッチャー
+pub struct CommandDispatcher {
+    commands: BTreeMap<String, RegisteredCommand>,
+}
+
+impl CommandDispatcher {
+    pub fn new() -> Self {
+        Self { commands: BTreeMap::new() }
+    }
+
+    /// 組み込みコマンドを登録したディスパッチャーを作る
+    pub fn with_builtins() -> Self {
+        let mut dispatcher = Self::new();
+        dispatcher.register("list", "オンラインのプレイヤーを表示します", |ctx, _| {
+            let info = ctx.server.status_info();
+            format!(
+                "There are {} of a max of {} players online: {}",
+                info.online_players.len(),
+                info.max_players,
+                info.online_players.join(", ")
+            )
+        });
+        dispatcher.register("version", "サーバーのバージョンを表示します", |ctx, _| {
+            let info = ctx.server.status_info();
+            format!(
+                "{} {} (Minecraft {}, protocol {})",
+                env!("CARGO_PKG_NAME"),
+                env!("CARGO_PKG_VERSION"),
+                info.version_name,
+                info.protocol
+            )
+        });
+        dispatcher.register("capture", "パケットキャプチャを切り替えます: capture <ip|player> <対象> <on|off> / capture list", capture_command);
+        dispatcher.register("properties", "現在の設定を server.properties 形式で書き出します: properties export [ファイル]", properties_command);
+        dispatcher.register("reload", "設定ファイルを読み直します", |ctx, _| {
+            match ctx.server.reload_config() {
+                Ok(changes) => changes.to_string(),
+                Err(e) => format!("Reload failed, keeping the current settings: {}", e),
+            }
+        });
+        dispatcher.register("stats", "TPS や keep-alive の往復時間をJSONで表示します", |ctx, _| {
+            ctx.server.stats.snapshot(ctx.server.players.len()).to_string()
+        });
+        dispatcher.register("player", "プレイヤーの位置や状態を表示します: player <名前>", player_command);
+        access::register(&mut dispatcher);
+        dispatcher.register("stop", "サーバーを停止します", |ctx, _| {
+            ctx.server.shutdown();
+            "Stopping the server".to_string()
+        });
+        dispatcher
+    }
+
+    pub fn register<F>(&mut self, name: &str, description: &str, handler: F)
+    where
+        F: Fn(&CommandContext, &[&str]) -> String + Send + Sync + 'static,
+    {
+        self.commands.insert(name.to_string(), RegisteredCommand {
+            description: description.to_string(),
+            handler: Box::new(handler),
+        });
+    }
+
+    /// 1行のコマンドを解析して実行し、出力を返す
+    pub fn dispatch(&self, server: &Arc<ServerContext>, source: CommandSource, line: &str) -> String {
+        let line = line.trim().trim_start_matches('/');
+        let mut parts = line.split_whitespace();
+        let name = match parts.next() {
+            Some(name) => name.to_lowercase(),
+            None => return String::new(),
+        };
+        let args: Vec<&str> = parts.collect();
+
+        if name == "help" {
+            return self.help();
+        }
+
+        match self.commands.get(&name) {
+            Some(command) => {
+                log::info!("{} issued server command: {}", source, line);
+                let ctx = CommandContext { server, source };
+                (command.handler)(&ctx, &args)
+            }
+            None => format!("Unknown command: {}. Type \"help\" for help.", name),
+        }
+    }
+
+    fn help(&self) -> String {
+        let mut lines = vec!["help - コマンドの一覧を表示します".to_string()];
+        for (name, command) in &self.commands {
+            lines.push(format!("{} - {}", name, command.description));
+        }
+        lines.join("\n")
+    }
+}
+
+fn capture_command(ctx: &CommandContext, args: &[&str]) -> String {
+    let targets = &ctx.server.capture;
+    let usage = "Usage: capture <ip|player> <target> <on|off> | capture list";
+
+    match args {
+        ["list"] => {
+            let (ips, usernames) = targets.list();
+            let ips: Vec<String> = ips.iter().map(|ip| ip.to_string()).collect();
+            format!("Capturing IPs: [{}], players: [{}]", ips.join(", "), usernames.join(", "))
+        }
+        [kind, target, toggle] => {
+            let enabled = match *toggle {
+                "on" => true,
+                "off" => false,
+                _ => return usage.to_string(),
+            };
+            let changed = match *kind {
+                "ip" => match target.parse() {
+                    Ok(ip) => targets.set_ip(ip, enabled),
+                    Err(_) => return format!("Invalid IP address: {}", target),
+                },
+                "player" => targets.set_username(target, enabled),
+                _ => return usage.to_string(),
+            };
+            match (changed, enabled) {
+                (true, true) => format!("Capture enabled for {} (from next connection)", target),
+                (true, false) => format!("Capture disabled for {}", target),
+                (false, _) => format!("Nothing changed for {}", target),
+            }
+        }
+        _ => usage.to_string(),
+    }
+}
+
+fn player_command(ctx: &CommandContext, args: &[&str]) -> String {
+    let [name] = args else {
+        return "Usage: player <name>".to_string();
+    };
+    let Some(player) = ctx.server.game.players().get_by_name(name) else {
+        return format!("{} is not in the game", name);
+    };
+    format!(
+        "{} ({}) [{}] at {:.2}, {:.2}, {:.2} facing {:.1}/{:.1}{}, health {}, food {}, level {}, slot {}, view distance {}, locale {}",
+        player.name,
+        player.uuid,
+        player.game_mode.name(),
+        player.position.x,
+        player.position.y,
+        player.position.z,
+        player.rotation.yaw,
+        player.rotation.pitch,
+        if player.on_ground { " on ground" } else { "" },
+        player.health,
+        player.food,
+        player.level,
+        player.selected_slot,
+        player.view_distance,
+        player.locale
+    )
+}
+
+fn properties_command(ctx: &CommandContext, args: &[&str]) -> String {
+    let path = match args {
+        ["export"] => "server.properties",
+        ["export", path] => path,
+        _ => return "Usage: properties export [file]".to_string(),
+    };
+    // 既存の vanilla の設定を上書きしないようにする
+    if std::path::Path::new(path).exists() {
+        return format!("{} already exists", path);
+    }
+    let result = ctx.server.config().to_properties()
+        .and_then(|text| std::fs::write(path, text).map_err(Into::into));
+    match result {
+        Ok(()) => format!("Exported the current configuration to {}", path),
+        Err(e) => format!("Failed to export to {}: {}", path, e),
+    }
+}
+
+impl Default for CommandDispatcher {
+    fn default() -> Self {
+        Self::with_builtins()
+    }
+}
@@ -0,0 +1,68 @@
+//! プレイヤーと他のエンティティに共通のコンポーネント
+
+pub mod tracker;
+
+use bevy::prelude::*;
+
+use crate::game::TickSet;
+
+/// プロトコルで使うエンティティ ID。ECS の [`Entity`] とは別に、サーバー全体で通し番号を振る
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct EntityId(pub i32);
+
+/// 次に振るエンティティ ID
+#[derive(Resource, Debug)]
+pub struct EntityIdAllocator {
+    next: i32,
+}
+
+impl Default for EntityIdAllocator {
+    fn default() -> Self {
+        Self { next: 1 }
+    }
+}
+
+impl EntityIdAllocator {
+    pub fn allocate(&mut self) -> EntityId {
+        let id = EntityId(self.next);
+        self.next = self.next.wrapping_add(1).max(1);
+        id
+    }
+}
+
+/// 足元の座標
+#[derive(Component, Debug, Clone, Copy, PartialEq, Default)]
+pub struct Position {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+}
+
+impl Position {
+    pub const fn new(x: f64, y: f64, z: f64) -> Self {
+        Self { x, y, z }
+    }
+
+    pub fn distance_squared(&self, other: &Position) -> f64 {
+        let (dx, dy, dz) = (self.x - other.x, self.y - other.y, self.z - other.z);
+        dx * dx + dy * dy + dz * dz
+    }
+}
+
+/// 向き (度)。yaw は南が 0 で時計回り、pitch は下向きが正
+#[derive(Component, Debug, Clone, Copy, PartialEq, Default)]
+pub struct Rotation {
+    pub yaw: f32,
+    pub pitch: f32,
+}
+
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct OnGround(pub bool);
+
+pub struct EntityPlugin;
+
+impl Plugin for EntityPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Update, tracker::track_entities.in_set(TickSet::Tracking));
+    }
+}
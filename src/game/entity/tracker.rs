@@ -0,0 +1,339 @@
+//! どのプレイヤーにどのエンティティを見せるか
+//!
+//! エンティティの追跡範囲とプレイヤーの描画距離 (サーバーの `view_distance` が上限) の狭い方に入ったら Spawn を送り、
+//! 出たり消えたりしたら Remove Entities を送る。見えているエンティティには、前の tick に送った
+//! 位置と向きとの差分を送る。パケットは tick の終わりにまとめて [`Outbound`] に積む。
+
+use std::collections::HashMap;
+
+use bevy::prelude::*;
+use uuid::Uuid;
+
+use crate::game::entity::{EntityId, OnGround, Position, Rotation};
+use crate::game::network::{ConnectionId, Outbound};
+use crate::game::GameConfig;
+use crate::game::player::{PlayerConnection, ViewDistance};
+use crate::net::play::entity::{
+    RemoveEntities, SetHeadRotation, SpawnEntity, SpawnPlayer, TeleportEntity, UpdateEntityPosition,
+    UpdateEntityPositionAndRotation, UpdateEntityRotation,
+};
+use crate::net::protocol::types::Angle;
+
+/// プレイヤーの追跡範囲 (vanilla と同じ 32 チャンク)。実際には見る側の描画距離で狭まる
+pub const PLAYER_TRACKING_RANGE: f64 = 512.0;
+/// 相対移動のパケットでの 1 ブロックの大きさ
+const POSITION_SCALE: f64 = 4096.0;
+
+/// クライアントにどう出すか
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntityKind {
+    /// Spawn Player で出す
+    Player,
+    /// Spawn Entity で出す。`type_id` はエンティティの種類のレジストリ ID
+    Object { type_id: i32, data: i32 },
+}
+
+/// 他のプレイヤーから見えるエンティティ
+#[derive(Component, Debug, Clone)]
+pub struct Tracked {
+    pub uuid: Uuid,
+    pub kind: EntityKind,
+    /// 水平方向の距離 (ブロック)
+    pub range: f64,
+    /// 見ているプレイヤーに最後に送った状態。差分はここから計算する
+    sent: Option<SentState>,
+}
+
+impl Tracked {
+    pub fn new(uuid: Uuid, kind: EntityKind, range: f64) -> Self {
+        Self { uuid, kind, range, sent: None }
+    }
+
+    pub fn player(uuid: Uuid) -> Self {
+        Self::new(uuid, EntityKind::Player, PLAYER_TRACKING_RANGE)
+    }
+}
+
+/// プレイヤーに見せているエンティティ。消えた後でも Remove Entities を送れるように ID も覚えておく
+#[derive(Component, Debug, Default)]
+pub struct VisibleEntities {
+    entities: HashMap<Entity, EntityId>,
+}
+
+impl VisibleEntities {
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.entities.contains_key(&entity)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+}
+
+/// パケットで表せる精度に丸めた状態
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SentState {
+    position: [i64; 3],
+    yaw: Angle,
+    pitch: Angle,
+    on_ground: bool,
+}
+
+impl SentState {
+    fn of(position: &Position, rotation: &Rotation, on_ground: &OnGround) -> Self {
+        let encode = |value: f64| (value * POSITION_SCALE).round() as i64;
+        Self {
+            position: [encode(position.x), encode(position.y), encode(position.z)],
+            yaw: Angle::from_degrees(rotation.yaw),
+            pitch: Angle::from_degrees(rotation.pitch),
+            on_ground: on_ground.0,
+        }
+    }
+}
+
+fn is_in_range(viewer: &Position, view_distance: u8, position: &Position, tracked: &Tracked) -> bool {
+    let range = tracked.range.min(view_distance as f64 * 16.0);
+    let (dx, dz) = (position.x - viewer.x, position.z - viewer.z);
+    dx * dx + dz * dz <= range * range
+}
+
+fn send_spawn(outbound: &mut Outbound, connection: ConnectionId, entity_id: EntityId, tracked: &Tracked, position: &Position, rotation: &Rotation) {
+    let (yaw, pitch) = (Angle::from_degrees(rotation.yaw), Angle::from_degrees(rotation.pitch));
+    match tracked.kind {
+        EntityKind::Player => {
+            outbound.send(connection, &SpawnPlayer {
+                entity_id: entity_id.0,
+                uuid: tracked.uuid,
+                x: position.x,
+                y: position.y,
+                z: position.z,
+                yaw,
+                pitch,
+            });
+            // Spawn Player には頭の向きがないので、続けて送る
+            outbound.send(connection, &SetHeadRotation { entity_id: entity_id.0, head_yaw: yaw });
+        }
+        EntityKind::Object { type_id, data } => {
+            outbound.send(connection, &SpawnEntity {
+                entity_id: entity_id.0,
+                uuid: tracked.uuid,
+                entity_type: type_id,
+                x: position.x,
+                y: position.y,
+                z: position.z,
+                pitch,
+                yaw,
+                head_yaw: yaw,
+                data,
+                velocity: [0; 3],
+            });
+        }
+    }
+}
+
+/// `from` から `current` への変化を、既に見ているプレイヤーに送る
+fn broadcast_changes(outbound: &mut Outbound, viewers: &[ConnectionId], entity_id: EntityId, from: &SentState, current: &SentState, position: &Position) {
+    let entity_id = entity_id.0;
+    let deltas = [0, 1, 2].map(|i| i16::try_from(current.position[i] - from.position[i]));
+    let moved = current.position != from.position;
+    let rotated = (current.yaw, current.pitch) != (from.yaw, from.pitch);
+    let (yaw, pitch, on_ground) = (current.yaw, current.pitch, current.on_ground);
+    match deltas {
+        [Ok(x), Ok(y), Ok(z)] => {
+            let delta = [x, y, z];
+            if moved && rotated {
+                outbound.broadcast(viewers.iter().copied(), &UpdateEntityPositionAndRotation { entity_id, delta, yaw, pitch, on_ground });
+            } else if rotated {
+                outbound.broadcast(viewers.iter().copied(), &UpdateEntityRotation { entity_id, yaw, pitch, on_ground });
+            } else {
+                // 接地しただけのときも移動量 0 で送る
+                outbound.broadcast(viewers.iter().copied(), &UpdateEntityPosition { entity_id, delta, on_ground });
+            }
+        }
+        // 8 ブロック以上動いたので、差分では送れない
+        _ => {
+            outbound.broadcast(viewers.iter().copied(), &TeleportEntity {
+                entity_id,
+                x: position.x,
+                y: position.y,
+                z: position.z,
+                yaw,
+                pitch,
+                on_ground,
+            });
+        }
+    }
+    if current.yaw != from.yaw {
+        outbound.broadcast(viewers.iter().copied(), &SetHeadRotation { entity_id, head_yaw: yaw });
+    }
+}
+
+/// 見えるエンティティを更新して、出入りと動きをパケットにする
+pub(crate) fn track_entities(
+    mut outbound: ResMut<Outbound>,
+    mut tracked: Query<(Entity, &EntityId, &mut Tracked, &Position, &Rotation, &OnGround)>,
+    mut viewers: Query<(Entity, &PlayerConnection, &Position, &ViewDistance, &mut VisibleEntities)>,
+    config: Res<GameConfig>,
+) {
+    // 前の tick から見え続けているプレイヤー。動きはこの人たちにだけ送る
+    let mut watching: HashMap<Entity, Vec<ConnectionId>> = HashMap::new();
+    for (viewer, connection, viewer_position, view_distance, mut visible) in &mut viewers {
+        // クライアントの希望よりサーバーの設定が近ければ、そちらに合わせる
+        let view_distance = view_distance.0.min(config.0.view_distance);
+        let mut removed = Vec::new();
+        let mut entering = Vec::new();
+        visible.entities.retain(|entity, entity_id| {
+            let still_visible = tracked.get(*entity)
+                .is_ok_and(|(_, _, tracked, position, ..)| is_in_range(viewer_position, view_distance, position, tracked));
+            if !still_visible {
+                removed.push(entity_id.0);
+            }
+            still_visible
+        });
+        for (entity, entity_id, tracked, position, ..) in tracked.iter() {
+            if entity == viewer {
+                continue;
+            }
+            if visible.contains(entity) {
+                watching.entry(entity).or_default().push(connection.id);
+            } else if is_in_range(viewer_position, view_distance, position, tracked) {
+                visible.entities.insert(entity, *entity_id);
+                entering.push(entity);
+            }
+        }
+
+        if !removed.is_empty() {
+            outbound.send(connection.id, &RemoveEntities { entity_ids: removed });
+        }
+        for entity in entering {
+            if let Ok((_, entity_id, tracked, position, rotation, _)) = tracked.get(entity) {
+                send_spawn(&mut outbound, connection.id, *entity_id, tracked, position, rotation);
+            }
+        }
+    }
+
+    for (entity, entity_id, mut tracked, position, rotation, on_ground) in &mut tracked {
+        let current = SentState::of(position, rotation, on_ground);
+        if tracked.sent == Some(current) {
+            continue;
+        }
+        // 見え始めたプレイヤーには今の状態で Spawn を送ったので、全員の基準がそろう
+        let from = tracked.sent.replace(current);
+        if let (Some(from), Some(viewers)) = (from, watching.get(&entity)) {
+            broadcast_changes(&mut outbound, viewers, *entity_id, &from, &current, position);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::{Bytes, BytesMut};
+    use std::sync::Arc;
+    use tokio::sync::mpsc::Receiver;
+
+    use crate::game::build_app;
+    use crate::game::network::{GameHandle, ServerboundPacket};
+    use crate::game::player::movement::TeleportPlayer;
+    use crate::game::player::PlayerIndex;
+    use crate::net::login::offline::offline_uuid;
+    use crate::net::login::profile::GameProfile;
+    use crate::net::play::entity::{REMOVE_ENTITIES_ID, SPAWN_PLAYER_ID, TELEPORT_ENTITY_ID, UPDATE_ENTITY_POSITION_ID};
+    use crate::net::play::movement::{ConfirmTeleportation, SetPlayerPosition};
+    use crate::net::players::{PlayerRegistry, SessionMessage};
+    use crate::net::protocol::Packet;
+    use crate::utils::config::ServerConfig;
+
+    /// 届いたパケットから `id` のものだけを取り出す
+    fn received<P: Packet>(egress: &mut Receiver<SessionMessage>, id: i32) -> Vec<P> {
+        let mut found = Vec::new();
+        while let Ok(message) = egress.try_recv() {
+            if let SessionMessage::Packet { id: received, body } = message {
+                if received == id {
+                    found.push(P::decode(&mut BytesMut::from(&body[..])).unwrap());
+                }
+            }
+        }
+        found
+    }
+
+    fn drain(egress: &mut Receiver<SessionMessage>) -> Vec<(i32, Bytes)> {
+        let mut sent = Vec::new();
+        while let Ok(SessionMessage::Packet { id, body }) = egress.try_recv() {
+            sent.push((id, body));
+        }
+        sent
+    }
+
+    #[tokio::test]
+    async fn test_spawn_move_and_remove() {
+        let handle = GameHandle::new();
+        let mut app = build_app(&handle).unwrap();
+        let players = PlayerRegistry::new();
+        let steve = players.register(GameProfile::new(offline_uuid("Steve"), "Steve"), "127.0.0.1:1".parse().unwrap(), 763).unwrap().session;
+        let alex = players.register(GameProfile::new(offline_uuid("Alex"), "Alex"), "127.0.0.1:2".parse().unwrap(), 763).unwrap().session;
+        let mut steve_egress = handle.connect(steve.clone()).await;
+        let mut alex_egress = handle.connect(alex.clone()).await;
+        app.update();
+        let index = app.world.resource::<PlayerIndex>();
+        let (steve_entity, alex_entity) = (index.get(&steve.uuid()).unwrap(), index.get(&alex.uuid()).unwrap());
+        let alex_id = app.world.get::<EntityId>(alex_entity).unwrap().0;
+
+        // 同じ場所にいるので互いに見える。自分自身は出さない
+        let spawned: Vec<SpawnPlayer> = received(&mut steve_egress, SPAWN_PLAYER_ID);
+        assert_eq!(spawned.len(), 1);
+        assert_eq!((spawned[0].entity_id, spawned[0].uuid), (alex_id, alex.uuid()));
+        assert_eq!(received::<SpawnPlayer>(&mut alex_egress, SPAWN_PLAYER_ID)[0].uuid, steve.uuid());
+        assert!(app.world.get::<VisibleEntities>(steve_entity).unwrap().contains(alex_entity));
+
+        // 動かなければ何も送らない
+        app.update();
+        assert!(drain(&mut steve_egress).is_empty());
+
+        // 1 ブロックの移動は差分で送る
+        let connection = ConnectionId(alex.id);
+        handle.send_packet(connection, ServerboundPacket::ConfirmTeleportation(ConfirmTeleportation { teleport_id: 0 })).await;
+        handle.send_packet(connection, ServerboundPacket::SetPlayerPosition(SetPlayerPosition { x: 1.5, y: 64.0, z: 0.5, on_ground: true })).await;
+        app.update();
+        let moved: Vec<UpdateEntityPosition> = received(&mut steve_egress, UPDATE_ENTITY_POSITION_ID);
+        assert_eq!(moved, vec![UpdateEntityPosition { entity_id: alex_id, delta: [4096, 0, 0], on_ground: true }]);
+
+        // 8 ブロックを超えると差分に収まらない
+        app.world.send_event(TeleportPlayer { entity: alex_entity, position: Position::new(21.5, 64.0, 0.5), rotation: None });
+        app.update();
+        let teleported: Vec<TeleportEntity> = received(&mut steve_egress, TELEPORT_ENTITY_ID);
+        assert_eq!((teleported.len(), teleported[0].x), (1, 21.5));
+
+        // 描画距離の外に出たら消し、戻ってきたらまた出す
+        app.world.send_event(TeleportPlayer { entity: alex_entity, position: Position::new(1000.5, 64.0, 0.5), rotation: None });
+        app.update();
+        let removed: Vec<RemoveEntities> = received(&mut steve_egress, REMOVE_ENTITIES_ID);
+        assert_eq!(removed, vec![RemoveEntities { entity_ids: vec![alex_id] }]);
+        assert!(app.world.get::<VisibleEntities>(steve_entity).unwrap().is_empty());
+        app.world.send_event(TeleportPlayer { entity: alex_entity, position: Position::new(3.5, 64.0, 0.5), rotation: None });
+        app.update();
+        assert_eq!(received::<SpawnPlayer>(&mut steve_egress, SPAWN_PLAYER_ID)[0].x, 3.5);
+
+        // サーバーの描画距離がクライアントより短ければ、そちらで切る
+        app.insert_resource(GameConfig(Arc::new(ServerConfig { view_distance: 2, ..ServerConfig::default() })));
+        app.world.send_event(TeleportPlayer { entity: alex_entity, position: Position::new(100.5, 64.0, 0.5), rotation: None });
+        app.update();
+        let removed: Vec<RemoveEntities> = received(&mut steve_egress, REMOVE_ENTITIES_ID);
+        assert_eq!(removed, vec![RemoveEntities { entity_ids: vec![alex_id] }]);
+        app.world.send_event(TeleportPlayer { entity: alex_entity, position: Position::new(3.5, 64.0, 0.5), rotation: None });
+        app.update();
+        assert_eq!(received::<SpawnPlayer>(&mut steve_egress, SPAWN_PLAYER_ID).len(), 1);
+
+        // 切断したら見ていたプレイヤーから消す
+        handle.disconnect(connection);
+        app.update();
+        app.update();
+        let removed: Vec<RemoveEntities> = received(&mut steve_egress, REMOVE_ENTITIES_ID);
+        assert_eq!(removed, vec![RemoveEntities { entity_ids: vec![alex_id] }]);
+        assert!(app.world.get::<VisibleEntities>(steve_entity).unwrap().is_empty());
+    }
+}
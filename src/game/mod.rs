@@ -1,3 +1,180 @@
-mod player;
-mod entity;
-mod world;
\ No newline at end of file
+//! ゲームの状態を進める Bevy の ECS
+//!
+//! 非同期の通信とは別の専用スレッドで、ヘッドレスの [`App`] を 20 TPS で回す。
+//! 1 tick は [`TickSet`] の順に、受信 → ゲームの処理 → 見え方の更新 → 送信と進む。
+
+pub mod entity;
+pub mod network;
+pub mod player;
+pub mod world;
+
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use bevy::app::ScheduleRunnerPlugin;
+use bevy::prelude::*;
+use log::{info, warn};
+
+use crate::net::context::ServerContext;
+use crate::utils::config::ServerConfig;
+use entity::EntityPlugin;
+use network::{GameHandle, IngressReceiver, NetworkPlugin};
+use player::{PlayerPlugin, SharedPlayerSnapshots};
+
+pub const TICKS_PER_SECOND: u32 = 20;
+pub const TICK_DURATION: Duration = Duration::from_millis(1000 / TICKS_PER_SECOND as u64);
+/// これ以上遅れたら追いつくのを諦めて、遅れた分の tick を飛ばす (vanilla と同じ)
+const MAX_LAG: Duration = Duration::from_secs(2);
+
+/// 1 tick の中の処理の順番
+#[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TickSet {
+    /// 接続タスクから届いたパケットをイベントにする
+    Ingress,
+    /// ゲームの処理
+    Simulation,
+    /// 処理の結果を、それが見えているプレイヤーへのパケットにする
+    Tracking,
+    /// 積まれたパケットを接続タスクへ送る
+    Egress,
+}
+
+/// 現在の設定。ゲームループが tick の前に差し替えるので、再読み込みした値はすぐに使われる
+#[derive(Resource, Debug, Clone, Default)]
+pub struct GameConfig(pub Arc<ServerConfig>);
+
+/// 起動してから進めた tick の数
+#[derive(Resource, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct GameTick(pub u64);
+
+/// ゲームの処理をまとめたプラグイン
+pub struct GamePlugin;
+
+impl Plugin for GamePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GameTick>()
+            .configure_sets(Update, (TickSet::Ingress, TickSet::Simulation, TickSet::Tracking, TickSet::Egress).chain())
+            .add_systems(First, advance_tick)
+            .add_plugins((NetworkPlugin, EntityPlugin, PlayerPlugin));
+    }
+}
+
+fn advance_tick(mut tick: ResMut<GameTick>) {
+    tick.0 += 1;
+}
+
+/// `handle` の接続タスクとつながった App を作る。`update` を1回呼ぶごとに1 tick 進む。
+/// 受信口は1つしかないので、2回目以降は `None`
+pub fn build_app(handle: &GameHandle) -> Option<App> {
+    let ingress = IngressReceiver::new(handle.take_receiver()?);
+    let mut app = App::new();
+    // tick はこちらで刻むので、Bevy のループは使わない
+    app.add_plugins(MinimalPlugins.build().disable::<ScheduleRunnerPlugin>())
+        .insert_resource(ingress)
+        .insert_resource(SharedPlayerSnapshots(handle.shared_players()))
+        .init_resource::<GameConfig>()
+        .add_plugins(GamePlugin);
+    Some(app)
+}
+
+/// ゲームスレッドを起動する。既に起動していれば `None`
+pub fn spawn_game_loop(ctx: Arc<ServerContext>) -> std::io::Result<Option<JoinHandle<()>>> {
+    let Some(app) = build_app(&ctx.game) else {
+        return Ok(None);
+    };
+    let thread = std::thread::Builder::new()
+        .name("Server thread".to_string())
+        .spawn(move || run_game_loop(ctx, app))?;
+    Ok(Some(thread))
+}
+
+/// 停止が要求されるまで [`TICK_DURATION`] ごとに tick を進め、1秒ごとに TPS を記録する
+fn run_game_loop(ctx: Arc<ServerContext>, mut app: App) {
+    let shutdown = ctx.shutdown_signal();
+    info!("Game loop started at {} TPS", TICKS_PER_SECOND);
+    let mut next_tick = Instant::now();
+    let mut window_start = next_tick;
+    let mut window_ticks = 0;
+    while !*shutdown.borrow() {
+        let config = ctx.config();
+        if !Arc::ptr_eq(&app.world.resource::<GameConfig>().0, &config) {
+            app.insert_resource(GameConfig(config));
+        }
+        app.update();
+        window_ticks += 1;
+
+        let now = Instant::now();
+        if window_ticks == TICKS_PER_SECOND {
+            let elapsed = now.duration_since(window_start).as_secs_f64();
+            ctx.stats.set_tps((window_ticks as f64 / elapsed).min(TICKS_PER_SECOND as f64));
+            window_start = now;
+            window_ticks = 0;
+        }
+
+        next_tick += TICK_DURATION;
+        if now > next_tick + MAX_LAG {
+            let behind = now - next_tick;
+            warn!(
+                "Can't keep up! Is the server overloaded? Running {}ms or {} ticks behind",
+                behind.as_millis(),
+                behind.as_millis() / TICK_DURATION.as_millis()
+            );
+            next_tick = now;
+        } else if next_tick > now {
+            std::thread::sleep(next_tick - now);
+        }
+    }
+    info!("Game loop stopped");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::network::{ClientConnected, ConnectionId, GameHandle, Outbound, PacketReceived, ServerboundPacket};
+    use crate::net::login::offline::offline_uuid;
+    use crate::net::login::profile::GameProfile;
+    use crate::net::play::chat::{ChatMessage, SystemChatMessage, SYSTEM_CHAT_MESSAGE_ID};
+    use crate::net::players::{PlayerRegistry, SessionMessage};
+
+    /// チャットを送り主にそのまま返す
+    fn echo(mut received: EventReader<PacketReceived>, mut outbound: ResMut<Outbound>) {
+        for event in received.read() {
+            if let ServerboundPacket::ChatMessage(chat) = &event.packet {
+                outbound.send(event.connection, &SystemChatMessage::text(&chat.message));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ingress_to_egress() {
+        let handle = GameHandle::new();
+        let mut app = build_app(&handle).unwrap();
+        app.add_systems(Update, echo.in_set(TickSet::Simulation));
+        assert!(build_app(&handle).is_none());
+
+        let players = PlayerRegistry::new();
+        let session = players.register(GameProfile::new(offline_uuid("Steve"), "Steve"), "127.0.0.1:1".parse().unwrap(), 763).unwrap().session;
+        let connection = ConnectionId(session.id);
+        let mut egress = handle.connect(session.clone()).await;
+        handle.send_packet(connection, ServerboundPacket::ChatMessage(ChatMessage::new("hello"))).await;
+
+        app.update();
+        assert_eq!(app.world.resource::<GameTick>().0, 1);
+        assert_eq!(app.world.resource::<Events<ClientConnected>>().len(), 1);
+        // 参加したときの位置などと一緒に届く
+        let mut sent = Vec::new();
+        while let Ok(SessionMessage::Packet { id, .. }) = egress.try_recv() {
+            sent.push(id);
+        }
+        assert!(sent.contains(&SYSTEM_CHAT_MESSAGE_ID), "{:?}", sent);
+        assert!(app.world.resource::<Outbound>().is_empty());
+
+        // 切断した接続から後で届いたパケットは捨てる
+        handle.disconnect(connection);
+        handle.send_packet(connection, ServerboundPacket::ChatMessage(ChatMessage::new("late"))).await;
+        app.update();
+        assert!(egress.try_recv().is_err());
+        assert!(app.world.resource::<network::Connections>().is_empty());
+    }
+}
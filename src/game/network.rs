@@ -0,0 +1,314 @@
+//! tokio の接続タスクとゲームスレッドの橋渡し
+//!
+//! 接続タスクはデコードしたパケットを [`Ingress`] として容量付きのチャネルに流し、
+//! ゲームスレッドは tick の最初 ([`TickSet::Ingress`]) にそれを ECS のイベントにする。
+//! システムが [`Outbound`] に積んだパケットは tick の最後 ([`TickSet::Egress`]) に
+//! 接続ごとの容量付きチャネルへ送られる。
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use bevy::prelude::*;
+use bytes::{Bytes, BytesMut};
+use log::{debug, warn};
+use parking_lot::Mutex;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::{TryRecvError, TrySendError};
+
+use crate::game::player::PlayerSnapshots;
+use crate::game::TickSet;
+use crate::net::play::chat::{ChatCommand, ChatMessage, CHAT_COMMAND_ID, CHAT_MESSAGE_ID};
+use crate::net::play::client_information::{ClientInformation, CLIENT_INFORMATION_ID};
+use crate::net::play::held_item::{SetHeldItem, SERVERBOUND_SET_HELD_ITEM_ID};
+use crate::net::play::movement::{
+    ConfirmTeleportation, SetPlayerOnGround, SetPlayerPosition, SetPlayerPositionAndRotation, SetPlayerRotation,
+    CONFIRM_TELEPORTATION_ID, SET_PLAYER_ON_GROUND_ID, SET_PLAYER_POSITION_AND_ROTATION_ID, SET_PLAYER_POSITION_ID,
+    SET_PLAYER_ROTATION_ID,
+};
+use crate::net::players::{Session, SessionMessage, OVERLOADED_MESSAGE};
+use crate::net::protocol::Packet;
+use crate::Result;
+
+/// 接続タスクからゲームスレッドへのチャネルの容量。溢れたら接続タスクが待つ
+pub const INGRESS_CAPACITY: usize = 4096;
+/// ゲームスレッドから1接続へのチャネルの容量。溢れたクライアントは切断する
+pub const EGRESS_CAPACITY: usize = 1024;
+
+/// ECS の中で接続を指す ID。[`Session::id`] と同じ値
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ConnectionId(pub u64);
+
+/// ゲームが扱う serverbound パケット。keep-alive などは接続タスクで処理して流さない
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerboundPacket {
+    ChatMessage(ChatMessage),
+    ChatCommand(ChatCommand),
+    ClientInformation(ClientInformation),
+    SetHeldItem(SetHeldItem),
+    SetPlayerPosition(SetPlayerPosition),
+    SetPlayerPositionAndRotation(SetPlayerPositionAndRotation),
+    SetPlayerRotation(SetPlayerRotation),
+    SetPlayerOnGround(SetPlayerOnGround),
+    ConfirmTeleportation(ConfirmTeleportation),
+    /// まだ型を用意していないパケット
+    Other { id: i32, body: Bytes },
+}
+
+impl ServerboundPacket {
+    pub fn decode(id: i32, body: &mut BytesMut) -> Result<Self> {
+        Ok(match id {
+            CHAT_MESSAGE_ID => Self::ChatMessage(ChatMessage::decode(body)?),
+            CHAT_COMMAND_ID => Self::ChatCommand(ChatCommand::decode(body)?),
+            CLIENT_INFORMATION_ID => Self::ClientInformation(ClientInformation::decode(body)?),
+            SERVERBOUND_SET_HELD_ITEM_ID => Self::SetHeldItem(SetHeldItem::decode(body)?),
+            SET_PLAYER_POSITION_ID => Self::SetPlayerPosition(SetPlayerPosition::decode(body)?),
+            SET_PLAYER_POSITION_AND_ROTATION_ID => Self::SetPlayerPositionAndRotation(SetPlayerPositionAndRotation::decode(body)?),
+            SET_PLAYER_ROTATION_ID => Self::SetPlayerRotation(SetPlayerRotation::decode(body)?),
+            SET_PLAYER_ON_GROUND_ID => Self::SetPlayerOnGround(SetPlayerOnGround::decode(body)?),
+            CONFIRM_TELEPORTATION_ID => Self::ConfirmTeleportation(ConfirmTeleportation::decode(body)?),
+            _ => Self::Other { id, body: body.split().freeze() },
+        })
+    }
+}
+
+/// 接続タスクからゲームスレッドへの通知
+#[derive(Debug)]
+pub enum Ingress {
+    /// プレイヤーがゲームに参加した。`egress` にこの接続へのパケットを送る
+    Connected { session: Arc<Session>, egress: mpsc::Sender<SessionMessage> },
+    Packet { connection: ConnectionId, packet: ServerboundPacket },
+    Disconnected { connection: ConnectionId },
+}
+
+/// 接続タスク側の送信口。[`ServerContext`](crate::net::context::ServerContext) が持つ
+pub struct GameHandle {
+    ingress: mpsc::Sender<Ingress>,
+    /// ゲームスレッドを起動するときに1度だけ取り出す
+    receiver: Mutex<Option<mpsc::Receiver<Ingress>>>,
+    players: Arc<PlayerSnapshots>,
+}
+
+impl GameHandle {
+    pub fn new() -> Self {
+        let (ingress, receiver) = mpsc::channel(INGRESS_CAPACITY);
+        Self { ingress, receiver: Mutex::new(Some(receiver)), players: Arc::default() }
+    }
+
+    /// ゲームに参加しているプレイヤー。tick の終わりに更新される
+    pub fn players(&self) -> &PlayerSnapshots {
+        &self.players
+    }
+
+    pub(crate) fn shared_players(&self) -> Arc<PlayerSnapshots> {
+        self.players.clone()
+    }
+
+    /// ゲームスレッド用の受信口。2回目以降は `None`
+    pub fn take_receiver(&self) -> Option<mpsc::Receiver<Ingress>> {
+        self.receiver.lock().take()
+    }
+
+    /// プレイヤーをゲームに参加させ、ゲームからのパケットを受け取るレシーバーを返す
+    pub async fn connect(&self, session: Arc<Session>) -> mpsc::Receiver<SessionMessage> {
+        let (egress, receiver) = mpsc::channel(EGRESS_CAPACITY);
+        self.send(Ingress::Connected { session, egress }).await;
+        receiver
+    }
+
+    pub async fn send_packet(&self, connection: ConnectionId, packet: ServerboundPacket) {
+        self.send(Ingress::Packet { connection, packet }).await;
+    }
+
+    /// 切断を伝える。`Drop` から呼べるように待たない。チャネルが一杯なら別のタスクで送る
+    pub fn disconnect(&self, connection: ConnectionId) {
+        match self.ingress.try_send(Ingress::Disconnected { connection }) {
+            Ok(()) | Err(TrySendError::Closed(_)) => {}
+            Err(TrySendError::Full(message)) => {
+                let ingress = self.ingress.clone();
+                tokio::spawn(async move {
+                    let _ = ingress.send(message).await;
+                });
+            }
+        }
+    }
+
+    async fn send(&self, message: Ingress) {
+        // ゲームスレッドが止まっていれば捨てる
+        if self.ingress.send(message).await.is_err() {
+            debug!("The game loop is not running; dropped a message from a connection");
+        }
+    }
+}
+
+impl Default for GameHandle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 接続タスクからの受信口
+#[derive(Resource)]
+pub struct IngressReceiver(Mutex<mpsc::Receiver<Ingress>>);
+
+impl IngressReceiver {
+    pub fn new(receiver: mpsc::Receiver<Ingress>) -> Self {
+        Self(Mutex::new(receiver))
+    }
+}
+
+/// ゲームに参加している接続
+pub struct ConnectionHandle {
+    pub session: Arc<Session>,
+    egress: mpsc::Sender<SessionMessage>,
+}
+
+#[derive(Resource, Default)]
+pub struct Connections(HashMap<ConnectionId, ConnectionHandle>);
+
+impl Connections {
+    pub fn get(&self, connection: ConnectionId) -> Option<&ConnectionHandle> {
+        self.0.get(&connection)
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = ConnectionId> + '_ {
+        self.0.keys().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+#[derive(Event, Debug, Clone)]
+pub struct ClientConnected {
+    pub connection: ConnectionId,
+    pub session: Arc<Session>,
+}
+
+#[derive(Event, Debug, Clone)]
+pub struct PacketReceived {
+    pub connection: ConnectionId,
+    pub packet: ServerboundPacket,
+}
+
+#[derive(Event, Debug, Clone)]
+pub struct ClientDisconnected {
+    pub connection: ConnectionId,
+    pub session: Arc<Session>,
+}
+
+/// この tick に送るパケット。接続ごとに積んだ順に送る
+#[derive(Resource, Default)]
+pub struct Outbound {
+    queues: HashMap<ConnectionId, Vec<SessionMessage>>,
+}
+
+impl Outbound {
+    pub fn send<P: Packet>(&mut self, connection: ConnectionId, packet: &P) {
+        match SessionMessage::packet(packet) {
+            Ok(message) => self.push(connection, message),
+            Err(e) => warn!("Failed to encode packet 0x{:02X}: {}", packet.packet_id(), e),
+        }
+    }
+
+    /// 1度だけエンコードして全員に送る
+    pub fn broadcast<P: Packet>(&mut self, connections: impl IntoIterator<Item = ConnectionId>, packet: &P) {
+        match SessionMessage::packet(packet) {
+            Ok(message) => {
+                for connection in connections {
+                    self.push(connection, message.clone());
+                }
+            }
+            Err(e) => warn!("Failed to encode packet 0x{:02X}: {}", packet.packet_id(), e),
+        }
+    }
+
+    pub fn kick(&mut self, connection: ConnectionId, reason: &str) {
+        self.push(connection, SessionMessage::Kick(reason.to_string()));
+    }
+
+    pub fn push(&mut self, connection: ConnectionId, message: SessionMessage) {
+        self.queues.entry(connection).or_default().push(message);
+    }
+
+    /// まだ送っていないパケットの数
+    pub fn len(&self) -> usize {
+        self.queues.values().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queues.values().all(Vec::is_empty)
+    }
+}
+
+/// 接続タスクとのチャネルを ECS につなぐ
+pub struct NetworkPlugin;
+
+impl Plugin for NetworkPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Connections>()
+            .init_resource::<Outbound>()
+            .add_event::<ClientConnected>()
+            .add_event::<PacketReceived>()
+            .add_event::<ClientDisconnected>()
+            .add_systems(Update, receive_ingress.in_set(TickSet::Ingress))
+            .add_systems(Update, flush_outbound.in_set(TickSet::Egress));
+    }
+}
+
+/// 前の tick から届いた通知をすべてイベントにする
+fn receive_ingress(
+    receiver: Res<IngressReceiver>,
+    mut connections: ResMut<Connections>,
+    mut connected: EventWriter<ClientConnected>,
+    mut received: EventWriter<PacketReceived>,
+    mut disconnected: EventWriter<ClientDisconnected>,
+) {
+    let mut receiver = receiver.0.lock();
+    loop {
+        match receiver.try_recv() {
+            Ok(Ingress::Connected { session, egress }) => {
+                let connection = ConnectionId(session.id);
+                connections.0.insert(connection, ConnectionHandle { session: session.clone(), egress });
+                connected.send(ClientConnected { connection, session });
+            }
+            Ok(Ingress::Packet { connection, packet }) => {
+                // 切断を処理した後に届いた分は捨てる
+                if connections.0.contains_key(&connection) {
+                    received.send(PacketReceived { connection, packet });
+                }
+            }
+            Ok(Ingress::Disconnected { connection }) => {
+                if let Some(handle) = connections.0.remove(&connection) {
+                    disconnected.send(ClientDisconnected { connection, session: handle.session });
+                }
+            }
+            Err(TryRecvError::Empty | TryRecvError::Disconnected) => break,
+        }
+    }
+}
+
+/// 積まれたパケットを接続タスクへ渡す
+fn flush_outbound(mut outbound: ResMut<Outbound>, connections: Res<Connections>) {
+    for (connection, messages) in outbound.queues.drain() {
+        let Some(handle) = connections.get(connection) else {
+            continue;
+        };
+        for message in messages {
+            match handle.egress.try_send(message) {
+                Ok(()) => {}
+                // 接続タスクは既に終わっている。切断の通知がこの後に届く
+                Err(TrySendError::Closed(_)) => break,
+                Err(TrySendError::Full(_)) => {
+                    warn!("{} is not reading packets fast enough, disconnecting", handle.session.name());
+                    handle.session.kick(OVERLOADED_MESSAGE);
+                    break;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,458 @@
+//! プレイヤーのエンティティ
+//!
+//! ゲームに参加するとプレイヤーのエンティティを作り、切断したら消す。
+//! ECS の中からは [`PlayerIndex`] で、コマンドなどゲームスレッドの外からは
+//! tick ごとに書き出す [`PlayerSnapshots`] で UUID から引ける。
+
+pub mod movement;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use bevy::prelude::*;
+use log::{debug, warn};
+use parking_lot::RwLock;
+use uuid::Uuid;
+
+use crate::game::entity::tracker::{Tracked, VisibleEntities};
+use crate::game::entity::{EntityId, EntityIdAllocator, OnGround, Position, Rotation};
+use crate::game::network::{ClientConnected, ClientDisconnected, ConnectionId, PacketReceived, ServerboundPacket};
+use crate::game::world::SPAWN_POSITION;
+use crate::game::TickSet;
+use crate::net::login::profile::GameProfile;
+use crate::net::play::client_information::ClientInformation;
+use crate::net::players::Session;
+use movement::{ChunkChanged, ChunkPosition, LastMove, PlayerMoved, TeleportPlayer, Teleports};
+
+/// vanilla のプレイヤーの最大体力
+pub const MAX_HEALTH: f32 = 20.0;
+/// 満腹度の最大値
+pub const MAX_FOOD: i32 = 20;
+/// ホットバーのスロット数
+pub const HOTBAR_SLOTS: i16 = 9;
+/// クライアントが送ってくる描画距離の範囲 (vanilla のオプションと同じ)
+pub const MIN_VIEW_DISTANCE: u8 = 2;
+pub const MAX_VIEW_DISTANCE: u8 = 32;
+
+/// プレイヤーの接続。パケットは [`Outbound`](crate::game::network::Outbound) にこの ID で積む
+#[derive(Component, Debug, Clone)]
+pub struct PlayerConnection {
+    pub id: ConnectionId,
+    pub session: Arc<Session>,
+}
+
+#[derive(Component, Debug, Clone, PartialEq)]
+pub struct Profile(pub GameProfile);
+
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GameMode {
+    #[default]
+    Survival,
+    Creative,
+    Adventure,
+    Spectator,
+}
+
+impl GameMode {
+    /// プロトコルでの値
+    pub fn id(self) -> u8 {
+        match self {
+            GameMode::Survival => 0,
+            GameMode::Creative => 1,
+            GameMode::Adventure => 2,
+            GameMode::Spectator => 3,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            GameMode::Survival => "survival",
+            GameMode::Creative => "creative",
+            GameMode::Adventure => "adventure",
+            GameMode::Spectator => "spectator",
+        }
+    }
+}
+
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct Health(pub f32);
+
+impl Default for Health {
+    fn default() -> Self {
+        Self(MAX_HEALTH)
+    }
+}
+
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct Food {
+    pub level: i32,
+    pub saturation: f32,
+    pub exhaustion: f32,
+}
+
+impl Default for Food {
+    /// vanilla で参加したときの値
+    fn default() -> Self {
+        Self { level: MAX_FOOD, saturation: 5.0, exhaustion: 0.0 }
+    }
+}
+
+#[derive(Component, Debug, Clone, Copy, PartialEq, Default)]
+pub struct Experience {
+    pub level: i32,
+    /// 次のレベルまでの進み具合 (0.0-1.0)
+    pub progress: f32,
+    pub total: i32,
+}
+
+/// ホットバーで選んでいるスロット (0-8)
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SelectedSlot(pub u8);
+
+/// クライアントの描画距離 (チャンク)
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ViewDistance(pub u8);
+
+impl ViewDistance {
+    pub fn from_client(view_distance: i8) -> Self {
+        Self((view_distance.max(0) as u8).clamp(MIN_VIEW_DISTANCE, MAX_VIEW_DISTANCE))
+    }
+}
+
+/// 最後に受け取った Client Information
+#[derive(Component, Debug, Clone, PartialEq, Default)]
+pub struct ClientSettings(pub ClientInformation);
+
+#[derive(Bundle)]
+pub struct PlayerBundle {
+    pub connection: PlayerConnection,
+    pub profile: Profile,
+    pub entity_id: EntityId,
+    pub position: Position,
+    pub rotation: Rotation,
+    pub on_ground: OnGround,
+    pub game_mode: GameMode,
+    pub health: Health,
+    pub food: Food,
+    pub experience: Experience,
+    pub selected_slot: SelectedSlot,
+    pub view_distance: ViewDistance,
+    pub settings: ClientSettings,
+    pub teleports: Teleports,
+    pub last_move: LastMove,
+    pub chunk: ChunkPosition,
+    pub tracked: Tracked,
+    pub visible: VisibleEntities,
+}
+
+impl PlayerBundle {
+    /// スポーン地点に置いた参加直後のプレイヤー
+    pub fn new(connection: ConnectionId, session: Arc<Session>, entity_id: EntityId) -> Self {
+        let settings = ClientInformation::default();
+        Self {
+            tracked: Tracked::player(session.uuid()),
+            profile: Profile(session.profile.clone()),
+            connection: PlayerConnection { id: connection, session },
+            entity_id,
+            position: SPAWN_POSITION,
+            rotation: Rotation::default(),
+            on_ground: OnGround(false),
+            game_mode: GameMode::default(),
+            health: Health::default(),
+            food: Food::default(),
+            experience: Experience::default(),
+            selected_slot: SelectedSlot::default(),
+            view_distance: ViewDistance::from_client(settings.view_distance),
+            settings: ClientSettings(settings),
+            teleports: Teleports::default(),
+            last_move: LastMove::default(),
+            chunk: ChunkPosition::of(&SPAWN_POSITION),
+            visible: VisibleEntities::default(),
+        }
+    }
+}
+
+/// プレイヤーのエンティティを UUID と接続から引く
+#[derive(Resource, Debug, Default)]
+pub struct PlayerIndex {
+    by_uuid: HashMap<Uuid, Entity>,
+    by_connection: HashMap<ConnectionId, Entity>,
+}
+
+impl PlayerIndex {
+    pub fn get(&self, uuid: &Uuid) -> Option<Entity> {
+        self.by_uuid.get(uuid).copied()
+    }
+
+    pub fn by_connection(&self, connection: ConnectionId) -> Option<Entity> {
+        self.by_connection.get(&connection).copied()
+    }
+
+    pub fn entities(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.by_connection.values().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_connection.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_connection.is_empty()
+    }
+}
+
+/// プレイヤーのエンティティを作った
+#[derive(Event, Debug, Clone)]
+pub struct PlayerJoined {
+    pub entity: Entity,
+    pub connection: ConnectionId,
+}
+
+/// プレイヤーのエンティティを消した。`entity` はもう存在しない
+#[derive(Event, Debug, Clone)]
+pub struct PlayerLeft {
+    pub entity: Entity,
+    pub entity_id: EntityId,
+    pub uuid: Uuid,
+}
+
+/// ゲームスレッドの外から読むための、tick の終わりの時点のプレイヤーの状態
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerSnapshot {
+    pub entity: Entity,
+    pub entity_id: EntityId,
+    pub uuid: Uuid,
+    pub name: String,
+    pub position: Position,
+    pub rotation: Rotation,
+    pub on_ground: bool,
+    pub game_mode: GameMode,
+    pub health: f32,
+    pub food: i32,
+    pub level: i32,
+    pub selected_slot: u8,
+    pub view_distance: u8,
+    pub locale: String,
+}
+
+/// tick ごとに書き出すプレイヤーの一覧。[`GameHandle::players`](crate::game::network::GameHandle::players) で取り出す
+#[derive(Debug, Default)]
+pub struct PlayerSnapshots {
+    players: RwLock<HashMap<Uuid, PlayerSnapshot>>,
+}
+
+impl PlayerSnapshots {
+    pub fn get(&self, uuid: &Uuid) -> Option<PlayerSnapshot> {
+        self.players.read().get(uuid).cloned()
+    }
+
+    /// 大文字小文字を区別しない
+    pub fn get_by_name(&self, name: &str) -> Option<PlayerSnapshot> {
+        self.players.read().values().find(|player| player.name.eq_ignore_ascii_case(name)).cloned()
+    }
+
+    /// 名前順
+    pub fn all(&self) -> Vec<PlayerSnapshot> {
+        let mut players: Vec<_> = self.players.read().values().cloned().collect();
+        players.sort_by(|a, b| a.name.cmp(&b.name));
+        players
+    }
+
+    pub fn len(&self) -> usize {
+        self.players.read().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.players.read().is_empty()
+    }
+}
+
+/// ゲームスレッドの中で [`PlayerSnapshots`] を書き換えるためのリソース
+#[derive(Resource, Clone)]
+pub struct SharedPlayerSnapshots(pub Arc<PlayerSnapshots>);
+
+pub struct PlayerPlugin;
+
+impl Plugin for PlayerPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PlayerIndex>()
+            .init_resource::<EntityIdAllocator>()
+            .add_event::<PlayerJoined>()
+            .add_event::<PlayerLeft>()
+            .add_event::<TeleportPlayer>()
+            .add_event::<PlayerMoved>()
+            .add_event::<ChunkChanged>()
+            // 同じ tick に届いた参加と設定と移動と切断をこの順で処理する。作ったエンティティを後のシステムから見えるようにする
+            .add_systems(
+                Update,
+                (
+                    spawn_players,
+                    apply_deferred,
+                    apply_player_settings,
+                    movement::send_join_position,
+                    movement::handle_movement,
+                    movement::apply_teleports,
+                    movement::update_chunk_positions,
+                    despawn_players,
+                )
+                    .chain()
+                    .in_set(TickSet::Simulation),
+            );
+        if !app.world.contains_resource::<SharedPlayerSnapshots>() {
+            app.insert_resource(SharedPlayerSnapshots(Arc::default()));
+        }
+        app.add_systems(Update, publish_snapshots.in_set(TickSet::Egress));
+    }
+}
+
+fn spawn_players(
+    mut commands: Commands,
+    mut connected: EventReader<ClientConnected>,
+    mut index: ResMut<PlayerIndex>,
+    mut entity_ids: ResMut<EntityIdAllocator>,
+    mut joined: EventWriter<PlayerJoined>,
+) {
+    for event in connected.read() {
+        let uuid = event.session.uuid();
+        let entity = commands.spawn(PlayerBundle::new(event.connection, event.session.clone(), entity_ids.allocate())).id();
+        // 再ログインでは古いエンティティが切断の処理まで残るが、UUID は新しい方を指す
+        index.by_uuid.insert(uuid, entity);
+        index.by_connection.insert(event.connection, entity);
+        debug!("Spawned {} as {:?}", event.session.name(), entity);
+        joined.send(PlayerJoined { entity, connection: event.connection });
+    }
+}
+
+/// Client Information と Set Held Item を反映する
+fn apply_player_settings(
+    mut received: EventReader<PacketReceived>,
+    index: Res<PlayerIndex>,
+    mut players: Query<(&Profile, &mut ClientSettings, &mut ViewDistance, &mut SelectedSlot)>,
+) {
+    for event in received.read() {
+        let Some(entity) = index.by_connection(event.connection) else {
+            continue;
+        };
+        let Ok((profile, mut settings, mut view_distance, mut selected_slot)) = players.get_mut(entity) else {
+            continue;
+        };
+        match &event.packet {
+            ServerboundPacket::ClientInformation(information) => {
+                view_distance.set_if_neq(ViewDistance::from_client(information.view_distance));
+                settings.0 = information.clone();
+            }
+            ServerboundPacket::SetHeldItem(held) => {
+                if (0..HOTBAR_SLOTS).contains(&held.slot) {
+                    selected_slot.set_if_neq(SelectedSlot(held.slot as u8));
+                } else {
+                    // vanilla と同じく無視する
+                    warn!("{} tried to select an invalid hotbar slot {}", profile.0.name, held.slot);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn despawn_players(
+    mut commands: Commands,
+    mut disconnected: EventReader<ClientDisconnected>,
+    mut index: ResMut<PlayerIndex>,
+    players: Query<&EntityId>,
+    mut left: EventWriter<PlayerLeft>,
+) {
+    for event in disconnected.read() {
+        let Some(entity) = index.by_connection.remove(&event.connection) else {
+            continue;
+        };
+        let uuid = event.session.uuid();
+        if index.by_uuid.get(&uuid) == Some(&entity) {
+            index.by_uuid.remove(&uuid);
+        }
+        let entity_id = players.get(entity).copied().unwrap_or(EntityId(0));
+        commands.entity(entity).despawn();
+        debug!("Despawned {}", event.session.name());
+        left.send(PlayerLeft { entity, entity_id, uuid });
+    }
+}
+
+#[allow(clippy::type_complexity)]
+fn publish_snapshots(
+    shared: Res<SharedPlayerSnapshots>,
+    index: Res<PlayerIndex>,
+    players: Query<(
+        Entity, &EntityId, &Profile, &Position, &Rotation, &OnGround, &GameMode,
+        &Health, &Food, &Experience, &SelectedSlot, &ViewDistance, &ClientSettings,
+    )>,
+) {
+    let snapshots = players.iter()
+        // 再ログインで置き換えられたエンティティは載せない
+        .filter(|(entity, _, profile, ..)| index.get(&profile.0.uuid) == Some(*entity))
+        .map(|(entity, entity_id, profile, position, rotation, on_ground, game_mode, health, food, experience, slot, view_distance, settings)| {
+            let snapshot = PlayerSnapshot {
+                entity,
+                entity_id: *entity_id,
+                uuid: profile.0.uuid,
+                name: profile.0.name.clone(),
+                position: *position,
+                rotation: *rotation,
+                on_ground: on_ground.0,
+                game_mode: *game_mode,
+                health: health.0,
+                food: food.level,
+                level: experience.level,
+                selected_slot: slot.0,
+                view_distance: view_distance.0,
+                locale: settings.0.locale.clone(),
+            };
+            (snapshot.uuid, snapshot)
+        })
+        .collect();
+    *shared.0.players.write() = snapshots;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::build_app;
+    use crate::game::network::GameHandle;
+    use crate::net::login::offline::offline_uuid;
+    use crate::net::play::held_item::SetHeldItem;
+    use crate::net::players::PlayerRegistry;
+
+    #[tokio::test]
+    async fn test_spawn_update_and_despawn() {
+        let handle = GameHandle::new();
+        let mut app = build_app(&handle).unwrap();
+        let players = PlayerRegistry::new();
+        let steve = players.register(GameProfile::new(offline_uuid("Steve"), "Steve"), "127.0.0.1:1".parse().unwrap(), 763).unwrap().session;
+        let connection = ConnectionId(steve.id);
+        let _egress = handle.connect(steve.clone()).await;
+        let information = ClientInformation { locale: "ja_jp".to_string(), view_distance: 64, ..ClientInformation::default() };
+        handle.send_packet(connection, ServerboundPacket::ClientInformation(information)).await;
+        app.update();
+
+        let entity = app.world.resource::<PlayerIndex>().get(&steve.uuid()).unwrap();
+        assert_eq!(*app.world.get::<Position>(entity).unwrap(), SPAWN_POSITION);
+        assert_eq!(*app.world.get::<GameMode>(entity).unwrap(), GameMode::Survival);
+        assert_eq!(app.world.get::<Health>(entity).unwrap().0, MAX_HEALTH);
+        assert_eq!(app.world.get::<Food>(entity).unwrap().level, MAX_FOOD);
+        // 作った tick のうちに届いた設定も反映される
+        assert_eq!(app.world.get::<ViewDistance>(entity).unwrap().0, MAX_VIEW_DISTANCE);
+        assert_eq!(app.world.get::<ClientSettings>(entity).unwrap().0.locale, "ja_jp");
+
+        handle.send_packet(connection, ServerboundPacket::SetHeldItem(SetHeldItem { slot: 4 })).await;
+        handle.send_packet(connection, ServerboundPacket::SetHeldItem(SetHeldItem { slot: 9 })).await;
+        app.update();
+        assert_eq!(app.world.get::<SelectedSlot>(entity).unwrap().0, 4);
+        let snapshot = handle.players().get_by_name("steve").unwrap();
+        assert_eq!((snapshot.entity, snapshot.selected_slot, snapshot.locale.as_str()), (entity, 4, "ja_jp"));
+
+        handle.disconnect(connection);
+        app.update();
+        assert!(app.world.get_entity(entity).is_none());
+        assert!(app.world.resource::<PlayerIndex>().is_empty());
+        assert!(handle.players().is_empty());
+        assert_eq!(app.world.resource::<Events<PlayerLeft>>().len(), 1);
+    }
+}
@@ -0,0 +1,353 @@
+//! クライアントから届く移動の検証と反映
+//!
+//! 位置はサーバーが決めたものを正とする。テレポートを送ったら Confirm Teleportation が返るまで
+//! 移動を無視し、速すぎる移動やワールドの外への移動は最後に受け付けた位置へ戻す (ラバーバンド)。
+
+use bevy::prelude::*;
+use log::{debug, warn};
+
+use crate::game::entity::{OnGround, Position, Rotation};
+use crate::game::network::{ConnectionId, Outbound, PacketReceived, ServerboundPacket};
+use crate::game::player::{PlayerConnection, PlayerIndex, Profile};
+use crate::game::{GameConfig, GameTick, TICKS_PER_SECOND};
+use crate::net::play::chunk::SetCenterChunk;
+use crate::net::play::movement::SynchronizePlayerPosition;
+
+/// 座標が壊れているときの切断理由 (vanilla と同じ)
+pub const INVALID_MOVE_MESSAGE: &str = "Invalid move player packet received";
+/// vanilla が壊れているとみなす座標の絶対値
+const MAX_COORDINATE_XZ: f64 = 3.0e7;
+const MAX_COORDINATE_Y: f64 = 2.0e7;
+/// 止まっていた時間は、この tick 数までしか移動量の余裕に数えない
+const MAX_IDLE_TICKS: u64 = TICKS_PER_SECOND as u64;
+
+/// 送ったテレポートの ID。確認されるまで `pending` に残る
+#[derive(Component, Debug, Default)]
+pub struct Teleports {
+    next_id: i32,
+    pending: Option<i32>,
+}
+
+impl Teleports {
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// 新しい ID を振る。前のテレポートの確認はもう受け付けない
+    fn start(&mut self) -> i32 {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        self.pending = Some(id);
+        id
+    }
+
+    fn confirm(&mut self, id: i32) -> bool {
+        if self.pending == Some(id) {
+            self.pending = None;
+            return true;
+        }
+        false
+    }
+}
+
+/// 最後に移動を受け付けた tick
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LastMove(pub u64);
+
+/// 足元のチャンク。クライアントに送った Set Center Chunk と同じ
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ChunkPosition {
+    pub x: i32,
+    pub z: i32,
+}
+
+impl ChunkPosition {
+    pub fn of(position: &Position) -> Self {
+        Self { x: (position.x / 16.0).floor() as i32, z: (position.z / 16.0).floor() as i32 }
+    }
+}
+
+/// プレイヤーをテレポートさせる。`rotation` が `None` なら向きは変えない
+#[derive(Event, Debug, Clone, Copy)]
+pub struct TeleportPlayer {
+    pub entity: Entity,
+    pub position: Position,
+    pub rotation: Option<Rotation>,
+}
+
+/// 検証を通った移動
+#[derive(Event, Debug, Clone, Copy)]
+pub struct PlayerMoved {
+    pub entity: Entity,
+    pub from: Position,
+    pub to: Position,
+}
+
+/// プレイヤーが別のチャンクに入った。チャンクの読み込みはこれを見る
+#[derive(Event, Debug, Clone, Copy)]
+pub struct ChunkChanged {
+    pub entity: Entity,
+    pub from: ChunkPosition,
+    pub to: ChunkPosition,
+}
+
+fn is_valid_position(position: &Position) -> bool {
+    [position.x, position.y, position.z].iter().all(|value| value.is_finite())
+        && position.x.abs() < MAX_COORDINATE_XZ
+        && position.z.abs() < MAX_COORDINATE_XZ
+        && position.y.abs() < MAX_COORDINATE_Y
+}
+
+/// yaw を -180〜180 に、pitch を -90〜90 に収める
+fn normalize_rotation(yaw: f32, pitch: f32) -> Option<Rotation> {
+    if !yaw.is_finite() || !pitch.is_finite() {
+        return None;
+    }
+    Some(Rotation { yaw: (yaw + 180.0).rem_euclid(360.0) - 180.0, pitch: pitch.clamp(-90.0, 90.0) })
+}
+
+fn send_teleport(outbound: &mut Outbound, connection: ConnectionId, teleports: &mut Teleports, position: &Position, rotation: &Rotation) {
+    let teleport_id = teleports.start();
+    outbound.send(connection, &SynchronizePlayerPosition {
+        x: position.x,
+        y: position.y,
+        z: position.z,
+        yaw: rotation.yaw,
+        pitch: rotation.pitch,
+        flags: 0,
+        teleport_id,
+    });
+}
+
+/// 参加したプレイヤーにサーバーが決めた位置を伝える
+pub(super) fn send_join_position(
+    mut outbound: ResMut<Outbound>,
+    mut players: Query<(&PlayerConnection, &Position, &Rotation, &mut Teleports), Added<Teleports>>,
+) {
+    for (connection, position, rotation, mut teleports) in &mut players {
+        send_teleport(&mut outbound, connection.id, &mut teleports, position, rotation);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(super) fn handle_movement(
+    mut received: EventReader<PacketReceived>,
+    index: Res<PlayerIndex>,
+    config: Res<GameConfig>,
+    tick: Res<GameTick>,
+    mut outbound: ResMut<Outbound>,
+    mut moved: EventWriter<PlayerMoved>,
+    mut players: Query<(&Profile, &mut Position, &mut Rotation, &mut OnGround, &mut Teleports, &mut LastMove)>,
+) {
+    let limits = &config.0.movement;
+    for event in received.read() {
+        let Some(entity) = index.by_connection(event.connection) else {
+            continue;
+        };
+        let Ok((profile, mut position, mut rotation, mut on_ground, mut teleports, mut last_move)) = players.get_mut(entity) else {
+            continue;
+        };
+        let (target, look, ground) = match &event.packet {
+            ServerboundPacket::ConfirmTeleportation(confirm) => {
+                if !teleports.confirm(confirm.teleport_id) {
+                    debug!("{} confirmed an outdated teleport {}", profile.0.name, confirm.teleport_id);
+                }
+                continue;
+            }
+            ServerboundPacket::SetPlayerPosition(p) => (Some(Position::new(p.x, p.y, p.z)), None, p.on_ground),
+            ServerboundPacket::SetPlayerPositionAndRotation(p) => (Some(Position::new(p.x, p.y, p.z)), Some((p.yaw, p.pitch)), p.on_ground),
+            ServerboundPacket::SetPlayerRotation(p) => (None, Some((p.yaw, p.pitch)), p.on_ground),
+            ServerboundPacket::SetPlayerOnGround(p) => (None, None, p.on_ground),
+            _ => continue,
+        };
+        // テレポート前の位置から送られた移動なので、確認が届くまでは無視する (vanilla と同じ)
+        if teleports.is_pending() {
+            continue;
+        }
+        let look = match look {
+            Some((yaw, pitch)) => match normalize_rotation(yaw, pitch) {
+                Some(look) => Some(look),
+                None => {
+                    warn!("{} sent an invalid rotation", profile.0.name);
+                    outbound.kick(event.connection, INVALID_MOVE_MESSAGE);
+                    continue;
+                }
+            },
+            None => None,
+        };
+        if target.is_some_and(|target| !is_valid_position(&target)) {
+            warn!("{} sent an invalid position", profile.0.name);
+            outbound.kick(event.connection, INVALID_MOVE_MESSAGE);
+            continue;
+        }
+
+        if let Some(look) = look {
+            rotation.set_if_neq(look);
+        }
+        on_ground.set_if_neq(OnGround(ground));
+        let Some(target) = target else {
+            continue;
+        };
+        if target.x.abs() > limits.world_border || target.z.abs() > limits.world_border {
+            debug!("{} tried to leave the world border", profile.0.name);
+            send_teleport(&mut outbound, event.connection, &mut teleports, &position, &rotation);
+            continue;
+        }
+        // 止まっていた分だけ余裕を持たせる
+        let ticks = tick.0.saturating_sub(last_move.0).clamp(1, MAX_IDLE_TICKS);
+        let allowed = limits.max_speed * ticks as f64 / TICKS_PER_SECOND as f64;
+        if target.distance_squared(&position) > allowed * allowed {
+            warn!(
+                "{} moved too quickly! {:.2},{:.2},{:.2}",
+                profile.0.name,
+                target.x - position.x,
+                target.y - position.y,
+                target.z - position.z
+            );
+            send_teleport(&mut outbound, event.connection, &mut teleports, &position, &rotation);
+            continue;
+        }
+        let from = *position;
+        position.set_if_neq(target);
+        last_move.0 = tick.0;
+        if from != target {
+            moved.send(PlayerMoved { entity, from, to: target });
+        }
+    }
+}
+
+/// コマンドやプラグインからのテレポートを反映する
+pub(super) fn apply_teleports(
+    mut requests: EventReader<TeleportPlayer>,
+    mut outbound: ResMut<Outbound>,
+    mut players: Query<(&PlayerConnection, &mut Position, &mut Rotation, &mut Teleports)>,
+) {
+    for request in requests.read() {
+        let Ok((connection, mut position, mut rotation, mut teleports)) = players.get_mut(request.entity) else {
+            continue;
+        };
+        *position = request.position;
+        if let Some(look) = request.rotation {
+            *rotation = look;
+        }
+        send_teleport(&mut outbound, connection.id, &mut teleports, &position, &rotation);
+    }
+}
+
+/// 別のチャンクに入ったら Set Center Chunk を送る。参加したときにも送る
+pub(super) fn update_chunk_positions(
+    mut outbound: ResMut<Outbound>,
+    mut changed: EventWriter<ChunkChanged>,
+    mut players: Query<(Entity, &PlayerConnection, &Position, &mut ChunkPosition), Changed<Position>>,
+) {
+    for (entity, connection, position, mut chunk) in &mut players {
+        let current = ChunkPosition::of(position);
+        if !chunk.is_added() && *chunk == current {
+            continue;
+        }
+        let from = *chunk;
+        *chunk = current;
+        outbound.send(connection.id, &SetCenterChunk { chunk_x: current.x, chunk_z: current.z });
+        if from != current {
+            changed.send(ChunkChanged { entity, from, to: current });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use bytes::BytesMut;
+    use tokio::sync::mpsc::Receiver;
+
+    use crate::game::build_app;
+    use crate::game::network::GameHandle;
+    use crate::game::world::SPAWN_POSITION;
+    use crate::net::login::offline::offline_uuid;
+    use crate::net::login::profile::GameProfile;
+    use crate::net::play::chunk::SET_CENTER_CHUNK_ID;
+    use crate::net::play::movement::{ConfirmTeleportation, SetPlayerPosition, SYNCHRONIZE_PLAYER_POSITION_ID};
+    use crate::net::players::{PlayerRegistry, SessionMessage};
+    use crate::net::protocol::Packet;
+    use crate::utils::config::{MovementConfig, ServerConfig};
+
+    /// 届いたパケットから Synchronize Player Position だけを取り出す
+    fn teleports(egress: &mut Receiver<SessionMessage>) -> Vec<SynchronizePlayerPosition> {
+        let mut found = Vec::new();
+        while let Ok(message) = egress.try_recv() {
+            if let SessionMessage::Packet { id: SYNCHRONIZE_PLAYER_POSITION_ID, body } = message {
+                found.push(SynchronizePlayerPosition::decode(&mut BytesMut::from(&body[..])).unwrap());
+            }
+        }
+        found
+    }
+
+    #[tokio::test]
+    async fn test_teleport_confirm_and_rubber_band() {
+        let handle = GameHandle::new();
+        let mut app = build_app(&handle).unwrap();
+        let movement = MovementConfig { max_speed: 20.0, world_border: 100.0 };
+        app.insert_resource(GameConfig(Arc::new(ServerConfig { movement, ..ServerConfig::default() })));
+        let players = PlayerRegistry::new();
+        let steve = players.register(GameProfile::new(offline_uuid("Steve"), "Steve"), "127.0.0.1:1".parse().unwrap(), 763).unwrap().session;
+        let connection = ConnectionId(steve.id);
+        let mut egress = handle.connect(steve.clone()).await;
+        let move_to = |x: f64, z: f64| ServerboundPacket::SetPlayerPosition(SetPlayerPosition { x, y: 64.0, z, on_ground: true });
+
+        // 参加するとスポーン地点へのテレポートとチャンクの中心が届く
+        app.update();
+        let entity = app.world.resource::<PlayerIndex>().get(&steve.uuid()).unwrap();
+        let mut sent = Vec::new();
+        while let Ok(SessionMessage::Packet { id, body }) = egress.try_recv() {
+            sent.push(id);
+            if id == SYNCHRONIZE_PLAYER_POSITION_ID {
+                let teleport = SynchronizePlayerPosition::decode(&mut BytesMut::from(&body[..])).unwrap();
+                assert_eq!((teleport.x, teleport.y, teleport.z), (SPAWN_POSITION.x, SPAWN_POSITION.y, SPAWN_POSITION.z));
+            }
+        }
+        assert_eq!(sent, vec![SYNCHRONIZE_PLAYER_POSITION_ID, SET_CENTER_CHUNK_ID]);
+
+        // 確認が届くまでの移動は無視する
+        handle.send_packet(connection, move_to(1.5, 0.5)).await;
+        app.update();
+        assert_eq!(*app.world.get::<Position>(entity).unwrap(), SPAWN_POSITION);
+        handle.send_packet(connection, ServerboundPacket::ConfirmTeleportation(ConfirmTeleportation { teleport_id: 0 })).await;
+        handle.send_packet(connection, move_to(1.5, 0.5)).await;
+        app.update();
+        assert_eq!(app.world.get::<Position>(entity).unwrap().x, 1.5);
+        assert_eq!(app.world.resource::<Events<PlayerMoved>>().len(), 1);
+
+        // 1 tick で 20 ブロックは速すぎるので元の位置に戻す
+        handle.send_packet(connection, move_to(21.5, 0.5)).await;
+        app.update();
+        let back = teleports(&mut egress);
+        assert_eq!((back.len(), back[0].x, back[0].teleport_id), (1, 1.5, 1));
+        assert_eq!(app.world.get::<Position>(entity).unwrap().x, 1.5);
+
+        // テレポートした先からでも、ワールドの外には出られない
+        handle.send_packet(connection, ServerboundPacket::ConfirmTeleportation(ConfirmTeleportation { teleport_id: 1 })).await;
+        app.world.send_event(TeleportPlayer { entity, position: Position::new(95.5, 64.0, 0.5), rotation: None });
+        app.update();
+        assert_eq!(teleports(&mut egress)[0].teleport_id, 2);
+        assert_eq!(*app.world.get::<ChunkPosition>(entity).unwrap(), ChunkPosition { x: 5, z: 0 });
+        handle.send_packet(connection, ServerboundPacket::ConfirmTeleportation(ConfirmTeleportation { teleport_id: 2 })).await;
+        for _ in 0..10 {
+            app.update();
+        }
+        handle.send_packet(connection, move_to(100.5, 0.5)).await;
+        app.update();
+        assert_eq!(teleports(&mut egress)[0].x, 95.5);
+
+        // 壊れた座標は切断する
+        handle.send_packet(connection, ServerboundPacket::ConfirmTeleportation(ConfirmTeleportation { teleport_id: 3 })).await;
+        handle.send_packet(connection, move_to(f64::NAN, 0.5)).await;
+        app.update();
+        let mut kicked = false;
+        while let Ok(message) = egress.try_recv() {
+            kicked |= message == SessionMessage::Kick(INVALID_MOVE_MESSAGE.to_string());
+        }
+        assert!(kicked);
+    }
+}
@@ -0,0 +1,6 @@
+//! ワールドの設定
+
+use crate::game::entity::Position;
+
+/// 参加したプレイヤーを置く位置
+pub const SPAWN_POSITION: Position = Position::new(0.5, 64.0, 0.5);
@@ -1,21 +1,38 @@
 pub mod net;
 pub mod varint;
 pub mod utils;
+pub mod command;
+pub mod client;
+pub mod game;
 
 mod logging;
 mod test;
-mod game;
 
+use std::path::Path;
+use std::sync::Arc;
 use tokio::io;
 pub use utils::config::ServerConfig;
 pub use net::error::{Result, ServerError};
 pub use logging::setup_logging;
-pub use net::start_server;
+pub use net::{serve, start_server};
+pub use client::{ping_legacy, ping_server, ServerStatus};
+use net::context::ServerContext;
 
 /// サーバーのメインエントリーポイント
 pub async fn run_server(config: ServerConfig) -> io::Result<()> {
+    run_context(ServerContext::new(config)).await
+}
+
+/// `path` から読み込んだ設定で起動する。SIGHUP や `reload` コマンドでこのファイルを読み直す
+pub async fn run_server_with_path(config: ServerConfig, path: &Path) -> io::Result<()> {
+    run_context(ServerContext::new(config).with_config_path(path)).await
+}
+
+async fn run_context(ctx: ServerContext) -> io::Result<()> {
     if let Err(e) = setup_logging() {
         eprintln!("Warning: Failed to setup logging: {}", e);
     }
-    start_server(config).await
+    let ctx = Arc::new(ctx);
+    command::console::spawn_console(ctx.clone());
+    serve(ctx).await
 }
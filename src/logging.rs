@@ -1,5 +1,5 @@
-use tracing::{Level, info, warn, error};
-use crate::io::Result;
+use tracing::{Level, info};
+use crate::io::{Error, Result};
 
 pub fn setup_logging() -> Result<()> {
     tracing_subscriber::fmt()
@@ -8,8 +8,9 @@ pub fn setup_logging() -> Result<()> {
         .with_thread_ids(true)
         .with_file(true)
         .with_line_number(true)
-        .init();
+        .try_init()
+        .map_err(Error::other)?;
 
     info!("ログシステムが初期化されました");
     Ok(())
-}
\ No newline at end of file
+}
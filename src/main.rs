@@ -1,20 +1,417 @@
-use std::error;
+//! サーバー本体と運用向けのサブコマンド
+//!
+//! 使い方:
+//!
+//! ```text
+//! testServer [run] [--config server.toml]
+//! testServer ping <host:port> [--timeout 5] [--legacy] [--json]
+//! testServer config init|validate|print [--config server.toml] [--force] [--show-secrets] [--json]
+//! testServer keygen [--out server-key.pem] [--force] [--json]
+//! ```
+//!
+//! 終了コード: 0 成功 / 1 実行時の失敗 / 2 引数の誤り / 3 設定の誤り
+//!
+//! サブコマンドが使わないオプションは引数の誤りとして扱う。
+//! `--force` は `config init` だけ、`--show-secrets` は `config print` だけが受け付ける。
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::Duration;
+
 use log::error;
+use serde_json::json;
+use testServer::net::login::encryption::EncryptionKeyPair;
+use testServer::{ServerConfig, ServerError};
+
+/// 既定の設定ファイル
+const DEFAULT_CONFIG_PATH: &str = "server.toml";
+/// `keygen` の既定の出力先
+const DEFAULT_KEY_PATH: &str = "server-key.pem";
+
+const EXIT_FAILURE: u8 = 1;
+const EXIT_USAGE: u8 = 2;
+const EXIT_CONFIG: u8 = 3;
+
+const USAGE: &str = "\
+Usage:
+  testServer [run] [--config <path>]
+  testServer ping <host:port> [--timeout <secs>] [--legacy] [--json]
+  testServer config <init|validate|print> [--config <path>] [--force] [--show-secrets] [--json]
+  testServer keygen [--out <path>] [--force] [--json]";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ConfigAction {
+    Init,
+    Validate,
+    Print,
+}
+
+impl ConfigAction {
+    fn name(self) -> &'static str {
+        match self {
+            ConfigAction::Init => "init",
+            ConfigAction::Validate => "validate",
+            ConfigAction::Print => "print",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Command {
+    Run { config: PathBuf },
+    Ping { address: String, timeout: Duration, legacy: bool, json: bool },
+    Config { action: ConfigAction, config: PathBuf, force: bool, show_secrets: bool, json: bool },
+    Keygen { out: PathBuf, force: bool, json: bool },
+    Help,
+}
+
+/// 値を取るものも含めた、すべてのオプションの正式な名前
+const FLAGS: &[&str] = &["--config", "--out", "--timeout", "--force", "--legacy", "--json", "--show-secrets"];
+
+/// `subcommand` が `flag` を使うか
+fn accepts(subcommand: &str, action: Option<ConfigAction>, flag: &str) -> bool {
+    match (subcommand, flag) {
+        ("run", "--config") => true,
+        ("ping", "--timeout" | "--legacy" | "--json") => true,
+        ("config", "--config" | "--json") => true,
+        ("config", "--force") => action == Some(ConfigAction::Init),
+        ("config", "--show-secrets") => action == Some(ConfigAction::Print),
+        ("keygen", "--out" | "--force" | "--json") => true,
+        _ => false,
+    }
+}
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Command, String> {
+    let mut args = args.into_iter().peekable();
+    let subcommand = match args.peek().map(String::as_str) {
+        Some("run" | "ping" | "config" | "keygen" | "help") => args.next(),
+        _ => None,
+    };
+    let action = match subcommand.as_deref() {
+        Some("config") => Some(match args.next().as_deref() {
+            Some("init") => ConfigAction::Init,
+            Some("validate") => ConfigAction::Validate,
+            Some("print") => ConfigAction::Print,
+            Some(other) => return Err(format!("unknown config action: {}", other)),
+            None => return Err("config requires init, validate or print".to_string()),
+        }),
+        _ => None,
+    };
+
+    let mut config = PathBuf::from(DEFAULT_CONFIG_PATH);
+    let mut out = PathBuf::from(DEFAULT_KEY_PATH);
+    let mut timeout = Duration::from_secs(5);
+    let mut address = None;
+    let mut force = false;
+    let mut legacy = false;
+    let mut json = false;
+    let mut show_secrets = false;
+    let name = subcommand.as_deref().unwrap_or("run");
+
+    while let Some(arg) = args.next() {
+        let flag = match arg.as_str() {
+            "-c" => "--config",
+            "-o" => "--out",
+            other => other,
+        };
+        if FLAGS.contains(&flag) && !accepts(name, action, flag) {
+            let command = action.map_or(name.to_string(), |action| format!("{} {}", name, action.name()));
+            return Err(format!("{} does not accept {}", command, arg));
+        }
+        let mut value = || args.next().ok_or_else(|| format!("{} requires a value", arg));
+        match flag {
+            "--config" => config = value()?.into(),
+            "--out" => out = value()?.into(),
+            "--timeout" => {
+                let secs: f64 = value()?.parse().map_err(|_| "invalid --timeout")?;
+                timeout = Duration::try_from_secs_f64(secs).map_err(|_| "invalid --timeout")?;
+            }
+            "--force" => force = true,
+            "--legacy" => legacy = true,
+            "--json" => json = true,
+            "--show-secrets" => show_secrets = true,
+            "-h" | "--help" => return Ok(Command::Help),
+            other if !other.starts_with('-') && subcommand.as_deref() == Some("ping") && address.is_none() => {
+                address = Some(other.to_string());
+            }
+            other => return Err(format!("unknown argument: {}", other)),
+        }
+    }
+
+    Ok(match subcommand.as_deref() {
+        None | Some("run") => Command::Run { config },
+        Some("ping") => Command::Ping {
+            address: address.ok_or("ping requires <host:port>")?,
+            timeout,
+            legacy,
+            json,
+        },
+        Some("config") => Command::Config { action: action.unwrap(), config, force, show_secrets, json },
+        Some("keygen") => Command::Keygen { out, force, json },
+        _ => Command::Help,
+    })
+}
+
+fn exit_code(error: &ServerError) -> ExitCode {
+    match error {
+        ServerError::Config(_) => ExitCode::from(EXIT_CONFIG),
+        _ => ExitCode::from(EXIT_FAILURE),
+    }
+}
+
+/// 失敗を `--json` なら標準出力に、そうでなければ標準エラーに書く
+fn report_error(json: bool, error: &ServerError) -> ExitCode {
+    if json {
+        println!("{}", json!({ "ok": false, "error": error.to_string() }));
+    } else {
+        eprintln!("{}", error);
+    }
+    exit_code(error)
+}
+
+/// 既存のファイルは検証や表示のために作り直さない
+fn load_existing(path: &Path) -> Result<ServerConfig, ServerError> {
+    if !path.exists() {
+        return Err(ServerError::Config(format!("{} が見つかりません", path.display())));
+    }
+    ServerConfig::load(path)
+}
+
+async fn run(path: &Path) -> ExitCode {
+    let config = match ServerConfig::load(path) {
+        Ok(config) => config,
+        Err(e) => {
+            error!("設定の読み込みに失敗しました: {}", e);
+            return exit_code(&e);
+        }
+    };
+
+    // サーバーの起動
+    if let Err(e) = testServer::run_server_with_path(config, path).await {
+        error!("サーバーの起動に失敗しました: {}", e);
+        return ExitCode::from(EXIT_FAILURE);
+    }
+    ExitCode::SUCCESS
+}
+
+async fn ping(address: &str, timeout: Duration, legacy: bool, json: bool) -> ExitCode {
+    let result = if legacy {
+        testServer::ping_legacy(address, timeout).await
+    } else {
+        testServer::ping_server(address, timeout).await
+    };
+    let (status, latency) = match result {
+        Ok(result) => result,
+        Err(e) => return report_error(json, &e),
+    };
+    let latency_ms = latency.as_secs_f64() * 1000.0;
+    if json {
+        println!("{}", json!({ "ok": true, "address": address, "latency_ms": latency_ms, "status": status }));
+    } else {
+        println!("{} ({:.1} ms)", address, latency_ms);
+        println!("  version: {} (protocol {})", status.version.name, status.version.protocol);
+        println!("  players: {}/{}", status.players.online, status.players.max);
+        for player in &status.players.sample {
+            println!("           {}", player.name);
+        }
+        println!("  motd:    {}", status.description.plain_text());
+        if let Some(favicon) = &status.favicon {
+            println!("  favicon: {} bytes", favicon.len());
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+fn config_command(action: ConfigAction, path: &Path, force: bool, show_secrets: bool, json: bool) -> ExitCode {
+    let result = match action {
+        ConfigAction::Init if path.exists() && !force => {
+            Err(ServerError::Config(format!("{} は既に存在します (--force で上書き)", path.display())))
+        }
+        ConfigAction::Init => ServerConfig::default().write_to(path).map(|_| {
+            if json {
+                println!("{}", json!({ "ok": true, "path": path }));
+            } else {
+                println!("Wrote the default configuration to {}", path.display());
+            }
+        }),
+        ConfigAction::Validate => load_existing(path).map(|_| {
+            if json {
+                println!("{}", json!({ "ok": true, "path": path }));
+            } else {
+                println!("{} is valid", path.display());
+            }
+        }),
+        ConfigAction::Print => load_existing(path).and_then(|config| {
+            // 標準出力は CI のログなどに残るので、明示されない限り秘密の値は伏せる
+            let config = if show_secrets { config } else { config.redacted() };
+            if json {
+                let value = serde_json::to_value(&config).map_err(|e| ServerError::Config(e.to_string()))?;
+                println!("{}", value);
+            } else {
+                print!("{}", config.to_toml_string()?);
+            }
+            Ok(())
+        }),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => report_error(json, &e),
+    }
+}
+
+/// 秘密鍵を所有者だけが読めるファイルに書く。`force` でなければ既存のファイルは上書きしない
+fn write_private_key(path: &Path, pem: &str, force: bool) -> std::io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true);
+    if force {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    // 上書きした既存のファイルには mode が効かないので、書く前に権限を絞る
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(std::fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(pem.as_bytes())
+}
+
+fn keygen(out: &Path, force: bool, json: bool) -> ExitCode {
+    if out.exists() && !force {
+        let message = format!("{} は既に存在します (--force で上書き)", out.display());
+        let error = ServerError::Io(std::io::Error::new(std::io::ErrorKind::AlreadyExists, message));
+        return report_error(json, &error);
+    }
+    let result = EncryptionKeyPair::generate().and_then(|keypair| keypair.private_key_pem())
+        .and_then(|pem| write_private_key(out, &pem, force).map_err(ServerError::from));
+    if let Err(e) = result {
+        return report_error(json, &e);
+    }
+    if json {
+        println!("{}", json!({ "ok": true, "path": out }));
+    } else {
+        println!("Wrote a new RSA key to {}", out.display());
+    }
+    ExitCode::SUCCESS
+}
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn error::Error>> {
+async fn main() -> ExitCode {
     // ロギングの初期化
     env_logger::init();
 
-    // 設定の読み込み
-    let config = testServer::ServerConfig::default();
+    let command = match parse_args(std::env::args().skip(1)) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            return ExitCode::from(EXIT_USAGE);
+        }
+    };
 
-    // サーバーの起動
-    if let Err(e) = testServer::run_server(config).await {
-        error!("サーバーの起動に失敗しました: {}", e);
-        return Err(e.into());
+    match command {
+        Command::Run { config } => run(&config).await,
+        Command::Ping { address, timeout, legacy, json } => ping(&address, timeout, legacy, json).await,
+        Command::Config { action, config, force, show_secrets, json } => config_command(action, &config, force, show_secrets, json),
+        Command::Keygen { out, force, json } => keygen(&out, force, json),
+        Command::Help => {
+            println!("{}", USAGE);
+            ExitCode::SUCCESS
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(parse(&[]), Ok(Command::Run { config: DEFAULT_CONFIG_PATH.into() }));
+        assert_eq!(parse(&["-c", "a.toml"]), Ok(Command::Run { config: "a.toml".into() }));
+        assert_eq!(
+            parse(&["ping", "example.com:25565", "--json", "--timeout", "1.5"]),
+            Ok(Command::Ping {
+                address: "example.com:25565".to_string(),
+                timeout: Duration::from_millis(1500),
+                legacy: false,
+                json: true,
+            })
+        );
+        assert_eq!(
+            parse(&["config", "validate", "--config", "server.properties"]),
+            Ok(Command::Config {
+                action: ConfigAction::Validate,
+                config: "server.properties".into(),
+                force: false,
+                show_secrets: false,
+                json: false,
+            })
+        );
+        assert_eq!(
+            parse(&["config", "print", "--show-secrets", "--json"]),
+            Ok(Command::Config {
+                action: ConfigAction::Print,
+                config: DEFAULT_CONFIG_PATH.into(),
+                force: false,
+                show_secrets: true,
+                json: true,
+            })
+        );
+        assert_eq!(
+            parse(&["keygen", "--out", "key.pem", "--force"]),
+            Ok(Command::Keygen { out: "key.pem".into(), force: true, json: false })
+        );
+
+        assert!(parse(&["ping"]).is_err());
+        assert!(parse(&["config"]).is_err());
+        assert!(parse(&["config", "delete"]).is_err());
+        assert!(parse(&["run", "extra"]).is_err());
+    }
+
+    #[test]
+    fn test_parse_args_rejects_unused_flags() {
+        assert_eq!(parse(&["run", "--json"]), Err("run does not accept --json".to_string()));
+        assert_eq!(parse(&["--force"]), Err("run does not accept --force".to_string()));
+        assert_eq!(parse(&["ping", "example.com", "--force"]), Err("ping does not accept --force".to_string()));
+        assert_eq!(parse(&["ping", "example.com", "-c", "a.toml"]), Err("ping does not accept -c".to_string()));
+        assert_eq!(parse(&["keygen", "--legacy"]), Err("keygen does not accept --legacy".to_string()));
+        assert_eq!(parse(&["config", "validate", "--force"]), Err("config validate does not accept --force".to_string()));
+        assert_eq!(parse(&["config", "init", "--show-secrets"]), Err("config init does not accept --show-secrets".to_string()));
+        assert_eq!(parse(&["help", "--json"]), Err("help does not accept --json".to_string()));
+        assert!(parse(&["config", "init", "--force"]).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_private_key_is_owner_only() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = std::env::temp_dir().join(format!("server-keygen-test-{}.pem", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        std::fs::write(&path, "old").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+
+        // --force なしでは既存のファイルを上書きしない
+        assert_eq!(write_private_key(&path, "pem", false).unwrap_err().kind(), std::io::ErrorKind::AlreadyExists);
+        write_private_key(&path, "pem", true).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "pem");
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+
+        std::fs::remove_file(&path).unwrap();
+        write_private_key(&path, "pem", false).unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        std::fs::remove_file(&path).unwrap();
     }
-3
-    Ok(())
 }
-//github commit test case 2 確認用コメント
@@ -0,0 +1,469 @@
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use chrono::{DateTime, FixedOffset, Local};
+use log::error;
+use parking_lot::RwLock;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::net::error::{Result, ServerError};
+use crate::utils::write_atomic;
+
+// ホワイトリスト・BAN・OP
+//
+// vanilla と同じ JSON ファイルをそのまま読み書きする。オンラインモードでは vanilla と同じく
+// UUID だけで同じプレイヤーか判定する。名前は変えられるので、名前で一致させると改名で BAN を逃れたり、
+// 空いた名前を取った別人が OP になったりする。オフラインモードでは UUID が名前から決まるので、
+// 大文字小文字を区別しない名前か UUID のどちらかが一致すれば同じプレイヤーとみなす。
+
+pub const WHITELIST_FILE: &str = "whitelist.json";
+pub const OPS_FILE: &str = "ops.json";
+pub const BANNED_PLAYERS_FILE: &str = "banned-players.json";
+pub const BANNED_IPS_FILE: &str = "banned-ips.json";
+/// ログインしたプレイヤーの名前と UUID。オフラインのプレイヤーをコマンドで指定したときに引く
+pub const USER_CACHE_FILE: &str = "usercache.json";
+
+/// BAN の理由を省略したときの値
+pub const DEFAULT_BAN_REASON: &str = "Banned by an operator.";
+/// BAN されたプレイヤーへの切断理由の1行目
+pub const BANNED_PLAYER_HEADLINE: &str = "You are banned from this server.";
+pub const BANNED_IP_HEADLINE: &str = "Your IP address is banned from this server.";
+/// OP に付与する既定の権限レベル
+pub const DEFAULT_OP_LEVEL: u8 = 4;
+
+/// `whitelist.json` のエントリ
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UserEntry {
+    pub uuid: Uuid,
+    pub name: String,
+}
+
+/// `ops.json` のエントリ
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OpEntry {
+    pub uuid: Uuid,
+    pub name: String,
+    pub level: u8,
+    #[serde(rename = "bypassesPlayerLimit")]
+    pub bypasses_player_limit: bool,
+}
+
+/// BAN の共通項目。`expires` が `None` なら無期限 (`"forever"`)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Ban {
+    #[serde(with = "date")]
+    pub created: DateTime<FixedOffset>,
+    pub source: String,
+    #[serde(with = "expiry")]
+    pub expires: Option<DateTime<FixedOffset>>,
+    pub reason: String,
+}
+
+/// `banned-players.json` のエントリ
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlayerBan {
+    pub uuid: Uuid,
+    pub name: String,
+    #[serde(flatten)]
+    pub ban: Ban,
+}
+
+/// `banned-ips.json` のエントリ
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IpBan {
+    pub ip: IpAddr,
+    #[serde(flatten)]
+    pub ban: Ban,
+}
+
+impl Ban {
+    /// 今作られた BAN。`reason` が空なら既定の理由になる
+    pub fn new(source: &str, reason: &str, expires: Option<DateTime<FixedOffset>>) -> Self {
+        Self {
+            created: Local::now().fixed_offset(),
+            source: source.to_string(),
+            expires,
+            reason: if reason.is_empty() { DEFAULT_BAN_REASON.to_string() } else { reason.to_string() },
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires.is_some_and(|expires| expires <= Local::now())
+    }
+
+    /// ログイン時や BAN した時に見せる切断理由
+    pub fn kick_message(&self, headline: &str) -> String {
+        let mut message = format!("{}\nReason: {}", headline, self.reason);
+        if let Some(expires) = self.expires {
+            message.push_str(&format!("\nYour ban will be removed on {}", expires.format(date::FORMAT)));
+        }
+        message
+    }
+}
+
+
+/// ホワイトリスト・OP・BAN の一覧。変更はすぐにファイルへ書き出す
+pub struct AccessLists {
+    directory: PathBuf,
+    whitelist: RwLock<Vec<UserEntry>>,
+    ops: RwLock<Vec<OpEntry>>,
+    banned_players: RwLock<Vec<PlayerBan>>,
+    banned_ips: RwLock<Vec<IpBan>>,
+    user_cache: RwLock<Vec<UserEntry>>,
+    /// UUID だけで照合するか。認証の設定に合わせて [`AccessLists::set_online_mode`] で切り替える
+    online_mode: AtomicBool,
+}
+
+struct Loaded {
+    whitelist: Vec<UserEntry>,
+    ops: Vec<OpEntry>,
+    banned_players: Vec<PlayerBan>,
+    banned_ips: Vec<IpBan>,
+}
+
+impl AccessLists {
+    /// `directory` にあるファイルを読み込む。読めないファイルはログに残して空の一覧にする
+    pub fn load(directory: impl Into<PathBuf>) -> Self {
+        let directory = directory.into();
+        Self {
+            whitelist: RwLock::new(load_or_log(&directory, WHITELIST_FILE)),
+            ops: RwLock::new(load_or_log(&directory, OPS_FILE)),
+            banned_players: RwLock::new(load_or_log(&directory, BANNED_PLAYERS_FILE)),
+            banned_ips: RwLock::new(load_or_log(&directory, BANNED_IPS_FILE)),
+            user_cache: RwLock::new(load_or_log(&directory, USER_CACHE_FILE)),
+            online_mode: AtomicBool::new(false),
+            directory,
+        }
+    }
+
+    pub fn set_online_mode(&self, online_mode: bool) {
+        self.online_mode.store(online_mode, Ordering::Relaxed);
+    }
+
+    pub fn online_mode(&self) -> bool {
+        self.online_mode.load(Ordering::Relaxed)
+    }
+
+    fn same_player(&self, uuid: &Uuid, name: &str, other_uuid: &Uuid, other_name: &str) -> bool {
+        uuid == other_uuid || (!self.online_mode() && name.eq_ignore_ascii_case(other_name))
+    }
+
+    /// ログインしたプレイヤーを覚えておく。改名していれば古い名前を置き換える
+    pub fn remember(&self, uuid: Uuid, name: &str) -> Result<()> {
+        let mut cache = self.user_cache.write();
+        if cache.iter().any(|entry| entry.uuid == uuid && entry.name == name) {
+            return Ok(());
+        }
+        cache.retain(|entry| entry.uuid != uuid && !entry.name.eq_ignore_ascii_case(name));
+        cache.push(UserEntry { uuid, name: name.to_string() });
+        self.save(USER_CACHE_FILE, &cache)
+    }
+
+    /// 過去にログインしたプレイヤーを名前で引く
+    pub fn cached_player(&self, name: &str) -> Option<UserEntry> {
+        self.user_cache.read().iter().find(|entry| entry.name.eq_ignore_ascii_case(name)).cloned()
+    }
+
+    /// 全ファイルを読み直す。1つでも読めなければ何も変えない
+    pub fn reload(&self) -> Result<()> {
+        let loaded = Loaded {
+            whitelist: load_list(&self.directory.join(WHITELIST_FILE))?,
+            ops: load_list(&self.directory.join(OPS_FILE))?,
+            banned_players: load_list(&self.directory.join(BANNED_PLAYERS_FILE))?,
+            banned_ips: load_list(&self.directory.join(BANNED_IPS_FILE))?,
+        };
+        *self.whitelist.write() = loaded.whitelist;
+        *self.ops.write() = loaded.ops;
+        *self.banned_players.write() = loaded.banned_players;
+        *self.banned_ips.write() = loaded.banned_ips;
+        Ok(())
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// ログインを許可するか判定する。拒否する場合は切断理由を返す
+    pub fn check_login(&self, uuid: &Uuid, name: &str, ip: IpAddr, whitelist_enabled: bool) -> std::result::Result<(), String> {
+        if let Some(entry) = self.banned_players.read().iter()
+            .find(|entry| self.same_player(uuid, name, &entry.uuid, &entry.name) && !entry.ban.is_expired())
+        {
+            return Err(entry.ban.kick_message(BANNED_PLAYER_HEADLINE));
+        }
+        if whitelist_enabled && !self.is_whitelisted(uuid, name) && !self.is_op(uuid, name) {
+            return Err("You are not white-listed on this server!".to_string());
+        }
+        if let Some(entry) = self.banned_ips.read().iter().find(|entry| entry.ip == ip && !entry.ban.is_expired()) {
+            return Err(entry.ban.kick_message(BANNED_IP_HEADLINE));
+        }
+        Ok(())
+    }
+
+    pub fn is_whitelisted(&self, uuid: &Uuid, name: &str) -> bool {
+        self.whitelist.read().iter().any(|entry| self.same_player(uuid, name, &entry.uuid, &entry.name))
+    }
+
+    pub fn is_op(&self, uuid: &Uuid, name: &str) -> bool {
+        self.op_entry(uuid, name).is_some()
+    }
+
+    pub fn op_entry(&self, uuid: &Uuid, name: &str) -> Option<OpEntry> {
+        self.ops.read().iter().find(|entry| self.same_player(uuid, name, &entry.uuid, &entry.name)).cloned()
+    }
+
+    pub fn whitelist(&self) -> Vec<UserEntry> {
+        self.whitelist.read().clone()
+    }
+
+    pub fn ops(&self) -> Vec<OpEntry> {
+        self.ops.read().clone()
+    }
+
+    /// 期限切れを除いた BAN の一覧
+    pub fn banned_players(&self) -> Vec<PlayerBan> {
+        self.banned_players.read().iter().filter(|entry| !entry.ban.is_expired()).cloned().collect()
+    }
+
+    pub fn banned_ips(&self) -> Vec<IpBan> {
+        self.banned_ips.read().iter().filter(|entry| !entry.ban.is_expired()).cloned().collect()
+    }
+
+    /// 追加した場合は `true`。既に載っていれば何もしない
+    pub fn add_to_whitelist(&self, entry: UserEntry) -> Result<bool> {
+        let mut list = self.whitelist.write();
+        if list.iter().any(|existing| self.same_player(&entry.uuid, &entry.name, &existing.uuid, &existing.name)) {
+            return Ok(false);
+        }
+        list.push(entry);
+        self.save(WHITELIST_FILE, &list)?;
+        Ok(true)
+    }
+
+    pub fn remove_from_whitelist(&self, name: &str) -> Result<bool> {
+        let mut list = self.whitelist.write();
+        self.remove_where(WHITELIST_FILE, &mut list, |entry| entry.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn op(&self, entry: OpEntry) -> Result<bool> {
+        let mut list = self.ops.write();
+        if list.iter().any(|existing| self.same_player(&entry.uuid, &entry.name, &existing.uuid, &existing.name)) {
+            return Ok(false);
+        }
+        list.push(entry);
+        self.save(OPS_FILE, &list)?;
+        Ok(true)
+    }
+
+    pub fn deop(&self, name: &str) -> Result<bool> {
+        let mut list = self.ops.write();
+        self.remove_where(OPS_FILE, &mut list, |entry| entry.name.eq_ignore_ascii_case(name))
+    }
+
+    /// BAN する。既存の BAN は置き換える
+    pub fn ban(&self, entry: PlayerBan) -> Result<()> {
+        let mut list = self.banned_players.write();
+        list.retain(|existing| !self.same_player(&entry.uuid, &entry.name, &existing.uuid, &existing.name) && !existing.ban.is_expired());
+        list.push(entry);
+        self.save(BANNED_PLAYERS_FILE, &list)
+    }
+
+    pub fn pardon(&self, name: &str) -> Result<bool> {
+        let mut list = self.banned_players.write();
+        self.remove_where(BANNED_PLAYERS_FILE, &mut list, |entry| entry.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn ban_ip(&self, entry: IpBan) -> Result<()> {
+        let mut list = self.banned_ips.write();
+        list.retain(|existing| existing.ip != entry.ip && !existing.ban.is_expired());
+        list.push(entry);
+        self.save(BANNED_IPS_FILE, &list)
+    }
+
+    pub fn pardon_ip(&self, ip: IpAddr) -> Result<bool> {
+        let mut list = self.banned_ips.write();
+        self.remove_where(BANNED_IPS_FILE, &mut list, |entry| entry.ip == ip)
+    }
+
+    fn remove_where<T: Serialize>(&self, file: &str, list: &mut Vec<T>, matches: impl Fn(&T) -> bool) -> Result<bool> {
+        let before = list.len();
+        list.retain(|entry| !matches(entry));
+        if list.len() == before {
+            return Ok(false);
+        }
+        self.save(file, list)?;
+        Ok(true)
+    }
+
+    fn save<T: Serialize>(&self, file: &str, list: &[T]) -> Result<()> {
+        save_list(&self.directory.join(file), list)
+    }
+}
+
+/// JSON にして [`write_atomic`] で保存する
+pub(crate) fn save_list<T: Serialize>(path: &Path, list: &[T]) -> Result<()> {
+    let json = serde_json::to_string_pretty(list)
+        .map_err(|e| ServerError::Io(io::Error::new(io::ErrorKind::InvalidData, e)))?;
+    write_atomic(path, json.as_bytes())
+}
+
+/// ファイルがなければ空の一覧
+pub(crate) fn load_list<T: DeserializeOwned>(path: &Path) -> Result<Vec<T>> {
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    if text.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    serde_json::from_str(&text).map_err(|e| ServerError::Config(format!("{}: {}", path.display(), e)))
+}
+
+fn load_or_log<T: DeserializeOwned>(directory: &Path, file: &str) -> Vec<T> {
+    load_list(&directory.join(file)).unwrap_or_else(|e| {
+        error!("Failed to load {}, starting with an empty list: {}", file, e);
+        Vec::new()
+    })
+}
+
+/// vanilla の日時形式 (`2024-01-31 12:00:00 +0900`)
+mod date {
+    use chrono::{DateTime, FixedOffset};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub const FORMAT: &str = "%Y-%m-%d %H:%M:%S %z";
+
+    pub fn serialize<S: Serializer>(value: &DateTime<FixedOffset>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&value.format(FORMAT).to_string())
+    }
+
+    pub fn parse(value: &str) -> Result<DateTime<FixedOffset>, chrono::ParseError> {
+        DateTime::parse_from_str(value, FORMAT)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<FixedOffset>, D::Error> {
+        parse(&String::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+    }
+}
+
+/// 期限。無期限は `"forever"`
+mod expiry {
+    use chrono::{DateTime, FixedOffset};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    const FOREVER: &str = "forever";
+
+    pub fn serialize<S: Serializer>(value: &Option<DateTime<FixedOffset>>, serializer: S) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => super::date::serialize(value, serializer),
+            None => serializer.serialize_str(FOREVER),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<DateTime<FixedOffset>>, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        if value == FOREVER {
+            return Ok(None);
+        }
+        super::date::parse(&value).map(Some).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("access-test-{}", Uuid::new_v4()))
+    }
+
+    #[test]
+    fn test_vanilla_files() {
+        let dir = temp_dir();
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(BANNED_PLAYERS_FILE), r#"[
+            {"uuid": "069a79f4-44e9-4726-a5be-fca90e38aaf5", "name": "Notch", "created": "2024-01-31 12:00:00 +0900",
+             "source": "Server", "expires": "forever", "reason": "Griefing"},
+            {"uuid": "853c80ef-3c37-49fd-aa49-938b674adae6", "name": "jeb_", "created": "2020-01-01 00:00:00 +0000",
+             "source": "Console", "expires": "2020-01-02 00:00:00 +0000", "reason": "Old"}
+        ]"#).unwrap();
+        fs::write(dir.join(OPS_FILE), r#"[{"uuid": "853c80ef-3c37-49fd-aa49-938b674adae6", "name": "jeb_", "level": 4, "bypassesPlayerLimit": false}]"#).unwrap();
+
+        let lists = AccessLists::load(&dir);
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let notch = lists.check_login(&Uuid::new_v4(), "notch", ip, false).unwrap_err();
+        assert!(notch.contains("Reason: Griefing"), "{}", notch);
+        // 期限切れの BAN は効かない
+        assert!(lists.check_login(&Uuid::new_v4(), "jeb_", ip, false).is_ok());
+        assert_eq!(lists.banned_players().len(), 1);
+
+        // ホワイトリストは OP も通す
+        assert!(lists.check_login(&Uuid::new_v4(), "Alex", ip, true).is_err());
+        assert!(lists.check_login(&Uuid::new_v4(), "jeb_", ip, true).is_ok());
+
+        let written: serde_json::Value = serde_json::from_str(&fs::read_to_string(dir.join(BANNED_PLAYERS_FILE)).unwrap()).unwrap();
+        assert_eq!(written[0]["created"], "2024-01-31 12:00:00 +0900");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_changes_are_persisted() {
+        let dir = temp_dir();
+        let lists = AccessLists::load(&dir);
+        let ip: IpAddr = "192.168.0.10".parse().unwrap();
+        let alex = UserEntry { uuid: Uuid::new_v4(), name: "Alex".to_string() };
+
+        assert!(lists.add_to_whitelist(alex.clone()).unwrap());
+        assert!(!lists.add_to_whitelist(alex.clone()).unwrap());
+        lists.ban_ip(IpBan { ip, ban: Ban::new("Console", "", Some(Local::now().fixed_offset() + Duration::hours(1))) }).unwrap();
+        let message = lists.check_login(&alex.uuid, "Alex", ip, true).unwrap_err();
+        assert!(message.contains(DEFAULT_BAN_REASON) && message.contains("will be removed on"), "{}", message);
+
+        let reloaded = AccessLists::load(&dir);
+        assert_eq!(reloaded.whitelist(), vec![alex]);
+        assert_eq!(reloaded.banned_ips().len(), 1);
+        assert!(reloaded.pardon_ip(ip).unwrap());
+        assert!(!reloaded.pardon_ip(ip).unwrap());
+        assert!(reloaded.remove_from_whitelist("ALEX").unwrap());
+
+        lists.reload().unwrap();
+        assert!(lists.whitelist().is_empty());
+        assert!(lists.check_login(&Uuid::new_v4(), "Alex", ip, false).is_ok());
+        assert!(!dir.join(format!("{}.tmp", WHITELIST_FILE)).exists());
+
+        fs::write(dir.join(OPS_FILE), "not json").unwrap();
+        assert!(matches!(lists.reload(), Err(ServerError::Config(_))));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_online_mode_matches_by_uuid() {
+        let dir = temp_dir();
+        let lists = AccessLists::load(&dir);
+        lists.set_online_mode(true);
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let notch = Uuid::new_v4();
+        let jeb = Uuid::new_v4();
+
+        lists.ban(PlayerBan { uuid: notch, name: "Notch".to_string(), ban: Ban::new("Server", "Griefing", None) }).unwrap();
+        lists.op(OpEntry { uuid: jeb, name: "jeb_".to_string(), level: DEFAULT_OP_LEVEL, bypasses_player_limit: false }).unwrap();
+        // 改名しても BAN は残り、空いた名前を取った別のアカウントは OP にならない
+        assert!(lists.check_login(&notch, "Renamed", ip, false).is_err());
+        assert!(lists.check_login(&Uuid::new_v4(), "Notch", ip, false).is_ok());
+        assert!(lists.is_op(&jeb, "jeb_renamed"));
+        assert!(!lists.is_op(&Uuid::new_v4(), "jeb_"));
+
+        // 改名すると古い名前では引けなくなる
+        lists.remember(notch, "Notch").unwrap();
+        lists.remember(notch, "Renamed").unwrap();
+        assert!(lists.cached_player("notch").is_none());
+        assert_eq!(AccessLists::load(&dir).cached_player("renamed").map(|entry| entry.uuid), Some(notch));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
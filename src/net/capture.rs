@@ -0,0 +1,375 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+
+use bytes::{Buf, BufMut, BytesMut};
+use log::{info, warn};
+use parking_lot::RwLock;
+
+use crate::net::login::success::LoginSuccess;
+use crate::net::protocol::codec::MAX_FRAME_LEN;
+use crate::net::protocol::handshake::HandshakePacket;
+use crate::net::protocol::registry::packet_name;
+use crate::net::protocol::{Direction, Packet, PacketState};
+use crate::utils::config::CaptureConfig;
+use crate::varint::utils::{read_string, read_varint_from, write_varint};
+
+// パケットキャプチャ
+//
+// 復号・展開後のフレームを1件ずつ記録する。ファイル形式:
+//
+// header: b"MCCAP" | u8 version | varint len + UTF-8 peer address
+// record: i64 BE timestamp (UNIX epoch からのマイクロ秒) | u8 direction | u8 state
+//         | varint packet id | varint len + data
+
+const MAGIC: &[u8; 5] = b"MCCAP";
+const VERSION: u8 = 1;
+
+/// キャプチャ対象のIPアドレスとユーザー名。コンソールから切り替えられる
+pub struct CaptureTargets {
+    directory: PathBuf,
+    ips: RwLock<HashSet<IpAddr>>,
+    usernames: RwLock<HashSet<String>>,
+}
+
+impl CaptureTargets {
+    pub fn new(config: &CaptureConfig) -> Self {
+        let ips = config.ips.iter()
+            .filter_map(|ip| match ip.parse() {
+                Ok(ip) => Some(ip),
+                Err(_) => {
+                    warn!("Ignoring invalid capture IP address: {}", ip);
+                    None
+                }
+            })
+            .collect();
+        let usernames = config.usernames.iter().map(|name| name.to_lowercase()).collect();
+
+        Self {
+            directory: PathBuf::from(&config.directory),
+            ips: RwLock::new(ips),
+            usernames: RwLock::new(usernames),
+        }
+    }
+
+    pub fn matches_ip(&self, ip: &IpAddr) -> bool {
+        self.ips.read().contains(ip)
+    }
+
+    pub fn matches_username(&self, username: &str) -> bool {
+        self.usernames.read().contains(&username.to_lowercase())
+    }
+
+    /// IPアドレスのキャプチャを切り替える。変更があれば `true`
+    pub fn set_ip(&self, ip: IpAddr, enabled: bool) -> bool {
+        let mut ips = self.ips.write();
+        if enabled { ips.insert(ip) } else { ips.remove(&ip) }
+    }
+
+    /// ユーザー名のキャプチャを切り替える。変更があれば `true`
+    pub fn set_username(&self, username: &str, enabled: bool) -> bool {
+        let mut usernames = self.usernames.write();
+        let username = username.to_lowercase();
+        if enabled { usernames.insert(username) } else { usernames.remove(&username) }
+    }
+
+    pub fn list(&self) -> (Vec<IpAddr>, Vec<String>) {
+        let mut ips: Vec<_> = self.ips.read().iter().copied().collect();
+        let mut usernames: Vec<_> = self.usernames.read().iter().cloned().collect();
+        ips.sort();
+        usernames.sort();
+        (ips, usernames)
+    }
+
+    /// キャプチャファイルを作成する
+    pub fn open(&self, peer: &SocketAddr, label: Option<&str>) -> io::Result<CaptureWriter> {
+        fs::create_dir_all(&self.directory)?;
+        let timestamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+        let peer_label = peer.to_string().replace([':', '[', ']'], "_");
+        let name = match label {
+            Some(label) => format!("{}_{}_{}.mccap", timestamp, label, peer_label),
+            None => format!("{}_{}.mccap", timestamp, peer_label),
+        };
+        let path = self.directory.join(name);
+        info!("Capturing packets of {} to {}", peer, path.display());
+        CaptureWriter::create(&path, peer)
+    }
+}
+
+/// 1フレーム分のキャプチャ記録
+#[derive(Debug, Clone)]
+pub struct CaptureRecord {
+    pub timestamp_micros: i64,
+    pub direction: Direction,
+    pub state: PacketState,
+    pub packet_id: i32,
+    pub data: Vec<u8>,
+}
+
+impl CaptureRecord {
+    pub fn new(direction: Direction, state: PacketState, packet_id: i32, data: &[u8]) -> Self {
+        Self {
+            timestamp_micros: chrono::Utc::now().timestamp_micros(),
+            direction,
+            state,
+            packet_id,
+            data: data.to_vec(),
+        }
+    }
+
+    pub fn name(&self) -> Option<&'static str> {
+        packet_name(self.state, self.direction, self.packet_id)
+    }
+
+    /// 既知のパケットであれば中身を解釈して表示用の文字列を返す
+    pub fn describe(&self) -> Option<String> {
+        let mut data = BytesMut::from(&self.data[..]);
+        match (self.state, self.direction, self.packet_id) {
+            (PacketState::Handshake, Direction::Serverbound, 0x00) => {
+                HandshakePacket::decode(&mut data).ok().map(|packet| format!("{:?}", packet))
+            }
+            (PacketState::Status, Direction::Clientbound, 0x00)
+            | (PacketState::Login, Direction::Serverbound, 0x00)
+            | (PacketState::Login, Direction::Clientbound, 0x00) => read_string(&mut data).ok(),
+            (PacketState::Login, Direction::Clientbound, 0x02) => {
+                LoginSuccess::decode(&mut data).ok().map(|packet| format!("{:?}", packet))
+            }
+            _ => None,
+        }
+    }
+}
+
+pub struct CaptureWriter {
+    out: BufWriter<File>,
+}
+
+impl CaptureWriter {
+    pub fn create(path: &Path, peer: &SocketAddr) -> io::Result<Self> {
+        let mut out = BufWriter::new(File::create(path)?);
+        let mut header = BytesMut::new();
+        header.put_slice(MAGIC);
+        header.put_u8(VERSION);
+        let peer = peer.to_string();
+        write_varint(&mut header, peer.len() as i32)?;
+        header.put_slice(peer.as_bytes());
+        out.write_all(&header)?;
+        Ok(Self { out })
+    }
+
+    pub fn write(&mut self, record: &CaptureRecord) -> io::Result<()> {
+        let mut buf = BytesMut::with_capacity(record.data.len() + 16);
+        buf.put_i64(record.timestamp_micros);
+        buf.put_u8(record.direction as u8);
+        buf.put_u8(record.state as u8);
+        write_varint(&mut buf, record.packet_id)?;
+        write_varint(&mut buf, record.data.len() as i32)?;
+        buf.put_slice(&record.data);
+        self.out.write_all(&buf)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// キャプチャファイルの読み取り
+pub struct CaptureReader {
+    input: BufReader<File>,
+    pub peer: String,
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// 長さの varint を読む。壊れたファイルで巨大な領域を確保しないよう、フレームの上限を超える長さは拒否する
+fn read_len(input: &mut impl Read) -> io::Result<usize> {
+    let len = read_varint_from(input)?;
+    match usize::try_from(len) {
+        Ok(len) if len <= MAX_FRAME_LEN => Ok(len),
+        _ => Err(invalid("invalid length")),
+    }
+}
+
+impl CaptureReader {
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let mut input = BufReader::new(File::open(path)?);
+        let mut magic = [0u8; 6];
+        input.read_exact(&mut magic)?;
+        if &magic[..5] != MAGIC || magic[5] != VERSION {
+            return Err(invalid("not a capture file"));
+        }
+        let len = read_len(&mut input)?;
+        let mut peer = vec![0u8; len];
+        input.read_exact(&mut peer)?;
+        let peer = String::from_utf8(peer).map_err(|_| invalid("invalid peer address"))?;
+        Ok(Self { input, peer })
+    }
+
+    /// 次の記録を読む。ファイル末尾なら `None`
+    pub fn next_record(&mut self) -> io::Result<Option<CaptureRecord>> {
+        let mut fixed = [0u8; 10];
+        match self.input.read_exact(&mut fixed) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+        let mut fixed = &fixed[..];
+        let timestamp_micros = fixed.get_i64();
+        let direction = Direction::from_u8(fixed.get_u8()).ok_or_else(|| invalid("invalid direction"))?;
+        let state = PacketState::from_i32(fixed.get_u8() as i32).ok_or_else(|| invalid("invalid state"))?;
+        let packet_id = read_varint_from(&mut self.input)?;
+        let len = read_len(&mut self.input)?;
+        let mut data = vec![0u8; len];
+        self.input.read_exact(&mut data)?;
+
+        Ok(Some(CaptureRecord { timestamp_micros, direction, state, packet_id, data }))
+    }
+}
+
+impl Iterator for CaptureReader {
+    type Item = io::Result<CaptureRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_record().transpose()
+    }
+}
+
+/// コネクションごとのキャプチャ状態
+///
+/// ユーザー名が分かるまではフレームを保留し、対象であればまとめて書き出す。
+pub enum CaptureSession {
+    Pending(Vec<CaptureRecord>),
+    Active(CaptureWriter),
+    Disabled,
+}
+
+impl CaptureSession {
+    pub fn start(targets: &CaptureTargets, peer: &SocketAddr) -> Self {
+        if targets.matches_ip(&peer.ip()) {
+            match targets.open(peer, None) {
+                Ok(writer) => return CaptureSession::Active(writer),
+                Err(e) => warn!("Failed to open capture file for {}: {}", peer, e),
+            }
+        }
+        CaptureSession::Pending(Vec::new())
+    }
+
+    /// 記録する可能性があるか。`false` ならレコードを作る必要もない
+    pub fn is_recording(&self) -> bool {
+        !matches!(self, CaptureSession::Disabled)
+    }
+
+    pub fn record(&mut self, record: CaptureRecord) {
+        match self {
+            CaptureSession::Pending(records) => records.push(record),
+            CaptureSession::Active(writer) => {
+                if let Err(e) = writer.write(&record) {
+                    warn!("Failed to write capture record: {}", e);
+                    *self = CaptureSession::Disabled;
+                }
+            }
+            CaptureSession::Disabled => {}
+        }
+    }
+
+    /// ユーザー名が判明した時点で呼ぶ。対象外なら以後は記録しない
+    pub fn identify(&mut self, targets: &CaptureTargets, peer: &SocketAddr, username: &str) {
+        let CaptureSession::Pending(records) = self else {
+            return;
+        };
+        if !targets.matches_username(username) {
+            *self = CaptureSession::Disabled;
+            return;
+        }
+
+        let records = std::mem::take(records);
+        *self = match targets.open(peer, Some(username)) {
+            Ok(writer) => CaptureSession::Active(writer),
+            Err(e) => {
+                warn!("Failed to open capture file for {}: {}", username, e);
+                CaptureSession::Disabled
+            }
+        };
+        for record in records {
+            self.record(record);
+        }
+    }
+}
+
+impl Drop for CaptureSession {
+    fn drop(&mut self) {
+        if let CaptureSession::Active(writer) = self {
+            if let Err(e) = writer.flush() {
+                warn!("Failed to flush capture file: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capture_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("mccap-test-{}", uuid::Uuid::new_v4()));
+        let config = CaptureConfig {
+            directory: dir.to_string_lossy().into_owned(),
+            ips: vec![],
+            usernames: vec!["Steve".to_string()],
+        };
+        let targets = CaptureTargets::new(&config);
+        let peer: SocketAddr = "127.0.0.1:50000".parse().unwrap();
+
+        let mut session = CaptureSession::start(&targets, &peer);
+        session.record(CaptureRecord::new(Direction::Serverbound, PacketState::Handshake, 0x00, &[1, 2, 3]));
+        session.identify(&targets, &peer, "steve");
+        session.record(CaptureRecord::new(Direction::Clientbound, PacketState::Login, 0x02, &[4; 300]));
+        drop(session);
+
+        let path = fs::read_dir(&dir).unwrap().next().unwrap().unwrap().path();
+        let mut reader = CaptureReader::open(&path).unwrap();
+        assert_eq!(reader.peer, "127.0.0.1:50000");
+        let records: Vec<_> = reader.by_ref().map(|r| r.unwrap()).collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].data, vec![1, 2, 3]);
+        assert_eq!(records[1].direction, Direction::Clientbound);
+        assert_eq!(records[1].name(), Some("Login Success"));
+        assert_eq!(records[1].data.len(), 300);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_reader_rejects_bad_lengths() {
+        let path = std::env::temp_dir().join(format!("mccap-bad-{}.mccap", uuid::Uuid::new_v4()));
+        let header = |len: i32| {
+            let mut buf = BytesMut::new();
+            buf.put_slice(MAGIC);
+            buf.put_u8(VERSION);
+            write_varint(&mut buf, len).unwrap();
+            buf
+        };
+
+        // 負の長さと、フレームの上限を超える長さ
+        for len in [-1, MAX_FRAME_LEN as i32 + 1] {
+            fs::write(&path, header(len)).unwrap();
+            assert_eq!(CaptureReader::open(&path).err().unwrap().kind(), io::ErrorKind::InvalidData);
+        }
+
+        let mut buf = header(0);
+        buf.put_i64(0);
+        buf.put_u8(Direction::Serverbound as u8);
+        buf.put_u8(PacketState::Play as u8);
+        write_varint(&mut buf, 0x00).unwrap();
+        write_varint(&mut buf, -1).unwrap();
+        fs::write(&path, buf).unwrap();
+        let mut reader = CaptureReader::open(&path).unwrap();
+        assert_eq!(reader.next_record().unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
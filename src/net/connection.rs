@@ -1,10 +1,33 @@
-use super::status::handle_status;
+use super::access::USER_CACHE_FILE;
+use super::capture::{CaptureRecord, CaptureSession};
+use crate::game::network::{ConnectionId, ServerboundPacket};
+use super::context::ServerContext;
+use super::login::disconnect::LoginDisconnect;
+use super::login::auth::AuthRequest;
+use super::login::encryption::request::EncryptionRequest;
+use super::login::encryption::response::EncryptionResponse;
+use super::login::encryption::{new_verify_token, server_hash, PacketCipher};
+use super::login::start::{is_valid_username, LoginStart};
+use super::protocol::{Direction, Packet, PacketState};
+use super::passwords::{self, GateReply, PasswordGate, TIMEOUT_MESSAGE, TOO_MANY_ATTEMPTS_MESSAGE};
+use super::play::chat::{ChatCommand, SystemChatMessage, CHAT_COMMAND_ID, CHAT_MESSAGE_ID};
+use super::play::disconnect::PlayDisconnect;
+use super::players::{Session, SessionMessage, SessionMessages};
+use super::play::keep_alive::{KeepAlive, SERVERBOUND_KEEP_ALIVE_ID};
+use super::protocol::codec::PacketCodec;
+use super::protocol::handshake::HandshakePacket;
+use super::protocol::types::ProtocolRead;
+use super::status::{handle_status, PongResponse};
+use crate::varint::utils::read_varint;
 use crate::net::error::ServerError;
-use crate::varint::utils::{read_i64, read_varint, write_i64, write_string, write_varint};
-use bytes::{Buf, BytesMut};
+use bytes::BytesMut;
+use log::{info, warn};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
-use uuid::Uuid;
+use tokio::sync::mpsc::Receiver;
 use crate::net::error::Result;
 
 // # Minecraft Server Implementation
@@ -30,114 +53,423 @@ use crate::net::error::Result;
 // }
 // ```
 
-/// コネクションハンドラー
-/// 新しい接続を処理し、適切なプロトコル処理を行います。
+/// 受信したフレーム（パケットIDと本体）
+#[derive(Debug)]
+pub struct Frame {
+    pub id: i32,
+    pub body: BytesMut,
+}
 
-pub async fn handle_connection(mut stream: TcpStream) -> Result<()> {
-    let mut buf = BytesMut::new();
-    let size = stream.read(&mut buf).await.map_err(ServerError::Io)?;
+/// 長さ付きフレームの読み書きを行うコネクション
+pub struct Connection {
+    stream: TcpStream,
+    peer: SocketAddr,
+    buf: BytesMut,
+    /// 送信用バッファ。パケットはここへ直接エンコードし、送信後も領域を使い回す
+    out: BytesMut,
+    codec: PacketCodec,
+    /// Encryption Response を受け取ってから有効になる
+    cipher: Option<PacketCipher>,
+    state: PacketState,
+    /// ハンドシェイクでクライアントが名乗ったプロトコルバージョン
+    protocol_version: i32,
+    capture: CaptureSession,
+    ctx: Arc<ServerContext>,
+}
 
-    if size == 0 {
-        return Ok(());  // 接続が閉じられた
+impl Connection {
+    pub fn new(stream: TcpStream, peer: SocketAddr, ctx: Arc<ServerContext>) -> Self {
+        Self {
+            stream,
+            peer,
+            buf: BytesMut::with_capacity(4096),
+            out: BytesMut::with_capacity(4096),
+            codec: PacketCodec::default(),
+            cipher: None,
+            state: PacketState::Handshake,
+            protocol_version: 0,
+            capture: CaptureSession::start(&ctx.capture, &peer),
+            ctx,
+        }
     }
 
-    let mut data = BytesMut::from(&buf[..size]);
+    pub fn peer(&self) -> SocketAddr {
+        self.peer
+    }
 
-    let _len = read_varint(&mut data)
-        .ok_or_else(|| ServerError::Protocol("Invalid packet length".to_string()))?;
-    let packet_id = read_varint(&mut data)
-        .ok_or_else(|| ServerError::Protocol("Invalid packet ID".to_string()))?;
-    match packet_id {
-        0x00 => {
-            let _protocol_version = read_varint(&mut data)
-                .ok_or_else(|| ServerError::Protocol("Invalid protocol version".to_string()))?;
-            let host_len = read_varint(&mut data).ok_or("Invalid host length")? as usize;
+    pub fn context(&self) -> &Arc<ServerContext> {
+        &self.ctx
+    }
 
-            if data.remaining() < host_len {
-                return Err(ServerError::Protocol("Insufficient data for host".to_string()));
-            }
+    pub fn state(&self) -> PacketState {
+        self.state
+    }
 
-            let _host = String::from_utf8(data.split_to(host_len).to_vec())
-                .map_err(|_| ServerError::Protocol("Invalid UTF-8 in host".to_string()))?;
+    pub fn set_state(&mut self, state: PacketState) {
+        self.state = state;
+    }
 
-            if data.remaining() < 2 {
-                return Err(ServerError::Protocol("Insufficient data for port".to_string()));
-            }
+    pub fn protocol_version(&self) -> i32 {
+        self.protocol_version
+    }
 
-            let _port = ((data.get_u8() as u16) << 8) | data.get_u8() as u16;
-            let next_state = read_varint(&mut data).ok_or("Invalid next state")?;
+    /// ユーザー名が判明したことを通知し、キャプチャ対象か判定する
+    pub fn identify(&mut self, username: &str) {
+        self.capture.identify(&self.ctx.capture, &self.peer, username);
+    }
+
+    /// 以降の送受信を共有鍵で暗号化する
+    pub fn enable_encryption(&mut self, shared_secret: &[u8]) -> Result<()> {
+        self.cipher = Some(PacketCipher::new(shared_secret)?);
+        Ok(())
+    }
 
-            match next_state {
-                1 => {
-                    handle_status(&mut stream).await?;
-                    handle_connection_ping(stream).await?;
+    /// 次のフレームを読む。接続が閉じられた場合は `None`
+    pub async fn read_frame(&mut self) -> Result<Option<Frame>> {
+        loop {
+            if let Some((id, body)) = self.codec.decode_frame(&mut self.buf)? {
+                let frame = Frame { id, body };
+                if self.capture.is_recording() {
+                    self.capture.record(CaptureRecord::new(Direction::Serverbound, self.state, frame.id, &frame.body));
                 }
-                2 => {
-                    handle_connection_login(stream).await?;
+                return Ok(Some(frame));
+            }
+            let start = self.buf.len();
+            if self.stream.read_buf(&mut self.buf).await.map_err(ServerError::Io)? == 0 {
+                if self.buf.is_empty() {
+                    return Ok(None);
                 }
-                _ => return Err("Invalid next state".into()),
+                return Err(ServerError::Protocol("Connection closed mid-packet".to_string()));
+            }
+            if let Some(cipher) = &mut self.cipher {
+                cipher.decrypt(&mut self.buf[start..]);
             }
         }
-        _ => return Err("Invalid initial packet ID".into()),
     }
-    
+
+    /// パケットIDと本体からフレームを組み立てて送信する
+    pub async fn write_frame(&mut self, id: i32, body: &[u8]) -> Result<()> {
+        self.codec.encode_frame(id, body, &mut self.out)?;
+        self.flush().await
+    }
+
+    /// パケットを送信バッファへ直接エンコードして送信する
+    pub async fn write_packet<P: Packet>(&mut self, packet: &P) -> Result<()> {
+        self.codec.encode_packet(packet, &mut self.out)?;
+        self.flush().await
+    }
+
+    /// 送信バッファに溜まったフレームを書き出す
+    async fn flush(&mut self) -> Result<()> {
+        // キャプチャには暗号化前のフレームを残す
+        if self.capture.is_recording() {
+            self.record_outgoing()?;
+        }
+        if let Some(cipher) = &mut self.cipher {
+            cipher.encrypt(&mut self.out);
+        }
+        let result = self.stream.write_all(&self.out).await;
+        self.out.clear();
+        Ok(result?)
+    }
+
+    /// 送信バッファ内のフレームをキャプチャに記録する
+    fn record_outgoing(&mut self) -> Result<()> {
+        let mut frames = &self.out[..];
+        while !frames.is_empty() {
+            let len = read_varint(&mut frames)? as usize;
+            let mut body = &frames[..len];
+            frames = &frames[len..];
+            let id = read_varint(&mut body)?;
+            self.capture.record(CaptureRecord::new(Direction::Clientbound, self.state, id, body));
+        }
+        Ok(())
+    }
+
+    /// 現在の状態に合った Disconnect パケットを送る
+    pub async fn disconnect(&mut self, reason: &str) -> Result<()> {
+        let reason_json = serde_json::json!({ "text": reason }).to_string();
+        match self.state {
+            PacketState::Login => self.write_packet(&LoginDisconnect { reason_json }).await,
+            PacketState::Play => self.write_packet(&PlayDisconnect { reason_json }).await,
+            _ => Ok(()),
+        }
+    }
+}
+
+/// コネクションハンドラー
+/// 新しい接続を処理し、適切なプロトコル処理を行います。
+pub async fn handle_connection(stream: TcpStream, ctx: Arc<ServerContext>) -> Result<()> {
+    let peer = stream.peer_addr()?;
+    let mut conn = Connection::new(stream, peer, ctx);
+
+    let Some(mut frame) = conn.read_frame().await? else {
+        return Ok(());  // 接続が閉じられた
+    };
+    if frame.id != 0x00 {
+        return Err("Invalid initial packet ID".into());
+    }
+    let handshake = HandshakePacket::decode(&mut frame.body)?;
+    conn.protocol_version = handshake.protocol_version;
+
+    match handshake.next_state {
+        PacketState::Status => {
+            conn.set_state(PacketState::Status);
+            handle_status(&mut conn).await?;
+            handle_connection_ping(&mut conn).await?;
+        }
+        PacketState::Login => {
+            conn.set_state(PacketState::Login);
+            handle_connection_login(&mut conn).await?;
+        }
+        _ => return Err("Invalid next state".into()),
+    }
+
     Ok(())
 }
 
-pub async fn handle_connection_ping(mut stream: TcpStream) -> Result<()> {
-    let mut buf = BytesMut::new();
-    let size = stream.read(&mut buf).await.map_err(ServerError::Io)?;
-    let mut data = BytesMut::from(&buf[..size]);
+pub async fn handle_connection_ping(conn: &mut Connection) -> Result<()> {
+    let Some(mut frame) = conn.read_frame().await? else {
+        return Ok(());
+    };
 
-    let _len = read_varint(&mut data)
-        .ok_or_else(|| ServerError::Protocol("Invalid packet length".to_string()))?;
-    let packet_id = read_varint(&mut data)
-        .ok_or_else(|| ServerError::Protocol("Invalid packet ID".to_string()))?;
+    if frame.id == 0x01 {
+        // ペイロードは VarLong ではなく固定長のビッグエンディアン long
+        let payload = i64::read(&mut frame.body)?;
+        conn.write_packet(&PongResponse { payload }).await?;
+    }
+    Ok(())
+}
 
-    if packet_id == 0x01 {
-        if let Some(ping_payload) = read_i64(&mut data) {
-            let mut pong = BytesMut::new();
-            write_varint(&mut pong, 0x01)?;
-            write_i64(&mut pong, ping_payload)?;
+pub async fn handle_connection_login(conn: &mut Connection) -> Result<()> {
+    let Some(mut frame) = conn.read_frame().await? else {
+        return Ok(());
+    };
 
-            let mut packet = BytesMut::new();
-            write_varint(&mut packet, pong.len() as i32)?;
-            packet.extend_from_slice(&pong);
+    if frame.id == 0x00 {
+        let login_start = LoginStart::decode(&mut frame.body)?;
+        conn.identify(&login_start.username);
 
-            stream.write_all(&packet).await?;
+        let config = conn.ctx.config();
+        if !is_valid_username(&login_start.username, config.username_policy) {
+            info!("Disconnecting {}: invalid username {:?}", conn.peer, login_start.username);
+            return conn.disconnect("Invalid player name").await;
+        }
+
+        let auth = conn.ctx.auth();
+        let server_hash = if auth.requires_encryption() {
+            match start_encryption(conn).await? {
+                Some(server_hash) => Some(server_hash),
+                None => return Ok(()),
+            }
+        } else {
+            None
+        };
+        let request = AuthRequest {
+            username: &login_start.username,
+            ip: conn.peer.ip(),
+            server_hash: server_hash.as_deref(),
+            access: &conn.ctx.access,
+        };
+        let profile = match auth.authenticate(&request).await {
+            Ok(profile) => profile,
+            Err(reason) => {
+                info!("Disconnecting {} ({}): {}", login_start.username, conn.peer, reason);
+                return conn.disconnect(&reason).await;
+            }
+        };
+
+        // 改名したプレイヤーもコマンドで新しい名前から引けるように覚えておく
+        if let Err(e) = conn.ctx.access.remember(profile.uuid, &profile.name) {
+            warn!("Failed to save {}: {}", USER_CACHE_FILE, e);
+        }
+        if let Err(reason) = conn.ctx.access.check_login(&profile.uuid, &profile.name, conn.peer.ip(), config.whitelist) {
+            info!("Disconnecting {} ({}): {}", profile.name, conn.peer, reason.replace('\n', " "));
+            return conn.disconnect(&reason).await;
         }
+        // パスワードを間違え続けた IP アドレスとプレイヤーは、期限まで入れない
+        if passwords::applies_to(&config.password_login, &profile) && conn.ctx.login_failures.is_blocked(conn.peer.ip(), &profile.uuid) {
+            info!("Disconnecting {} ({}): too many failed password attempts", profile.name, conn.peer);
+            return conn.disconnect(TOO_MANY_ATTEMPTS_MESSAGE).await;
+        }
+
+        // Login Success より先にタブリストのパケットが届かないよう、登録前に送る
+        conn.write_packet(&profile.login_success()).await?;
+        conn.set_state(PacketState::Play);
+
+        // 同じプレイヤーが既にオンラインなら古い方を切断する
+        let mut registration = conn.ctx.players.register(profile, conn.peer, conn.protocol_version)?;
+        for previous in &registration.replaced {
+            info!("{} logged in again from {}, disconnecting the previous session", previous.name(), conn.peer);
+            previous.kick(DUPLICATE_LOGIN_MESSAGE);
+        }
+        conn.ctx.stats.record_join(conn.ctx.players.len());
+        let session = SessionGuard { ctx: conn.ctx.clone(), session: registration.session };
+
+        handle_play(conn, &session.session, &mut registration.messages).await?;
     }
     Ok(())
 }
 
-pub async fn handle_connection_login(mut stream: TcpStream) -> Result<()> {
-    let mut buf = BytesMut::new();
-    let size = stream.read(&mut buf).await.map_err(ServerError::Io)?;
-    let mut data = BytesMut::from(&buf[..size]);
+/// Encryption Request を送り、応答を検証して暗号化を有効にする。
+/// セッションサーバーに渡すサーバーハッシュを返す。応答の前に切断されたら `None`
+async fn start_encryption(conn: &mut Connection) -> Result<Option<String>> {
+    let keypair = conn.ctx.keypair()?;
+    let verify_token = new_verify_token();
+    conn.write_packet(&EncryptionRequest::from_keypair(&keypair, &verify_token)).await?;
+
+    let Some(mut frame) = conn.read_frame().await? else {
+        return Ok(None);
+    };
+    if frame.id != 0x01 {
+        return Err(ServerError::Protocol(format!("Encryption Response を待っていましたが 0x{:02X} を受信しました", frame.id)));
+    }
+    let response = EncryptionResponse::decode_with_key(&mut frame.body, keypair.private_key())
+        .map_err(|e| ServerError::Protocol(e.to_string()))?;
+    if response.verify_token != verify_token {
+        return Err(ServerError::Protocol("verify token が一致しません".into()));
+    }
+    conn.enable_encryption(&response.shared_secret)?;
+    Ok(Some(server_hash("", &response.shared_secret, keypair.public_key_der())))
+}
+
+/// 同じプレイヤーが別の場所からログインしたときの切断理由
+pub const DUPLICATE_LOGIN_MESSAGE: &str = "You logged in from another location";
 
-    let _len = read_varint(&mut data)
-        .ok_or_else(|| ServerError::Protocol("Invalid packet length".to_string()))?;
-    let packet_id = read_varint(&mut data)
-        .ok_or_else(|| ServerError::Protocol("Invalid packet ID".to_string()))?;
+/// 接続が終わったらセッションを一覧から外す
+struct SessionGuard {
+    ctx: Arc<ServerContext>,
+    session: Arc<Session>,
+}
 
-    if packet_id == 0x00 {
-        let name_len = read_varint(&mut data).ok_or_else(|| ServerError::Protocol("Invalid name length".to_string()))?;
-        let name = String::from_utf8(data.split_to(name_len as usize).to_vec())
-            .map_err(|_| ServerError::Protocol("Invalid UTF-8 in name".to_string()))?;
+impl Drop for SessionGuard {
+    fn drop(&mut self) {
+        self.ctx.players.unregister(&self.session);
+        self.ctx.game.disconnect(ConnectionId(self.session.id));
+    }
+}
 
-        let uuid = Uuid::new_v4();
-        let uuid_str = uuid.to_string();
+/// Play 状態の接続を保つ。Keep Alive を定期的に送り、往復時間を記録する。
+/// `messages` とゲームスレッドから届いたパケットはそのまま送り、切断を指示されたら理由を送って終わる。
+/// クライアントからのパケットはデコードしてゲームスレッドに渡す
+pub async fn handle_play(conn: &mut Connection, session: &Arc<Session>, messages: &mut SessionMessages) -> Result<()> {
+    let config = conn.ctx.config();
+    let interval = config.keep_alive_interval;
+    let timeout = config.keep_alive_timeout;
+    let mut ticker = tokio::time::interval_at(tokio::time::Instant::now() + interval, interval);
+    let mut pending: Option<(i64, Instant)> = None;
+    // 認証が済むまではチャットと移動を受け付けない
+    let mut gate = PasswordGate::for_player(&config.password_login, &conn.ctx.passwords, &session.profile, conn.peer.ip());
+    let deadline = gate.as_ref().map_or_else(tokio::time::Instant::now, PasswordGate::deadline);
+    if let Some(gate) = &gate {
+        conn.write_packet(&SystemChatMessage::text(gate.prompt())).await?;
+    }
+    // 認証が済んでからゲームに参加させる
+    let mut egress = match gate {
+        Some(_) => None,
+        None => Some(conn.ctx.game.connect(session.clone()).await),
+    };
 
-        let mut response = BytesMut::new();
-        write_varint(&mut response, 0x02)?;
-        write_string(&mut response, &uuid_str)?;
-        write_string(&mut response, &name)?;
+    loop {
+        tokio::select! {
+            frame = conn.read_frame() => {
+                let Some(mut frame) = frame? else {
+                    return Ok(());
+                };
+                if let Some(current) = gate.as_mut() {
+                    if frame.id == CHAT_COMMAND_ID {
+                        let command = ChatCommand::decode(&mut frame.body)?;
+                        match current.handle_command(&config.password_login, &conn.ctx.passwords, &conn.ctx.login_failures, &session.profile, &command.command).await? {
+                            GateReply::Message(message) => conn.write_packet(&SystemChatMessage::text(&message)).await?,
+                            GateReply::Authenticated(message) => {
+                                conn.write_packet(&SystemChatMessage::text(&message)).await?;
+                                gate = None;
+                                egress = Some(conn.ctx.game.connect(session.clone()).await);
+                            }
+                            GateReply::Kick(reason) => {
+                                conn.disconnect(&reason).await?;
+                                return Ok(());
+                            }
+                        }
+                        continue;
+                    }
+                    if frame.id == CHAT_MESSAGE_ID {
+                        conn.write_packet(&SystemChatMessage::text(current.prompt())).await?;
+                        continue;
+                    }
+                    if frame.id != SERVERBOUND_KEEP_ALIVE_ID {
+                        continue;
+                    }
+                }
+                if frame.id == SERVERBOUND_KEEP_ALIVE_ID {
+                    let keep_alive = KeepAlive::decode(&mut frame.body)?;
+                    if let Some((id, sent_at)) = pending {
+                        if id == keep_alive.id {
+                            let rtt = sent_at.elapsed();
+                            conn.ctx.stats.record_keep_alive(rtt);
+                            session.set_latency(rtt);
+                            pending = None;
+                        }
+                    }
+                } else if egress.is_some() {
+                    let packet = ServerboundPacket::decode(frame.id, &mut frame.body)?;
+                    conn.ctx.game.send_packet(ConnectionId(session.id), packet).await;
+                }
+            }
+            Some(message) = messages.recv() => {
+                if deliver(conn, message).await? {
+                    return Ok(());
+                }
+            }
+            message = recv_from_game(&mut egress) => match message {
+                Some(message) => {
+                    if deliver(conn, message).await? {
+                        return Ok(());
+                    }
+                }
+                // ゲームスレッドが止まった
+                None => egress = None,
+            },
+            _ = tokio::time::sleep_until(deadline), if gate.is_some() => {
+                info!("{} did not log in in time", session.name());
+                conn.disconnect(TIMEOUT_MESSAGE).await?;
+                return Ok(());
+            }
+            _ = ticker.tick() => {
+                if let Some((_, sent_at)) = pending {
+                    if sent_at.elapsed() > timeout {
+                        conn.disconnect("Timed out").await?;
+                        return Ok(());
+                    }
+                    continue;
+                }
+                // バニラと同じくミリ秒のタイムスタンプをIDにする
+                let id = chrono::Utc::now().timestamp_millis();
+                conn.write_packet(&KeepAlive { id, serverbound: false }).await?;
+                pending = Some((id, Instant::now()));
+            }
+        }
+    }
+}
 
-        let mut packet = BytesMut::new();
-        write_varint(&mut packet, response.len() as i32)?;
-        packet.extend_from_slice(&response);
+/// ゲームに参加するまでは何も返さない
+async fn recv_from_game(egress: &mut Option<Receiver<SessionMessage>>) -> Option<SessionMessage> {
+    match egress {
+        Some(egress) => egress.recv().await,
+        None => std::future::pending().await,
+    }
+}
 
-        stream.write_all(&packet).await?;
+/// セッションへの指示をクライアントに送る。切断したら `true`
+async fn deliver(conn: &mut Connection, message: SessionMessage) -> Result<bool> {
+    match message {
+        SessionMessage::Packet { id, body } => {
+            conn.write_frame(id, &body).await?;
+            Ok(false)
+        }
+        SessionMessage::Kick(reason) => {
+            conn.disconnect(&reason).await?;
+            Ok(true)
+        }
     }
-    Ok(())
 }
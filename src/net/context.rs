@@ -0,0 +1,169 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+
+use parking_lot::RwLock;
+use tokio::sync::watch;
+use log::{info, warn};
+
+use crate::command::CommandDispatcher;
+use crate::game::network::GameHandle;
+use crate::net::access::AccessLists;
+use crate::net::capture::CaptureTargets;
+use crate::net::login::auth::AuthChain;
+use crate::net::login::encryption::EncryptionKeyPair;
+use crate::net::passwords::{LoginLimiter, PasswordStore};
+use crate::net::players::PlayerRegistry;
+use crate::net::stats::ServerStats;
+use crate::net::error::{Result, ServerError};
+use crate::utils::config::{ConfigChanges, ServerConfig};
+
+/// サーバーが報告するバージョン名
+pub const VERSION_NAME: &str = "1.20.1";
+/// サーバーが話すプロトコルバージョン
+pub const PROTOCOL_VERSION: i32 = 763;
+
+/// 全コネクションで共有されるサーバーの状態
+pub struct ServerContext {
+    /// 再読み込みで丸ごと差し替えるので、使うたびに [`ServerContext::config`] で取り出す
+    config: RwLock<Arc<ServerConfig>>,
+    config_path: Option<PathBuf>,
+    /// `auth` の設定から作る。設定が変わったら作り直す
+    auth: RwLock<Arc<AuthChain>>,
+    pub commands: CommandDispatcher,
+    pub capture: CaptureTargets,
+    pub access: AccessLists,
+    pub passwords: PasswordStore,
+    /// パスワード認証の失敗回数。接続をまたいで数える
+    pub login_failures: LoginLimiter,
+    pub players: PlayerRegistry,
+    pub stats: ServerStats,
+    /// ゲームスレッドへの送信口
+    pub game: GameHandle,
+    /// 起動時に1度だけ読み込むか生成する。[`ServerContext::keypair`] で取り出す
+    keypair: OnceLock<Arc<EncryptionKeyPair>>,
+    shutdown: watch::Sender<bool>,
+}
+
+/// ステータス応答やQueryで公開するサーバー情報のスナップショット
+#[derive(Debug, Clone)]
+pub struct StatusInfo {
+    pub version_name: String,
+    pub protocol: i32,
+    pub motd: String,
+    pub max_players: usize,
+    pub online_players: Vec<String>,
+}
+
+impl ServerContext {
+    pub fn new(config: ServerConfig) -> Self {
+        if !crate::varint::configure(config.varint.clone()) {
+            warn!("VarInt settings were already in use; the [varint] section is ignored");
+        }
+        let (shutdown, _) = watch::channel(false);
+        let access = AccessLists::load(&config.data_directory);
+        access.set_online_mode(config.auth.online_mode());
+        Self {
+            capture: CaptureTargets::new(&config.capture),
+            access,
+            passwords: PasswordStore::load(&config.data_directory),
+            login_failures: LoginLimiter::new(&config.password_login),
+            players: PlayerRegistry::new(),
+            auth: RwLock::new(Arc::new(AuthChain::from_config(&config.auth))),
+            config: RwLock::new(Arc::new(config)),
+            config_path: None,
+            commands: CommandDispatcher::with_builtins(),
+            stats: ServerStats::new(),
+            game: GameHandle::new(),
+            keypair: OnceLock::new(),
+            shutdown,
+        }
+    }
+
+    /// `reload` で読み直す設定ファイルを指定する
+    pub fn with_config_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config_path = Some(path.into());
+        self
+    }
+
+    pub fn config_path(&self) -> Option<&Path> {
+        self.config_path.as_deref()
+    }
+
+    /// サーバーの RSA 鍵。最初の呼び出しで `encryption_key` のファイルを読み込むか新しく生成し、以後は同じ鍵を返す
+    pub fn keypair(&self) -> Result<Arc<EncryptionKeyPair>> {
+        if let Some(keypair) = self.keypair.get() {
+            return Ok(keypair.clone());
+        }
+        let path = self.config().encryption_key.clone();
+        let keypair = Arc::new(EncryptionKeyPair::load_or_generate(&path)?);
+        if path.is_empty() {
+            info!("Generated a new RSA key pair");
+        } else {
+            info!("Loaded the RSA key pair from {}", path);
+        }
+        Ok(self.keypair.get_or_init(|| keypair).clone())
+    }
+
+    /// ログインに使う認証方法の並び
+    pub fn auth(&self) -> Arc<AuthChain> {
+        self.auth.read().clone()
+    }
+
+    /// 現在の設定
+    pub fn config(&self) -> Arc<ServerConfig> {
+        self.config.read().clone()
+    }
+
+    /// 設定ファイルとホワイトリスト・BAN のファイルを読み直し、稼働中に変えられる項目を差し替える
+    pub fn reload_config(&self) -> Result<ConfigChanges> {
+        let path = self.config_path.as_deref()
+            .ok_or_else(|| ServerError::Config("設定ファイルを指定せずに起動しています".into()))?;
+        // 消えたファイルを既定値で作り直さないようにする
+        if !path.exists() {
+            return Err(ServerError::Config(format!("{} が見つかりません", path.display())));
+        }
+        let loaded = ServerConfig::load(path)?;
+        // ホワイトリストや BAN のファイルも読み直す
+        self.access.reload()?;
+        let changes = self.apply_config(loaded);
+        info!("{}", changes);
+        Ok(changes)
+    }
+
+    /// 検証済みの設定を反映する。再起動が必要な項目は元の値のまま残る
+    pub fn apply_config(&self, new: ServerConfig) -> ConfigChanges {
+        let mut current = self.config.write();
+        let (next, changes) = current.reloaded(new);
+        if changes.applied.contains(&"auth") {
+            *self.auth.write() = Arc::new(AuthChain::from_config(&next.auth));
+            self.access.set_online_mode(next.auth.online_mode());
+        }
+        if changes.applied.contains(&"password_login") {
+            self.login_failures.set_limits(&next.password_login);
+        }
+        *current = Arc::new(next);
+        changes
+    }
+
+    /// `handle_status` とQueryサーバーが共通で使うサーバー情報を返す
+    pub fn status_info(&self) -> StatusInfo {
+        let config = self.config();
+        StatusInfo {
+            version_name: VERSION_NAME.to_string(),
+            protocol: PROTOCOL_VERSION,
+            motd: config.motd.clone(),
+            max_players: config.max_players,
+            online_players: self.players.names(),
+        }
+    }
+
+    /// サーバーの停止を要求する
+    pub fn shutdown(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    /// 停止要求を待つためのレシーバー
+    pub fn shutdown_signal(&self) -> watch::Receiver<bool> {
+        self.shutdown.subscribe()
+    }
+}
@@ -14,6 +14,9 @@ pub enum ServerError {
 
     #[error("Configuration error: {0}")]
     Config(String),
+
+    #[error("Disconnected: {0}")]
+    Disconnected(String),
 }
 
 // FromUtf8Error から ServerError への変換を実装
@@ -23,6 +26,17 @@ impl From<FromUtf8Error> for ServerError {
     }
 }
 
+// DecodeError から ServerError への変換を実装
+// パケット本体はフレーム単位で揃っているため、ここでのバイト不足も不正なパケットとして扱う
+impl From<crate::varint::DecodeError> for ServerError {
+    fn from(error: crate::varint::DecodeError) -> Self {
+        match error {
+            crate::varint::DecodeError::Incomplete => ServerError::VarInt(crate::varint::VarIntError::BufferUnderflow),
+            crate::varint::DecodeError::Invalid { reason } => ServerError::Protocol(reason),
+        }
+    }
+}
+
 // &str から ServerError への変換を実装
 impl From<&str> for ServerError {
     fn from(error: &str) -> Self {
@@ -0,0 +1,324 @@
+//! ログイン時の認証
+//!
+//! [`Authenticator`] は LoginStart の名前 (とオンライン認証ならサーバーハッシュ) から
+//! ゲームプロファイルを決める。設定の `[[auth.chain]]` の順に [`AuthChain`] が試し、
+//! 最初に受け入れた方法のプロファイルでログインさせる。
+
+use std::future::Future;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::time::Duration;
+
+use log::{debug, warn};
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::client::DEFAULT_SESSION_SERVER;
+use crate::net::access::AccessLists;
+use crate::net::login::offline::offline_uuid;
+use crate::net::login::profile::GameProfile;
+use crate::net::login::success::ProfileProperty;
+use crate::utils::config::{AuthConfig, AuthMethod};
+
+/// どの方法でも受け入れられなかったときの切断理由 (vanilla と同じ)
+pub const UNVERIFIED_MESSAGE: &str = "Failed to verify username!";
+
+/// 認証の判定材料
+pub struct AuthRequest<'a> {
+    /// LoginStart で名乗った名前
+    pub username: &'a str,
+    pub ip: IpAddr,
+    /// 暗号化を済ませていればセッションサーバーに渡すサーバーハッシュ
+    pub server_hash: Option<&'a str>,
+    pub access: &'a AccessLists,
+}
+
+/// 1つの認証方法の判定
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuthResult {
+    /// このプロファイルでログインさせる
+    Accepted(GameProfile),
+    /// ログインを拒否する。理由はクライアントに表示する
+    Rejected(String),
+    /// この方法では判断できない。次の方法に回す
+    Pass,
+}
+
+pub type AuthFuture<'a> = Pin<Box<dyn Future<Output = AuthResult> + Send + 'a>>;
+
+pub trait Authenticator: Send + Sync {
+    /// ログに出す名前
+    fn name(&self) -> &str;
+
+    /// Encryption Request でサーバーハッシュを作っておく必要があるか
+    fn requires_encryption(&self) -> bool {
+        false
+    }
+
+    fn authenticate<'a>(&'a self, request: &'a AuthRequest<'a>) -> AuthFuture<'a>;
+}
+
+/// 名前から vanilla と同じオフライン UUID を作る
+pub struct OfflineAuthenticator {
+    /// ホワイトリストに載っている名前だけを受け入れる
+    pub whitelisted_only: bool,
+}
+
+impl Authenticator for OfflineAuthenticator {
+    fn name(&self) -> &str {
+        "offline"
+    }
+
+    fn authenticate<'a>(&'a self, request: &'a AuthRequest<'a>) -> AuthFuture<'a> {
+        Box::pin(async move {
+            let uuid = offline_uuid(request.username);
+            if self.whitelisted_only && !request.access.is_whitelisted(&uuid, request.username) {
+                return AuthResult::Pass;
+            }
+            AuthResult::Accepted(GameProfile::new(uuid, request.username))
+        })
+    }
+}
+
+/// セッションサーバーの `hasJoined` に問い合わせる。Mojang と Yggdrasil 互換サーバーで共通
+pub struct SessionServerAuthenticator {
+    name: String,
+    /// `.../session/minecraft/hasJoined` の手前まで
+    session_server: String,
+    timeout: Duration,
+    client: reqwest::Client,
+}
+
+impl SessionServerAuthenticator {
+    pub fn new(name: &str, session_server: &str, timeout: Duration) -> Self {
+        Self {
+            name: name.to_string(),
+            session_server: session_server.trim_end_matches('/').to_string(),
+            timeout,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn mojang(timeout: Duration) -> Self {
+        Self::new("mojang", DEFAULT_SESSION_SERVER, timeout)
+    }
+
+    /// authlib-injector と同じく、API のルートの下の `/sessionserver` を使う
+    pub fn yggdrasil(api_root: &str, timeout: Duration) -> Self {
+        Self::new(api_root, &format!("{}/sessionserver", api_root.trim_end_matches('/')), timeout)
+    }
+
+    async fn has_joined(&self, username: &str, server_hash: &str) -> crate::Result<Option<GameProfile>> {
+        let response = self.client
+            .get(format!("{}/session/minecraft/hasJoined", self.session_server))
+            .query(&[("username", username), ("serverId", server_hash)])
+            .timeout(self.timeout)
+            .send()
+            .await
+            .map_err(|e| crate::ServerError::Protocol(format!("セッションサーバーへの接続に失敗: {}", e)))?;
+        // 204 No Content はセッションが見つからないことを表す
+        if response.status() == reqwest::StatusCode::NO_CONTENT {
+            return Ok(None);
+        }
+        if !response.status().is_success() {
+            return Err(crate::ServerError::Protocol(format!("セッションサーバーの応答が不正です: {}", response.status())));
+        }
+        let profile: ProfileJson = response.json().await
+            .map_err(|e| crate::ServerError::Protocol(format!("プロファイルを読み込めません: {}", e)))?;
+        Ok(Some(profile.into()))
+    }
+}
+
+impl Authenticator for SessionServerAuthenticator {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn requires_encryption(&self) -> bool {
+        true
+    }
+
+    fn authenticate<'a>(&'a self, request: &'a AuthRequest<'a>) -> AuthFuture<'a> {
+        Box::pin(async move {
+            let Some(server_hash) = request.server_hash else {
+                return AuthResult::Pass;
+            };
+            match self.has_joined(request.username, server_hash).await {
+                Ok(Some(profile)) => AuthResult::Accepted(profile),
+                Ok(None) => AuthResult::Pass,
+                // 後ろに別の方法があればそちらに任せる
+                Err(e) => {
+                    warn!("Could not verify {} with {}: {}", request.username, self.name, e);
+                    AuthResult::Pass
+                }
+            }
+        })
+    }
+}
+
+/// JSON ファイルに並べたプロファイル。名前 (大文字小文字を区別しない) が一致すれば受け入れる。
+/// ファイルはログインのたびに読むので、編集はすぐに反映される
+pub struct ProfileFileAuthenticator {
+    path: PathBuf,
+}
+
+impl ProfileFileAuthenticator {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// ランタイムのスレッドを止めないよう、ファイルは非同期に読む
+    async fn find(&self, username: &str) -> crate::Result<Option<GameProfile>> {
+        let text = tokio::fs::read_to_string(&self.path).await?;
+        let profiles: Vec<ProfileJson> = serde_json::from_str(&text)
+            .map_err(|e| crate::ServerError::Config(format!("{}: {}", self.path.display(), e)))?;
+        Ok(profiles.into_iter().find(|profile| profile.name.eq_ignore_ascii_case(username)).map(Into::into))
+    }
+}
+
+impl Authenticator for ProfileFileAuthenticator {
+    fn name(&self) -> &str {
+        "file"
+    }
+
+    fn authenticate<'a>(&'a self, request: &'a AuthRequest<'a>) -> AuthFuture<'a> {
+        Box::pin(async move {
+            match self.find(request.username).await {
+                Ok(Some(profile)) => AuthResult::Accepted(profile),
+                Ok(None) => AuthResult::Pass,
+                Err(e) => {
+                    warn!("Could not read profiles: {}", e);
+                    AuthResult::Pass
+                }
+            }
+        })
+    }
+}
+
+/// セッションサーバーの応答と、プロファイルファイルのエントリ
+#[derive(Deserialize)]
+struct ProfileJson {
+    id: Uuid,
+    name: String,
+    #[serde(default)]
+    properties: Vec<PropertyJson>,
+}
+
+#[derive(Deserialize)]
+struct PropertyJson {
+    name: String,
+    value: String,
+    signature: Option<String>,
+}
+
+impl From<ProfileJson> for GameProfile {
+    fn from(profile: ProfileJson) -> Self {
+        GameProfile {
+            uuid: profile.id,
+            name: profile.name,
+            properties: profile.properties.into_iter()
+                .map(|property| ProfileProperty { name: property.name, value: property.value, signature: property.signature })
+                .collect(),
+        }
+    }
+}
+
+/// 設定の順に認証方法を試す
+pub struct AuthChain {
+    authenticators: Vec<Box<dyn Authenticator>>,
+}
+
+impl AuthChain {
+    pub fn new(authenticators: Vec<Box<dyn Authenticator>>) -> Self {
+        Self { authenticators }
+    }
+
+    pub fn from_config(config: &AuthConfig) -> Self {
+        let authenticators = config.chain.iter()
+            .map(|method| -> Box<dyn Authenticator> {
+                match method {
+                    AuthMethod::Offline { whitelisted_only } => Box::new(OfflineAuthenticator { whitelisted_only: *whitelisted_only }),
+                    AuthMethod::Mojang => Box::new(SessionServerAuthenticator::mojang(config.timeout)),
+                    AuthMethod::Yggdrasil { url } => Box::new(SessionServerAuthenticator::yggdrasil(url, config.timeout)),
+                    AuthMethod::File { path } => Box::new(ProfileFileAuthenticator::new(path)),
+                }
+            })
+            .collect();
+        Self::new(authenticators)
+    }
+
+    /// どれか1つでも暗号化が必要なら、認証の前に Encryption Request を送る
+    pub fn requires_encryption(&self) -> bool {
+        self.authenticators.iter().any(|authenticator| authenticator.requires_encryption())
+    }
+
+    /// 最初に受け入れた方法のプロファイルを返す。拒否されるか、どの方法も受け入れなければ切断理由
+    pub async fn authenticate(&self, request: &AuthRequest<'_>) -> Result<GameProfile, String> {
+        for authenticator in &self.authenticators {
+            match authenticator.authenticate(request).await {
+                AuthResult::Accepted(profile) => {
+                    debug!("{} authenticated by {} as {}", request.username, authenticator.name(), profile.uuid);
+                    return Ok(profile);
+                }
+                AuthResult::Rejected(reason) => return Err(reason),
+                AuthResult::Pass => {}
+            }
+        }
+        Err(UNVERIFIED_MESSAGE.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::access::UserEntry;
+
+    struct Reject;
+
+    impl Authenticator for Reject {
+        fn name(&self) -> &str {
+            "reject"
+        }
+
+        fn authenticate<'a>(&'a self, _request: &'a AuthRequest<'a>) -> AuthFuture<'a> {
+            Box::pin(async { AuthResult::Rejected("nope".to_string()) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_chain_order_and_fallback() {
+        let dir = std::env::temp_dir().join(format!("server-auth-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let profiles = dir.join("profiles.json");
+        std::fs::write(&profiles, r#"[{"id": "069a79f444e94726a5befca90e38aaf5", "name": "Notch",
+            "properties": [{"name": "textures", "value": "e30="}]}]"#).unwrap();
+        let access = AccessLists::load(&dir);
+        access.add_to_whitelist(UserEntry { uuid: offline_uuid("Alex"), name: "Alex".to_string() }).unwrap();
+        let request = |username| AuthRequest { username, ip: IpAddr::from([127, 0, 0, 1]), server_hash: None, access: &access };
+
+        // ファイルにあれば固定のプロファイル、なければホワイトリストの名前だけオフラインで受け入れる
+        let chain = AuthChain::new(vec![
+            Box::new(ProfileFileAuthenticator::new(&profiles)),
+            Box::new(OfflineAuthenticator { whitelisted_only: true }),
+        ]);
+        assert!(!chain.requires_encryption());
+        let notch = chain.authenticate(&request("notch")).await.unwrap();
+        assert_eq!((notch.uuid.to_string().as_str(), notch.name.as_str()), ("069a79f4-44e9-4726-a5be-fca90e38aaf5", "Notch"));
+        assert_eq!(notch.properties[0].value, "e30=");
+        assert_eq!(chain.authenticate(&request("Alex")).await.unwrap().uuid, offline_uuid("Alex"));
+        assert_eq!(chain.authenticate(&request("Steve")).await, Err(UNVERIFIED_MESSAGE.to_string()));
+
+        // 拒否されたら後ろの方法は試さない
+        let chain = AuthChain::new(vec![Box::new(Reject), Box::new(OfflineAuthenticator { whitelisted_only: false })]);
+        assert_eq!(chain.authenticate(&request("Steve")).await, Err("nope".to_string()));
+
+        let config = AuthConfig {
+            chain: vec![AuthMethod::Yggdrasil { url: "https://example.com/api/yggdrasil/".to_string() }],
+            ..AuthConfig::default()
+        };
+        assert!(AuthChain::from_config(&config).requires_encryption());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
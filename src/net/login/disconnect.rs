@@ -1,8 +1,7 @@
 use crate::net::protocol::Packet;
-use crate::varint::utils::write_string;
+use crate::varint::utils::{read_string, string_len, write_string};
 use crate::ServerError;
 use bytes::BytesMut;
-use uuid::Uuid;
 
 #[derive(Debug, Clone)]
 pub struct LoginDisconnect {
@@ -19,7 +18,12 @@ impl Packet for LoginDisconnect {
         Ok(())
     }
 
-    fn decode(_buf: &mut BytesMut) -> Result<Self, ServerError> {
-        Err(ServerError::Protocol("LoginDisconnect は送信用のみです".into()))
+    fn encoded_len(&self) -> Option<usize> {
+        Some(string_len(&self.reason_json))
+    }
+
+    fn decode(buf: &mut BytesMut) -> Result<Self, ServerError> {
+        let reason_json = read_string(buf)?;
+        Ok(Self { reason_json })
     }
 }
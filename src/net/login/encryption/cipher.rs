@@ -0,0 +1,38 @@
+use aes::cipher::generic_array::GenericArray;
+use aes::cipher::{BlockDecryptMut, BlockEncryptMut, KeyIvInit};
+use aes::Aes128;
+
+use crate::ServerError;
+
+type Encryptor = cfb8::Encryptor<Aes128>;
+type Decryptor = cfb8::Decryptor<Aes128>;
+
+/// 共有鍵を鍵とIVに使う AES-128/CFB8 のストリーム暗号
+///
+/// 送信側と受信側で別々の状態を持つため、読み書きで半分ずつ使う。
+pub struct PacketCipher {
+    encryptor: Encryptor,
+    decryptor: Decryptor,
+}
+
+impl PacketCipher {
+    pub fn new(shared_secret: &[u8]) -> Result<Self, ServerError> {
+        let encryptor = Encryptor::new_from_slices(shared_secret, shared_secret)
+            .map_err(|_| ServerError::Protocol("共有鍵の長さが不正です".into()))?;
+        let decryptor = Decryptor::new_from_slices(shared_secret, shared_secret)
+            .map_err(|_| ServerError::Protocol("共有鍵の長さが不正です".into()))?;
+        Ok(Self { encryptor, decryptor })
+    }
+
+    pub fn encrypt(&mut self, data: &mut [u8]) {
+        for byte in data.chunks_mut(1) {
+            self.encryptor.encrypt_block_mut(GenericArray::from_mut_slice(byte));
+        }
+    }
+
+    pub fn decrypt(&mut self, data: &mut [u8]) {
+        for byte in data.chunks_mut(1) {
+            self.decryptor.decrypt_block_mut(GenericArray::from_mut_slice(byte));
+        }
+    }
+}
@@ -1,5 +1,72 @@
 pub mod key;
 pub mod request;
 pub mod response;
+pub mod cipher;
 
-pub use key::EncryptionKeyPair;
\ No newline at end of file
+use sha1::{Digest, Sha1};
+
+pub use cipher::PacketCipher;
+pub use key::{new_verify_token, EncryptionKeyPair};
+
+/// セッションサーバーに渡すサーバーハッシュ
+///
+/// SHA-1 ダイジェストを符号付き整数とみなした16進表記 (先頭の0は省略し、負数は `-` 付き)。
+pub fn server_hash(server_id: &str, shared_secret: &[u8], public_key_der: &[u8]) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(server_id.as_bytes());
+    hasher.update(shared_secret);
+    hasher.update(public_key_der);
+    let mut digest: [u8; 20] = hasher.finalize().into();
+
+    let negative = digest[0] & 0x80 != 0;
+    if negative {
+        // 2の補数で絶対値に直す
+        let mut carry = true;
+        for byte in digest.iter_mut().rev() {
+            *byte = !*byte;
+            if carry {
+                let (value, overflow) = byte.overflowing_add(1);
+                *byte = value;
+                carry = overflow;
+            }
+        }
+    }
+
+    let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    let hex = hex.trim_start_matches('0');
+    if negative {
+        format!("-{}", hex)
+    } else {
+        hex.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn digest(name: &str) -> String {
+        server_hash(name, &[], &[])
+    }
+
+    #[test]
+    fn test_server_hash_matches_minecraft_digest() {
+        assert_eq!(digest("Notch"), "4ed1f46bbe04bc756bcb17c0c7ce3e4632f06a48");
+        assert_eq!(digest("jeb_"), "-7c9d5b0044c130109a5d7b5fb5c317c02b4e28c1");
+        assert_eq!(digest("simon"), "88e16a1019277b15d58faf0541e11910eb756f6");
+    }
+
+    #[test]
+    fn test_cipher_roundtrip() {
+        let secret = [7u8; 16];
+        let mut sender = PacketCipher::new(&secret).unwrap();
+        let mut receiver = PacketCipher::new(&secret).unwrap();
+
+        let mut data = b"hello minecraft".to_vec();
+        sender.encrypt(&mut data[..5]);
+        sender.encrypt(&mut data[5..]);
+        assert_ne!(&data, b"hello minecraft");
+        receiver.decrypt(&mut data);
+        assert_eq!(&data, b"hello minecraft");
+    }
+}
@@ -1,6 +1,6 @@
 use crate::net::login::encryption::EncryptionKeyPair;
 use crate::net::protocol::Packet;
-use crate::varint::utils::{write_string, write_varint};
+use crate::varint::utils::{read_string, read_varint, write_string, write_varint};
 use crate::ServerError;
 use bytes::BytesMut;
 
@@ -12,11 +12,12 @@ pub struct EncryptionRequest {
 }
 
 impl EncryptionRequest {
-    pub fn from_keypair(keypair: &EncryptionKeyPair) -> Self {
+    /// `verify_token` はコネクションごとに [`new_verify_token`](super::key::new_verify_token) で作る
+    pub fn from_keypair(keypair: &EncryptionKeyPair, verify_token: &[u8]) -> Self {
         EncryptionRequest {
             server_id: "".to_string(),
-            public_key: keypair.public_key_der(),
-            verify_token: keypair.verify_token().to_vec(),
+            public_key: keypair.public_key_der().to_vec(),
+            verify_token: verify_token.to_vec(),
         }
     }
 }
@@ -37,7 +38,22 @@ impl Packet for EncryptionRequest {
         Ok(())
     }
 
-    fn decode(_buf: &mut BytesMut) -> Result<Self, ServerError> {
-        Err(ServerError::Protocol("EncryptionRequest は送信用専用です".into()))
+    fn decode(buf: &mut BytesMut) -> Result<Self, ServerError> {
+        let invalid = || ServerError::Protocol("EncryptionRequest の読み込みに失敗".into());
+        let server_id = read_string(buf)?;
+
+        let key_len = read_varint(buf)? as usize;
+        if buf.len() < key_len {
+            return Err(invalid());
+        }
+        let public_key = buf.split_to(key_len).to_vec();
+
+        let token_len = read_varint(buf)? as usize;
+        if buf.len() < token_len {
+            return Err(invalid());
+        }
+        let verify_token = buf.split_to(token_len).to_vec();
+
+        Ok(EncryptionRequest { server_id, public_key, verify_token })
     }
 }
@@ -2,7 +2,7 @@ use bytes::BytesMut;
 use rsa::{Pkcs1v15Encrypt, RsaPrivateKey};
 use crate::net::protocol::{Packet, PacketError};
 use crate::ServerError;
-use crate::varint::utils::{read_varint, write_string, write_varint};
+use crate::varint::utils::{read_varint, write_varint};
 #[derive(Debug, Clone)]
 pub struct EncryptionResponse {
     pub shared_secret: Vec<u8>,
@@ -11,10 +11,18 @@ pub struct EncryptionResponse {
 
 impl EncryptionResponse {
     pub fn decode_with_key(buf: &mut BytesMut, private_key: &RsaPrivateKey) -> Result<Self, PacketError> {
-        let secret_len = read_varint(buf).ok_or(PacketError::DecodeError("shared_secretの長さ読み込み失敗".into()))?;
+        let secret_len = read_varint(buf)
+            .map_err(|e| PacketError::DecodeError(format!("shared_secretの長さ読み込み失敗: {}", e)))?;
+        if secret_len < 0 || buf.len() < secret_len as usize {
+            return Err(PacketError::IncompletePacket);
+        }
         let encrypted_secret = buf.split_to(secret_len as usize);
 
-        let token_len = read_varint(buf).ok_or(PacketError::DecodeError("verify_tokenの長さ読み込み失敗".into()))?;
+        let token_len = read_varint(buf)
+            .map_err(|e| PacketError::DecodeError(format!("verify_tokenの長さ読み込み失敗: {}", e)))?;
+        if token_len < 0 || buf.len() < token_len as usize {
+            return Err(PacketError::IncompletePacket);
+        }
         let encrypted_token = buf.split_to(token_len as usize);
 
         let shared_secret = private_key.decrypt(Pkcs1v15Encrypt, &encrypted_secret)
@@ -35,8 +43,13 @@ impl Packet for EncryptionResponse {
         0x01
     }
 
-    fn encode(&self, _buf: &mut BytesMut) -> Result<(), ServerError> {
-        Err(ServerError::Protocol("EncryptionResponse は受信用専用です".into()))
+    /// クライアント側で使う。各フィールドはサーバーの公開鍵で暗号化済みのものを入れる
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), ServerError> {
+        write_varint(buf, self.shared_secret.len() as i32)?;
+        buf.extend_from_slice(&self.shared_secret);
+        write_varint(buf, self.verify_token.len() as i32)?;
+        buf.extend_from_slice(&self.verify_token);
+        Ok(())
     }
 
     fn decode(_buf: &mut BytesMut) -> Result<Self, ServerError> {
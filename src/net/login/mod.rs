@@ -1,6 +1,7 @@
 pub mod start;
 pub mod success;
 pub mod disconnect;
-mod encryption;
-
-
+pub mod encryption;
+pub mod offline;
+pub mod auth;
+pub mod profile;
@@ -0,0 +1,26 @@
+use md5::{Digest, Md5};
+use uuid::Uuid;
+
+// オフラインモードの UUID
+//
+// vanilla は `UUID.nameUUIDFromBytes("OfflinePlayer:" + name)`、つまり MD5 による
+// バージョン3の UUID を使う。ホワイトリストや BAN の名前だけのエントリに UUID を埋めるときも同じ値にする。
+
+/// オフラインモードのプレイヤー UUID
+pub fn offline_uuid(username: &str) -> Uuid {
+    let mut bytes: [u8; 16] = Md5::digest(format!("OfflinePlayer:{}", username)).into();
+    bytes[6] = (bytes[6] & 0x0f) | 0x30;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    Uuid::from_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offline_uuid() {
+        // Java の UUID.nameUUIDFromBytes("OfflinePlayer:Notch".getBytes()) と一致する
+        assert_eq!(offline_uuid("Notch").to_string(), "b50ad385-829d-3141-a216-7e7d7539ba7f");
+    }
+}
@@ -0,0 +1,32 @@
+use uuid::Uuid;
+
+use crate::net::login::offline::offline_uuid;
+use crate::net::login::success::{LoginSuccess, ProfileProperty};
+
+/// ログインしたプレイヤーの UUID・名前・プロパティ (skin など)
+#[derive(Debug, Clone, PartialEq)]
+pub struct GameProfile {
+    pub uuid: Uuid,
+    pub name: String,
+    pub properties: Vec<ProfileProperty>,
+}
+
+impl GameProfile {
+    pub fn new(uuid: Uuid, name: &str) -> Self {
+        Self { uuid, name: name.to_string(), properties: Vec::new() }
+    }
+
+    /// オフラインモードの UUID (名前から作ったもの) か。オンライン認証の UUID とは重ならない
+    pub fn is_offline(&self) -> bool {
+        self.uuid == offline_uuid(&self.name)
+    }
+
+    /// このプロファイルでログインを完了させるパケット
+    pub fn login_success(&self) -> LoginSuccess {
+        LoginSuccess {
+            uuid: self.uuid,
+            username: self.name.clone(),
+            properties: self.properties.clone(),
+        }
+    }
+}
@@ -1,11 +1,29 @@
 use crate::net::protocol::Packet;
+use crate::utils::config::UsernamePolicy;
 use crate::varint::utils::{read_string, write_string};
 use crate::ServerError;
-use bytes::BytesMut;
+use bytes::{Buf, BufMut, BytesMut};
+use uuid::Uuid;
+
+/// ユーザー名の最大文字数
+pub const MAX_USERNAME_LENGTH: usize = 16;
+
+/// ユーザー名が `policy` で許される形か
+pub fn is_valid_username(name: &str, policy: UsernamePolicy) -> bool {
+    let length = name.chars().count();
+    if length == 0 || length > MAX_USERNAME_LENGTH {
+        return false;
+    }
+    match policy {
+        UsernamePolicy::Strict => name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        UsernamePolicy::Lenient => !name.chars().any(|c| c.is_whitespace() || c.is_control()),
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct LoginStart {
     pub username: String,
+    pub player_uuid: Option<Uuid>,
 }
 
 impl Packet for LoginStart {
@@ -15,11 +33,45 @@ impl Packet for LoginStart {
 
     fn encode(&self, buf: &mut BytesMut) -> Result<(), ServerError> {
         write_string(buf, &self.username)?;
+        buf.put_u8(self.player_uuid.is_some() as u8);
+        if let Some(uuid) = self.player_uuid {
+            buf.put_u128(uuid.as_u128());
+        }
         Ok(())
     }
 
     fn decode(buf: &mut BytesMut) -> Result<LoginStart, ServerError> {
-        let username = read_string(buf).ok_or_else(|| ServerError::Protocol("ユーザー名の読み込みに失敗".into()))?;
-        Ok(Self { username })
+        let username = read_string(buf)?;
+        // 1.19 以前のクライアントは UUID を送らない
+        let player_uuid = if buf.has_remaining() && buf.get_u8() != 0 {
+            if buf.remaining() < 16 {
+                return Err(ServerError::Protocol("UUIDの読み込みに失敗".into()));
+            }
+            Some(Uuid::from_u128(buf.get_u128()))
+        } else {
+            None
+        };
+        Ok(Self { username, player_uuid })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_valid_username() {
+        assert!(is_valid_username("Steve", UsernamePolicy::Strict));
+        assert!(is_valid_username("a_1234567890_bcd", UsernamePolicy::Strict));
+        assert!(!is_valid_username("", UsernamePolicy::Strict));
+        assert!(!is_valid_username("a_1234567890_bcde", UsernamePolicy::Strict));
+        assert!(!is_valid_username(".Bedrock", UsernamePolicy::Strict));
+        assert!(!is_valid_username("スティーブ", UsernamePolicy::Strict));
+
+        assert!(is_valid_username(".Bedrock", UsernamePolicy::Lenient));
+        assert!(is_valid_username("スティーブ", UsernamePolicy::Lenient));
+        assert!(!is_valid_username("two words", UsernamePolicy::Lenient));
+        assert!(!is_valid_username("tab\tname", UsernamePolicy::Lenient));
+        assert!(!is_valid_username(&"a".repeat(17), UsernamePolicy::Lenient));
     }
 }
@@ -1,13 +1,29 @@
+use crate::net::protocol::types::ProtocolRead;
 use crate::net::protocol::Packet;
-use crate::varint::utils::write_string;
+use crate::varint::utils::{read_string, read_varint, string_len, varint_len, write_string, write_varint};
 use crate::ServerError;
-use bytes::BytesMut;
+use bytes::{Buf, BufMut, BytesMut};
 use uuid::Uuid;
 
+/// ゲームプロファイルのプロパティ (skin など)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProfileProperty {
+    pub name: String,
+    pub value: String,
+    pub signature: Option<String>,
+}
+
+impl ProtocolRead for ProfileProperty {
+    fn read<B: Buf + ?Sized>(buf: &mut B) -> Result<Self, ServerError> {
+        Ok(Self { name: String::read(buf)?, value: String::read(buf)?, signature: Option::read(buf)? })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct LoginSuccess {
     pub uuid: Uuid,
     pub username: String,
+    pub properties: Vec<ProfileProperty>,
 }
 
 impl Packet for LoginSuccess {
@@ -16,12 +32,56 @@ impl Packet for LoginSuccess {
     }
 
     fn encode(&self, buf: &mut BytesMut) -> Result<(), ServerError> {
-        write_string(buf, &self.uuid.hyphenated().to_string())?;
+        buf.put_u128(self.uuid.as_u128());
         write_string(buf, &self.username)?;
+        write_varint(buf, self.properties.len() as i32)?;
+        for property in &self.properties {
+            write_string(buf, &property.name)?;
+            write_string(buf, &property.value)?;
+            buf.put_u8(property.signature.is_some() as u8);
+            if let Some(signature) = &property.signature {
+                write_string(buf, signature)?;
+            }
+        }
         Ok(())
     }
 
-    fn decode(_buf: &mut BytesMut) -> Result<LoginSuccess, ServerError> {
-        Err(ServerError::Protocol("LoginSuccess はクライアントへの送信専用です".into()))
+    fn encoded_len(&self) -> Option<usize> {
+        let properties: usize = self.properties.iter()
+            .map(|property| {
+                string_len(&property.name)
+                    + string_len(&property.value)
+                    + 1
+                    + property.signature.as_deref().map_or(0, string_len)
+            })
+            .sum();
+        Some(16 + string_len(&self.username) + varint_len(self.properties.len() as i32) + properties)
+    }
+
+    fn decode(buf: &mut BytesMut) -> Result<LoginSuccess, ServerError> {
+        let invalid = || ServerError::Protocol("LoginSuccess の読み込みに失敗".into());
+        if buf.remaining() < 16 {
+            return Err(invalid());
+        }
+        let uuid = Uuid::from_u128(buf.get_u128());
+        let username = read_string(buf)?;
+
+        let count = read_varint(buf)?;
+        let mut properties = Vec::new();
+        for _ in 0..count {
+            let name = read_string(buf)?;
+            let value = read_string(buf)?;
+            if !buf.has_remaining() {
+                return Err(invalid());
+            }
+            let signature = if buf.get_u8() != 0 {
+                Some(read_string(buf)?)
+            } else {
+                None
+            };
+            properties.push(ProfileProperty { name, value, signature });
+        }
+
+        Ok(LoginSuccess { uuid, username, properties })
     }
 }
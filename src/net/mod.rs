@@ -1,8 +1,18 @@
 pub mod server;
+pub mod access;
 pub mod connection;
 pub mod error;
 pub mod status;
-pub(crate) mod protocol;
-mod login;
+pub mod context;
+pub mod query;
+pub mod rcon;
+pub mod reload;
+pub mod protocol;
+pub mod capture;
+pub mod stats;
+pub mod passwords;
+pub mod players;
+pub mod login;
+pub mod play;
 
-pub use server::start_server;
\ No newline at end of file
+pub use server::{serve, start_server};
\ No newline at end of file
@@ -0,0 +1,307 @@
+//! オフラインモード向けのパスワード認証 (AuthMe 方式)
+//!
+//! オフラインモードでは誰でも好きな名前で入れるので、初回の `/register` で決めたパスワードを
+//! 次回以降 `/login` で確かめる。認証が済むまではチャットと移動を受け付けない。
+//! パスワードは Argon2id のハッシュ (PHC 文字列) だけを、オフライン UUID をキーに保存する。
+
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use log::{error, info};
+use parking_lot::RwLock;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+use uuid::Uuid;
+
+use crate::net::access::{load_list, save_list};
+use crate::net::error::{Result, ServerError};
+use crate::net::login::profile::GameProfile;
+use crate::net::rcon::AuthRateLimiter;
+use crate::utils::config::PasswordLoginConfig;
+
+pub const PASSWORDS_FILE: &str = "passwords.json";
+
+pub const REGISTER_PROMPT: &str = "Please register with /register <password>";
+pub const LOGIN_PROMPT: &str = "Please log in with /login <password>";
+pub const TIMEOUT_MESSAGE: &str = "Login timed out";
+pub const TOO_MANY_ATTEMPTS_MESSAGE: &str = "Too many failed login attempts";
+
+/// `passwords.json` のエントリ
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PasswordEntry {
+    pub uuid: Uuid,
+    /// 確認用。照合には使わない
+    pub name: String,
+    pub hash: String,
+}
+
+/// パスワードを Argon2id でハッシュにする。重いので非同期の処理からは `spawn_blocking` で呼ぶ
+pub fn hash_password(password: &str) -> Result<String> {
+    let mut salt = [0u8; 16];
+    rand::rng().fill_bytes(&mut salt);
+    let salt = SaltString::encode_b64(&salt)
+        .map_err(|e| ServerError::Protocol(format!("ソルトを作れません: {}", e)))?;
+    let hash = Argon2::default().hash_password(password.as_bytes(), &salt)
+        .map_err(|e| ServerError::Protocol(format!("パスワードをハッシュにできません: {}", e)))?;
+    Ok(hash.to_string())
+}
+
+/// 壊れたハッシュは一致しないものとして扱う
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash)
+        .is_ok_and(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+}
+
+/// 登録済みのパスワード。変更はすぐにファイルへ書き出す
+pub struct PasswordStore {
+    path: PathBuf,
+    entries: RwLock<Vec<PasswordEntry>>,
+}
+
+impl PasswordStore {
+    /// `directory` の `passwords.json` を読み込む。読めなければログに残して空にする
+    pub fn load(directory: impl AsRef<Path>) -> Self {
+        let path = directory.as_ref().join(PASSWORDS_FILE);
+        let entries = load_list(&path).unwrap_or_else(|e| {
+            error!("Failed to load {}, starting with no registered players: {}", PASSWORDS_FILE, e);
+            Vec::new()
+        });
+        Self { path, entries: RwLock::new(entries) }
+    }
+
+    pub fn is_registered(&self, uuid: &Uuid) -> bool {
+        self.entries.read().iter().any(|entry| entry.uuid == *uuid)
+    }
+
+    pub fn hash(&self, uuid: &Uuid) -> Option<String> {
+        self.entries.read().iter().find(|entry| entry.uuid == *uuid).map(|entry| entry.hash.clone())
+    }
+
+    /// 登録する。既に登録済みなら何もせず `false`
+    pub fn register(&self, entry: PasswordEntry) -> Result<bool> {
+        let mut entries = self.entries.write();
+        if entries.iter().any(|existing| existing.uuid == entry.uuid) {
+            return Ok(false);
+        }
+        entries.push(entry);
+        save_list(&self.path, &entries)?;
+        Ok(true)
+    }
+
+    /// 登録を消す。パスワードを忘れたプレイヤーを登録し直させるときに使う
+    pub fn unregister(&self, uuid: &Uuid) -> Result<bool> {
+        let mut entries = self.entries.write();
+        let before = entries.len();
+        entries.retain(|entry| entry.uuid != *uuid);
+        if entries.len() == before {
+            return Ok(false);
+        }
+        save_list(&self.path, &entries)?;
+        Ok(true)
+    }
+}
+
+/// パスワードの失敗を接続をまたいで数える。つなぎ直しで回数が戻らないよう、
+/// IP アドレスとプレイヤーの両方で数え、どちらかが上限に達したらログインさせない
+pub struct LoginLimiter {
+    by_ip: AuthRateLimiter<IpAddr>,
+    by_player: AuthRateLimiter<Uuid>,
+}
+
+impl LoginLimiter {
+    pub fn new(config: &PasswordLoginConfig) -> Self {
+        Self {
+            by_ip: AuthRateLimiter::new(config.max_attempts, config.block_duration, config.block_duration),
+            by_player: AuthRateLimiter::new(config.max_attempts, config.block_duration, config.block_duration),
+        }
+    }
+
+    /// 設定の再読み込みで変わった上限を反映する
+    pub fn set_limits(&self, config: &PasswordLoginConfig) {
+        self.by_ip.set_limits(config.max_attempts, config.block_duration, config.block_duration);
+        self.by_player.set_limits(config.max_attempts, config.block_duration, config.block_duration);
+    }
+
+    pub fn is_blocked(&self, ip: IpAddr, uuid: &Uuid) -> bool {
+        // 両方を確かめ、期限切れの記録を片付ける
+        let ip_blocked = self.by_ip.is_blocked(&ip);
+        self.by_player.is_blocked(uuid) || ip_blocked
+    }
+
+    /// ブロックされるまでに残っている失敗の回数
+    pub fn failures_left(&self, ip: IpAddr, uuid: &Uuid) -> u32 {
+        self.by_ip.failures_left(&ip).min(self.by_player.failures_left(uuid))
+    }
+
+    /// 失敗を記録する。ブロックされた場合は `true`
+    pub fn record_failure(&self, ip: IpAddr, uuid: Uuid) -> bool {
+        let ip_blocked = self.by_ip.record_failure(ip);
+        self.by_player.record_failure(uuid) || ip_blocked
+    }
+
+    pub fn record_success(&self, ip: IpAddr, uuid: &Uuid) {
+        self.by_ip.record_success(&ip);
+        self.by_player.record_success(uuid);
+    }
+}
+
+/// 認証前のプレイヤーのコマンドへの応答
+#[derive(Debug, Clone, PartialEq)]
+pub enum GateReply {
+    /// メッセージを表示して、認証待ちのまま続ける
+    Message(String),
+    /// 認証が済んだ。メッセージを表示して制限を解く
+    Authenticated(String),
+    /// 理由を表示して切断する
+    Kick(String),
+}
+
+/// パスワード認証の対象か。オンライン認証のプレイヤーは対象外
+pub fn applies_to(config: &PasswordLoginConfig, profile: &GameProfile) -> bool {
+    config.enabled && profile.is_offline()
+}
+
+/// `/register` か `/login` を済ませるまでの制限状態
+pub struct PasswordGate {
+    registered: bool,
+    /// 失敗を数えるための接続元
+    remote: IpAddr,
+    deadline: Instant,
+}
+
+impl PasswordGate {
+    /// 有効になっていて、オフラインのプレイヤーなら制限をかける
+    pub fn for_player(config: &PasswordLoginConfig, store: &PasswordStore, profile: &GameProfile, remote: IpAddr) -> Option<Self> {
+        if !applies_to(config, profile) {
+            return None;
+        }
+        Some(Self {
+            registered: store.is_registered(&profile.uuid),
+            remote,
+            deadline: Instant::now() + config.timeout,
+        })
+    }
+
+    /// これまでに認証を終えなければ切断する
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    pub fn prompt(&self) -> &'static str {
+        if self.registered { LOGIN_PROMPT } else { REGISTER_PROMPT }
+    }
+
+    /// 先頭の `/` を除いたコマンドを処理する。`register` と `login` 以外には案内を返す
+    pub async fn handle_command(&mut self, config: &PasswordLoginConfig, store: &PasswordStore, limiter: &LoginLimiter, profile: &GameProfile, command: &str) -> Result<GateReply> {
+        let args: Vec<&str> = command.split_whitespace().collect();
+        match args.as_slice() {
+            ["register", password] => self.register(config, store, profile, password).await,
+            ["register", password, confirm] => {
+                if password != confirm {
+                    return Ok(GateReply::Message("The passwords do not match".to_string()));
+                }
+                self.register(config, store, profile, password).await
+            }
+            ["login", password] => self.login(store, limiter, profile, password).await,
+            _ => Ok(GateReply::Message(self.prompt().to_string())),
+        }
+    }
+
+    async fn register(&mut self, config: &PasswordLoginConfig, store: &PasswordStore, profile: &GameProfile, password: &str) -> Result<GateReply> {
+        if self.registered {
+            return Ok(GateReply::Message(format!("You are already registered. {}", LOGIN_PROMPT)));
+        }
+        if password.chars().count() < config.min_password_length {
+            return Ok(GateReply::Message(format!("Your password must be at least {} characters long", config.min_password_length)));
+        }
+        let password = password.to_string();
+        let hash = tokio::task::spawn_blocking(move || hash_password(&password)).await
+            .map_err(|e| ServerError::Protocol(format!("パスワードのハッシュ化に失敗: {}", e)))??;
+        let entry = PasswordEntry { uuid: profile.uuid, name: profile.name.clone(), hash };
+        if !store.register(entry)? {
+            // 同じ名前の別の接続が先に登録した
+            self.registered = true;
+            return Ok(GateReply::Message(format!("You are already registered. {}", LOGIN_PROMPT)));
+        }
+        info!("{} registered a password", profile.name);
+        Ok(GateReply::Authenticated("Successfully registered!".to_string()))
+    }
+
+    async fn login(&mut self, store: &PasswordStore, limiter: &LoginLimiter, profile: &GameProfile, password: &str) -> Result<GateReply> {
+        // 別の接続で使い切っていれば、ここでも試させない
+        if limiter.is_blocked(self.remote, &profile.uuid) {
+            return Ok(GateReply::Kick(TOO_MANY_ATTEMPTS_MESSAGE.to_string()));
+        }
+        let Some(hash) = store.hash(&profile.uuid) else {
+            self.registered = false;
+            return Ok(GateReply::Message(format!("You are not registered yet. {}", REGISTER_PROMPT)));
+        };
+        let password = password.to_string();
+        let matches = tokio::task::spawn_blocking(move || verify_password(&password, &hash)).await
+            .map_err(|e| ServerError::Protocol(format!("パスワードの照合に失敗: {}", e)))?;
+        if matches {
+            limiter.record_success(self.remote, &profile.uuid);
+            return Ok(GateReply::Authenticated("Successfully logged in!".to_string()));
+        }
+        if limiter.record_failure(self.remote, profile.uuid) {
+            info!("{} ({}) failed to log in too many times", profile.name, self.remote);
+            return Ok(GateReply::Kick(TOO_MANY_ATTEMPTS_MESSAGE.to_string()));
+        }
+        Ok(GateReply::Message(format!("Wrong password ({} attempts left)", limiter.failures_left(self.remote, &profile.uuid))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::login::offline::offline_uuid;
+
+    #[tokio::test]
+    async fn test_register_then_login() {
+        let dir = std::env::temp_dir().join(format!("server-passwords-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let config = PasswordLoginConfig { enabled: true, max_attempts: 2, ..PasswordLoginConfig::default() };
+        let store = PasswordStore::load(&dir);
+        let limiter = LoginLimiter::new(&config);
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let steve = GameProfile::new(offline_uuid("Steve"), "Steve");
+
+        // オンライン認証のプレイヤーと、無効のときは制限しない
+        assert!(PasswordGate::for_player(&config, &store, &GameProfile::new(Uuid::new_v4(), "Steve"), ip).is_none());
+        assert!(PasswordGate::for_player(&PasswordLoginConfig::default(), &store, &steve, ip).is_none());
+
+        let mut gate = PasswordGate::for_player(&config, &store, &steve, ip).unwrap();
+        assert_eq!(gate.prompt(), REGISTER_PROMPT);
+        assert_eq!(gate.handle_command(&config, &store, &limiter, &steve, "spawn").await.unwrap(), GateReply::Message(REGISTER_PROMPT.to_string()));
+        assert!(matches!(gate.handle_command(&config, &store, &limiter, &steve, "register short").await.unwrap(), GateReply::Message(m) if m.contains("at least 6")));
+        assert!(matches!(gate.handle_command(&config, &store, &limiter, &steve, "register hunter22 hunter23").await.unwrap(), GateReply::Message(m) if m.contains("do not match")));
+        assert!(matches!(gate.handle_command(&config, &store, &limiter, &steve, "register hunter22").await.unwrap(), GateReply::Authenticated(_)));
+
+        // ハッシュだけが保存され、読み直しても残っている
+        let text = std::fs::read_to_string(dir.join(PASSWORDS_FILE)).unwrap();
+        assert!(text.contains("$argon2id$") && !text.contains("hunter22"));
+        let store = PasswordStore::load(&dir);
+        let mut gate = PasswordGate::for_player(&config, &store, &steve, ip).unwrap();
+        assert_eq!(gate.prompt(), LOGIN_PROMPT);
+        assert!(matches!(gate.handle_command(&config, &store, &limiter, &steve, "login hunter22").await.unwrap(), GateReply::Authenticated(_)));
+
+        let mut gate = PasswordGate::for_player(&config, &store, &steve, ip).unwrap();
+        assert!(matches!(gate.handle_command(&config, &store, &limiter, &steve, "login wrong").await.unwrap(), GateReply::Message(m) if m.contains("1 attempts left")));
+        assert_eq!(gate.handle_command(&config, &store, &limiter, &steve, "login wrong").await.unwrap(), GateReply::Kick(TOO_MANY_ATTEMPTS_MESSAGE.to_string()));
+
+        // つなぎ直しても、別の IP アドレスからでも回数は戻らない
+        assert!(limiter.is_blocked(ip, &steve.uuid));
+        let mut gate = PasswordGate::for_player(&config, &store, &steve, "10.0.0.2".parse().unwrap()).unwrap();
+        assert_eq!(gate.handle_command(&config, &store, &limiter, &steve, "login hunter22").await.unwrap(), GateReply::Kick(TOO_MANY_ATTEMPTS_MESSAGE.to_string()));
+        // 同じ IP アドレスからの別のプレイヤーも締め出す
+        assert!(limiter.is_blocked(ip, &offline_uuid("Alex")));
+        assert!(!limiter.is_blocked("10.0.0.3".parse().unwrap(), &offline_uuid("Alex")));
+
+        assert!(store.unregister(&steve.uuid).unwrap());
+        assert!(!store.is_registered(&steve.uuid));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,177 @@
+use crate::net::protocol::Packet;
+use crate::varint::utils::{read_string, read_varint, string_len, write_string, write_varint};
+use crate::ServerError;
+use bytes::{Buf, BufMut, BytesMut};
+
+pub const CHAT_COMMAND_ID: i32 = 0x04;
+pub const CHAT_MESSAGE_ID: i32 = 0x05;
+pub const SYSTEM_CHAT_MESSAGE_ID: i32 = 0x64;
+
+/// 既読メッセージを表す固定長ビットセット (20ビット) のバイト数
+const ACKNOWLEDGED_BYTES: usize = 3;
+const SIGNATURE_BYTES: usize = 256;
+
+fn incomplete(name: &str) -> ServerError {
+    ServerError::Protocol(format!("{} の読み込みに失敗", name))
+}
+
+/// Chat Message (serverbound)。署名なしのメッセージのみ扱う
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChatMessage {
+    pub message: String,
+    pub timestamp: i64,
+    pub salt: i64,
+}
+
+impl ChatMessage {
+    pub fn new(message: impl Into<String>) -> Self {
+        Self {
+            message: message.into(),
+            timestamp: chrono::Utc::now().timestamp_millis(),
+            salt: 0,
+        }
+    }
+}
+
+impl Packet for ChatMessage {
+    fn packet_id(&self) -> i32 {
+        CHAT_MESSAGE_ID
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), ServerError> {
+        write_string(buf, &self.message)?;
+        buf.put_i64(self.timestamp);
+        buf.put_i64(self.salt);
+        buf.put_u8(0); // 署名なし
+        write_varint(buf, 0)?; // message count
+        buf.put_bytes(0, ACKNOWLEDGED_BYTES);
+        Ok(())
+    }
+
+    fn encoded_len(&self) -> Option<usize> {
+        Some(string_len(&self.message) + 8 + 8 + 1 + 1 + ACKNOWLEDGED_BYTES)
+    }
+
+    fn decode(buf: &mut BytesMut) -> Result<Self, ServerError> {
+        let message = read_string(buf)?;
+        if buf.remaining() < 17 {
+            return Err(incomplete("Chat Message"));
+        }
+        let timestamp = buf.get_i64();
+        let salt = buf.get_i64();
+        if buf.get_u8() != 0 {
+            if buf.remaining() < SIGNATURE_BYTES {
+                return Err(incomplete("Chat Message"));
+            }
+            buf.advance(SIGNATURE_BYTES);
+        }
+        // message count と既読ビットセットは使わない
+        read_varint(buf)?;
+        if buf.remaining() < ACKNOWLEDGED_BYTES {
+            return Err(incomplete("Chat Message"));
+        }
+        buf.advance(ACKNOWLEDGED_BYTES);
+        Ok(Self { message, timestamp, salt })
+    }
+}
+
+/// Chat Command (serverbound)。先頭の `/` を除いたコマンド文字列
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChatCommand {
+    pub command: String,
+    pub timestamp: i64,
+    pub salt: i64,
+}
+
+impl ChatCommand {
+    pub fn new(command: impl Into<String>) -> Self {
+        Self {
+            command: command.into(),
+            timestamp: chrono::Utc::now().timestamp_millis(),
+            salt: 0,
+        }
+    }
+}
+
+impl Packet for ChatCommand {
+    fn packet_id(&self) -> i32 {
+        CHAT_COMMAND_ID
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), ServerError> {
+        write_string(buf, &self.command)?;
+        buf.put_i64(self.timestamp);
+        buf.put_i64(self.salt);
+        write_varint(buf, 0)?; // 引数の署名なし
+        write_varint(buf, 0)?; // message count
+        buf.put_bytes(0, ACKNOWLEDGED_BYTES);
+        Ok(())
+    }
+
+    fn encoded_len(&self) -> Option<usize> {
+        Some(string_len(&self.command) + 8 + 8 + 1 + 1 + ACKNOWLEDGED_BYTES)
+    }
+
+    fn decode(buf: &mut BytesMut) -> Result<Self, ServerError> {
+        let command = read_string(buf)?;
+        if buf.remaining() < 16 {
+            return Err(incomplete("Chat Command"));
+        }
+        let timestamp = buf.get_i64();
+        let salt = buf.get_i64();
+        let signatures = read_varint(buf)?;
+        for _ in 0..signatures {
+            read_string(buf)?;
+            if buf.remaining() < SIGNATURE_BYTES {
+                return Err(incomplete("Chat Command"));
+            }
+            buf.advance(SIGNATURE_BYTES);
+        }
+        read_varint(buf)?;
+        if buf.remaining() < ACKNOWLEDGED_BYTES {
+            return Err(incomplete("Chat Command"));
+        }
+        buf.advance(ACKNOWLEDGED_BYTES);
+        Ok(Self { command, timestamp, salt })
+    }
+}
+
+/// System Chat Message (clientbound)
+#[derive(Debug, Clone, PartialEq)]
+pub struct SystemChatMessage {
+    pub content_json: String,
+    pub overlay: bool,
+}
+
+impl SystemChatMessage {
+    pub fn text(text: &str) -> Self {
+        Self {
+            content_json: serde_json::json!({ "text": text }).to_string(),
+            overlay: false,
+        }
+    }
+}
+
+impl Packet for SystemChatMessage {
+    fn packet_id(&self) -> i32 {
+        SYSTEM_CHAT_MESSAGE_ID
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), ServerError> {
+        write_string(buf, &self.content_json)?;
+        buf.put_u8(self.overlay as u8);
+        Ok(())
+    }
+
+    fn encoded_len(&self) -> Option<usize> {
+        Some(string_len(&self.content_json) + 1)
+    }
+
+    fn decode(buf: &mut BytesMut) -> Result<Self, ServerError> {
+        let content_json = read_string(buf)?;
+        if !buf.has_remaining() {
+            return Err(incomplete("System Chat Message"));
+        }
+        Ok(Self { content_json, overlay: buf.get_u8() != 0 })
+    }
+}
@@ -0,0 +1,33 @@
+use crate::net::protocol::Packet;
+use crate::varint::utils::{read_varint, varint_len, write_varint};
+use crate::ServerError;
+use bytes::BytesMut;
+
+pub const SET_CENTER_CHUNK_ID: i32 = 0x4E;
+
+/// Set Center Chunk。クライアントはこのチャンクを中心に描画距離の外のチャンクを捨てる
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SetCenterChunk {
+    pub chunk_x: i32,
+    pub chunk_z: i32,
+}
+
+impl Packet for SetCenterChunk {
+    fn packet_id(&self) -> i32 {
+        SET_CENTER_CHUNK_ID
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), ServerError> {
+        write_varint(buf, self.chunk_x)?;
+        write_varint(buf, self.chunk_z)?;
+        Ok(())
+    }
+
+    fn encoded_len(&self) -> Option<usize> {
+        Some(varint_len(self.chunk_x) + varint_len(self.chunk_z))
+    }
+
+    fn decode(buf: &mut BytesMut) -> Result<Self, ServerError> {
+        Ok(Self { chunk_x: read_varint(buf)?, chunk_z: read_varint(buf)? })
+    }
+}
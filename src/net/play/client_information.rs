@@ -0,0 +1,91 @@
+use crate::net::protocol::Packet;
+use crate::varint::utils::{read_string, read_varint, string_len, varint_len, write_string, write_varint};
+use crate::ServerError;
+use bytes::{Buf, BufMut, BytesMut};
+
+pub const CLIENT_INFORMATION_ID: i32 = 0x08;
+
+/// Client Information (serverbound)。参加直後と設定を変えたときに送られる
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientInformation {
+    /// `en_us` など
+    pub locale: String,
+    /// クライアント側の描画距離 (チャンク)
+    pub view_distance: i8,
+    /// 0: 全て表示, 1: コマンドのみ, 2: 非表示
+    pub chat_mode: i32,
+    pub chat_colors: bool,
+    /// スキンのレイヤーのビットマスク
+    pub displayed_skin_parts: u8,
+    /// 0: 左手, 1: 右手
+    pub main_hand: i32,
+    pub text_filtering: bool,
+    pub allow_server_listings: bool,
+}
+
+impl Default for ClientInformation {
+    /// vanilla のクライアントの初期設定
+    fn default() -> Self {
+        Self {
+            locale: "en_us".to_string(),
+            view_distance: 10,
+            chat_mode: 0,
+            chat_colors: true,
+            displayed_skin_parts: 0x7F,
+            main_hand: 1,
+            text_filtering: false,
+            allow_server_listings: true,
+        }
+    }
+}
+
+impl Packet for ClientInformation {
+    fn packet_id(&self) -> i32 {
+        CLIENT_INFORMATION_ID
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), ServerError> {
+        write_string(buf, &self.locale)?;
+        buf.put_i8(self.view_distance);
+        write_varint(buf, self.chat_mode)?;
+        buf.put_u8(self.chat_colors as u8);
+        buf.put_u8(self.displayed_skin_parts);
+        write_varint(buf, self.main_hand)?;
+        buf.put_u8(self.text_filtering as u8);
+        buf.put_u8(self.allow_server_listings as u8);
+        Ok(())
+    }
+
+    fn encoded_len(&self) -> Option<usize> {
+        Some(string_len(&self.locale) + 1 + varint_len(self.chat_mode) + 2 + varint_len(self.main_hand) + 2)
+    }
+
+    fn decode(buf: &mut BytesMut) -> Result<Self, ServerError> {
+        let incomplete = || ServerError::Protocol("Client Information の読み込みに失敗".into());
+        let locale = read_string(buf)?;
+        if !buf.has_remaining() {
+            return Err(incomplete());
+        }
+        let view_distance = buf.get_i8();
+        let chat_mode = read_varint(buf)?;
+        if buf.remaining() < 2 {
+            return Err(incomplete());
+        }
+        let chat_colors = buf.get_u8() != 0;
+        let displayed_skin_parts = buf.get_u8();
+        let main_hand = read_varint(buf)?;
+        if buf.remaining() < 2 {
+            return Err(incomplete());
+        }
+        Ok(Self {
+            locale,
+            view_distance,
+            chat_mode,
+            chat_colors,
+            displayed_skin_parts,
+            main_hand,
+            text_filtering: buf.get_u8() != 0,
+            allow_server_listings: buf.get_u8() != 0,
+        })
+    }
+}
@@ -0,0 +1,30 @@
+use crate::net::protocol::Packet;
+use crate::varint::utils::{read_string, string_len, write_string};
+use crate::ServerError;
+use bytes::BytesMut;
+
+/// Disconnect (play)
+#[derive(Debug, Clone)]
+pub struct PlayDisconnect {
+    pub reason_json: String,
+}
+
+impl Packet for PlayDisconnect {
+    fn packet_id(&self) -> i32 {
+        0x1A
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), ServerError> {
+        write_string(buf, &self.reason_json)?;
+        Ok(())
+    }
+
+    fn encoded_len(&self) -> Option<usize> {
+        Some(string_len(&self.reason_json))
+    }
+
+    fn decode(buf: &mut BytesMut) -> Result<Self, ServerError> {
+        let reason_json = read_string(buf)?;
+        Ok(Self { reason_json })
+    }
+}
@@ -0,0 +1,323 @@
+use crate::net::protocol::types::{read_array_bounded, Angle, ProtocolRead, ProtocolWrite, VarInt, MAX_ARRAY_LENGTH};
+use crate::net::protocol::Packet;
+use crate::ServerError;
+use bytes::BytesMut;
+use uuid::Uuid;
+
+pub const SPAWN_ENTITY_ID: i32 = 0x01;
+pub const SPAWN_PLAYER_ID: i32 = 0x03;
+pub const UPDATE_ENTITY_POSITION_ID: i32 = 0x2B;
+pub const UPDATE_ENTITY_POSITION_AND_ROTATION_ID: i32 = 0x2C;
+pub const UPDATE_ENTITY_ROTATION_ID: i32 = 0x2D;
+pub const REMOVE_ENTITIES_ID: i32 = 0x3E;
+pub const SET_HEAD_ROTATION_ID: i32 = 0x42;
+pub const TELEPORT_ENTITY_ID: i32 = 0x68;
+
+/// Spawn Entity。プレイヤー以外のエンティティを出す
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpawnEntity {
+    pub entity_id: i32,
+    pub uuid: Uuid,
+    pub entity_type: i32,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub pitch: Angle,
+    pub yaw: Angle,
+    pub head_yaw: Angle,
+    /// 種類ごとの追加データ (落下するブロックの種類など)
+    pub data: i32,
+    /// 1/8000 ブロック毎 tick
+    pub velocity: [i16; 3],
+}
+
+impl Packet for SpawnEntity {
+    fn packet_id(&self) -> i32 {
+        SPAWN_ENTITY_ID
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), ServerError> {
+        VarInt(self.entity_id).write(buf)?;
+        self.uuid.write(buf)?;
+        VarInt(self.entity_type).write(buf)?;
+        self.x.write(buf)?;
+        self.y.write(buf)?;
+        self.z.write(buf)?;
+        self.pitch.write(buf)?;
+        self.yaw.write(buf)?;
+        self.head_yaw.write(buf)?;
+        VarInt(self.data).write(buf)?;
+        for velocity in self.velocity {
+            velocity.write(buf)?;
+        }
+        Ok(())
+    }
+
+    fn decode(buf: &mut BytesMut) -> Result<Self, ServerError> {
+        Ok(Self {
+            entity_id: VarInt::read(buf)?.0,
+            uuid: Uuid::read(buf)?,
+            entity_type: VarInt::read(buf)?.0,
+            x: f64::read(buf)?,
+            y: f64::read(buf)?,
+            z: f64::read(buf)?,
+            pitch: Angle::read(buf)?,
+            yaw: Angle::read(buf)?,
+            head_yaw: Angle::read(buf)?,
+            data: VarInt::read(buf)?.0,
+            velocity: [i16::read(buf)?, i16::read(buf)?, i16::read(buf)?],
+        })
+    }
+}
+
+/// Spawn Player。先に Player Info Update で UUID が伝わっていないとクライアントは無視する
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpawnPlayer {
+    pub entity_id: i32,
+    pub uuid: Uuid,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub yaw: Angle,
+    pub pitch: Angle,
+}
+
+impl Packet for SpawnPlayer {
+    fn packet_id(&self) -> i32 {
+        SPAWN_PLAYER_ID
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), ServerError> {
+        VarInt(self.entity_id).write(buf)?;
+        self.uuid.write(buf)?;
+        self.x.write(buf)?;
+        self.y.write(buf)?;
+        self.z.write(buf)?;
+        self.yaw.write(buf)?;
+        self.pitch.write(buf)?;
+        Ok(())
+    }
+
+    fn decode(buf: &mut BytesMut) -> Result<Self, ServerError> {
+        Ok(Self {
+            entity_id: VarInt::read(buf)?.0,
+            uuid: Uuid::read(buf)?,
+            x: f64::read(buf)?,
+            y: f64::read(buf)?,
+            z: f64::read(buf)?,
+            yaw: Angle::read(buf)?,
+            pitch: Angle::read(buf)?,
+        })
+    }
+}
+
+/// Update Entity Position。移動量は 1/4096 ブロック単位なので、8 ブロックまでしか表せない
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UpdateEntityPosition {
+    pub entity_id: i32,
+    pub delta: [i16; 3],
+    pub on_ground: bool,
+}
+
+impl Packet for UpdateEntityPosition {
+    fn packet_id(&self) -> i32 {
+        UPDATE_ENTITY_POSITION_ID
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), ServerError> {
+        VarInt(self.entity_id).write(buf)?;
+        for delta in self.delta {
+            delta.write(buf)?;
+        }
+        self.on_ground.write(buf)
+    }
+
+    fn decode(buf: &mut BytesMut) -> Result<Self, ServerError> {
+        Ok(Self {
+            entity_id: VarInt::read(buf)?.0,
+            delta: [i16::read(buf)?, i16::read(buf)?, i16::read(buf)?],
+            on_ground: bool::read(buf)?,
+        })
+    }
+}
+
+/// Update Entity Position and Rotation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UpdateEntityPositionAndRotation {
+    pub entity_id: i32,
+    pub delta: [i16; 3],
+    pub yaw: Angle,
+    pub pitch: Angle,
+    pub on_ground: bool,
+}
+
+impl Packet for UpdateEntityPositionAndRotation {
+    fn packet_id(&self) -> i32 {
+        UPDATE_ENTITY_POSITION_AND_ROTATION_ID
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), ServerError> {
+        VarInt(self.entity_id).write(buf)?;
+        for delta in self.delta {
+            delta.write(buf)?;
+        }
+        self.yaw.write(buf)?;
+        self.pitch.write(buf)?;
+        self.on_ground.write(buf)
+    }
+
+    fn decode(buf: &mut BytesMut) -> Result<Self, ServerError> {
+        Ok(Self {
+            entity_id: VarInt::read(buf)?.0,
+            delta: [i16::read(buf)?, i16::read(buf)?, i16::read(buf)?],
+            yaw: Angle::read(buf)?,
+            pitch: Angle::read(buf)?,
+            on_ground: bool::read(buf)?,
+        })
+    }
+}
+
+/// Update Entity Rotation。体の向きだけで、頭の向きは [`SetHeadRotation`] で送る
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UpdateEntityRotation {
+    pub entity_id: i32,
+    pub yaw: Angle,
+    pub pitch: Angle,
+    pub on_ground: bool,
+}
+
+impl Packet for UpdateEntityRotation {
+    fn packet_id(&self) -> i32 {
+        UPDATE_ENTITY_ROTATION_ID
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), ServerError> {
+        VarInt(self.entity_id).write(buf)?;
+        self.yaw.write(buf)?;
+        self.pitch.write(buf)?;
+        self.on_ground.write(buf)
+    }
+
+    fn decode(buf: &mut BytesMut) -> Result<Self, ServerError> {
+        Ok(Self {
+            entity_id: VarInt::read(buf)?.0,
+            yaw: Angle::read(buf)?,
+            pitch: Angle::read(buf)?,
+            on_ground: bool::read(buf)?,
+        })
+    }
+}
+
+/// Remove Entities
+#[derive(Debug, Clone, PartialEq)]
+pub struct RemoveEntities {
+    pub entity_ids: Vec<i32>,
+}
+
+impl Packet for RemoveEntities {
+    fn packet_id(&self) -> i32 {
+        REMOVE_ENTITIES_ID
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), ServerError> {
+        VarInt(self.entity_ids.len() as i32).write(buf)?;
+        for id in &self.entity_ids {
+            VarInt(*id).write(buf)?;
+        }
+        Ok(())
+    }
+
+    fn decode(buf: &mut BytesMut) -> Result<Self, ServerError> {
+        let ids: Vec<VarInt> = read_array_bounded(buf, MAX_ARRAY_LENGTH)?;
+        Ok(Self { entity_ids: ids.into_iter().map(|id| id.0).collect() })
+    }
+}
+
+/// Set Head Rotation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SetHeadRotation {
+    pub entity_id: i32,
+    pub head_yaw: Angle,
+}
+
+impl Packet for SetHeadRotation {
+    fn packet_id(&self) -> i32 {
+        SET_HEAD_ROTATION_ID
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), ServerError> {
+        VarInt(self.entity_id).write(buf)?;
+        self.head_yaw.write(buf)
+    }
+
+    fn decode(buf: &mut BytesMut) -> Result<Self, ServerError> {
+        Ok(Self { entity_id: VarInt::read(buf)?.0, head_yaw: Angle::read(buf)? })
+    }
+}
+
+/// Teleport Entity。移動量が [`UpdateEntityPosition`] に収まらないときに送る
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TeleportEntity {
+    pub entity_id: i32,
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub yaw: Angle,
+    pub pitch: Angle,
+    pub on_ground: bool,
+}
+
+impl Packet for TeleportEntity {
+    fn packet_id(&self) -> i32 {
+        TELEPORT_ENTITY_ID
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), ServerError> {
+        VarInt(self.entity_id).write(buf)?;
+        self.x.write(buf)?;
+        self.y.write(buf)?;
+        self.z.write(buf)?;
+        self.yaw.write(buf)?;
+        self.pitch.write(buf)?;
+        self.on_ground.write(buf)
+    }
+
+    fn decode(buf: &mut BytesMut) -> Result<Self, ServerError> {
+        Ok(Self {
+            entity_id: VarInt::read(buf)?.0,
+            x: f64::read(buf)?,
+            y: f64::read(buf)?,
+            z: f64::read(buf)?,
+            yaw: Angle::read(buf)?,
+            pitch: Angle::read(buf)?,
+            on_ground: bool::read(buf)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entity_packets_roundtrip() {
+        let uuid = Uuid::new_v4();
+        let spawn = SpawnPlayer { entity_id: 300, uuid, x: 0.5, y: 64.0, z: -3.25, yaw: Angle::from_degrees(90.0), pitch: Angle(0) };
+        let mut buf = BytesMut::new();
+        spawn.encode(&mut buf).unwrap();
+        // VarInt(2) + UUID(16) + 座標(24) + 角度(2)
+        assert_eq!(buf.len(), 44);
+        assert_eq!(SpawnPlayer::decode(&mut buf).unwrap(), spawn);
+
+        let moved = UpdateEntityPositionAndRotation { entity_id: 1, delta: [4096, -1, i16::MIN], yaw: Angle(64), pitch: Angle(192), on_ground: true };
+        let mut buf = BytesMut::new();
+        moved.encode(&mut buf).unwrap();
+        assert_eq!(UpdateEntityPositionAndRotation::decode(&mut buf).unwrap(), moved);
+
+        let removed = RemoveEntities { entity_ids: vec![1, 300, 70000] };
+        let mut buf = BytesMut::new();
+        removed.encode(&mut buf).unwrap();
+        assert_eq!(&buf[..3], &[3, 1, 0xAC]);
+        assert_eq!(RemoveEntities::decode(&mut buf).unwrap(), removed);
+    }
+}
@@ -0,0 +1,33 @@
+use crate::net::protocol::Packet;
+use crate::ServerError;
+use bytes::{Buf, BufMut, BytesMut};
+
+pub const SERVERBOUND_SET_HELD_ITEM_ID: i32 = 0x28;
+
+/// Set Held Item (serverbound)。ホットバーで選んだスロット (0-8)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SetHeldItem {
+    pub slot: i16,
+}
+
+impl Packet for SetHeldItem {
+    fn packet_id(&self) -> i32 {
+        SERVERBOUND_SET_HELD_ITEM_ID
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), ServerError> {
+        buf.put_i16(self.slot);
+        Ok(())
+    }
+
+    fn encoded_len(&self) -> Option<usize> {
+        Some(2)
+    }
+
+    fn decode(buf: &mut BytesMut) -> Result<Self, ServerError> {
+        if buf.remaining() < 2 {
+            return Err(ServerError::Protocol("Set Held Item の読み込みに失敗".into()));
+        }
+        Ok(Self { slot: buf.get_i16() })
+    }
+}
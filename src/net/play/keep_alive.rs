@@ -0,0 +1,40 @@
+use crate::net::protocol::Packet;
+use crate::ServerError;
+use bytes::{Buf, BufMut, BytesMut};
+
+pub const CLIENTBOUND_KEEP_ALIVE_ID: i32 = 0x23;
+pub const SERVERBOUND_KEEP_ALIVE_ID: i32 = 0x12;
+
+/// Keep Alive。サーバーが送ったIDをクライアントがそのまま返す
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct KeepAlive {
+    pub id: i64,
+    pub serverbound: bool,
+}
+
+impl Packet for KeepAlive {
+    fn packet_id(&self) -> i32 {
+        if self.serverbound {
+            SERVERBOUND_KEEP_ALIVE_ID
+        } else {
+            CLIENTBOUND_KEEP_ALIVE_ID
+        }
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), ServerError> {
+        buf.put_i64(self.id);
+        Ok(())
+    }
+
+    fn encoded_len(&self) -> Option<usize> {
+        Some(8)
+    }
+
+    /// 方向はパケットIDから呼び出し側が判断するため、ここでは serverbound として読む
+    fn decode(buf: &mut BytesMut) -> Result<Self, ServerError> {
+        if buf.remaining() < 8 {
+            return Err(ServerError::Protocol("Keep Alive の読み込みに失敗".into()));
+        }
+        Ok(Self { id: buf.get_i64(), serverbound: true })
+    }
+}
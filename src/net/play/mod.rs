@@ -0,0 +1,9 @@
+pub mod chat;
+pub mod chunk;
+pub mod client_information;
+pub mod disconnect;
+pub mod entity;
+pub mod held_item;
+pub mod keep_alive;
+pub mod movement;
+pub mod player_info;
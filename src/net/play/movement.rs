@@ -0,0 +1,252 @@
+use crate::net::protocol::Packet;
+use crate::varint::utils::{read_varint, varint_len, write_varint};
+use crate::ServerError;
+use bytes::{Buf, BufMut, BytesMut};
+
+pub const CONFIRM_TELEPORTATION_ID: i32 = 0x00;
+pub const SET_PLAYER_POSITION_ID: i32 = 0x14;
+pub const SET_PLAYER_POSITION_AND_ROTATION_ID: i32 = 0x15;
+pub const SET_PLAYER_ROTATION_ID: i32 = 0x16;
+pub const SET_PLAYER_ON_GROUND_ID: i32 = 0x17;
+pub const SYNCHRONIZE_PLAYER_POSITION_ID: i32 = 0x3C;
+
+fn check_remaining(buf: &BytesMut, needed: usize, name: &str) -> Result<(), ServerError> {
+    if buf.remaining() < needed {
+        return Err(ServerError::Protocol(format!("{} の読み込みに失敗", name)));
+    }
+    Ok(())
+}
+
+/// Set Player Position
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SetPlayerPosition {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub on_ground: bool,
+}
+
+impl Packet for SetPlayerPosition {
+    fn packet_id(&self) -> i32 {
+        SET_PLAYER_POSITION_ID
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), ServerError> {
+        buf.put_f64(self.x);
+        buf.put_f64(self.y);
+        buf.put_f64(self.z);
+        buf.put_u8(self.on_ground as u8);
+        Ok(())
+    }
+
+    fn encoded_len(&self) -> Option<usize> {
+        Some(25)
+    }
+
+    fn decode(buf: &mut BytesMut) -> Result<Self, ServerError> {
+        check_remaining(buf, 25, "Set Player Position")?;
+        Ok(Self {
+            x: buf.get_f64(),
+            y: buf.get_f64(),
+            z: buf.get_f64(),
+            on_ground: buf.get_u8() != 0,
+        })
+    }
+}
+
+/// Set Player Position and Rotation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SetPlayerPositionAndRotation {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub on_ground: bool,
+}
+
+impl Packet for SetPlayerPositionAndRotation {
+    fn packet_id(&self) -> i32 {
+        SET_PLAYER_POSITION_AND_ROTATION_ID
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), ServerError> {
+        buf.put_f64(self.x);
+        buf.put_f64(self.y);
+        buf.put_f64(self.z);
+        buf.put_f32(self.yaw);
+        buf.put_f32(self.pitch);
+        buf.put_u8(self.on_ground as u8);
+        Ok(())
+    }
+
+    fn encoded_len(&self) -> Option<usize> {
+        Some(33)
+    }
+
+    fn decode(buf: &mut BytesMut) -> Result<Self, ServerError> {
+        check_remaining(buf, 33, "Set Player Position and Rotation")?;
+        Ok(Self {
+            x: buf.get_f64(),
+            y: buf.get_f64(),
+            z: buf.get_f64(),
+            yaw: buf.get_f32(),
+            pitch: buf.get_f32(),
+            on_ground: buf.get_u8() != 0,
+        })
+    }
+}
+
+/// Set Player Rotation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SetPlayerRotation {
+    pub yaw: f32,
+    pub pitch: f32,
+    pub on_ground: bool,
+}
+
+impl Packet for SetPlayerRotation {
+    fn packet_id(&self) -> i32 {
+        SET_PLAYER_ROTATION_ID
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), ServerError> {
+        buf.put_f32(self.yaw);
+        buf.put_f32(self.pitch);
+        buf.put_u8(self.on_ground as u8);
+        Ok(())
+    }
+
+    fn encoded_len(&self) -> Option<usize> {
+        Some(9)
+    }
+
+    fn decode(buf: &mut BytesMut) -> Result<Self, ServerError> {
+        check_remaining(buf, 9, "Set Player Rotation")?;
+        Ok(Self {
+            yaw: buf.get_f32(),
+            pitch: buf.get_f32(),
+            on_ground: buf.get_u8() != 0,
+        })
+    }
+}
+
+/// Set Player On Ground。その場で動かずに接地状態だけ変わったとき
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SetPlayerOnGround {
+    pub on_ground: bool,
+}
+
+impl Packet for SetPlayerOnGround {
+    fn packet_id(&self) -> i32 {
+        SET_PLAYER_ON_GROUND_ID
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), ServerError> {
+        buf.put_u8(self.on_ground as u8);
+        Ok(())
+    }
+
+    fn encoded_len(&self) -> Option<usize> {
+        Some(1)
+    }
+
+    fn decode(buf: &mut BytesMut) -> Result<Self, ServerError> {
+        check_remaining(buf, 1, "Set Player On Ground")?;
+        Ok(Self { on_ground: buf.get_u8() != 0 })
+    }
+}
+
+/// Synchronize Player Position の `flags`。立っているビットの値は現在値からの相対
+pub mod relative {
+    pub const X: u8 = 0x01;
+    pub const Y: u8 = 0x02;
+    pub const Z: u8 = 0x04;
+    pub const YAW: u8 = 0x08;
+    pub const PITCH: u8 = 0x10;
+}
+
+/// Synchronize Player Position (clientbound)。クライアントは `teleport_id` を Confirm Teleportation で返す
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SynchronizePlayerPosition {
+    pub x: f64,
+    pub y: f64,
+    pub z: f64,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub flags: u8,
+    pub teleport_id: i32,
+}
+
+impl SynchronizePlayerPosition {
+    /// `flags` に従って現在の位置と向きに適用する
+    pub fn apply(&self, position: (f64, f64, f64), rotation: (f32, f32)) -> ((f64, f64, f64), (f32, f32)) {
+        let pick = |flag: u8, value: f64, current: f64| if self.flags & flag != 0 { current + value } else { value };
+        (
+            (pick(relative::X, self.x, position.0), pick(relative::Y, self.y, position.1), pick(relative::Z, self.z, position.2)),
+            (
+                pick(relative::YAW, self.yaw as f64, rotation.0 as f64) as f32,
+                pick(relative::PITCH, self.pitch as f64, rotation.1 as f64) as f32,
+            ),
+        )
+    }
+}
+
+impl Packet for SynchronizePlayerPosition {
+    fn packet_id(&self) -> i32 {
+        SYNCHRONIZE_PLAYER_POSITION_ID
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), ServerError> {
+        buf.put_f64(self.x);
+        buf.put_f64(self.y);
+        buf.put_f64(self.z);
+        buf.put_f32(self.yaw);
+        buf.put_f32(self.pitch);
+        buf.put_u8(self.flags);
+        write_varint(buf, self.teleport_id)?;
+        Ok(())
+    }
+
+    fn encoded_len(&self) -> Option<usize> {
+        Some(33 + varint_len(self.teleport_id))
+    }
+
+    fn decode(buf: &mut BytesMut) -> Result<Self, ServerError> {
+        check_remaining(buf, 33, "Synchronize Player Position")?;
+        Ok(Self {
+            x: buf.get_f64(),
+            y: buf.get_f64(),
+            z: buf.get_f64(),
+            yaw: buf.get_f32(),
+            pitch: buf.get_f32(),
+            flags: buf.get_u8(),
+            teleport_id: read_varint(buf)?,
+        })
+    }
+}
+
+/// Confirm Teleportation (serverbound)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConfirmTeleportation {
+    pub teleport_id: i32,
+}
+
+impl Packet for ConfirmTeleportation {
+    fn packet_id(&self) -> i32 {
+        CONFIRM_TELEPORTATION_ID
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), ServerError> {
+        write_varint(buf, self.teleport_id)?;
+        Ok(())
+    }
+
+    fn encoded_len(&self) -> Option<usize> {
+        Some(varint_len(self.teleport_id))
+    }
+
+    fn decode(buf: &mut BytesMut) -> Result<Self, ServerError> {
+        Ok(Self { teleport_id: read_varint(buf)? })
+    }
+}
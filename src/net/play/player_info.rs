@@ -0,0 +1,184 @@
+use crate::net::login::success::ProfileProperty;
+use crate::net::protocol::types::{read_array_bounded, read_array_len, ProtocolRead, VarInt, MAX_ARRAY_LENGTH};
+use crate::net::protocol::Packet;
+use crate::varint::utils::{varint_len, write_string, write_varint};
+use crate::ServerError;
+use bytes::{BufMut, BytesMut};
+use uuid::Uuid;
+
+pub const PLAYER_INFO_REMOVE_ID: i32 = 0x39;
+pub const PLAYER_INFO_UPDATE_ID: i32 = 0x3A;
+
+/// Player Info Update のアクション (ビットの順にエントリのデータが並ぶ)
+pub const ADD_PLAYER: u8 = 0x01;
+pub const INITIALIZE_CHAT: u8 = 0x02;
+pub const UPDATE_GAME_MODE: u8 = 0x04;
+pub const UPDATE_LISTED: u8 = 0x08;
+pub const UPDATE_LATENCY: u8 = 0x10;
+pub const UPDATE_DISPLAY_NAME: u8 = 0x20;
+
+/// タブリストの1行
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerInfoEntry {
+    pub uuid: Uuid,
+    pub name: String,
+    pub properties: Vec<ProfileProperty>,
+    pub game_mode: i32,
+    pub listed: bool,
+    /// ミリ秒
+    pub latency: i32,
+}
+
+/// Player Info Update (0x3A)。`actions` に含まれる項目だけを送る。
+/// チャット署名と表示名は扱わず、常に「なし」として書く
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerInfoUpdate {
+    pub actions: u8,
+    pub entries: Vec<PlayerInfoEntry>,
+}
+
+/// Player Info Remove (0x39)
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerInfoRemove {
+    pub uuids: Vec<Uuid>,
+}
+
+impl Packet for PlayerInfoUpdate {
+    fn packet_id(&self) -> i32 {
+        PLAYER_INFO_UPDATE_ID
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), ServerError> {
+        buf.put_u8(self.actions);
+        write_varint(buf, self.entries.len() as i32)?;
+        for entry in &self.entries {
+            buf.put_u128(entry.uuid.as_u128());
+            if self.actions & ADD_PLAYER != 0 {
+                write_string(buf, &entry.name)?;
+                write_varint(buf, entry.properties.len() as i32)?;
+                for property in &entry.properties {
+                    write_string(buf, &property.name)?;
+                    write_string(buf, &property.value)?;
+                    buf.put_u8(property.signature.is_some() as u8);
+                    if let Some(signature) = &property.signature {
+                        write_string(buf, signature)?;
+                    }
+                }
+            }
+            if self.actions & INITIALIZE_CHAT != 0 {
+                buf.put_u8(0);
+            }
+            if self.actions & UPDATE_GAME_MODE != 0 {
+                write_varint(buf, entry.game_mode)?;
+            }
+            if self.actions & UPDATE_LISTED != 0 {
+                buf.put_u8(entry.listed as u8);
+            }
+            if self.actions & UPDATE_LATENCY != 0 {
+                write_varint(buf, entry.latency)?;
+            }
+            if self.actions & UPDATE_DISPLAY_NAME != 0 {
+                buf.put_u8(0);
+            }
+        }
+        Ok(())
+    }
+
+    fn decode(buf: &mut BytesMut) -> Result<Self, ServerError> {
+        let actions = u8::read(buf)?;
+        if actions & (INITIALIZE_CHAT | UPDATE_DISPLAY_NAME) != 0 {
+            return Err(ServerError::Protocol("チャット署名と表示名のアクションには対応していません".into()));
+        }
+
+        // エントリの中身は `actions` で変わるので、要素数だけを検査して1つずつ読む
+        let count = read_array_len(buf, MAX_ARRAY_LENGTH)?;
+        let mut entries = Vec::with_capacity(count);
+        for _ in 0..count {
+            let mut entry = PlayerInfoEntry {
+                uuid: Uuid::read(buf)?,
+                name: String::new(),
+                properties: Vec::new(),
+                game_mode: 0,
+                listed: false,
+                latency: 0,
+            };
+            if actions & ADD_PLAYER != 0 {
+                entry.name = String::read(buf)?;
+                entry.properties = read_array_bounded(buf, MAX_ARRAY_LENGTH)?;
+            }
+            if actions & UPDATE_GAME_MODE != 0 {
+                entry.game_mode = VarInt::read(buf)?.0;
+            }
+            if actions & UPDATE_LISTED != 0 {
+                entry.listed = bool::read(buf)?;
+            }
+            if actions & UPDATE_LATENCY != 0 {
+                entry.latency = VarInt::read(buf)?.0;
+            }
+            entries.push(entry);
+        }
+        Ok(Self { actions, entries })
+    }
+}
+
+impl Packet for PlayerInfoRemove {
+    fn packet_id(&self) -> i32 {
+        PLAYER_INFO_REMOVE_ID
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<(), ServerError> {
+        write_varint(buf, self.uuids.len() as i32)?;
+        for uuid in &self.uuids {
+            buf.put_u128(uuid.as_u128());
+        }
+        Ok(())
+    }
+
+    fn encoded_len(&self) -> Option<usize> {
+        Some(varint_len(self.uuids.len() as i32) + 16 * self.uuids.len())
+    }
+
+    fn decode(buf: &mut BytesMut) -> Result<Self, ServerError> {
+        Ok(Self { uuids: read_array_bounded(buf, MAX_ARRAY_LENGTH)? })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_player_info_roundtrip() {
+        let update = PlayerInfoUpdate {
+            actions: ADD_PLAYER | UPDATE_GAME_MODE | UPDATE_LISTED | UPDATE_LATENCY,
+            entries: vec![PlayerInfoEntry {
+                uuid: Uuid::new_v4(),
+                name: "Steve".to_string(),
+                properties: vec![ProfileProperty { name: "textures".to_string(), value: "e30=".to_string(), signature: None }],
+                game_mode: 1,
+                listed: true,
+                latency: 42,
+            }],
+        };
+        let mut buf = BytesMut::new();
+        update.encode(&mut buf).unwrap();
+        assert_eq!(PlayerInfoUpdate::decode(&mut buf).unwrap(), update);
+        assert!(buf.is_empty());
+
+        let remove = PlayerInfoRemove { uuids: vec![Uuid::new_v4(), Uuid::new_v4()] };
+        let mut buf = BytesMut::new();
+        remove.encode(&mut buf).unwrap();
+        assert_eq!(Some(buf.len()), remove.encoded_len());
+        assert_eq!(PlayerInfoRemove::decode(&mut buf).unwrap(), remove);
+
+        // 要素数が上限や残りのバイト数を超えていれば、要素を読む前に弾く
+        let mut buf = BytesMut::new();
+        buf.put_u8(UPDATE_LATENCY);
+        write_varint(&mut buf, MAX_ARRAY_LENGTH as i32 + 1).unwrap();
+        assert!(PlayerInfoUpdate::decode(&mut buf).is_err());
+        let mut buf = BytesMut::new();
+        write_varint(&mut buf, 1000).unwrap();
+        buf.put_u128(0);
+        assert!(PlayerInfoRemove::decode(&mut buf).is_err());
+    }
+}
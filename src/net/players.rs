@@ -0,0 +1,431 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::{Bytes, BytesMut};
+use parking_lot::Mutex;
+use log::warn;
+use tokio::sync::mpsc::error::{TryRecvError, TrySendError};
+use tokio::sync::{broadcast, mpsc, watch};
+use uuid::Uuid;
+
+use crate::net::login::profile::GameProfile;
+use crate::net::play::player_info::{
+    PlayerInfoEntry, PlayerInfoRemove, PlayerInfoUpdate, ADD_PLAYER, UPDATE_GAME_MODE, UPDATE_LATENCY, UPDATE_LISTED,
+};
+use crate::net::protocol::Packet;
+use crate::Result;
+
+/// 参加・退出イベントを溜めておく数。遅れた購読者は古いものから取りこぼす
+const EVENT_CAPACITY: usize = 256;
+/// 1セッションへのチャネルの容量。溢れたクライアントは切断する
+pub const SESSION_CAPACITY: usize = 1024;
+/// 全員のタブリストに往復時間を送る間隔。vanilla と同じ 600 tick
+pub const LATENCY_BROADCAST_INTERVAL: Duration = Duration::from_secs(30);
+
+/// 送信が追いつかないクライアントの切断理由
+pub const OVERLOADED_MESSAGE: &str = "Disconnected: too many pending packets";
+
+/// 接続中のセッションへ送る指示
+#[derive(Debug, Clone, PartialEq)]
+pub enum SessionMessage {
+    /// エンコード済みのパケットを送る
+    Packet { id: i32, body: Bytes },
+    /// 理由を表示して切断する
+    Kick(String),
+}
+
+impl SessionMessage {
+    /// パケットを1度だけエンコードする。ブロードキャストではこれを全員に複製して送る
+    pub fn packet<P: Packet>(packet: &P) -> Result<Self> {
+        let mut body = BytesMut::with_capacity(packet.encoded_len().unwrap_or(64));
+        packet.encode(&mut body)?;
+        Ok(Self::Packet { id: packet.packet_id(), body: body.freeze() })
+    }
+}
+
+/// プレイヤーの参加と退出。`PlayerRegistry::subscribe` で受け取る
+#[derive(Debug, Clone)]
+pub enum PlayerEvent {
+    Joined(Arc<Session>),
+    Left(Arc<Session>),
+}
+
+/// ログイン済みのプレイヤー1人分のセッション。接続タスクへの送信口を持つ
+#[derive(Debug)]
+pub struct Session {
+    /// 同じプレイヤーの古いセッションと区別するための通し番号
+    pub id: u64,
+    pub profile: GameProfile,
+    pub remote: SocketAddr,
+    pub protocol_version: i32,
+    /// keep-alive の往復時間 (ミリ秒)
+    latency: AtomicU32,
+    sender: mpsc::Sender<SessionMessage>,
+    /// 切断の指示。パケットのチャネルが詰まっていても届くように分けておく
+    kick: watch::Sender<Option<String>>,
+}
+
+impl Session {
+    pub fn uuid(&self) -> Uuid {
+        self.profile.uuid
+    }
+
+    pub fn name(&self) -> &str {
+        &self.profile.name
+    }
+
+    /// 最後に計測した往復時間。まだ計測していなければ 0
+    pub fn latency(&self) -> Duration {
+        Duration::from_millis(self.latency.load(Ordering::Relaxed) as u64)
+    }
+
+    pub fn set_latency(&self, rtt: Duration) {
+        self.latency.store(rtt.as_millis().min(i32::MAX as u128) as u32, Ordering::Relaxed);
+    }
+
+    /// パケットを送る。既に接続が終わっていれば `false`
+    pub fn send<P: Packet>(&self, packet: &P) -> Result<bool> {
+        Ok(self.send_message(SessionMessage::packet(packet)?))
+    }
+
+    /// 切断を指示する。既に接続が終わっていれば `false`
+    pub fn kick(&self, reason: &str) -> bool {
+        self.kick.send(Some(reason.to_string())).is_ok()
+    }
+
+    /// 溢れたら待たずに切断し、`false` を返す
+    fn send_message(&self, message: SessionMessage) -> bool {
+        match self.sender.try_send(message) {
+            Ok(()) => true,
+            Err(TrySendError::Closed(_)) => false,
+            Err(TrySendError::Full(_)) => {
+                warn!("{} is not reading packets fast enough, disconnecting", self.name());
+                self.kick(OVERLOADED_MESSAGE);
+                false
+            }
+        }
+    }
+
+    /// タブリストに載せる行
+    pub fn player_info(&self) -> PlayerInfoEntry {
+        PlayerInfoEntry {
+            uuid: self.uuid(),
+            name: self.profile.name.clone(),
+            properties: self.profile.properties.clone(),
+            game_mode: 0,
+            listed: true,
+            latency: self.latency.load(Ordering::Relaxed) as i32,
+        }
+    }
+}
+
+/// 接続タスクがセッションへの指示を受け取る口
+#[derive(Debug)]
+pub struct SessionMessages {
+    packets: mpsc::Receiver<SessionMessage>,
+    kick: watch::Receiver<Option<String>>,
+}
+
+impl SessionMessages {
+    /// 次の指示を待つ。切断の指示は溜まっているパケットより先に返す。
+    /// キャンセルしても指示は失われないので `select!` の中で使える
+    pub async fn recv(&mut self) -> Option<SessionMessage> {
+        tokio::select! {
+            biased;
+            Ok(()) = self.kick.changed() => self.kick.borrow_and_update().clone().map(SessionMessage::Kick),
+            message = self.packets.recv() => message,
+        }
+    }
+
+    pub fn try_recv(&mut self) -> std::result::Result<SessionMessage, TryRecvError> {
+        if self.kick.has_changed().unwrap_or(false) {
+            if let Some(reason) = self.kick.borrow_and_update().clone() {
+                return Ok(SessionMessage::Kick(reason));
+            }
+        }
+        self.packets.try_recv()
+    }
+}
+
+/// `PlayerRegistry::register` の結果
+pub struct Registration {
+    pub session: Arc<Session>,
+    /// 接続タスクが受け取るメッセージ
+    pub messages: SessionMessages,
+    /// 入れ替えで一覧から外れた古いセッション。呼び出し側で切断する
+    pub replaced: Vec<Arc<Session>>,
+}
+
+#[derive(Default)]
+struct Sessions {
+    by_uuid: HashMap<Uuid, Arc<Session>>,
+    /// 小文字にした名前から UUID
+    by_name: HashMap<String, Uuid>,
+}
+
+impl Sessions {
+    fn remove(&mut self, uuid: &Uuid) -> Option<Arc<Session>> {
+        let session = self.by_uuid.remove(uuid)?;
+        self.by_name.remove(&session.name().to_lowercase());
+        Some(session)
+    }
+
+    fn broadcast(&self, message: &SessionMessage, except: Option<u64>) {
+        for session in self.by_uuid.values().filter(|session| Some(session.id) != except) {
+            session.send_message(message.clone());
+        }
+    }
+}
+
+/// オンラインのプレイヤー。UUID と名前 (大文字小文字を区別しない) のどちらでも引ける。
+/// 参加と退出のたびに全員のタブリストを更新し、`PlayerEvent` を流す
+pub struct PlayerRegistry {
+    sessions: Mutex<Sessions>,
+    next_id: AtomicU64,
+    events: broadcast::Sender<PlayerEvent>,
+}
+
+impl PlayerRegistry {
+    pub fn new() -> Self {
+        Self {
+            sessions: Mutex::new(Sessions::default()),
+            next_id: AtomicU64::new(0),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+        }
+    }
+
+    /// セッションを登録する。同じ UUID か名前のセッションがあれば入れ替え、取り除いたものを返す。
+    /// 判定と入れ替えは1つのロックの中で行うので、同時にログインしても片方しか残らない
+    pub fn register(&self, profile: GameProfile, remote: SocketAddr, protocol_version: i32) -> Result<Registration> {
+        let (sender, packets) = mpsc::channel(SESSION_CAPACITY);
+        let (kick, kick_receiver) = watch::channel(None);
+        let messages = SessionMessages { packets, kick: kick_receiver };
+        let session = Arc::new(Session {
+            id: self.next_id.fetch_add(1, Ordering::Relaxed),
+            profile,
+            remote,
+            protocol_version,
+            latency: AtomicU32::new(0),
+            sender,
+            kick,
+        });
+
+        let key = session.name().to_lowercase();
+        let mut sessions = self.sessions.lock();
+        let mut replaced = Vec::new();
+        replaced.extend(sessions.remove(&session.uuid()));
+        if let Some(previous_uuid) = sessions.by_name.get(&key).copied() {
+            replaced.extend(sessions.remove(&previous_uuid));
+        }
+        if !replaced.is_empty() {
+            let remove = PlayerInfoRemove { uuids: replaced.iter().map(|previous| previous.uuid()).collect() };
+            sessions.broadcast(&SessionMessage::packet(&remove)?, None);
+        }
+
+        // 他のプレイヤーには新しい行だけ、本人には自分を含む全員を送る
+        let add = |entries| PlayerInfoUpdate { actions: ADD_PLAYER | UPDATE_GAME_MODE | UPDATE_LISTED | UPDATE_LATENCY, entries };
+        sessions.broadcast(&SessionMessage::packet(&add(vec![session.player_info()]))?, None);
+        sessions.by_uuid.insert(session.uuid(), session.clone());
+        sessions.by_name.insert(key, session.uuid());
+        session.send(&add(sessions.by_uuid.values().map(|session| session.player_info()).collect()))?;
+        drop(sessions);
+
+        for previous in &replaced {
+            let _ = self.events.send(PlayerEvent::Left(previous.clone()));
+        }
+        let _ = self.events.send(PlayerEvent::Joined(session.clone()));
+        Ok(Registration { session, messages, replaced })
+    }
+
+    /// セッションを取り除く。既に新しいセッションに入れ替わっていれば何もしない
+    pub fn unregister(&self, session: &Session) -> bool {
+        let mut sessions = self.sessions.lock();
+        let Some(current) = sessions.by_uuid.get(&session.uuid()).filter(|current| current.id == session.id).cloned() else {
+            return false;
+        };
+        sessions.remove(&current.uuid());
+        if let Ok(remove) = SessionMessage::packet(&PlayerInfoRemove { uuids: vec![current.uuid()] }) {
+            sessions.broadcast(&remove, None);
+        }
+        drop(sessions);
+
+        let _ = self.events.send(PlayerEvent::Left(current));
+        true
+    }
+
+    /// 全員の往復時間を1つのパケットにまとめてタブリストに送る。
+    /// [`LATENCY_BROADCAST_INTERVAL`] ごとに呼ぶ
+    pub fn broadcast_latency(&self) -> Result<()> {
+        let sessions = self.sessions.lock();
+        if sessions.by_uuid.is_empty() {
+            return Ok(());
+        }
+        let entries = sessions.by_uuid.values().map(|session| session.player_info()).collect();
+        sessions.broadcast(&SessionMessage::packet(&PlayerInfoUpdate { actions: UPDATE_LATENCY, entries })?, None);
+        Ok(())
+    }
+
+    pub fn get(&self, uuid: &Uuid) -> Option<Arc<Session>> {
+        self.sessions.lock().by_uuid.get(uuid).cloned()
+    }
+
+    pub fn get_by_name(&self, name: &str) -> Option<Arc<Session>> {
+        let sessions = self.sessions.lock();
+        let uuid = sessions.by_name.get(&name.to_lowercase())?;
+        sessions.by_uuid.get(uuid).cloned()
+    }
+
+    /// オンラインの全セッション。順序は決まっていない
+    pub fn sessions(&self) -> Vec<Arc<Session>> {
+        self.sessions.lock().by_uuid.values().cloned().collect()
+    }
+
+    /// オンラインのプレイヤー名 (名前順)
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.sessions.lock().by_uuid.values().map(|session| session.profile.name.clone()).collect();
+        names.sort_by_key(|name| name.to_lowercase());
+        names
+    }
+
+    /// 全員にパケットを送る。エンコードは1度だけ
+    pub fn broadcast<P: Packet>(&self, packet: &P) -> Result<()> {
+        let message = SessionMessage::packet(packet)?;
+        self.sessions.lock().broadcast(&message, None);
+        Ok(())
+    }
+
+    /// `except` 以外の全員にパケットを送る
+    pub fn broadcast_except<P: Packet>(&self, packet: &P, except: &Session) -> Result<()> {
+        let message = SessionMessage::packet(packet)?;
+        self.sessions.lock().broadcast(&message, Some(except.id));
+        Ok(())
+    }
+
+    /// 以後の参加・退出イベントを受け取る
+    pub fn subscribe(&self) -> broadcast::Receiver<PlayerEvent> {
+        self.events.subscribe()
+    }
+
+    pub fn len(&self) -> usize {
+        self.sessions.lock().by_uuid.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for PlayerRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::play::player_info::PLAYER_INFO_UPDATE_ID;
+
+    fn register(registry: &PlayerRegistry, uuid: Uuid, name: &str) -> Registration {
+        registry.register(GameProfile::new(uuid, name), "127.0.0.1:25565".parse().unwrap(), 763).unwrap()
+    }
+
+    /// 受け取ったパケットを ID と一緒に取り出す
+    fn next_packet(messages: &mut SessionMessages) -> (i32, BytesMut) {
+        match messages.try_recv() {
+            Ok(SessionMessage::Packet { id, body }) => (id, BytesMut::from(&body[..])),
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_register_replaces_duplicates() {
+        let registry = PlayerRegistry::new();
+        let uuid = Uuid::new_v4();
+        let first = register(&registry, uuid, "Steve");
+        assert!(first.replaced.is_empty());
+        let mut first_rx = first.messages;
+        next_packet(&mut first_rx);
+
+        // 同じ UUID のログインは古いセッションを追い出す
+        let second = register(&registry, uuid, "Steve");
+        assert_eq!(second.replaced.len(), 1);
+        assert_eq!(second.replaced[0].id, first.session.id);
+        assert!(second.replaced[0].kick("bye"));
+        assert_eq!(first_rx.try_recv(), Ok(SessionMessage::Kick("bye".to_string())));
+
+        // 古いセッションの後始末で新しいセッションを消さない
+        assert!(!registry.unregister(&first.session));
+        assert_eq!(registry.get_by_name("STEVE").map(|s| s.id), Some(second.session.id));
+
+        // 名前が同じで UUID が違う場合も入れ替える
+        let third = register(&registry, Uuid::new_v4(), "steve");
+        assert_eq!(third.replaced[0].id, second.session.id);
+        assert!(registry.get(&uuid).is_none());
+        assert_eq!(registry.len(), 1);
+
+        assert!(registry.unregister(&third.session));
+        assert!(registry.is_empty());
+    }
+
+    #[test]
+    fn test_overflow_kicks_session() {
+        let registry = PlayerRegistry::new();
+        let mut steve = register(&registry, Uuid::new_v4(), "Steve");
+        next_packet(&mut steve.messages);
+
+        let remove = PlayerInfoRemove { uuids: vec![] };
+        for _ in 0..SESSION_CAPACITY {
+            assert!(steve.session.send(&remove).unwrap());
+        }
+        // 溢れたら待たずに切断を指示し、溜まったパケットより先に届ける
+        assert!(!steve.session.send(&remove).unwrap());
+        assert_eq!(steve.messages.try_recv(), Ok(SessionMessage::Kick(OVERLOADED_MESSAGE.to_string())));
+    }
+
+    #[test]
+    fn test_tab_list_and_events() {
+        let registry = PlayerRegistry::new();
+        let mut events = registry.subscribe();
+
+        let mut alex = register(&registry, Uuid::new_v4(), "Alex");
+        let (id, mut body) = next_packet(&mut alex.messages);
+        assert_eq!(id, PLAYER_INFO_UPDATE_ID);
+        assert_eq!(PlayerInfoUpdate::decode(&mut body).unwrap().entries.len(), 1);
+
+        // 新しいプレイヤーは全員分、既にいるプレイヤーは新しい1行だけを受け取る
+        let mut steve = register(&registry, Uuid::new_v4(), "Steve");
+        let (_, mut body) = next_packet(&mut steve.messages);
+        assert_eq!(PlayerInfoUpdate::decode(&mut body).unwrap().entries.len(), 2);
+        let (_, mut body) = next_packet(&mut alex.messages);
+        assert_eq!(PlayerInfoUpdate::decode(&mut body).unwrap().entries[0].name, "Steve");
+        assert_eq!(registry.names(), vec!["Alex".to_string(), "Steve".to_string()]);
+
+        // 往復時間は記録するだけで、定期的な送信で全員分をまとめて送る
+        steve.session.set_latency(Duration::from_millis(42));
+        assert!(alex.messages.try_recv().is_err());
+        registry.broadcast_latency().unwrap();
+        let (_, mut body) = next_packet(&mut alex.messages);
+        let update = PlayerInfoUpdate::decode(&mut body).unwrap();
+        assert_eq!(update.actions, UPDATE_LATENCY);
+        assert_eq!(update.entries.len(), 2);
+        assert_eq!(update.entries.iter().find(|entry| entry.uuid == steve.session.uuid()).unwrap().latency, 42);
+        next_packet(&mut steve.messages);
+
+        // 退出すると残りのプレイヤーのタブリストから消える
+        assert!(registry.unregister(&steve.session));
+        let (_, mut body) = next_packet(&mut alex.messages);
+        assert_eq!(PlayerInfoRemove::decode(&mut body).unwrap().uuids, vec![steve.session.uuid()]);
+
+        let joined: Vec<String> = std::iter::from_fn(|| events.try_recv().ok())
+            .map(|event| match event {
+                PlayerEvent::Joined(session) => format!("+{}", session.name()),
+                PlayerEvent::Left(session) => format!("-{}", session.name()),
+            })
+            .collect();
+        assert_eq!(joined, vec!["+Alex", "+Steve", "-Steve"]);
+    }
+}
@@ -1,10 +1,14 @@
-use bytes::{BytesMut, BufMut};
-use std::io;
-use crate::varint::utils::{read_varint, write_varint};
+use bytes::{Buf, BufMut, BytesMut};
+use crate::varint::utils::{peek_varint, read_varint, varint_len, write_varint};
+use crate::varint::DecodeError;
 use crate::net::error::Result;
 use crate::net::protocol::{Packet, PacketError};
 use crate::ServerError;
 
+/// フレーム長の最大バイト数と、それで表せる最大のフレーム長
+const MAX_HEADER_LEN: usize = 3;
+pub(crate) const MAX_FRAME_LEN: usize = 2_097_151;
+
 pub struct PacketCodec {
     max_packet_size: usize,
 }
@@ -14,53 +18,139 @@ impl PacketCodec {
         Self { max_packet_size }
     }
 
+    fn check_len(&self, len: usize) -> Result<()> {
+        if len > self.max_packet_size || len > MAX_FRAME_LEN {
+            return Err(ServerError::Protocol("パケットが大きすぎます".to_string()));
+        }
+        Ok(())
+    }
+
+    /// パケットを長さ付きフレームとして `buf` の末尾に直接書き込む。
+    /// 失敗した場合、`buf` は呼び出し前の長さに戻す
     pub fn encode_packet<P: Packet>(&self, packet: &P, buf: &mut BytesMut) -> Result<()> {
-        let mut packet_buf = BytesMut::new();
+        let start = buf.len();
+        let result = match packet.encoded_len() {
+            Some(body_len) => self.encode_sized(packet, body_len, buf),
+            None => self.encode_unsized(packet, start, buf),
+        };
+        if result.is_err() {
+            buf.truncate(start);
+        }
+        result
+    }
 
-        // パケットIDの書き込み
-        write_varint(&mut packet_buf, packet.packet_id())?;
+    /// 本体の長さが分かっている場合は、長さ・ID・本体を順に書くだけで済む
+    fn encode_sized<P: Packet>(&self, packet: &P, body_len: usize, buf: &mut BytesMut) -> Result<()> {
+        let packet_id = packet.packet_id();
+        let len = varint_len(packet_id) + body_len;
+        self.check_len(len)?;
 
-        // パケットデータのエンコード
-        packet.encode(&mut packet_buf)?;
+        buf.reserve(varint_len(len as i32) + len);
+        write_varint(buf, len as i32)?;
+        let body_start = buf.len();
+        write_varint(buf, packet_id)?;
+        packet.encode(buf)?;
 
-        // パケット長の検証
-        if packet_buf.len() > self.max_packet_size {
-            return Err(ServerError::Protocol("パケットが大きすぎます".to_string()));
+        if buf.len() - body_start != len {
+            return Err(ServerError::Protocol(
+                PacketError::EncodeError(format!(
+                    "encoded_len が実際の長さと一致しません: 0x{:02X} ({} != {})",
+                    packet_id,
+                    len,
+                    buf.len() - body_start
+                ))
+                .to_string(),
+            ));
         }
+        Ok(())
+    }
 
-        // パケット長の書き込み
-        write_varint(buf, packet_buf.len() as i32)?;
+    /// 長さ部分を最大長で確保しておき、書き終えてから実際の長さを書く。
+    /// フレームがバッファの先頭なら長さを確保した領域の右詰めで書いて前の余りを捨てるので、本体は1回しか書かない。
+    /// 前に別のフレームがあるときは、余りを詰めるために本体をもう1度コピーする
+    fn encode_unsized<P: Packet>(&self, packet: &P, start: usize, buf: &mut BytesMut) -> Result<()> {
+        buf.put_bytes(0, MAX_HEADER_LEN);
+        write_varint(buf, packet.packet_id())?;
+        packet.encode(buf)?;
 
-        // パケットデータの書き込み
-        buf.extend_from_slice(&packet_buf);
+        let len = buf.len() - start - MAX_HEADER_LEN;
+        self.check_len(len)?;
 
+        let header_len = varint_len(len as i32);
+        let unused = MAX_HEADER_LEN - header_len;
+        if start == 0 {
+            write_varint(&mut &mut buf[unused..MAX_HEADER_LEN], len as i32)?;
+            buf.advance(unused);
+            return Ok(());
+        }
+        if unused > 0 {
+            buf.copy_within(start + MAX_HEADER_LEN.., start + header_len);
+            buf.truncate(buf.len() - unused);
+        }
+        write_varint(&mut &mut buf[start..start + header_len], len as i32)?;
         Ok(())
     }
 
-    pub fn decode_packet<P: Packet>(&self, buf: &mut BytesMut) -> Result<Option<P>> {
-        // パケット長の読み取り
-        let packet_length = match read_varint(buf) {
-            Some(len) => len as usize,
-            None => return Ok(None),
+    /// パケットIDとエンコード済みの本体から長さ付きフレームを書き込む
+    pub fn encode_frame(&self, packet_id: i32, body: &[u8], buf: &mut BytesMut) -> Result<()> {
+        let len = varint_len(packet_id) + body.len();
+        self.check_len(len)?;
+
+        buf.reserve(varint_len(len as i32) + len);
+        write_varint(buf, len as i32)?;
+        write_varint(buf, packet_id)?;
+        buf.extend_from_slice(body);
+        Ok(())
+    }
+
+    /// 完全なフレームがあればパケットIDと本体を取り出す。足りなければバッファに触れず `None`
+    pub fn decode_frame(&self, buf: &mut BytesMut) -> Result<Option<(i32, BytesMut)>> {
+        // パケット長はまだ消費せずに読む
+        let (packet_length, header_len) = match peek_varint(buf) {
+            Ok(header) => header,
+            Err(DecodeError::Incomplete) if buf.len() < 3 => return Ok(None),
+            Err(_) => return Err(ServerError::Protocol("不正なパケット長".to_string())),
         };
+        if header_len > 3 || packet_length < 0 {
+            return Err(ServerError::Protocol("不正なパケット長".to_string()));
+        }
+        let packet_length = packet_length as usize;
 
         // パケットサイズの検証
-        if packet_length > self.max_packet_size {
-            return Err(ServerError::Protocol("パケットが大きすぎます".to_string()));
+        if packet_length == 0 {
+            return Err(ServerError::Protocol("不正なパケット長".to_string()));
         }
+        self.check_len(packet_length)?;
 
         // 完全なパケットを受信したか確認
-        if buf.len() < packet_length {
+        if buf.len() < header_len + packet_length {
             return Ok(None);
         }
 
         // パケットデータの分離
-        let packet_data = buf.split_to(packet_length);
-        let mut packet_buf = BytesMut::from(&packet_data[..]);
+        buf.advance(header_len);
+        let mut packet_buf = buf.split_to(packet_length);
+        let packet_id = read_varint(&mut packet_buf)
+            .map_err(|e| ServerError::Protocol(format!("不正なパケットID: {}", e)))?;
+
+        Ok(Some((packet_id, packet_buf)))
+    }
+
+    pub fn decode_packet<P: Packet>(&self, buf: &mut BytesMut) -> Result<Option<P>> {
+        let Some((_packet_id, mut packet_buf)) = self.decode_frame(buf)? else {
+            return Ok(None);
+        };
 
         // パケットのデコード
         let packet = P::decode(&mut packet_buf)?;
 
         Ok(Some(packet))
     }
-}
\ No newline at end of file
+}
+
+impl Default for PacketCodec {
+    /// 3バイトのVarIntで表せる最大長を上限にする
+    fn default() -> Self {
+        Self::new(MAX_FRAME_LEN)
+    }
+}
@@ -27,12 +27,10 @@ impl Packet for HandshakePacket {
     }
 
     fn decode(buf: &mut BytesMut) -> Result<Self> {
-        let protocol_version = read_varint(buf)
-            .ok_or_else(|| ServerError::Protocol("プロトコルバージョンの読み取りに失敗".to_string()))?;
+        let protocol_version = read_varint(buf)?;
 
         let server_address = {
-            let len = read_varint(buf)
-                .ok_or_else(|| ServerError::Protocol("アドレス長の読み取りに失敗".to_string()))? as usize;
+            let len = read_varint(buf)? as usize;
             if buf.len() < len {
                 return Err(ServerError::Protocol(IncompletePacket.to_string()));
             }
@@ -46,8 +44,7 @@ impl Packet for HandshakePacket {
         let server_port = u16::from_be_bytes([buf[0], buf[1]]);
         buf.advance(2);
 
-        let next_state = PacketState::from_i32(read_varint(buf)
-            .ok_or_else(|| ServerError::Protocol("次の状態の読み取りに失敗".to_string()))?)
+        let next_state = PacketState::from_i32(read_varint(buf)?)
             .ok_or_else(|| ServerError::Protocol("不正な次の状態".to_string()))?;
 
         Ok(HandshakePacket {
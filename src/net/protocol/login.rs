@@ -2,7 +2,6 @@ use crate::net::error::Result;
 use crate::net::protocol::{Packet, PacketError};
 use crate::varint::utils::{read_string, write_string};
 use bytes::BytesMut;
-use crate::ServerError;
 
 pub struct LoginStart {
     pub username: String,
@@ -19,8 +18,7 @@ impl Packet for LoginStart {
     }
 
     fn decode(buf: &mut BytesMut) -> Result<Self> {
-        let name = read_string(buf)
-            .ok_or_else(|| ServerError::Protocol("ユーザー名読み込み失敗".into()))?;
+        let name = read_string(buf)?;
         Ok(Self { username: name })
     }
 }
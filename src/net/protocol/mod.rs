@@ -1,5 +1,7 @@
-pub(crate) mod handshake;
-pub(crate) mod codec;
+pub mod handshake;
+pub mod codec;
+pub mod registry;
+pub mod types;
 mod login;
 
 use bytes::BytesMut;
@@ -26,6 +28,25 @@ impl PacketState {
     }
 }
 
+/// パケットの送信方向
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    /// クライアント → サーバー
+    Serverbound = 0,
+    /// サーバー → クライアント
+    Clientbound = 1,
+}
+
+impl Direction {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Direction::Serverbound),
+            1 => Some(Direction::Clientbound),
+            _ => None,
+        }
+    }
+}
+
 pub trait Packet: Send + Sized {
     /// パケットのIDを返す
     fn packet_id(&self) -> i32;
@@ -33,6 +54,12 @@ pub trait Packet: Send + Sized {
     /// パケットをバイトストリームにエンコード
     fn encode(&self, buf: &mut BytesMut) -> Result<()>;
 
+    /// エンコード後の本体 (パケットIDを除く) のバイト数。
+    /// 分かっていれば長さを先に書けるので、コーデックは1回の書き込みでフレームを作れる
+    fn encoded_len(&self) -> Option<usize> {
+        None
+    }
+
     /// バイトストリームからパケットをデコード
     fn decode(buf: &mut BytesMut) -> Result<Self>;
 }
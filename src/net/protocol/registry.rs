@@ -0,0 +1,84 @@
+use super::{Direction, PacketState};
+
+// プロトコル 763 (1.20.1) のパケットID と名前の対応表
+//
+// https://wiki.vg/index.php?title=Protocol&oldid=18375
+
+const HANDSHAKE_SERVERBOUND: &[(i32, &str)] = &[
+    (0x00, "Handshake"),
+];
+
+const STATUS_SERVERBOUND: &[(i32, &str)] = &[
+    (0x00, "Status Request"),
+    (0x01, "Ping Request"),
+];
+
+const STATUS_CLIENTBOUND: &[(i32, &str)] = &[
+    (0x00, "Status Response"),
+    (0x01, "Pong Response"),
+];
+
+const LOGIN_SERVERBOUND: &[(i32, &str)] = &[
+    (0x00, "Login Start"),
+    (0x01, "Encryption Response"),
+    (0x02, "Login Plugin Response"),
+];
+
+const LOGIN_CLIENTBOUND: &[(i32, &str)] = &[
+    (0x00, "Disconnect (login)"),
+    (0x01, "Encryption Request"),
+    (0x02, "Login Success"),
+    (0x03, "Set Compression"),
+    (0x04, "Login Plugin Request"),
+];
+
+const PLAY_SERVERBOUND: &[(i32, &str)] = &[
+    (0x00, "Confirm Teleportation"),
+    (0x04, "Chat Command"),
+    (0x05, "Chat Message"),
+    (0x08, "Client Information"),
+    (0x12, "Keep Alive"),
+    (0x14, "Set Player Position"),
+    (0x15, "Set Player Position and Rotation"),
+    (0x16, "Set Player Rotation"),
+    (0x17, "Set Player On Ground"),
+];
+
+const PLAY_CLIENTBOUND: &[(i32, &str)] = &[
+    (0x01, "Spawn Entity"),
+    (0x03, "Spawn Player"),
+    (0x1A, "Disconnect (play)"),
+    (0x23, "Keep Alive"),
+    (0x28, "Login (play)"),
+    (0x2B, "Update Entity Position"),
+    (0x2C, "Update Entity Position and Rotation"),
+    (0x2D, "Update Entity Rotation"),
+    (0x39, "Player Info Remove"),
+    (0x3A, "Player Info Update"),
+    (0x3C, "Synchronize Player Position"),
+    (0x3E, "Remove Entities"),
+    (0x42, "Set Head Rotation"),
+    (0x64, "System Chat Message"),
+    (0x68, "Teleport Entity"),
+];
+
+fn table(state: PacketState, direction: Direction) -> &'static [(i32, &'static str)] {
+    match (state, direction) {
+        (PacketState::Handshake, Direction::Serverbound) => HANDSHAKE_SERVERBOUND,
+        (PacketState::Handshake, Direction::Clientbound) => &[],
+        (PacketState::Status, Direction::Serverbound) => STATUS_SERVERBOUND,
+        (PacketState::Status, Direction::Clientbound) => STATUS_CLIENTBOUND,
+        (PacketState::Login, Direction::Serverbound) => LOGIN_SERVERBOUND,
+        (PacketState::Login, Direction::Clientbound) => LOGIN_CLIENTBOUND,
+        (PacketState::Play, Direction::Serverbound) => PLAY_SERVERBOUND,
+        (PacketState::Play, Direction::Clientbound) => PLAY_CLIENTBOUND,
+    }
+}
+
+/// 状態・方向・IDからパケット名を引く
+pub fn packet_name(state: PacketState, direction: Direction, id: i32) -> Option<&'static str> {
+    table(state, direction)
+        .iter()
+        .find(|(packet_id, _)| *packet_id == id)
+        .map(|(_, name)| *name)
+}
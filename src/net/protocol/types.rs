@@ -0,0 +1,613 @@
+//! プロトコルのデータ型
+//!
+//! https://wiki.vg/Protocol#Data_types に沿って、各型の読み書きを
+//! [`ProtocolRead`] / [`ProtocolWrite`] として実装する。
+//! `Buf` / `BufMut` に対してジェネリックなので、`Bytes` や `&[u8]` から直接読める。
+
+use std::fmt;
+
+use bytes::{Buf, BufMut};
+use uuid::Uuid;
+
+use crate::net::error::{Result, ServerError};
+use crate::net::protocol::PacketError;
+
+/// 文字列の既定の最大長 (UTF-16 コード単位)
+pub const MAX_STRING_LENGTH: usize = 32767;
+/// 配列の既定の最大要素数
+pub const MAX_ARRAY_LENGTH: usize = 1 << 16;
+/// VarInt / VarLong の最大バイト数
+pub const MAX_VARINT_BYTES: usize = 5;
+pub const MAX_VARLONG_BYTES: usize = 10;
+
+pub trait ProtocolRead: Sized {
+    fn read<B: Buf + ?Sized>(buf: &mut B) -> Result<Self>;
+}
+
+pub trait ProtocolWrite {
+    fn write<B: BufMut + ?Sized>(&self, buf: &mut B) -> Result<()>;
+}
+
+fn incomplete() -> ServerError {
+    ServerError::Protocol(PacketError::IncompletePacket.to_string())
+}
+
+fn invalid(reason: impl Into<String>) -> ServerError {
+    ServerError::Protocol(PacketError::DecodeError(reason.into()).to_string())
+}
+
+fn ensure<B: Buf + ?Sized>(buf: &B, needed: usize) -> Result<()> {
+    if buf.remaining() < needed {
+        return Err(incomplete());
+    }
+    Ok(())
+}
+
+macro_rules! fixed_width {
+    ($($ty:ty => $get:ident, $put:ident;)*) => {
+        $(
+            impl ProtocolRead for $ty {
+                fn read<B: Buf + ?Sized>(buf: &mut B) -> Result<Self> {
+                    ensure(buf, std::mem::size_of::<$ty>())?;
+                    Ok(buf.$get())
+                }
+            }
+
+            impl ProtocolWrite for $ty {
+                fn write<B: BufMut + ?Sized>(&self, buf: &mut B) -> Result<()> {
+                    buf.$put(*self);
+                    Ok(())
+                }
+            }
+        )*
+    };
+}
+
+// 固定長の整数と浮動小数点数はすべてビッグエンディアン
+fixed_width! {
+    u8 => get_u8, put_u8;
+    i8 => get_i8, put_i8;
+    u16 => get_u16, put_u16;
+    i16 => get_i16, put_i16;
+    i32 => get_i32, put_i32;
+    i64 => get_i64, put_i64;
+    u64 => get_u64, put_u64;
+    f32 => get_f32, put_f32;
+    f64 => get_f64, put_f64;
+}
+
+impl ProtocolRead for bool {
+    fn read<B: Buf + ?Sized>(buf: &mut B) -> Result<Self> {
+        match u8::read(buf)? {
+            0 => Ok(false),
+            1 => Ok(true),
+            value => Err(invalid(format!("不正な真偽値: {}", value))),
+        }
+    }
+}
+
+impl ProtocolWrite for bool {
+    fn write<B: BufMut + ?Sized>(&self, buf: &mut B) -> Result<()> {
+        buf.put_u8(*self as u8);
+        Ok(())
+    }
+}
+
+/// 可変長の32ビット整数
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct VarInt(pub i32);
+
+impl VarInt {
+    /// エンコード後のバイト数
+    pub fn encoded_len(self) -> usize {
+        crate::varint::utils::varint_len(self.0)
+    }
+}
+
+impl ProtocolRead for VarInt {
+    fn read<B: Buf + ?Sized>(buf: &mut B) -> Result<Self> {
+        let mut result = 0u32;
+        for i in 0..MAX_VARINT_BYTES {
+            let byte = u8::read(buf)?;
+            result |= ((byte & 0x7F) as u32) << (7 * i);
+            if byte & 0x80 == 0 {
+                return Ok(VarInt(result as i32));
+            }
+        }
+        Err(invalid("VarInt が長すぎます"))
+    }
+}
+
+impl ProtocolWrite for VarInt {
+    fn write<B: BufMut + ?Sized>(&self, buf: &mut B) -> Result<()> {
+        let mut value = self.0 as u32;
+        loop {
+            if value & !0x7F == 0 {
+                buf.put_u8(value as u8);
+                return Ok(());
+            }
+            buf.put_u8((value as u8 & 0x7F) | 0x80);
+            value >>= 7;
+        }
+    }
+}
+
+/// 可変長の64ビット整数
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct VarLong(pub i64);
+
+impl ProtocolRead for VarLong {
+    fn read<B: Buf + ?Sized>(buf: &mut B) -> Result<Self> {
+        let mut result = 0u64;
+        for i in 0..MAX_VARLONG_BYTES {
+            let byte = u8::read(buf)?;
+            result |= ((byte & 0x7F) as u64) << (7 * i);
+            if byte & 0x80 == 0 {
+                return Ok(VarLong(result as i64));
+            }
+        }
+        Err(invalid("VarLong が長すぎます"))
+    }
+}
+
+impl ProtocolWrite for VarLong {
+    fn write<B: BufMut + ?Sized>(&self, buf: &mut B) -> Result<()> {
+        let mut value = self.0 as u64;
+        loop {
+            if value & !0x7F == 0 {
+                buf.put_u8(value as u8);
+                return Ok(());
+            }
+            buf.put_u8((value as u8 & 0x7F) | 0x80);
+            value >>= 7;
+        }
+    }
+}
+
+/// 最大長を指定して文字列を読む
+pub fn read_string_bounded<B: Buf + ?Sized>(buf: &mut B, max_length: usize) -> Result<String> {
+    let len = VarInt::read(buf)?.0;
+    // UTF-8 では1コード単位が最大3バイトになる
+    if len < 0 || len as usize > max_length * 3 {
+        return Err(invalid(format!("文字列が長すぎます: {} バイト", len)));
+    }
+    let len = len as usize;
+    ensure(buf, len)?;
+    let mut bytes = vec![0u8; len];
+    buf.copy_to_slice(&mut bytes);
+    let value = String::from_utf8(bytes).map_err(|_| invalid("不正なUTF-8シーケンス"))?;
+    if value.encode_utf16().count() > max_length {
+        return Err(invalid(format!("文字列が長すぎます: 最大 {} 文字", max_length)));
+    }
+    Ok(value)
+}
+
+/// 最大長を指定して文字列を書く
+pub fn write_string_bounded<B: BufMut + ?Sized>(buf: &mut B, value: &str, max_length: usize) -> Result<()> {
+    if value.encode_utf16().count() > max_length {
+        return Err(ServerError::Protocol(
+            PacketError::EncodeError(format!("文字列が長すぎます: 最大 {} 文字", max_length)).to_string(),
+        ));
+    }
+    VarInt(value.len() as i32).write(buf)?;
+    buf.put_slice(value.as_bytes());
+    Ok(())
+}
+
+impl ProtocolRead for String {
+    fn read<B: Buf + ?Sized>(buf: &mut B) -> Result<Self> {
+        read_string_bounded(buf, MAX_STRING_LENGTH)
+    }
+}
+
+impl ProtocolWrite for String {
+    fn write<B: BufMut + ?Sized>(&self, buf: &mut B) -> Result<()> {
+        write_string_bounded(buf, self, MAX_STRING_LENGTH)
+    }
+}
+
+impl ProtocolWrite for &str {
+    fn write<B: BufMut + ?Sized>(&self, buf: &mut B) -> Result<()> {
+        write_string_bounded(buf, self, MAX_STRING_LENGTH)
+    }
+}
+
+/// 最大長が型で決まっている文字列 (ユーザー名なら `BoundedString<16>`)
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Default)]
+pub struct BoundedString<const MAX: usize>(pub String);
+
+impl<const MAX: usize> ProtocolRead for BoundedString<MAX> {
+    fn read<B: Buf + ?Sized>(buf: &mut B) -> Result<Self> {
+        read_string_bounded(buf, MAX).map(BoundedString)
+    }
+}
+
+impl<const MAX: usize> ProtocolWrite for BoundedString<MAX> {
+    fn write<B: BufMut + ?Sized>(&self, buf: &mut B) -> Result<()> {
+        write_string_bounded(buf, &self.0, MAX)
+    }
+}
+
+impl ProtocolRead for Uuid {
+    fn read<B: Buf + ?Sized>(buf: &mut B) -> Result<Self> {
+        ensure(buf, 16)?;
+        Ok(Uuid::from_u128(buf.get_u128()))
+    }
+}
+
+impl ProtocolWrite for Uuid {
+    fn write<B: BufMut + ?Sized>(&self, buf: &mut B) -> Result<()> {
+        buf.put_u128(self.as_u128());
+        Ok(())
+    }
+}
+
+/// ブロック座標。x と z は26ビット、y は12ビットの符号付き整数として1つの long に詰める
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Position {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+}
+
+impl Position {
+    pub const MIN_XZ: i32 = -(1 << 25);
+    pub const MAX_XZ: i32 = (1 << 25) - 1;
+    pub const MIN_Y: i32 = -(1 << 11);
+    pub const MAX_Y: i32 = (1 << 11) - 1;
+
+    pub fn new(x: i32, y: i32, z: i32) -> Self {
+        Self { x, y, z }
+    }
+
+    pub fn pack(self) -> i64 {
+        ((self.x as i64 & 0x3FF_FFFF) << 38) | ((self.z as i64 & 0x3FF_FFFF) << 12) | (self.y as i64 & 0xFFF)
+    }
+
+    pub fn unpack(value: i64) -> Self {
+        // 算術シフトで符号拡張する
+        Self {
+            x: (value >> 38) as i32,
+            y: (value << 52 >> 52) as i32,
+            z: (value << 26 >> 38) as i32,
+        }
+    }
+}
+
+impl ProtocolRead for Position {
+    fn read<B: Buf + ?Sized>(buf: &mut B) -> Result<Self> {
+        Ok(Position::unpack(i64::read(buf)?))
+    }
+}
+
+impl ProtocolWrite for Position {
+    fn write<B: BufMut + ?Sized>(&self, buf: &mut B) -> Result<()> {
+        if !(Self::MIN_XZ..=Self::MAX_XZ).contains(&self.x)
+            || !(Self::MIN_XZ..=Self::MAX_XZ).contains(&self.z)
+            || !(Self::MIN_Y..=Self::MAX_Y).contains(&self.y)
+        {
+            return Err(ServerError::Protocol(
+                PacketError::EncodeError(format!("座標が範囲外です: {:?}", self)).to_string(),
+            ));
+        }
+        self.pack().write(buf)
+    }
+}
+
+/// 1回転を256段階で表す角度
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Angle(pub u8);
+
+impl Angle {
+    pub fn from_degrees(degrees: f32) -> Self {
+        Angle((degrees.rem_euclid(360.0) / 360.0 * 256.0) as i32 as u8)
+    }
+
+    pub fn to_degrees(self) -> f32 {
+        self.0 as f32 * 360.0 / 256.0
+    }
+}
+
+impl ProtocolRead for Angle {
+    fn read<B: Buf + ?Sized>(buf: &mut B) -> Result<Self> {
+        Ok(Angle(u8::read(buf)?))
+    }
+}
+
+impl ProtocolWrite for Angle {
+    fn write<B: BufMut + ?Sized>(&self, buf: &mut B) -> Result<()> {
+        self.0.write(buf)
+    }
+}
+
+/// `namespace:path` 形式の名前空間付きID
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Identifier {
+    pub namespace: String,
+    pub path: String,
+}
+
+impl Identifier {
+    pub const DEFAULT_NAMESPACE: &'static str = "minecraft";
+
+    /// 文字列を解析する。名前空間が省略されていれば `minecraft`
+    pub fn parse(value: &str) -> Result<Self> {
+        let (namespace, path) = value.split_once(':').unwrap_or((Self::DEFAULT_NAMESPACE, value));
+        let valid_namespace = |c: char| matches!(c, 'a'..='z' | '0'..='9' | '.' | '-' | '_');
+        let valid_path = |c: char| valid_namespace(c) || c == '/';
+
+        if namespace.is_empty() || !namespace.chars().all(valid_namespace) {
+            return Err(invalid(format!("不正な名前空間: {}", value)));
+        }
+        if path.is_empty() || !path.chars().all(valid_path) {
+            return Err(invalid(format!("不正なパス: {}", value)));
+        }
+        Ok(Self { namespace: namespace.to_string(), path: path.to_string() })
+    }
+
+    pub fn minecraft(path: &str) -> Self {
+        Self { namespace: Self::DEFAULT_NAMESPACE.to_string(), path: path.to_string() }
+    }
+}
+
+impl fmt::Display for Identifier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.namespace, self.path)
+    }
+}
+
+impl ProtocolRead for Identifier {
+    fn read<B: Buf + ?Sized>(buf: &mut B) -> Result<Self> {
+        Identifier::parse(&String::read(buf)?)
+    }
+}
+
+impl ProtocolWrite for Identifier {
+    fn write<B: BufMut + ?Sized>(&self, buf: &mut B) -> Result<()> {
+        write_string_bounded(buf, &self.to_string(), MAX_STRING_LENGTH)
+    }
+}
+
+/// 長さ付きのビットセット (long の配列)
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct BitSet(pub Vec<u64>);
+
+impl BitSet {
+    pub fn get(&self, index: usize) -> bool {
+        self.0.get(index / 64).is_some_and(|word| word & (1 << (index % 64)) != 0)
+    }
+
+    pub fn set(&mut self, index: usize, value: bool) {
+        let word = index / 64;
+        if word >= self.0.len() {
+            if !value {
+                return;
+            }
+            self.0.resize(word + 1, 0);
+        }
+        if value {
+            self.0[word] |= 1 << (index % 64);
+        } else {
+            self.0[word] &= !(1 << (index % 64));
+        }
+    }
+}
+
+impl ProtocolRead for BitSet {
+    fn read<B: Buf + ?Sized>(buf: &mut B) -> Result<Self> {
+        read_array_bounded(buf, MAX_ARRAY_LENGTH).map(BitSet)
+    }
+}
+
+impl ProtocolWrite for BitSet {
+    fn write<B: BufMut + ?Sized>(&self, buf: &mut B) -> Result<()> {
+        self.0.write(buf)
+    }
+}
+
+/// 長さの前置がない固定長ビットセット (`BYTES` バイト)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedBitSet<const BYTES: usize>(pub [u8; BYTES]);
+
+impl<const BYTES: usize> Default for FixedBitSet<BYTES> {
+    fn default() -> Self {
+        FixedBitSet([0; BYTES])
+    }
+}
+
+impl<const BYTES: usize> ProtocolRead for FixedBitSet<BYTES> {
+    fn read<B: Buf + ?Sized>(buf: &mut B) -> Result<Self> {
+        ensure(buf, BYTES)?;
+        let mut bits = [0u8; BYTES];
+        buf.copy_to_slice(&mut bits);
+        Ok(FixedBitSet(bits))
+    }
+}
+
+impl<const BYTES: usize> ProtocolWrite for FixedBitSet<BYTES> {
+    fn write<B: BufMut + ?Sized>(&self, buf: &mut B) -> Result<()> {
+        buf.put_slice(&self.0);
+        Ok(())
+    }
+}
+
+/// 要素数の上限を指定して VarInt 長さ付きの配列を読む
+pub fn read_array_bounded<T: ProtocolRead>(buf: &mut (impl Buf + ?Sized), max_length: usize) -> Result<Vec<T>> {
+    let len = if std::mem::size_of::<T>() > 0 {
+        read_array_len(buf, max_length)?
+    } else {
+        read_len_prefix(buf, max_length)?
+    };
+    (0..len).map(|_| T::read(buf)).collect()
+}
+
+/// 配列の要素数だけを読む。要素の読み方が前のフィールドで変わる配列に使う。
+/// 要素は最低1バイトとみなし、残りより多い要素数は読む前に弾く
+pub fn read_array_len(buf: &mut (impl Buf + ?Sized), max_length: usize) -> Result<usize> {
+    let len = read_len_prefix(buf, max_length)?;
+    if len > buf.remaining() {
+        return Err(incomplete());
+    }
+    Ok(len)
+}
+
+fn read_len_prefix(buf: &mut (impl Buf + ?Sized), max_length: usize) -> Result<usize> {
+    let len = VarInt::read(buf)?.0;
+    if len < 0 || len as usize > max_length {
+        return Err(invalid(format!("配列が長すぎます: {} 要素", len)));
+    }
+    Ok(len as usize)
+}
+
+impl<T: ProtocolRead> ProtocolRead for Vec<T> {
+    fn read<B: Buf + ?Sized>(buf: &mut B) -> Result<Self> {
+        read_array_bounded(buf, MAX_ARRAY_LENGTH)
+    }
+}
+
+impl<T: ProtocolWrite> ProtocolWrite for Vec<T> {
+    fn write<B: BufMut + ?Sized>(&self, buf: &mut B) -> Result<()> {
+        self.as_slice().write(buf)
+    }
+}
+
+impl<T: ProtocolWrite> ProtocolWrite for [T] {
+    fn write<B: BufMut + ?Sized>(&self, buf: &mut B) -> Result<()> {
+        VarInt(self.len() as i32).write(buf)?;
+        for item in self {
+            item.write(buf)?;
+        }
+        Ok(())
+    }
+}
+
+/// 真偽値が前置されたオプショナル値
+impl<T: ProtocolRead> ProtocolRead for Option<T> {
+    fn read<B: Buf + ?Sized>(buf: &mut B) -> Result<Self> {
+        if bool::read(buf)? {
+            Ok(Some(T::read(buf)?))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl<T: ProtocolWrite> ProtocolWrite for Option<T> {
+    fn write<B: BufMut + ?Sized>(&self, buf: &mut B) -> Result<()> {
+        match self {
+            Some(value) => {
+                true.write(buf)?;
+                value.write(buf)
+            }
+            None => false.write(buf),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::{Bytes, BytesMut};
+
+    fn roundtrip<T: ProtocolRead + ProtocolWrite + PartialEq + fmt::Debug>(value: T) -> BytesMut {
+        let mut buf = BytesMut::new();
+        value.write(&mut buf).unwrap();
+        let encoded = buf.clone();
+        assert_eq!(T::read(&mut buf).unwrap(), value);
+        assert!(buf.is_empty());
+        encoded
+    }
+
+    #[test]
+    fn test_fixed_width_is_big_endian() {
+        assert_eq!(&roundtrip(0x0102_0304_0506_0708i64)[..], &[1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(&roundtrip(-2i16)[..], &[0xFF, 0xFE]);
+        roundtrip(1.5f64);
+        roundtrip(true);
+
+        let mut buf = BytesMut::from(&[1, 2, 3][..]);
+        assert!(i64::read(&mut buf).is_err());
+    }
+
+    #[test]
+    fn test_varint_and_varlong() {
+        assert_eq!(&roundtrip(VarInt(300))[..], &[0xAC, 0x02]);
+        assert_eq!(&roundtrip(VarInt(-1))[..], &[0xFF, 0xFF, 0xFF, 0xFF, 0x0F]);
+        assert_eq!(roundtrip(VarLong(i64::MIN)).len(), 10);
+        assert_eq!(VarInt(2_097_151).encoded_len(), 3);
+        assert_eq!(VarInt(-1).encoded_len(), 5);
+
+        let mut overlong = BytesMut::from(&[0x80, 0x80, 0x80, 0x80, 0x80, 0x01][..]);
+        assert!(VarInt::read(&mut overlong).is_err());
+    }
+
+    #[test]
+    fn test_position_packing() {
+        let position = Position::new(18357644, 831, -20882616);
+        assert_eq!(position.pack(), 0x4607_632C_15B4_833F);
+        roundtrip(position);
+        roundtrip(Position::new(-1, -64, -1));
+        assert!(Position::new(1 << 25, 0, 0).write(&mut BytesMut::new()).is_err());
+    }
+
+    #[test]
+    fn test_angle_and_identifier() {
+        assert_eq!(Angle::from_degrees(90.0), Angle(64));
+        assert_eq!(Angle::from_degrees(-90.0), Angle(192));
+        assert_eq!(Angle(128).to_degrees(), 180.0);
+
+        assert_eq!(Identifier::parse("stone").unwrap(), Identifier::minecraft("stone"));
+        roundtrip(Identifier::parse("mymod:blocks/ore").unwrap());
+        assert!(Identifier::parse("Bad:Name").is_err());
+        assert!(Identifier::parse("minecraft:").is_err());
+    }
+
+    #[test]
+    fn test_strings_respect_limits() {
+        roundtrip("Steve".to_string());
+        roundtrip(BoundedString::<16>("Notch".to_string()));
+
+        let mut buf = BytesMut::new();
+        write_string_bounded(&mut buf, &"a".repeat(17), MAX_STRING_LENGTH).unwrap();
+        assert!(BoundedString::<16>::read(&mut buf).is_err());
+        assert!(BoundedString::<16>("a".repeat(17)).write(&mut BytesMut::new()).is_err());
+    }
+
+    #[test]
+    fn test_bitset_arrays_and_optionals() {
+        let mut bits = BitSet::default();
+        bits.set(3, true);
+        bits.set(70, true);
+        assert!(bits.get(70) && bits.get(3) && !bits.get(4));
+        roundtrip(bits);
+        assert_eq!(&roundtrip(FixedBitSet::<3>([1, 2, 3]))[..], &[1, 2, 3]);
+
+        roundtrip(vec![VarInt(1), VarInt(1000)]);
+        roundtrip(Some(Uuid::from_u128(42)));
+        roundtrip::<Option<Uuid>>(None);
+
+        let mut buf = BytesMut::new();
+        VarInt(10).write(&mut buf).unwrap();
+        assert!(read_array_bounded::<u8>(&mut buf, 4).is_err());
+    }
+
+    #[test]
+    fn test_read_from_slices_and_bytes() {
+        let mut buf = BytesMut::new();
+        VarInt(300).write(&mut buf).unwrap();
+        "hello".write(&mut buf).unwrap();
+        Position::new(1, 2, 3).write(&mut buf).unwrap();
+
+        let mut slice = &buf[..];
+        assert_eq!(VarInt::read(&mut slice).unwrap(), VarInt(300));
+        assert_eq!(String::read(&mut slice).unwrap(), "hello");
+        assert_eq!(Position::read(&mut slice).unwrap(), Position::new(1, 2, 3));
+        assert!(slice.is_empty());
+
+        let mut bytes = Bytes::from(buf.to_vec()).chain(&[0x01][..]);
+        VarInt::read(&mut bytes).unwrap();
+        String::read(&mut bytes).unwrap();
+        Position::read(&mut bytes).unwrap();
+        assert!(bool::read(&mut bytes).unwrap());
+    }
+}
@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::{Buf, BufMut, BytesMut};
+use log::{info, warn};
+use parking_lot::Mutex;
+use tokio::net::UdpSocket;
+
+use super::context::{ServerContext, StatusInfo};
+
+// GameSpy4 Query プロトコル (server.properties の enable-query)
+//
+// https://wiki.vg/Query
+
+const MAGIC: [u8; 2] = [0xFE, 0xFD];
+const TYPE_HANDSHAKE: u8 = 9;
+const TYPE_STAT: u8 = 0;
+
+/// フルステータス応答のK/Vセクション前に置かれる固定パディング
+const FULL_STAT_KV_PADDING: &[u8] = b"splitnum\x00\x80\x00";
+/// フルステータス応答のプレイヤーセクション前に置かれる固定パディング
+const FULL_STAT_PLAYER_PADDING: &[u8] = b"\x01player_\x00\x00";
+
+const GAME_TYPE: &str = "SMP";
+const GAME_ID: &str = "MINECRAFT";
+const MAP_NAME: &str = "world";
+/// 同時に保持するトークンの上限。送信元を偽装したハンドシェイクでメモリを使い切られないようにする
+pub const MAX_CHALLENGE_TOKENS: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum QueryRequest {
+    Handshake { session_id: i32 },
+    BasicStat { session_id: i32, token: i32 },
+    FullStat { session_id: i32, token: i32 },
+}
+
+impl QueryRequest {
+    /// 受信したデータグラムを解析する。不正なものは `None`
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let mut buf = data;
+        if buf.len() < 7 || buf[..2] != MAGIC {
+            return None;
+        }
+        buf.advance(2);
+        let kind = buf.get_u8();
+        // セッションIDは各バイトの下位4ビットのみ有効
+        let session_id = buf.get_i32() & 0x0F0F_0F0F;
+
+        match (kind, buf.remaining()) {
+            (TYPE_HANDSHAKE, 0) => Some(QueryRequest::Handshake { session_id }),
+            (TYPE_STAT, 4) => Some(QueryRequest::BasicStat { session_id, token: buf.get_i32() }),
+            (TYPE_STAT, 8) => Some(QueryRequest::FullStat { session_id, token: buf.get_i32() }),
+            _ => None,
+        }
+    }
+}
+
+/// 送信元アドレスごとに発行したチャレンジトークン
+pub struct ChallengeTokens {
+    tokens: Mutex<HashMap<SocketAddr, (i32, Instant)>>,
+    lifetime: Duration,
+}
+
+impl ChallengeTokens {
+    pub fn new(lifetime: Duration) -> Self {
+        Self {
+            tokens: Mutex::new(HashMap::new()),
+            lifetime,
+        }
+    }
+
+    /// 新しいトークンを発行する（同じアドレスの古いトークンは置き換える）。
+    /// 期限内のトークンが上限まであれば発行せず `None`
+    pub fn issue(&self, addr: SocketAddr) -> Option<i32> {
+        let mut tokens = self.tokens.lock();
+        if tokens.len() >= MAX_CHALLENGE_TOKENS && !tokens.contains_key(&addr) {
+            let lifetime = self.lifetime;
+            tokens.retain(|_, (_, at)| at.elapsed() < lifetime);
+            if tokens.len() >= MAX_CHALLENGE_TOKENS {
+                return None;
+            }
+        }
+        let token = (rand::random::<u32>() >> 1) as i32;
+        tokens.insert(addr, (token, Instant::now()));
+        Some(token)
+    }
+
+    /// トークンが有効期限内で、そのアドレスに発行したものと一致するか
+    pub fn verify(&self, addr: &SocketAddr, token: i32) -> bool {
+        match self.tokens.lock().get(addr) {
+            Some(&(issued, at)) => issued == token && at.elapsed() < self.lifetime,
+            None => false,
+        }
+    }
+
+    /// 期限切れのトークンを破棄する
+    pub fn purge_expired(&self) {
+        let lifetime = self.lifetime;
+        self.tokens.lock().retain(|_, (_, at)| at.elapsed() < lifetime);
+    }
+}
+
+fn put_cstring(buf: &mut BytesMut, value: &str) {
+    buf.put_slice(value.as_bytes());
+    buf.put_u8(0);
+}
+
+fn response_header(buf: &mut BytesMut, kind: u8, session_id: i32) {
+    buf.put_u8(kind);
+    buf.put_i32(session_id);
+}
+
+pub fn handshake_response(session_id: i32, token: i32) -> BytesMut {
+    let mut buf = BytesMut::new();
+    response_header(&mut buf, TYPE_HANDSHAKE, session_id);
+    put_cstring(&mut buf, &token.to_string());
+    buf
+}
+
+pub fn basic_stat_response(session_id: i32, info: &StatusInfo, host: &SocketAddr) -> BytesMut {
+    let mut buf = BytesMut::new();
+    response_header(&mut buf, TYPE_STAT, session_id);
+    put_cstring(&mut buf, &info.motd);
+    put_cstring(&mut buf, GAME_TYPE);
+    put_cstring(&mut buf, MAP_NAME);
+    put_cstring(&mut buf, &info.online_players.len().to_string());
+    put_cstring(&mut buf, &info.max_players.to_string());
+    // hostport だけはリトルエンディアン
+    buf.put_u16_le(host.port());
+    put_cstring(&mut buf, &host.ip().to_string());
+    buf
+}
+
+pub fn full_stat_response(session_id: i32, info: &StatusInfo, host: &SocketAddr) -> BytesMut {
+    let plugins = format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+    let pairs = [
+        ("hostname", info.motd.clone()),
+        ("gametype", GAME_TYPE.to_string()),
+        ("game_id", GAME_ID.to_string()),
+        ("version", info.version_name.clone()),
+        ("plugins", plugins),
+        ("map", MAP_NAME.to_string()),
+        ("numplayers", info.online_players.len().to_string()),
+        ("maxplayers", info.max_players.to_string()),
+        ("hostport", host.port().to_string()),
+        ("hostip", host.ip().to_string()),
+    ];
+
+    let mut buf = BytesMut::new();
+    response_header(&mut buf, TYPE_STAT, session_id);
+    buf.put_slice(FULL_STAT_KV_PADDING);
+    for (key, value) in pairs.iter() {
+        put_cstring(&mut buf, key);
+        put_cstring(&mut buf, value);
+    }
+    buf.put_u8(0);
+
+    buf.put_slice(FULL_STAT_PLAYER_PADDING);
+    for name in &info.online_players {
+        put_cstring(&mut buf, name);
+    }
+    buf.put_u8(0);
+    buf
+}
+
+/// Queryリスナーを起動する。`start_server` から別タスクとして呼ばれる
+pub async fn start_query(ctx: Arc<ServerContext>) -> tokio::io::Result<()> {
+    let config = ctx.config();
+    let game_addr: SocketAddr = config.listen_address.parse()
+        .unwrap_or_else(|_| SocketAddr::new(IpAddr::from([0, 0, 0, 0]), 25565));
+    let bind_addr = SocketAddr::new(game_addr.ip(), config.query.port);
+
+    let socket = UdpSocket::bind(bind_addr).await?;
+    info!("Query listener started on udp://{}", bind_addr);
+
+    let lifetime = config.query.token_lifetime;
+    let tokens = Arc::new(ChallengeTokens::new(lifetime));
+
+    // 期限切れトークンの定期破棄
+    let purge_tokens = tokens.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(lifetime);
+        loop {
+            interval.tick().await;
+            purge_tokens.purge_expired();
+        }
+    });
+
+    let mut buf = [0u8; 1460];
+    loop {
+        let (size, peer) = socket.recv_from(&mut buf).await?;
+
+        let response = match QueryRequest::parse(&buf[..size]) {
+            Some(QueryRequest::Handshake { session_id }) => match tokens.issue(peer) {
+                Some(token) => handshake_response(session_id, token),
+                None => {
+                    warn!("Too many outstanding query challenges, ignoring handshake from {}", peer);
+                    continue;
+                }
+            },
+            Some(QueryRequest::BasicStat { session_id, token }) if tokens.verify(&peer, token) => {
+                basic_stat_response(session_id, &ctx.status_info(), &game_addr)
+            }
+            Some(QueryRequest::FullStat { session_id, token }) if tokens.verify(&peer, token) => {
+                full_stat_response(session_id, &ctx.status_info(), &game_addr)
+            }
+            // 不正なパケットや無効なトークンには応答しない
+            _ => continue,
+        };
+
+        if let Err(e) = socket.send_to(&response, peer).await {
+            warn!("Failed to send query response to {}: {}", peer, e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info() -> StatusInfo {
+        StatusInfo {
+            version_name: "1.20.1".to_string(),
+            protocol: 763,
+            motd: "A Minecraft Server".to_string(),
+            max_players: 20,
+            online_players: vec!["Steve".to_string(), "Alex".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_parse_requests() {
+        let handshake = [0xFE, 0xFD, 0x09, 0x00, 0x00, 0x00, 0x01];
+        assert_eq!(QueryRequest::parse(&handshake), Some(QueryRequest::Handshake { session_id: 1 }));
+
+        let basic = [0xFE, 0xFD, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x91, 0x29, 0x5B];
+        assert_eq!(
+            QueryRequest::parse(&basic),
+            Some(QueryRequest::BasicStat { session_id: 0x0F0F_0F0F, token: 9513307 })
+        );
+
+        let mut full = basic.to_vec();
+        full.extend_from_slice(&[0, 0, 0, 0]);
+        assert!(matches!(QueryRequest::parse(&full), Some(QueryRequest::FullStat { .. })));
+
+        assert_eq!(QueryRequest::parse(&[0xFE, 0xFD, 0x09]), None);
+        assert_eq!(QueryRequest::parse(&[0x00, 0xFD, 0x09, 0, 0, 0, 1]), None);
+    }
+
+    #[test]
+    fn test_challenge_tokens_expire() {
+        let addr: SocketAddr = "127.0.0.1:50000".parse().unwrap();
+        let tokens = ChallengeTokens::new(Duration::from_millis(0));
+        let token = tokens.issue(addr).unwrap();
+        assert!(!tokens.verify(&addr, token));
+        tokens.purge_expired();
+        assert!(tokens.tokens.lock().is_empty());
+
+        let tokens = ChallengeTokens::new(Duration::from_secs(30));
+        let token = tokens.issue(addr).unwrap();
+        assert!(tokens.verify(&addr, token));
+        assert!(!tokens.verify(&addr, token.wrapping_add(1)));
+    }
+
+    #[test]
+    fn test_challenge_tokens_are_capped() {
+        let tokens = ChallengeTokens::new(Duration::from_secs(30));
+        for port in 0..MAX_CHALLENGE_TOKENS as u16 {
+            assert!(tokens.issue(SocketAddr::from(([10, 0, 0, 1], port))).is_some());
+        }
+        assert_eq!(tokens.issue("10.0.0.2:1".parse().unwrap()), None);
+        // 発行済みのアドレスは置き換えられる
+        assert!(tokens.issue("10.0.0.1:0".parse().unwrap()).is_some());
+        assert_eq!(tokens.tokens.lock().len(), MAX_CHALLENGE_TOKENS);
+
+        // 期限切れがあれば場所を空けて発行する
+        let tokens = ChallengeTokens::new(Duration::from_millis(0));
+        for port in 0..MAX_CHALLENGE_TOKENS as u16 {
+            tokens.issue(SocketAddr::from(([10, 0, 0, 1], port)));
+        }
+        assert!(tokens.issue("10.0.0.2:1".parse().unwrap()).is_some());
+    }
+
+    #[test]
+    fn test_stat_responses() {
+        let host: SocketAddr = "127.0.0.1:25565".parse().unwrap();
+
+        let basic = basic_stat_response(1, &info(), &host);
+        assert_eq!(&basic[..5], &[0x00, 0x00, 0x00, 0x00, 0x01]);
+        assert!(basic.ends_with(b"20\x00\xDD\x63127.0.0.1\x00"));
+
+        let full = full_stat_response(1, &info(), &host);
+        assert_eq!(&full[5..16], FULL_STAT_KV_PADDING);
+        assert!(full.ends_with(b"\x01player_\x00\x00Steve\x00Alex\x00\x00"));
+    }
+}
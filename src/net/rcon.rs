@@ -0,0 +1,345 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::{Buf, BufMut, BytesMut};
+use log::{info, warn};
+use parking_lot::Mutex;
+use sha1::{Digest, Sha1};
+use subtle::ConstantTimeEq;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use super::context::ServerContext;
+use crate::command::CommandSource;
+use crate::net::error::{Result, ServerError};
+
+// Source RCON プロトコル
+//
+// https://developer.valvesoftware.com/wiki/Source_RCON_Protocol
+
+pub const TYPE_RESPONSE: i32 = 0;
+pub const TYPE_COMMAND: i32 = 2;
+pub const TYPE_AUTH_RESPONSE: i32 = 2;
+pub const TYPE_LOGIN: i32 = 3;
+
+/// 認証失敗時の応答に使うリクエストID
+pub const AUTH_FAILED_ID: i32 = -1;
+
+/// 1パケットに載せる応答ペイロードの最大バイト数
+pub const MAX_RESPONSE_PAYLOAD: usize = 4096;
+/// 受け付けるパケット長（長さフィールドの値）の上限
+const MAX_INCOMING_LENGTH: usize = 4096 + 10;
+/// リクエストID・種別・終端の2バイトを合わせた最小長
+const MIN_LENGTH: usize = 10;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RconPacket {
+    pub request_id: i32,
+    pub kind: i32,
+    pub payload: String,
+}
+
+impl RconPacket {
+    pub fn new(request_id: i32, kind: i32, payload: impl Into<String>) -> Self {
+        Self { request_id, kind, payload: payload.into() }
+    }
+
+    pub fn encode(&self, buf: &mut BytesMut) {
+        buf.put_i32_le((self.payload.len() + MIN_LENGTH) as i32);
+        buf.put_i32_le(self.request_id);
+        buf.put_i32_le(self.kind);
+        buf.put_slice(self.payload.as_bytes());
+        buf.put_slice(&[0, 0]);
+    }
+
+    /// バッファから1パケットを取り出す。データが足りなければ `None`
+    pub fn decode(buf: &mut BytesMut) -> Result<Option<Self>> {
+        if buf.len() < 4 {
+            return Ok(None);
+        }
+        let length = i32::from_le_bytes([buf[0], buf[1], buf[2], buf[3]]);
+        if length < MIN_LENGTH as i32 || length as usize > MAX_INCOMING_LENGTH {
+            return Err(ServerError::Protocol(format!("不正なRCONパケット長: {}", length)));
+        }
+        let length = length as usize;
+        if buf.len() < 4 + length {
+            return Ok(None);
+        }
+
+        buf.advance(4);
+        let mut body = buf.split_to(length);
+        let request_id = body.get_i32_le();
+        let kind = body.get_i32_le();
+        let payload = &body[..body.len() - 2];
+        let payload = String::from_utf8(payload.to_vec())?;
+
+        Ok(Some(Self { request_id, kind, payload }))
+    }
+}
+
+/// 長い出力を文字境界を保ったまま複数の応答パケットに分割する
+pub fn fragment_response(request_id: i32, output: &str) -> Vec<RconPacket> {
+    let mut packets = Vec::new();
+    let mut rest = output;
+    loop {
+        let mut end = rest.len().min(MAX_RESPONSE_PAYLOAD);
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (chunk, tail) = rest.split_at(end);
+        packets.push(RconPacket::new(request_id, TYPE_RESPONSE, chunk));
+        rest = tail;
+        if rest.is_empty() {
+            break;
+        }
+    }
+    packets
+}
+
+struct FailureRecord {
+    count: u32,
+    window_start: Instant,
+    blocked_until: Option<Instant>,
+}
+
+/// パスワードを比べる。長さも漏らさないよう、固定長のダイジェストを定数時間で比べる
+pub fn password_matches(given: &str, expected: &str) -> bool {
+    Sha1::digest(given.as_bytes()).ct_eq(&Sha1::digest(expected.as_bytes())).into()
+}
+
+/// IPアドレスごとの認証失敗を数え、一定回数を超えたらブロックする。
+/// キーを変えればプレイヤーごとなど、IPアドレス以外の単位でも数えられる
+pub struct AuthRateLimiter<K = IpAddr> {
+    failures: Mutex<HashMap<K, FailureRecord>>,
+    limits: Mutex<RateLimits>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct RateLimits {
+    max_failures: u32,
+    window: Duration,
+    block_duration: Duration,
+}
+
+impl<K: Eq + Hash + Clone> AuthRateLimiter<K> {
+    pub fn new(max_failures: u32, window: Duration, block_duration: Duration) -> Self {
+        Self {
+            failures: Mutex::new(HashMap::new()),
+            limits: Mutex::new(RateLimits { max_failures, window, block_duration }),
+        }
+    }
+
+    /// 設定の再読み込みで変わった上限を反映する。記録済みの失敗回数はそのまま
+    pub fn set_limits(&self, max_failures: u32, window: Duration, block_duration: Duration) {
+        *self.limits.lock() = RateLimits { max_failures, window, block_duration };
+    }
+
+    pub fn is_blocked(&self, key: &K) -> bool {
+        let mut failures = self.failures.lock();
+        match failures.get(key).and_then(|record| record.blocked_until) {
+            Some(until) if until > Instant::now() => true,
+            Some(_) => {
+                failures.remove(key);
+                false
+            }
+            None => false,
+        }
+    }
+
+    /// ブロックされるまでに残っている失敗の回数
+    pub fn failures_left(&self, key: &K) -> u32 {
+        let limits = *self.limits.lock();
+        let count = self.failures.lock().get(key)
+            .filter(|record| record.window_start.elapsed() <= limits.window)
+            .map_or(0, |record| record.count);
+        limits.max_failures.saturating_sub(count)
+    }
+
+    /// 認証失敗を記録する。このアドレスがブロックされた場合は `true`
+    pub fn record_failure(&self, key: K) -> bool {
+        let now = Instant::now();
+        let limits = *self.limits.lock();
+        let mut failures = self.failures.lock();
+        let record = failures.entry(key).or_insert(FailureRecord {
+            count: 0,
+            window_start: now,
+            blocked_until: None,
+        });
+
+        if now.duration_since(record.window_start) > limits.window {
+            record.count = 0;
+            record.window_start = now;
+        }
+        record.count += 1;
+
+        if record.count >= limits.max_failures {
+            record.blocked_until = Some(now + limits.block_duration);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn record_success(&self, key: &K) {
+        self.failures.lock().remove(key);
+    }
+}
+
+/// RCONリスナーを起動する。`start_server` から別タスクとして呼ばれる
+pub async fn start_rcon(ctx: Arc<ServerContext>) -> Result<()> {
+    let config = ctx.config();
+    if config.rcon.password.is_empty() {
+        return Err(ServerError::Config("RCONのパスワードが設定されていません".into()));
+    }
+
+    let game_addr: SocketAddr = config.listen_address.parse()
+        .map_err(|_| ServerError::Config(format!("不正な待ち受けアドレス: {}", config.listen_address)))?;
+    let bind_addr = SocketAddr::new(game_addr.ip(), config.rcon.port);
+    let listener = TcpListener::bind(bind_addr).await?;
+    info!("RCON running on {}", bind_addr);
+
+    let limiter = Arc::new(AuthRateLimiter::new(
+        config.rcon.max_failed_attempts,
+        config.rcon.failure_window,
+        config.rcon.block_duration,
+    ));
+
+    let mut shutdown = ctx.shutdown_signal();
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = shutdown.wait_for(|stopped| *stopped) => return Ok(()),
+        };
+
+        // 再読み込みされた上限を使う
+        let current = ctx.config();
+        limiter.set_limits(current.rcon.max_failed_attempts, current.rcon.failure_window, current.rcon.block_duration);
+        if limiter.is_blocked(&peer.ip()) {
+            warn!("Rejected RCON connection from blocked address {}", peer);
+            continue;
+        }
+
+        let ctx = ctx.clone();
+        let limiter = limiter.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_rcon_client(stream, peer, ctx, limiter).await {
+                warn!("RCON connection {} closed: {}", peer, e);
+            }
+        });
+    }
+}
+
+async fn handle_rcon_client(
+    mut stream: TcpStream,
+    peer: SocketAddr,
+    ctx: Arc<ServerContext>,
+    limiter: Arc<AuthRateLimiter>,
+) -> Result<()> {
+    let mut buf = BytesMut::with_capacity(1460);
+    let mut authenticated = false;
+
+    loop {
+        let packet = match RconPacket::decode(&mut buf)? {
+            Some(packet) => packet,
+            None => {
+                if stream.read_buf(&mut buf).await? == 0 {
+                    return Ok(());
+                }
+                continue;
+            }
+        };
+
+        let mut out = BytesMut::new();
+        match packet.kind {
+            TYPE_LOGIN => {
+                if password_matches(&packet.payload, &ctx.config().rcon.password) {
+                    authenticated = true;
+                    limiter.record_success(&peer.ip());
+                    info!("RCON client {} authenticated", peer);
+                    RconPacket::new(packet.request_id, TYPE_AUTH_RESPONSE, "").encode(&mut out);
+                } else {
+                    authenticated = false;
+                    let blocked = limiter.record_failure(peer.ip());
+                    warn!("RCON authentication failed from {}", peer);
+                    RconPacket::new(AUTH_FAILED_ID, TYPE_AUTH_RESPONSE, "").encode(&mut out);
+                    if blocked {
+                        warn!("Too many RCON authentication failures from {}, blocking", peer);
+                        stream.write_all(&out).await?;
+                        return Ok(());
+                    }
+                }
+            }
+            TYPE_COMMAND if authenticated => {
+                let output = ctx.commands.dispatch(&ctx, CommandSource::Rcon(peer), &packet.payload);
+                for fragment in fragment_response(packet.request_id, &output) {
+                    fragment.encode(&mut out);
+                }
+            }
+            // 分割応答の終端検出用に、クライアントが送る空の応答パケットをそのまま返す
+            TYPE_RESPONSE if authenticated => {
+                RconPacket::new(packet.request_id, TYPE_RESPONSE, "").encode(&mut out);
+            }
+            _ => {
+                return Err(ServerError::Protocol("認証前のRCONリクエスト".into()));
+            }
+        }
+        stream.write_all(&out).await?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packet_roundtrip() {
+        let packet = RconPacket::new(42, TYPE_COMMAND, "list");
+        let mut buf = BytesMut::new();
+        packet.encode(&mut buf);
+        assert_eq!(buf.len(), 4 + 10 + 4);
+
+        let mut partial = BytesMut::from(&buf[..8]);
+        assert_eq!(RconPacket::decode(&mut partial).unwrap(), None);
+        assert_eq!(RconPacket::decode(&mut buf).unwrap(), Some(packet));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn test_fragment_response() {
+        let output = "あ".repeat(MAX_RESPONSE_PAYLOAD);
+        let fragments = fragment_response(7, &output);
+        assert!(fragments.len() > 1);
+        assert!(fragments.iter().all(|p| p.payload.len() <= MAX_RESPONSE_PAYLOAD && p.request_id == 7));
+        assert_eq!(fragments.iter().map(|p| p.payload.as_str()).collect::<String>(), output);
+
+        assert_eq!(fragment_response(1, "").len(), 1);
+    }
+
+    #[test]
+    fn test_password_matches() {
+        assert!(password_matches("secret", "secret"));
+        assert!(!password_matches("secret", "Secret"));
+        assert!(!password_matches("secret", "secret2"));
+        assert!(!password_matches("", "secret"));
+    }
+
+    #[test]
+    fn test_rate_limiter_blocks_after_failures() {
+        let ip: IpAddr = "10.0.0.1".parse().unwrap();
+        let limiter = AuthRateLimiter::new(3, Duration::from_secs(60), Duration::from_secs(60));
+        assert!(!limiter.record_failure(ip));
+        assert!(!limiter.record_failure(ip));
+        assert!(!limiter.is_blocked(&ip));
+        assert_eq!(limiter.failures_left(&ip), 1);
+        assert!(limiter.record_failure(ip));
+        assert!(limiter.is_blocked(&ip));
+
+        let other: IpAddr = "10.0.0.2".parse().unwrap();
+        limiter.record_failure(other);
+        limiter.record_success(&other);
+        assert!(!limiter.record_failure(other));
+    }
+}
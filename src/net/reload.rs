@@ -0,0 +1,81 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use log::{info, warn};
+
+use super::context::ServerContext;
+
+/// `watch_config` が有効なときに設定ファイルの更新時刻を確認する間隔
+const WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// SIGHUP と設定ファイルの監視による再読み込みを開始する。
+/// 設定ファイルなしで作られたコンテキストでは何もしない
+pub fn spawn_reload_tasks(ctx: &Arc<ServerContext>) {
+    if ctx.config_path().is_none() {
+        return;
+    }
+
+    #[cfg(unix)]
+    tokio::spawn(reload_on_sighup(ctx.clone()));
+    tokio::spawn(watch_config_file(ctx.clone()));
+}
+
+fn reload(ctx: &ServerContext, trigger: &str) {
+    info!("Reloading configuration ({})", trigger);
+    if let Err(e) = ctx.reload_config() {
+        // 読み込めなければ今の設定のまま動かし続ける
+        warn!("Configuration reload failed, keeping the current settings: {}", e);
+    }
+}
+
+#[cfg(unix)]
+async fn reload_on_sighup(ctx: Arc<ServerContext>) {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            warn!("Failed to listen for SIGHUP: {}", e);
+            return;
+        }
+    };
+    let mut shutdown = ctx.shutdown_signal();
+    loop {
+        tokio::select! {
+            received = hangup.recv() => {
+                if received.is_none() {
+                    return;
+                }
+                reload(&ctx, "SIGHUP");
+            }
+            _ = shutdown.wait_for(|stopped| *stopped) => return,
+        }
+    }
+}
+
+fn modified(ctx: &ServerContext) -> Option<SystemTime> {
+    let path = ctx.config_path()?;
+    std::fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+/// 更新時刻が変わったら読み直す。`watch_config` は再読み込みで切り替えられるので毎回確認する
+async fn watch_config_file(ctx: Arc<ServerContext>) {
+    let mut last_modified = modified(&ctx);
+    let mut ticker = tokio::time::interval(WATCH_INTERVAL);
+    let mut shutdown = ctx.shutdown_signal();
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = shutdown.wait_for(|stopped| *stopped) => return,
+        }
+
+        let current = modified(&ctx);
+        if current == last_modified {
+            continue;
+        }
+        last_modified = current;
+        if ctx.config().watch_config && current.is_some() {
+            reload(&ctx, "file changed");
+        }
+    }
+}
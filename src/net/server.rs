@@ -1,17 +1,91 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use log::{error, warn};
 use tokio::net::TcpListener;
 use super::connection::handle_connection;
+use super::context::ServerContext;
+use super::players::LATENCY_BROADCAST_INTERVAL;
+use super::query::start_query;
+use super::rcon::start_rcon;
+use super::reload::spawn_reload_tasks;
+use crate::game::spawn_game_loop;
 use crate::utils::config::ServerConfig;
 
 pub async fn start_server(config: ServerConfig) -> tokio::io::Result<()> {
+    serve(Arc::new(ServerContext::new(config))).await
+}
+
+/// 作成済みのコンテキストでサーバーを起動する。`stop` が実行されると戻る
+pub async fn serve(ctx: Arc<ServerContext>) -> tokio::io::Result<()> {
+    let config = ctx.config();
+    // 鍵の読み込みに失敗したら接続を受け付ける前に止める
+    ctx.keypair().map_err(std::io::Error::other)?;
     let listener = TcpListener::bind(&config.listen_address).await?;
+    spawn_game_loop(ctx.clone())?;
     println!("Minecraft Rust server library is listening on {}", config.listen_address);
 
+    if config.query.enabled {
+        let query_ctx = ctx.clone();
+        tokio::spawn(async move {
+            if let Err(e) = start_query(query_ctx).await {
+                error!("Query server error: {}", e);
+            }
+        });
+    }
+
+    if config.rcon.enabled {
+        let rcon_ctx = ctx.clone();
+        tokio::spawn(async move {
+            if let Err(e) = start_rcon(rcon_ctx).await {
+                error!("RCON server error: {}", e);
+            }
+        });
+    }
+
+    spawn_reload_tasks(&ctx);
+    tokio::spawn(broadcast_latency(ctx.clone()));
+
+    let active = Arc::new(AtomicUsize::new(0));
+    let mut shutdown = ctx.shutdown_signal();
     loop {
-        let (stream, _) = listener.accept().await?;
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => accepted?,
+            _ = shutdown.wait_for(|stopped| *stopped) => break,
+        };
+        // 上限は再読み込みで変わるので接続ごとに確認する
+        let max_connections = ctx.config().max_connections;
+        if active.fetch_add(1, Ordering::AcqRel) >= max_connections {
+            active.fetch_sub(1, Ordering::AcqRel);
+            warn!("Rejected connection from {}: max_connections ({}) reached", peer, max_connections);
+            continue;
+        }
+        let ctx = ctx.clone();
+        let active = active.clone();
         tokio::spawn(async move {
-            if let Err(e) = handle_connection(stream).await {
+            if let Err(e) = handle_connection(stream, ctx).await {
                 eprintln!("Error: {:?}", e);
             }
+            active.fetch_sub(1, Ordering::AcqRel);
         });
     }
-}
\ No newline at end of file
+
+    println!("Server stopped");
+    Ok(())
+}
+
+/// 全員の往復時間を定期的にタブリストへ送る
+async fn broadcast_latency(ctx: Arc<ServerContext>) {
+    let start = tokio::time::Instant::now() + LATENCY_BROADCAST_INTERVAL;
+    let mut ticker = tokio::time::interval_at(start, LATENCY_BROADCAST_INTERVAL);
+    let mut shutdown = ctx.shutdown_signal();
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {
+                if let Err(e) = ctx.players.broadcast_latency() {
+                    warn!("Failed to send player latency: {}", e);
+                }
+            }
+            _ = shutdown.wait_for(|stopped| *stopped) => return,
+        }
+    }
+}
@@ -0,0 +1,114 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use parking_lot::Mutex;
+
+/// 保持する keep-alive 往復時間のサンプル数
+const MAX_RTT_SAMPLES: usize = 4096;
+
+/// 負荷試験や監視向けにサーバー側で観測した値
+pub struct ServerStats {
+    keep_alive_rtts: Mutex<VecDeque<Duration>>,
+    tps: Mutex<Option<f64>>,
+    /// 起動してからのログイン数
+    total_joins: AtomicU64,
+    /// 同時にオンラインだったプレイヤー数の最大
+    peak_online: AtomicUsize,
+}
+
+/// ソート済みのサンプルから百分位数を取る
+pub fn percentile(sorted: &[Duration], p: f64) -> Option<Duration> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = ((p / 100.0) * (sorted.len() - 1) as f64).round() as usize;
+    Some(sorted[rank.min(sorted.len() - 1)])
+}
+
+/// p50/p90/p99/max をミリ秒で表したJSON
+pub fn latency_summary(samples: &[Duration]) -> serde_json::Value {
+    let mut sorted = samples.to_vec();
+    sorted.sort();
+    let ms = |d: Option<Duration>| d.map(|d| d.as_secs_f64() * 1000.0);
+    serde_json::json!({
+        "samples": sorted.len(),
+        "p50": ms(percentile(&sorted, 50.0)),
+        "p90": ms(percentile(&sorted, 90.0)),
+        "p99": ms(percentile(&sorted, 99.0)),
+        "max": ms(sorted.last().copied()),
+    })
+}
+
+impl ServerStats {
+    pub fn new() -> Self {
+        Self {
+            keep_alive_rtts: Mutex::new(VecDeque::with_capacity(MAX_RTT_SAMPLES)),
+            tps: Mutex::new(None),
+            total_joins: AtomicU64::new(0),
+            peak_online: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn record_keep_alive(&self, rtt: Duration) {
+        let mut rtts = self.keep_alive_rtts.lock();
+        if rtts.len() == MAX_RTT_SAMPLES {
+            rtts.pop_front();
+        }
+        rtts.push_back(rtt);
+    }
+
+    /// プレイヤーの参加を記録する。`online` は参加後のオンライン人数
+    pub fn record_join(&self, online: usize) {
+        self.total_joins.fetch_add(1, Ordering::Relaxed);
+        self.peak_online.fetch_max(online, Ordering::Relaxed);
+    }
+
+    pub fn total_joins(&self) -> u64 {
+        self.total_joins.load(Ordering::Relaxed)
+    }
+
+    pub fn peak_online(&self) -> usize {
+        self.peak_online.load(Ordering::Relaxed)
+    }
+
+    /// ゲームループが計測した TPS を記録する
+    pub fn set_tps(&self, tps: f64) {
+        *self.tps.lock() = Some(tps);
+    }
+
+    pub fn tps(&self) -> Option<f64> {
+        *self.tps.lock()
+    }
+
+    pub fn snapshot(&self, online_players: usize) -> serde_json::Value {
+        let rtts: Vec<Duration> = self.keep_alive_rtts.lock().iter().copied().collect();
+        serde_json::json!({
+            "tps": self.tps(),
+            "online_players": online_players,
+            "peak_online_players": self.peak_online(),
+            "total_joins": self.total_joins(),
+            "keep_alive_rtt_ms": latency_summary(&rtts),
+        })
+    }
+}
+
+impl Default for ServerStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile() {
+        let samples: Vec<Duration> = (1..=100).map(Duration::from_millis).collect();
+        assert_eq!(percentile(&samples, 50.0), Some(Duration::from_millis(51)));
+        assert_eq!(percentile(&samples, 99.0), Some(Duration::from_millis(99)));
+        assert_eq!(percentile(&samples, 100.0), Some(Duration::from_millis(100)));
+        assert_eq!(percentile(&[], 50.0), None);
+    }
+}
@@ -1,49 +1,84 @@
-use tokio::net::TcpStream;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use bytes::{BytesMut, Buf, BufMut};
-use crate::varint::utils::{read_varint, write_varint};
+use bytes::{BufMut, BytesMut};
+use crate::varint::utils::{read_string, string_len, write_string};
+use crate::net::connection::Connection;
+use crate::net::context::StatusInfo;
 use crate::net::error::Result;
+use crate::net::protocol::types::ProtocolRead;
+use crate::net::protocol::Packet;
 
-pub async fn handle_status(stream: &mut TcpStream) -> Result<()> {
-    let mut buf = [0u8; 1024];
-    let size = stream.read(&mut buf).await?;
-    let mut data = BytesMut::from(&buf[..size]);
-    let _len = read_varint(&mut data).ok_or_else(|| crate::net::error::ServerError::Protocol("Invalid length".to_string()))?;
-    let packet_id = read_varint(&mut data).ok_or_else(|| crate::net::error::ServerError::Protocol("Invalid packet ID".to_string()))?;
+/// Status Response (0x00)。サーバー情報のJSON
+#[derive(Debug, Clone)]
+pub struct StatusResponse {
+    pub json: String,
+}
 
-    if packet_id == 0x00 {
-        // Status Response
-        let response = serde_json::json!({
-            "version": { "name": "1.20.1", "protocol": 763 },
-            "players": { "max": 20, "online": 0 },
-            "description": { "text": "5io Test Server" }
-        });
-
-        let response_str = response.to_string();
-        let mut response_buf = BytesMut::new();
-        write_varint(&mut response_buf, 0x00)?;
-        write_varint(&mut response_buf, response_str.len() as i32)?;
-        response_buf.put_slice(response_str.as_bytes());
-
-        let mut full_packet = BytesMut::new();
-        write_varint(&mut full_packet, response_buf.len() as i32)?;
-        full_packet.put(response_buf);
-
-        stream.write_all(&full_packet).await?;
-    } else if packet_id == 0x01 {
-        // Ping Response
-        let payload = data.get_i64();
-        let mut pong_buf = BytesMut::new();
-        write_varint(&mut pong_buf, 0x01)?;
-        pong_buf.put_i64(payload);
+impl Packet for StatusResponse {
+    fn packet_id(&self) -> i32 {
+        0x00
+    }
 
-        let mut full = BytesMut::new();
-        write_varint(&mut full, pong_buf.len() as i32)?;
-        full.put(pong_buf);
+    fn encode(&self, buf: &mut BytesMut) -> Result<()> {
+        write_string(buf, &self.json)?;
+        Ok(())
+    }
 
-        stream.write_all(&full).await?;
+    fn encoded_len(&self) -> Option<usize> {
+        Some(string_len(&self.json))
     }
 
-    Ok(())
+    fn decode(buf: &mut BytesMut) -> Result<Self> {
+        Ok(Self { json: read_string(buf)? })
+    }
 }
 
+/// Pong Response (0x01)。Ping Request のペイロードをそのまま返す
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PongResponse {
+    pub payload: i64,
+}
+
+impl Packet for PongResponse {
+    fn packet_id(&self) -> i32 {
+        0x01
+    }
+
+    fn encode(&self, buf: &mut BytesMut) -> Result<()> {
+        buf.put_i64(self.payload);
+        Ok(())
+    }
+
+    fn encoded_len(&self) -> Option<usize> {
+        Some(8)
+    }
+
+    fn decode(buf: &mut BytesMut) -> Result<Self> {
+        Ok(Self { payload: i64::read(buf)? })
+    }
+}
+
+/// Status Response に載せるJSONを組み立てる
+pub fn status_json(info: &StatusInfo) -> serde_json::Value {
+    serde_json::json!({
+        "version": { "name": info.version_name, "protocol": info.protocol },
+        "players": { "max": info.max_players, "online": info.online_players.len() },
+        "description": { "text": info.motd }
+    })
+}
+
+pub async fn handle_status(conn: &mut Connection) -> Result<()> {
+    let Some(mut frame) = conn.read_frame().await? else {
+        return Ok(());
+    };
+
+    if frame.id == 0x00 {
+        // Status Response
+        let json = status_json(&conn.context().status_info()).to_string();
+        conn.write_packet(&StatusResponse { json }).await?;
+    } else if frame.id == 0x01 {
+        // Ping Response
+        let payload = i64::read(&mut frame.body)?;
+        conn.write_packet(&PongResponse { payload }).await?;
+    }
+
+    Ok(())
+}
@@ -1,13 +1,60 @@
 
 #[cfg(test)]
 mod server_tests {
-    use crate::{run_server, ServerConfig};
-    use tokio::net::TcpStream;
+    use crate::client::{self, Client, ClientAuth, ClientConfig};
+    use crate::command::CommandSource;
+    use crate::net::context::ServerContext;
+    use crate::net::login::encryption::EncryptionKeyPair;
+    use crate::net::login::offline::offline_uuid;
+    use crate::net::play::chat::{SystemChatMessage, SYSTEM_CHAT_MESSAGE_ID};
+    use crate::net::play::entity::{RemoveEntities, SpawnPlayer, UpdateEntityPosition, REMOVE_ENTITIES_ID, SPAWN_PLAYER_ID, UPDATE_ENTITY_POSITION_ID};
+    use crate::net::play::movement::{SynchronizePlayerPosition, SYNCHRONIZE_PLAYER_POSITION_ID};
+    use crate::net::play::player_info::{PlayerInfoRemove, PlayerInfoUpdate, PLAYER_INFO_REMOVE_ID, PLAYER_INFO_UPDATE_ID};
+    use crate::utils::{AuthConfig, AuthMethod, PasswordLoginConfig};
+    use crate::{run_server, serve, ServerConfig};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use uuid::Uuid;
+
+    fn test_config(port: u16) -> ServerConfig {
+        // usercache.json などを作業ディレクトリに書き出さないよう、データはテストごとに一時ディレクトリへ置く
+        let data = std::env::temp_dir().join(format!("server-data-{}-{}", port, std::process::id()));
+        ServerConfig {
+            listen_address: format!("127.0.0.1:{}", port),
+            data_directory: data.to_string_lossy().into_owned(),
+            ..ServerConfig::default()
+        }
+    }
+
+    /// 接続できるようになるまで待つ
+    async fn wait_until_listening(address: &str) -> TcpStream {
+        for _ in 0..50 {
+            if let Ok(stream) = TcpStream::connect(address).await {
+                return stream;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("server did not start listening on {}", address);
+    }
+
+    async fn start_test_server(port: u16) -> Arc<ServerContext> {
+        let ctx = Arc::new(ServerContext::new(test_config(port)));
+        tokio::spawn(serve(ctx.clone()));
+        wait_until_listening(&ctx.config().listen_address).await;
+        ctx
+    }
 
     #[tokio::test]
     async fn test_server_startup() {
-        let config = ServerConfig::default();
-        assert!(run_server(config).await.is_ok());
+        let ctx = Arc::new(ServerContext::new(test_config(25601)));
+        let server = tokio::spawn(serve(ctx.clone()));
+        wait_until_listening(&ctx.config().listen_address).await;
+
+        ctx.shutdown();
+        assert!(server.await.unwrap().is_ok());
     }
 
     #[tokio::test]
@@ -17,15 +64,478 @@ mod server_tests {
         tokio::spawn(server);
 
         // 接続テスト
-        let stream = TcpStream::connect("127.0.0.1:25565").await;
-        assert!(stream.is_ok());
+        let stream = wait_until_listening("127.0.0.1:25565").await;
+        assert!(stream.peer_addr().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_client_status_and_login() {
+        let ctx = start_test_server(25602).await;
+        let address = ctx.config().listen_address.clone();
+
+        let (status, _latency) = client::status(&address, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(status["version"]["protocol"], 763);
+        assert_eq!(status["description"]["text"], ctx.config().motd.as_str());
+
+        let mut bot = Client::connect(ClientConfig::offline(&address, "Steve")).await.unwrap();
+        assert_eq!(bot.username, "Steve");
+        bot.send_chat("hello").await.unwrap();
+        bot.move_to(0.5, 64.0, 0.5, true).await.unwrap();
+
+        ctx.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_ping_server() {
+        let ctx = start_test_server(25603).await;
+        let address = ctx.config().listen_address.clone();
+
+        let (status, latency) = crate::ping_server(&address, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(status.version.protocol, 763);
+        assert_eq!(status.players.max, ctx.config().max_players as i64);
+        assert_eq!(status.description.plain_text(), ctx.config().motd);
+        assert!(!status.legacy);
+        assert!(latency < Duration::from_secs(5));
+
+        ctx.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_whitelist_and_bans_at_login() {
+        let dir = std::env::temp_dir().join(format!("server-access-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let config = ServerConfig {
+            whitelist: true,
+            data_directory: dir.to_string_lossy().into_owned(),
+            ..test_config(25604)
+        };
+        let ctx = Arc::new(ServerContext::new(config));
+        tokio::spawn(serve(ctx.clone()));
+        let address = ctx.config().listen_address.clone();
+        wait_until_listening(&address).await;
+
+        let rejected = Client::connect(ClientConfig::offline(&address, "Steve")).await;
+        assert!(matches!(rejected, Err(crate::ServerError::Disconnected(reason)) if reason.contains("white-listed")));
+
+        ctx.commands.dispatch(&ctx, CommandSource::Console, "whitelist add Steve");
+        assert!(Client::connect(ClientConfig::offline(&address, "Steve")).await.is_ok());
+
+        ctx.commands.dispatch(&ctx, CommandSource::Console, "tempban steve 1h Spamming");
+        let banned = Client::connect(ClientConfig::offline(&address, "Steve")).await;
+        assert!(matches!(banned, Err(crate::ServerError::Disconnected(reason)) if reason.contains("Reason: Spamming")));
+        assert!(dir.join("banned-players.json").exists());
+
+        ctx.shutdown();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// BAN の一覧を置くディレクトリを分けてサーバーを起動する
+    async fn start_server_with_data(port: u16, name: &str) -> (Arc<ServerContext>, std::path::PathBuf) {
+        let dir = std::env::temp_dir().join(format!("server-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let config = ServerConfig { data_directory: dir.to_string_lossy().into_owned(), ..test_config(port) };
+        let ctx = Arc::new(ServerContext::new(config));
+        tokio::spawn(serve(ctx.clone()));
+        wait_until_listening(&ctx.config().listen_address).await;
+        (ctx, dir)
+    }
+
+    #[tokio::test]
+    async fn test_ban_kicks_online_player() {
+        let (ctx, dir) = start_server_with_data(25616, "ban-kick-test").await;
+        let address = ctx.config().listen_address.clone();
+
+        let mut alex = Client::connect(ClientConfig::offline(&address, "Alex")).await.unwrap();
+        let _steve = Client::connect(ClientConfig::offline(&address, "Steve")).await.unwrap();
+        ctx.commands.dispatch(&ctx, CommandSource::Console, "ban alex Griefing");
+
+        let kicked = alex.wait_for(-1, Duration::from_secs(5)).await;
+        assert!(matches!(kicked, Err(crate::ServerError::Disconnected(reason)) if reason.contains("Reason: Griefing")));
+        // 他のプレイヤーは残る
+        wait_for_tick(|| ctx.players.len() == 1).await;
+        assert!(ctx.players.get_by_name("Steve").is_some());
+
+        ctx.shutdown();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ban_ip_kicks_every_session_from_address() {
+        let (ctx, dir) = start_server_with_data(25617, "ban-ip-kick-test").await;
+        let address = ctx.config().listen_address.clone();
+
+        let mut alex = Client::connect(ClientConfig::offline(&address, "Alex")).await.unwrap();
+        let mut steve = Client::connect(ClientConfig::offline(&address, "Steve")).await.unwrap();
+        let output = ctx.commands.dispatch(&ctx, CommandSource::Console, "ban-ip 127.0.0.1 Alt accounts");
+        assert!(output.contains("2 players disconnected"), "{}", output);
+
+        for bot in [&mut alex, &mut steve] {
+            let kicked = bot.wait_for(-1, Duration::from_secs(5)).await;
+            assert!(matches!(kicked, Err(crate::ServerError::Disconnected(reason)) if reason.contains("IP address is banned")));
+        }
+
+        ctx.shutdown();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_invalid_and_duplicate_logins() {
+        let ctx = start_test_server(25605).await;
+        let address = ctx.config().listen_address.clone();
+
+        let invalid = Client::connect(ClientConfig::offline(&address, "no spaces")).await;
+        assert!(matches!(invalid, Err(crate::ServerError::Disconnected(reason)) if reason.contains("Invalid player name")));
+
+        let mut first = Client::connect(ClientConfig::offline(&address, "Alex")).await.unwrap();
+        let _second = Client::connect(ClientConfig::offline(&address, "alex")).await.unwrap();
+        let kicked = first.wait_for(-1, Duration::from_secs(5)).await;
+        assert!(matches!(kicked, Err(crate::ServerError::Disconnected(reason)) if reason.contains("another location")));
+        assert_eq!(ctx.players.get_by_name("Alex").map(|session| session.name().to_string()), Some("alex".to_string()));
+
+        ctx.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_tab_list_and_online_count() {
+        let ctx = start_test_server(25606).await;
+        let address = ctx.config().listen_address.clone();
+        let timeout = Duration::from_secs(5);
+
+        let mut alex = Client::connect(ClientConfig::offline(&address, "Alex")).await.unwrap();
+        let own: PlayerInfoUpdate = alex.wait_for_packet(PLAYER_INFO_UPDATE_ID, timeout).await.unwrap();
+        assert_eq!(own.entries.iter().map(|entry| entry.name.as_str()).collect::<Vec<_>>(), vec!["Alex"]);
+
+        let mut steve = Client::connect(ClientConfig::offline(&address, "Steve")).await.unwrap();
+        let all: PlayerInfoUpdate = steve.wait_for_packet(PLAYER_INFO_UPDATE_ID, timeout).await.unwrap();
+        assert_eq!(all.entries.len(), 2);
+        let added: PlayerInfoUpdate = alex.wait_for_packet(PLAYER_INFO_UPDATE_ID, timeout).await.unwrap();
+        assert_eq!(added.entries[0].name, "Steve");
+
+        let (status, _) = client::ping_server(&address, timeout).await.unwrap();
+        assert_eq!(status.players.online, 2);
+        assert_eq!(ctx.stats.peak_online(), 2);
+
+        // 切断すると残ったプレイヤーのタブリストから消える
+        let steve_uuid = ctx.players.get_by_name("Steve").unwrap().uuid();
+        drop(steve);
+        let removed: PlayerInfoRemove = alex.wait_for_packet(PLAYER_INFO_REMOVE_ID, timeout).await.unwrap();
+        assert_eq!(removed.uuids, vec![steve_uuid]);
+        assert_eq!(ctx.status_info().online_players, vec!["Alex".to_string()]);
+
+        ctx.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_game_loop_reports_tps() {
+        let ctx = start_test_server(25612).await;
+        assert_eq!(ctx.stats.tps(), None);
+        // 20 tick ごとに記録する
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        let tps = ctx.stats.tps().expect("the game loop did not report TPS");
+        assert!(tps > 15.0 && tps <= 20.0, "{}", tps);
+        // 2回目の起動はしない
+        assert!(crate::game::spawn_game_loop(ctx.clone()).unwrap().is_none());
+        ctx.shutdown();
+    }
+
+    /// 条件を満たすまで tick を待つ
+    async fn wait_for_tick(mut condition: impl FnMut() -> bool) {
+        for _ in 0..50 {
+            if condition() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("the game state did not change in time");
+    }
+
+    #[tokio::test]
+    async fn test_player_entity_lifecycle() {
+        let ctx = start_test_server(25613).await;
+        let address = ctx.config().listen_address.clone();
+
+        let mut alex = Client::connect(ClientConfig::offline(&address, "Alex")).await.unwrap();
+        alex.send_client_information("ja_jp", 12).await.unwrap();
+        wait_for_tick(|| ctx.game.players().get_by_name("alex").is_some_and(|player| player.locale == "ja_jp")).await;
+        let output = ctx.commands.dispatch(&ctx, CommandSource::Console, "player alex");
+        assert!(output.starts_with(&format!("Alex ({}) [survival]", offline_uuid("Alex"))), "{}", output);
+        assert!(output.contains("view distance 12"), "{}", output);
+
+        // 切断したらエンティティも消える
+        drop(alex);
+        wait_for_tick(|| ctx.game.players().is_empty()).await;
+        assert!(ctx.commands.dispatch(&ctx, CommandSource::Console, "player Alex").contains("not in the game"));
+
+        ctx.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_player_movement_validation() {
+        let ctx = start_test_server(25614).await;
+        let address = ctx.config().listen_address.clone();
+
+        // 参加時のテレポートを確認するまで移動は無視される
+        let mut steve = Client::connect(ClientConfig::offline(&address, "Steve")).await.unwrap();
+        steve.wait_for(SYNCHRONIZE_PLAYER_POSITION_ID, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(steve.position, Some((0.5, 64.0, 0.5)));
+        steve.move_to(3.5, 64.0, 0.5, true).await.unwrap();
+        wait_for_tick(|| ctx.game.players().get_by_name("Steve").is_some_and(|player| player.position.x == 3.5)).await;
+
+        // 速すぎる移動は元の位置に戻される
+        steve.move_to(500.0, 64.0, 0.5, true).await.unwrap();
+        let sync: SynchronizePlayerPosition = steve.wait_for_packet(SYNCHRONIZE_PLAYER_POSITION_ID, Duration::from_secs(5)).await.unwrap();
+        assert_eq!((sync.x, sync.y, sync.z), (3.5, 64.0, 0.5));
+        assert_eq!(steve.position, Some((3.5, 64.0, 0.5)));
+        assert_eq!(ctx.game.players().get_by_name("Steve").unwrap().position.x, 3.5);
+
+        ctx.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_players_see_each_other() {
+        let ctx = start_test_server(25615).await;
+        let address = ctx.config().listen_address.clone();
+        let timeout = Duration::from_secs(5);
+
+        let mut steve = Client::connect(ClientConfig::offline(&address, "Steve")).await.unwrap();
+        let mut alex = Client::connect(ClientConfig::offline(&address, "Alex")).await.unwrap();
+        let spawned: SpawnPlayer = steve.wait_for_packet(SPAWN_PLAYER_ID, timeout).await.unwrap();
+        assert_eq!(spawned.uuid, alex.uuid);
+        let alex_id = spawned.entity_id;
+        assert_eq!(alex.wait_for_packet::<SpawnPlayer>(SPAWN_PLAYER_ID, timeout).await.unwrap().uuid, steve.uuid);
+
+        // 動きが伝わる
+        alex.move_to(1.5, 64.0, 0.5, true).await.unwrap();
+        let moved: UpdateEntityPosition = steve.wait_for_packet(UPDATE_ENTITY_POSITION_ID, timeout).await.unwrap();
+        assert_eq!((moved.entity_id, moved.delta), (alex_id, [4096, 0, 0]));
+
+        drop(alex);
+        let removed: RemoveEntities = steve.wait_for_packet(REMOVE_ENTITIES_ID, timeout).await.unwrap();
+        assert_eq!(removed.entity_ids, vec![alex_id]);
+
+        ctx.shutdown();
+    }
+
+    #[tokio::test]
+    async fn test_encryption_key_from_config() {
+        let dir = std::env::temp_dir().join(format!("server-keypair-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("server-key.pem");
+        let saved = EncryptionKeyPair::generate().unwrap();
+        std::fs::write(&path, saved.private_key_pem().unwrap()).unwrap();
+
+        let config = ServerConfig { encryption_key: path.to_string_lossy().into_owned(), ..test_config(25607) };
+        let ctx = Arc::new(ServerContext::new(config));
+        tokio::spawn(serve(ctx.clone()));
+        wait_until_listening(&ctx.config().listen_address).await;
+        let keypair = ctx.keypair().unwrap();
+        assert_eq!(keypair.public_key_der(), saved.public_key_der());
+        // 全コネクションで同じ鍵を使う
+        assert!(Arc::ptr_eq(&keypair, &ctx.keypair().unwrap()));
+        ctx.shutdown();
+
+        // 鍵を読み込めなければ起動しない
+        let config = ServerConfig { encryption_key: dir.join("missing.pem").to_string_lossy().into_owned(), ..test_config(25608) };
+        assert!(serve(Arc::new(ServerContext::new(config))).await.is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Yggdrasil 互換のセッションサーバーのモック。
+    /// `join` で記録したサーバーハッシュを `hasJoined` で照合し、`profiles` にある名前だけを返す
+    async fn mock_session_server(port: u16, profiles: Vec<(Uuid, &'static str)>) {
+        let listener = TcpListener::bind(("127.0.0.1", port)).await.unwrap();
+        let joined = Arc::new(parking_lot::Mutex::new(HashMap::<String, String>::new()));
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let (joined, profiles) = (joined.clone(), profiles.clone());
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut buf = [0u8; 1024];
+                    let (head, body) = loop {
+                        let n = stream.read(&mut buf).await.unwrap();
+                        request.extend_from_slice(&buf[..n]);
+                        let text = String::from_utf8_lossy(&request).into_owned();
+                        if let Some((head, body)) = text.split_once("\r\n\r\n") {
+                            let length = head.lines()
+                                .find_map(|line| line.to_ascii_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse().unwrap()))
+                                .unwrap_or(0);
+                            if body.len() >= length || n == 0 {
+                                break (head.to_string(), body.to_string());
+                            }
+                        }
+                    };
+                    let path = head.split(' ').nth(1).unwrap_or_default();
+                    let response = if path.ends_with("/session/minecraft/join") {
+                        let join: serde_json::Value = serde_json::from_str(&body).unwrap();
+                        joined.lock().insert(join["serverId"].as_str().unwrap().to_string(), join["selectedProfile"].as_str().unwrap().to_string());
+                        None
+                    } else {
+                        let query: HashMap<&str, &str> = path.split_once('?').map(|(_, query)| query).unwrap_or_default()
+                            .split('&').filter_map(|pair| pair.split_once('=')).collect();
+                        let selected = joined.lock().get(query["serverId"]).cloned();
+                        profiles.iter()
+                            .find(|(uuid, name)| *name == query["username"] && selected == Some(uuid.simple().to_string()))
+                            .map(|(uuid, name)| serde_json::json!({ "id": uuid.simple().to_string(), "name": name, "properties": [] }).to_string())
+                    };
+                    let reply = match response {
+                        Some(json) => format!("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", json.len(), json),
+                        None => "HTTP/1.1 204 No Content\r\nConnection: close\r\n\r\n".to_string(),
+                    };
+                    stream.write_all(reply.as_bytes()).await.unwrap();
+                });
+            }
+        });
+    }
+
+    #[tokio::test]
+    async fn test_yggdrasil_with_offline_fallback() {
+        let steve = Uuid::new_v4();
+        mock_session_server(25610, vec![(steve, "Steve")]).await;
+
+        let dir = std::env::temp_dir().join(format!("server-auth-login-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let config = ServerConfig {
+            data_directory: dir.to_string_lossy().into_owned(),
+            auth: AuthConfig {
+                chain: vec![
+                    AuthMethod::Yggdrasil { url: "http://127.0.0.1:25610/api".to_string() },
+                    AuthMethod::Offline { whitelisted_only: true },
+                ],
+                ..AuthConfig::default()
+            },
+            ..test_config(25609)
+        };
+        let ctx = Arc::new(ServerContext::new(config));
+        tokio::spawn(serve(ctx.clone()));
+        let address = ctx.config().listen_address.clone();
+        wait_until_listening(&address).await;
+        ctx.commands.dispatch(&ctx, CommandSource::Console, "whitelist add Alex");
+
+        let online = |username: &str, profile_id| ClientConfig {
+            auth: ClientAuth::Online {
+                access_token: "token".to_string(),
+                profile_id,
+                session_server: "http://127.0.0.1:25610/api/sessionserver".to_string(),
+            },
+            ..ClientConfig::offline(&address, username)
+        };
+
+        // セッションサーバーが認めたプレイヤーはそのプロファイルで入り、以後は暗号化される
+        let mut client = Client::connect(online("Steve", steve)).await.unwrap();
+        assert_eq!(client.uuid, steve);
+        let tab: PlayerInfoUpdate = client.wait_for_packet(PLAYER_INFO_UPDATE_ID, Duration::from_secs(5)).await.unwrap();
+        assert_eq!(tab.entries[0].uuid, steve);
+
+        // 知らない名前はホワイトリストに載っていればオフラインの UUID で入れる
+        let alex = Client::connect(online("Alex", Uuid::new_v4())).await.unwrap();
+        assert_eq!(alex.uuid, offline_uuid("Alex"));
+        let rejected = Client::connect(online("Bob", Uuid::new_v4())).await;
+        assert!(matches!(rejected, Err(crate::ServerError::Disconnected(reason)) if reason.contains("Failed to verify username")));
+
+        ctx.shutdown();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_password_login() {
+        let dir = std::env::temp_dir().join(format!("server-password-login-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let config = ServerConfig {
+            data_directory: dir.to_string_lossy().into_owned(),
+            password_login: PasswordLoginConfig {
+                enabled: true,
+                max_attempts: 2,
+                timeout: Duration::from_secs(2),
+                ..PasswordLoginConfig::default()
+            },
+            ..test_config(25611)
+        };
+        let ctx = Arc::new(ServerContext::new(config));
+        tokio::spawn(serve(ctx.clone()));
+        let address = ctx.config().listen_address.clone();
+        wait_until_listening(&address).await;
+        let timeout = Duration::from_secs(5);
+        let text = |message: SystemChatMessage| serde_json::from_str::<serde_json::Value>(&message.content_json).unwrap()["text"].as_str().unwrap().to_string();
+
+        // 初回は登録を求められ、登録するまでチャットは届かない
+        let mut steve = Client::connect(ClientConfig::offline(&address, "Steve")).await.unwrap();
+        let prompt = steve.wait_for_packet(SYSTEM_CHAT_MESSAGE_ID, timeout).await.map(text).unwrap();
+        assert!(prompt.contains("/register"), "{}", prompt);
+        steve.send_chat("hello").await.unwrap();
+        let prompt = steve.wait_for_packet(SYSTEM_CHAT_MESSAGE_ID, timeout).await.map(text).unwrap();
+        assert!(prompt.contains("/register"), "{}", prompt);
+        steve.send_chat("/register secret123").await.unwrap();
+        assert_eq!(steve.wait_for_packet(SYSTEM_CHAT_MESSAGE_ID, timeout).await.map(text).unwrap(), "Successfully registered!");
+        assert!(ctx.passwords.is_registered(&offline_uuid("Steve")));
+        drop(steve);
+
+        // 何もしなければ時間切れで切断される
+        let mut idle = Client::connect(ClientConfig::offline(&address, "Steve")).await.unwrap();
+        let kicked = idle.wait_for(-1, timeout).await;
+        assert!(matches!(kicked, Err(crate::ServerError::Disconnected(reason)) if reason.contains("timed out")));
+
+        // 次からはログインを求められ、間違え続けると切断される
+        let mut steve = Client::connect(ClientConfig::offline(&address, "Steve")).await.unwrap();
+        let prompt = steve.wait_for_packet(SYSTEM_CHAT_MESSAGE_ID, timeout).await.map(text).unwrap();
+        assert!(prompt.contains("/login"), "{}", prompt);
+        steve.send_chat("/login wrong").await.unwrap();
+        let reply = steve.wait_for_packet(SYSTEM_CHAT_MESSAGE_ID, timeout).await.map(text).unwrap();
+        assert!(reply.contains("1 attempts left"), "{}", reply);
+        steve.send_chat("/login wrong").await.unwrap();
+        let kicked = steve.wait_for(-1, timeout).await;
+        assert!(matches!(kicked, Err(crate::ServerError::Disconnected(reason)) if reason.contains("Too many failed")));
+
+        // つなぎ直しても回数は戻らず、期限まではログインさせない
+        let refused = Client::connect(ClientConfig::offline(&address, "Steve")).await;
+        assert!(matches!(refused, Err(crate::ServerError::Disconnected(reason)) if reason.contains("Too many failed")));
+
+        ctx.shutdown();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_reload_command() {
+        let dir = std::env::temp_dir().join(format!("server-reload-test-{}", std::process::id()));
+        let path = dir.join("server.toml");
+        let _ = std::fs::remove_dir_all(&dir);
+        let config = ServerConfig::load(&path).unwrap();
+        let ctx = Arc::new(ServerContext::new(config.clone()).with_config_path(&path));
+
+        let edited = ServerConfig {
+            motd: "After reload".to_string(),
+            listen_address: "127.0.0.1:25599".to_string(),
+            ..config.clone()
+        };
+        edited.write_to(&path).unwrap();
+        let output = ctx.commands.dispatch(&ctx, CommandSource::Console, "reload");
+        assert!(output.contains("applied: motd"), "{}", output);
+        assert!(output.contains("restart required for: listen_address"), "{}", output);
+        assert_eq!(ctx.status_info().motd, "After reload");
+        assert_eq!(ctx.config().listen_address, config.listen_address);
+
+        // ホワイトリストの切り替えは設定ファイルにも残る
+        let output = ctx.commands.dispatch(&ctx, CommandSource::Console, "whitelist on");
+        assert_eq!(output, "Whitelist is now turned on");
+        assert!(ServerConfig::load(&path).unwrap().whitelist);
+        assert_eq!(ServerConfig::load(&path).unwrap().motd, "After reload");
+
+        // 不正な設定は反映しない
+        std::fs::write(&path, "max_connections = 0\n").unwrap();
+        let output = ctx.commands.dispatch(&ctx, CommandSource::Console, "reload");
+        assert!(output.starts_with("Reload failed"), "{}", output);
+        assert_eq!(ctx.status_info().motd, "After reload");
+
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
+
 #[cfg(test)]
 mod tests {
     use crate::net::protocol::codec::PacketCodec;
     use crate::net::protocol::handshake::HandshakePacket;
-    use crate::net::protocol::PacketState;
+    use crate::net::protocol::{Packet, PacketState};
+    use crate::net::status::StatusResponse;
     use crate::utils::config::VarIntConfig;
     use crate::varint::{OptimizedVarInt, VarIntEncoder, VarIntError};
     use bytes::BytesMut;
@@ -65,4 +575,97 @@ mod tests {
         assert_eq!(decoded_packet.next_state, original_packet.next_state);
     }
 
+    /// 長さを申告しないパケット
+    struct RawPacket(Vec<u8>);
+
+    impl Packet for RawPacket {
+        fn packet_id(&self) -> i32 {
+            0x42
+        }
+
+        fn encode(&self, buf: &mut BytesMut) -> crate::Result<()> {
+            buf.extend_from_slice(&self.0);
+            Ok(())
+        }
+
+        fn decode(buf: &mut BytesMut) -> crate::Result<Self> {
+            Ok(Self(buf.split().to_vec()))
+        }
+    }
+
+    /// 実際と異なる長さを申告するパケット
+    struct WrongLengthPacket;
+
+    impl Packet for WrongLengthPacket {
+        fn packet_id(&self) -> i32 {
+            0x01
+        }
+
+        fn encode(&self, buf: &mut BytesMut) -> crate::Result<()> {
+            buf.extend_from_slice(&[1, 2, 3]);
+            Ok(())
+        }
+
+        fn encoded_len(&self) -> Option<usize> {
+            Some(2)
+        }
+
+        fn decode(_buf: &mut BytesMut) -> crate::Result<Self> {
+            Ok(Self)
+        }
+    }
+
+    #[test]
+    fn test_codec_single_pass_encoding() {
+        let codec = PacketCodec::default();
+
+        // 長さの有無にかかわらず encode_frame と同じバイト列になる
+        for len in [0, 1, 126, 127, 200, 16_383, 16_384, 100_000] {
+            let body = vec![7u8; len];
+            let mut expected = BytesMut::new();
+            codec.encode_frame(0x42, &body, &mut expected).unwrap();
+
+            let mut buf = BytesMut::from(&b"prefix"[..]);
+            codec.encode_packet(&RawPacket(body.clone()), &mut buf).unwrap();
+            assert_eq!(&buf[6..], &expected[..], "{}", len);
+            // 空のバッファでは長さを右詰めで書く
+            let mut buf = BytesMut::new();
+            codec.encode_packet(&RawPacket(body.clone()), &mut buf).unwrap();
+            assert_eq!(&buf[..], &expected[..], "{}", len);
+
+            let json = "x".repeat(len);
+            let mut sized = BytesMut::new();
+            codec.encode_packet(&StatusResponse { json: json.clone() }, &mut sized).unwrap();
+            let decoded = codec.decode_packet::<StatusResponse>(&mut sized).unwrap().unwrap();
+            assert_eq!(decoded.json, json);
+        }
+
+        // 申告と実際の長さが違えばエラーにして、書きかけのフレームを残さない
+        let mut buf = BytesMut::from(&b"prefix"[..]);
+        assert!(codec.encode_packet(&WrongLengthPacket, &mut buf).is_err());
+        assert_eq!(&buf[..], b"prefix");
+
+        let small = PacketCodec::new(10);
+        assert!(small.encode_packet(&RawPacket(vec![0; 10]), &mut buf).is_err());
+        assert_eq!(&buf[..], b"prefix");
+    }
+
+    #[test]
+    fn test_codec_partial_and_malformed_frames() {
+        let codec = PacketCodec::default();
+
+        // 長さの途中までしか届いていないフレームは待つ
+        let mut buf = BytesMut::from(&[0x80][..]);
+        assert!(codec.decode_frame(&mut buf).unwrap().is_none());
+        assert_eq!(buf.len(), 1);
+
+        // 4バイト以上のパケット長は不正
+        let mut buf = BytesMut::from(&[0x80, 0x80, 0x80, 0x01][..]);
+        assert!(codec.decode_frame(&mut buf).is_err());
+
+        // フレーム内で途切れたパケットIDは不正
+        let mut buf = BytesMut::from(&[0x01, 0x80][..]);
+        assert!(codec.decode_frame(&mut buf).is_err());
+    }
+
 }
@@ -1,20 +1,26 @@
-mod varint;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
 
-use std::fs::File;
-use std::io::Read;
-pub use varint::VarIntConfig;
+pub use config::{AuthConfig, AuthMethod, MovementConfig, PasswordLoginConfig, ServerConfig, UsernamePolicy, VarIntConfig};
 pub mod config;
+pub mod properties;
 
-pub fn load_config() -> VarIntConfig {
-    // 設定ファイルからの読み込みを試行
-    if let Ok(mut file) = File::open("utils/varint.toml") {
-        let mut contents = String::new();
-        if file.read_to_string(&mut contents).is_ok() {
-            if let Ok(config) = toml::from_str(&contents) {
-                return config;
-            }
-        }
+/// 設定ファイルを読み込む。詳しくは [`ServerConfig::load`]
+pub fn load_config(path: &Path) -> crate::Result<ServerConfig> {
+    ServerConfig::load(path)
+}
+
+/// 一時ファイルに書いてから置き換え、書きかけのファイルが残らないようにする
+pub(crate) fn write_atomic(path: &Path, contents: &[u8]) -> crate::Result<()> {
+    if let Some(directory) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(directory)?;
     }
-    // 設定ファイルがない場合はデフォルト値を使用
-    VarIntConfig::default()
-}
\ No newline at end of file
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let mut out = fs::File::create(&tmp)?;
+    out.write_all(contents)?;
+    out.sync_all()?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
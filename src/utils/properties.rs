@@ -0,0 +1,247 @@
+//! vanilla の `server.properties` との相互変換
+//!
+//! Java の `Properties` 形式 (`key=value`、`#` コメント、`\uXXXX` エスケープ) を読み書きし、
+//! 対応しているキーだけを [`ServerConfig`] に反映する。
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+use crate::net::error::{Result, ServerError};
+use crate::utils::config::{AuthMethod, ServerConfig};
+
+/// Properties 形式のテキストをキーと値に分解する
+pub fn parse_properties(text: &str) -> BTreeMap<String, String> {
+    let mut properties = BTreeMap::new();
+    let mut lines = text.lines();
+
+    while let Some(line) = lines.next() {
+        let mut logical = line.trim_start().to_string();
+        if logical.is_empty() || logical.starts_with('#') || logical.starts_with('!') {
+            continue;
+        }
+        // 行末の奇数個のバックスラッシュは継続行
+        while ends_with_continuation(&logical) {
+            logical.pop();
+            match lines.next() {
+                Some(next) => logical.push_str(next.trim_start()),
+                None => break,
+            }
+        }
+
+        let (key, value) = split_key_value(&logical);
+        properties.insert(unescape(key), unescape(value));
+    }
+    properties
+}
+
+fn ends_with_continuation(line: &str) -> bool {
+    line.chars().rev().take_while(|&c| c == '\\').count() % 2 == 1
+}
+
+/// 最初のエスケープされていない `=`、`:`、空白でキーと値を分ける
+fn split_key_value(line: &str) -> (&str, &str) {
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' => escaped = true,
+            '=' | ':' => return (&line[..i], line[i + 1..].trim_start()),
+            c if c.is_whitespace() => {
+                let rest = line[i..].trim_start();
+                let rest = rest.strip_prefix(['=', ':']).unwrap_or(rest);
+                return (&line[..i], rest.trim_start());
+            }
+            _ => {}
+        }
+    }
+    (line, "")
+}
+
+fn unescape(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => result.push('\t'),
+            Some('n') => result.push('\n'),
+            Some('r') => result.push('\r'),
+            Some('f') => result.push('\u{0C}'),
+            Some('u') => {
+                let hex: String = chars.by_ref().take(4).collect();
+                match u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32) {
+                    Some(decoded) => result.push(decoded),
+                    None => result.push_str(&hex),
+                }
+            }
+            Some(other) => result.push(other),
+            None => {}
+        }
+    }
+    result
+}
+
+fn escape(value: &str, is_key: bool) -> String {
+    let mut result = String::with_capacity(value.len());
+    for (i, c) in value.chars().enumerate() {
+        match c {
+            '\\' => result.push_str("\\\\"),
+            '\t' => result.push_str("\\t"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\u{0C}' => result.push_str("\\f"),
+            '=' | ':' | '#' | '!' => {
+                result.push('\\');
+                result.push(c);
+            }
+            ' ' if is_key || i == 0 => result.push_str("\\ "),
+            c if (c as u32) < 0x20 || (c as u32) > 0x7E => {
+                // BMP 外の文字はサロゲートペアで書く
+                let mut units = [0u16; 2];
+                for unit in c.encode_utf16(&mut units) {
+                    result.push_str(&format!("\\u{:04X}", unit));
+                }
+            }
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+/// キーと値を Properties 形式で書き出す
+pub fn write_properties(properties: &BTreeMap<String, String>) -> String {
+    let mut text = String::from("#Minecraft server properties\n");
+    text.push_str(&format!("#{}\n", chrono::Local::now().format("%a %b %d %H:%M:%S %Z %Y")));
+    for (key, value) in properties {
+        text.push_str(&format!("{}={}\n", escape(key, true), escape(value, false)));
+    }
+    text
+}
+
+fn parse_value<T: FromStr>(key: &str, value: &str) -> Result<T> {
+    value.trim().parse()
+        .map_err(|_| ServerError::Config(format!("server.properties: {} の値が不正です: {}", key, value)))
+}
+
+impl ServerConfig {
+    /// `server.properties` の内容を既定値の上に反映する。
+    /// 対応していないキーは2つ目の戻り値として返す
+    pub fn from_properties(text: &str) -> Result<(Self, Vec<String>)> {
+        let mut config = Self::default();
+        let mut ignored = Vec::new();
+
+        let default_addr: SocketAddr = config.listen_address.parse()
+            .map_err(|_| ServerError::Config("listen_address が不正です".to_string()))?;
+        let mut ip = default_addr.ip().to_string();
+        let mut port = default_addr.port();
+
+        for (key, value) in parse_properties(text) {
+            match key.as_str() {
+                "server-ip" => {
+                    // 空なら全インターフェースで待ち受ける
+                    ip = if value.trim().is_empty() { "0.0.0.0".to_string() } else { value.trim().to_string() };
+                }
+                "server-port" => port = parse_value(&key, &value)?,
+                "motd" => config.motd = value,
+                "max-players" => config.max_players = parse_value(&key, &value)?,
+                "white-list" => config.whitelist = parse_value(&key, &value)?,
+                "view-distance" => config.view_distance = parse_value(&key, &value)?,
+                "online-mode" => {
+                    config.auth.chain = if parse_value(&key, &value)? {
+                        vec![AuthMethod::Mojang]
+                    } else {
+                        vec![AuthMethod::Offline { whitelisted_only: false }]
+                    };
+                }
+                "max-world-size" => config.movement.world_border = parse_value(&key, &value)?,
+                "enable-query" => config.query.enabled = parse_value(&key, &value)?,
+                "query.port" => config.query.port = parse_value(&key, &value)?,
+                "enable-rcon" => config.rcon.enabled = parse_value(&key, &value)?,
+                "rcon.port" => config.rcon.port = parse_value(&key, &value)?,
+                "rcon.password" => config.rcon.password = value,
+                _ => ignored.push(key),
+            }
+        }
+
+        config.listen_address = match ip.parse::<std::net::IpAddr>() {
+            Ok(ip) => SocketAddr::new(ip, port).to_string(),
+            Err(_) => format!("{}:{}", ip, port),
+        };
+        Ok((config, ignored))
+    }
+
+    /// 対応しているキーを `server.properties` 形式で書き出す
+    pub fn to_properties(&self) -> Result<String> {
+        let addr: SocketAddr = self.listen_address.parse()
+            .map_err(|_| ServerError::Config(format!("listen_address が不正です: {}", self.listen_address)))?;
+        let server_ip = if addr.ip().is_unspecified() { String::new() } else { addr.ip().to_string() };
+
+        let properties = BTreeMap::from([
+            ("server-ip".to_string(), server_ip),
+            ("server-port".to_string(), addr.port().to_string()),
+            ("motd".to_string(), self.motd.clone()),
+            ("max-players".to_string(), self.max_players.to_string()),
+            ("white-list".to_string(), self.whitelist.to_string()),
+            ("view-distance".to_string(), self.view_distance.to_string()),
+            // Yggdrasil やファイルの設定は properties では表せないので、オンラインかどうかだけを書く
+            ("online-mode".to_string(), self.auth.online_mode().to_string()),
+            ("max-world-size".to_string(), self.movement.world_border.to_string()),
+            ("enable-query".to_string(), self.query.enabled.to_string()),
+            ("query.port".to_string(), self.query.port.to_string()),
+            ("enable-rcon".to_string(), self.rcon.enabled.to_string()),
+            ("rcon.port".to_string(), self.rcon.port.to_string()),
+            ("rcon.password".to_string(), self.rcon.password.clone()),
+        ]);
+        Ok(write_properties(&properties))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_java_properties() {
+        let text = "#comment\n! also comment\nmotd=A \\u00A7aGreen\\ Server\nkey\\=with\\:sep = value\n\
+                    spaced   value here\nlong=first \\\n    second\nempty=\n";
+        let properties = parse_properties(text);
+        assert_eq!(properties["motd"], "A §aGreen Server");
+        assert_eq!(properties["key=with:sep"], "value");
+        assert_eq!(properties["spaced"], "value here");
+        assert_eq!(properties["long"], "first second");
+        assert_eq!(properties["empty"], "");
+
+        let written = write_properties(&properties);
+        assert_eq!(parse_properties(&written), properties);
+    }
+
+    #[test]
+    fn test_vanilla_properties_roundtrip() {
+        let text = "server-ip=\nserver-port=25570\nmotd=\\u00A76Hello\nmax-players=42\nonline-mode=true\n\
+                    view-distance=12\nmax-world-size=1000\nenable-rcon=true\nrcon.password=hunter2\nrcon.port=25580\n";
+        let (config, ignored) = ServerConfig::from_properties(text).unwrap();
+        assert_eq!(config.listen_address, "0.0.0.0:25570");
+        assert_eq!(config.motd, "§6Hello");
+        assert_eq!(config.max_players, 42);
+        assert_eq!(config.view_distance, 12);
+        assert!(config.rcon.enabled);
+        assert_eq!(config.rcon.password, "hunter2");
+        assert_eq!(config.rcon.port, 25580);
+        assert_eq!(config.auth.chain, vec![AuthMethod::Mojang]);
+        assert_eq!(config.movement.world_border, 1000.0);
+        assert!(ignored.is_empty());
+
+        let (exported, ignored) = ServerConfig::from_properties(&config.to_properties().unwrap()).unwrap();
+        assert!(ignored.is_empty());
+        assert_eq!(exported, config);
+
+        assert!(matches!(ServerConfig::from_properties("max-players=lots"), Err(ServerError::Config(_))));
+    }
+}
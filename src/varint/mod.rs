@@ -1,83 +1,200 @@
 use bevy::utils::thiserror;
-use bytes::{Buf, BufMut, BytesMut};
+use bytes::{Buf, BufMut};
 use std::io;
 pub use optimized::OptimizedVarInt;
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
+use crate::utils::config::VarIntConfig;
 
 mod optimized;
 mod metrics;
 
 // 基本的なトレイト定義
+// `Buf` / `BufMut` に対してジェネリックなので、`BytesMut` だけでなく
+// `Bytes`、`&[u8]`、`Chain` などのバッファをコピーせずに扱える
 pub trait VarIntEncoder {
-    fn write_varint(&self, buf: &mut BytesMut, value: i32) -> Result<()>;
-    fn read_varint(&self, buf: &mut BytesMut) -> Option<i32>;
-    fn write_varint_batch(&self, values: &[i32], buf: &mut BytesMut) -> Result<()>;
-    fn write_i64(&self, buf: &mut BytesMut, value: i64) -> Result<()>;
-    fn read_i64(&self, buf: &mut BytesMut) -> Option<i64>;
-    fn write_string(&self, buf: &mut BytesMut, value: &str) -> Result<()>;
-    fn read_string(&self, buf: &mut BytesMut) -> Option<String>;
+    fn write_varint<B: BufMut + ?Sized>(&self, buf: &mut B, value: i32) -> Result<()>;
+    fn read_varint<B: Buf + ?Sized>(&self, buf: &mut B) -> DecodeResult<i32>;
+    fn write_varint_batch<B: BufMut + ?Sized>(&self, values: &[i32], buf: &mut B) -> Result<()>;
+    /// `count` 個の VarInt を読んで `out` に追加する。失敗した場合は途中まで消費されている
+    fn read_varint_batch<B: Buf + ?Sized>(&self, buf: &mut B, count: usize, out: &mut Vec<i32>) -> DecodeResult<()> {
+        for _ in 0..count {
+            out.push(self.read_varint(buf)?);
+        }
+        Ok(())
+    }
+    fn write_i64<B: BufMut + ?Sized>(&self, buf: &mut B, value: i64) -> Result<()>;
+    fn read_i64<B: Buf + ?Sized>(&self, buf: &mut B) -> DecodeResult<i64>;
+    fn write_string<B: BufMut + ?Sized>(&self, buf: &mut B, value: &str) -> Result<()>;
+    fn read_string<B: Buf + ?Sized>(&self, buf: &mut B) -> DecodeResult<String>;
+}
+
+// グローバルインスタンスの設定。最初に使われた時点で確定する
+static GLOBAL_CONFIG: OnceLock<VarIntConfig> = OnceLock::new();
+
+/// `GLOBAL_VARINT` / `THREAD_LOCAL_VARINT` の設定を与える。
+/// すでに別の設定で使われ始めていた場合は `false` を返す
+pub fn configure(config: VarIntConfig) -> bool {
+    GLOBAL_CONFIG.get_or_init(|| config.clone()) == &config
+}
+
+fn global_config() -> VarIntConfig {
+    GLOBAL_CONFIG.get_or_init(VarIntConfig::default).clone()
 }
 
 // グローバルインスタンス
 lazy_static::lazy_static! {
-    pub static ref GLOBAL_VARINT: Arc<OptimizedVarInt> = Arc::new(
-        OptimizedVarInt::new(crate::utils::config::VarIntConfig::default())
-    );
+    pub static ref GLOBAL_VARINT: Arc<OptimizedVarInt> = Arc::new(OptimizedVarInt::new(global_config()));
 }
 
 // スレッドローカルインスタンス
 thread_local! {
-    pub static THREAD_LOCAL_VARINT: OptimizedVarInt = 
-        OptimizedVarInt::new(crate::utils::config::VarIntConfig::default());
+    pub static THREAD_LOCAL_VARINT: OptimizedVarInt = OptimizedVarInt::new(global_config());
 }
 
 // 便利な関数群
 pub mod utils {
     use super::*;
 
-    pub fn read_varint(buf: &mut BytesMut) -> Option<i32> {
-        let mut result = 0;
-        let mut shift = 0;
+    /// VarInt としてエンコードしたときのバイト数。分岐せずに最上位ビットの位置から求める
+    #[inline]
+    pub const fn varint_len(value: i32) -> usize {
+        let bits = 32 - (value as u32 | 1).leading_zeros() as usize;
+        bits.div_ceil(7)
+    }
 
-        loop {
-            if !buf.has_remaining() {
-                return None;
+    /// VarInt 長さ付き文字列としてエンコードしたときのバイト数
+    #[inline]
+    pub fn string_len(value: &str) -> usize {
+        varint_len(value.len() as i32) + value.len()
+    }
+
+    /// バッファを消費せずに VarInt を読み、値と使ったバイト数を返す
+    pub fn peek_varint(bytes: &[u8]) -> DecodeResult<(i32, usize)> {
+        let mut result = 0u32;
+        for (i, &byte) in bytes.iter().take(MAX_VARINT_LENGTH).enumerate() {
+            result |= ((byte & SEGMENT_BITS) as u32) << (7 * i);
+            if byte & CONTINUE_BIT == 0 {
+                return Ok((result as i32, i + 1));
+            }
+        }
+        if bytes.len() >= MAX_VARINT_LENGTH {
+            return Err(DecodeError::invalid("VarInt が長すぎます"));
+        }
+        Err(DecodeError::Incomplete)
+    }
+
+    /// バッファを消費せずに VarLong を読み、値と使ったバイト数を返す
+    pub fn peek_varlong(bytes: &[u8]) -> DecodeResult<(i64, usize)> {
+        let mut result = 0u64;
+        for (i, &byte) in bytes.iter().take(MAX_VARLONG_LENGTH).enumerate() {
+            result |= ((byte & SEGMENT_BITS) as u64) << (7 * i);
+            if byte & CONTINUE_BIT == 0 {
+                return Ok((result as i64, i + 1));
             }
+        }
+        if bytes.len() >= MAX_VARLONG_LENGTH {
+            return Err(DecodeError::invalid("VarLong が長すぎます"));
+        }
+        Err(DecodeError::Incomplete)
+    }
 
-            let byte = buf.get_u8();
-            result |= ((byte & 0b0111_1111) as i32) << shift;
+    /// `buf` の先頭 `N` バイト (足りなければ残り全部) を消費せずにコピーする。
+    /// チャンクをまたぐ場合は `chunks_vectored` を使い、全体を覗けなければ `None`
+    fn peek_prefix<B: Buf + ?Sized, const N: usize>(buf: &B) -> Option<([u8; N], usize)> {
+        let mut head = [0u8; N];
+        let wanted = N.min(buf.remaining());
+        let chunk = buf.chunk();
+        if chunk.len() >= wanted {
+            head[..wanted].copy_from_slice(&chunk[..wanted]);
+            return Some((head, wanted));
+        }
 
-            if (byte & 0b1000_0000) == 0 {
-                break;
+        let mut slices = [io::IoSlice::new(&[]); N];
+        let count = buf.chunks_vectored(&mut slices);
+        let mut filled = 0;
+        for slice in &slices[..count] {
+            let n = slice.len().min(wanted - filled);
+            head[filled..filled + n].copy_from_slice(&slice[..n]);
+            filled += n;
+        }
+        (filled == wanted).then_some((head, filled))
+    }
+
+    /// `peek` で先頭を解釈し、成功したときだけ消費する。
+    ///
+    /// ただし `chunks_vectored` を実装していない `Buf` で値がチャンクをまたぐと、
+    /// 1バイトずつ読むしかないので `Incomplete` やエラーのときも読んだ分を消費する。
+    /// その場合、呼び出し側はバッファを使えなくなったものとして扱う
+    fn read_prefixed<B: Buf + ?Sized, T, const N: usize>(
+        buf: &mut B,
+        peek: fn(&[u8]) -> DecodeResult<(T, usize)>,
+    ) -> DecodeResult<T> {
+        // 連続した領域に収まっていればコピーせずに読む
+        match peek(buf.chunk()) {
+            Ok((value, len)) => {
+                buf.advance(len);
+                return Ok(value);
             }
+            Err(DecodeError::Incomplete) if buf.chunk().len() < buf.remaining() => {}
+            Err(e) => return Err(e),
+        }
 
-            shift += 7;
+        if let Some((head, filled)) = peek_prefix::<B, N>(buf) {
+            let (value, len) = peek(&head[..filled])?;
+            buf.advance(len);
+            return Ok(value);
+        }
 
-            if shift >= 32 {
-                return None;  // 整数が大きすぎる
+        // 全チャンクを覗けない実装では1バイトずつ消費するしかない
+        let mut head = [0u8; N];
+        let mut len = 0;
+        while len < N && buf.has_remaining() {
+            head[len] = buf.get_u8();
+            len += 1;
+            if head[len - 1] & CONTINUE_BIT == 0 {
+                break;
             }
         }
+        peek(&head[..len]).map(|(value, _)| value)
+    }
+
+    /// VarInt を読む。バイトが足りない場合は `Incomplete` を返す。
+    /// 全チャンクを覗けない `Buf` 以外では、このときバッファを消費しない
+    pub fn read_varint<B: Buf + ?Sized>(buf: &mut B) -> DecodeResult<i32> {
+        read_prefixed::<B, i32, MAX_VARINT_LENGTH>(buf, peek_varint)
+    }
 
-        Some(result)
+    /// VarLong を読む。消費については [`read_varint`] と同じ
+    pub fn read_varlong<B: Buf + ?Sized>(buf: &mut B) -> DecodeResult<i64> {
+        read_prefixed::<B, i64, MAX_VARLONG_LENGTH>(buf, peek_varlong)
     }
 
-    pub fn write_varint(buf: &mut BytesMut, mut value: i32) -> io::Result<()> {
+    pub fn write_varint<B: BufMut + ?Sized>(buf: &mut B, value: i32) -> io::Result<()> {
+        // 負の値も5バイトで表すため、符号なしとしてシフトする
+        let mut value = value as u32;
         loop {
-            let mut temp = (value & 0b0111_1111) as u8;
-            value >>= 7;
-            if value != 0 {
-                temp |= 0b1000_0000;
-            }
-            buf.put_u8(temp);
-            if value == 0 {
-                break;
+            if value & !(SEGMENT_BITS as u32) == 0 {
+                buf.put_u8(value as u8);
+                return Ok(());
             }
+            buf.put_u8((value as u8 & SEGMENT_BITS) | CONTINUE_BIT);
+            value >>= 7;
         }
-        Ok(())
     }
 
+    pub fn write_varlong<B: BufMut + ?Sized>(buf: &mut B, value: i64) -> io::Result<()> {
+        let mut value = value as u64;
+        loop {
+            if value & !(SEGMENT_BITS as u64) == 0 {
+                buf.put_u8(value as u8);
+                return Ok(());
+            }
+            buf.put_u8((value as u8 & SEGMENT_BITS) | CONTINUE_BIT);
+            value >>= 7;
+        }
+    }
 
-    pub fn write_varint_batch(values: &[i32], buf: &mut BytesMut) {
+    pub fn write_varint_batch<B: BufMut + ?Sized>(values: &[i32], buf: &mut B) {
         GLOBAL_VARINT.write_varint_batch(values, buf).expect("test");
         // GLOBALインスタンスを使用して各値を個別に書き込む
         // for &value in values {
@@ -85,32 +202,128 @@ pub mod utils {
         // }
     }
 
-    pub fn write_i64(buf: &mut BytesMut, value: i64) -> io::Result<()> {
+    /// VarLong として書き込む。固定長の long は `protocol::types` の `i64` 実装を使う
+    pub fn write_i64<B: BufMut + ?Sized>(buf: &mut B, value: i64) -> io::Result<()> {
         GLOBAL_VARINT.write_i64(buf, value).map_err(|e| io::Error::new(io::ErrorKind::Other, e))
     }
 
-    pub fn read_i64(buf: &mut BytesMut) -> Option<i64> {
-        if buf.is_empty() {
-            return None;
-        }
+    /// VarLong として読み込む
+    pub fn read_i64<B: Buf + ?Sized>(buf: &mut B) -> DecodeResult<i64> {
         GLOBAL_VARINT.read_i64(buf)
     }
 
-    pub fn write_string(buf: &mut BytesMut, value: &str) -> io::Result<()> {
+    pub fn write_string<B: BufMut + ?Sized>(buf: &mut B, value: &str) -> io::Result<()> {
         if value.len() > i32::MAX as usize {
             return Err(io::Error::new(io::ErrorKind::InvalidData, "String too large"));
         }
         GLOBAL_VARINT.write_string(buf, value).map_err(|e| io::Error::new(io::ErrorKind::Other, e))
     }
-    pub fn read_string(buf: &mut BytesMut) -> Option<String> {
-        let len = read_varint(buf)? as usize;
 
-        if buf.len() < len {
-            return None;
+    /// 文字列の長さを読み、(本体のバイト数, 長さ部分のバイト数) を返す。消費はしない
+    fn peek_string_header<B: Buf + ?Sized>(buf: &B) -> DecodeResult<Option<(usize, usize)>> {
+        let Some((head, filled)) = peek_prefix::<B, MAX_VARINT_LENGTH>(buf) else {
+            return Ok(None);
+        };
+        let (len, header_len) = peek_varint(&head[..filled])?;
+        if len < 0 {
+            return Err(DecodeError::invalid(format!("不正な文字列長: {}", len)));
+        }
+        Ok(Some((len as usize, header_len)))
+    }
+
+    /// VarInt 長さ付きの文字列を読む。全体が揃うまではバッファを消費しない
+    pub fn read_string<B: Buf + ?Sized>(buf: &mut B) -> DecodeResult<String> {
+        let len = match peek_string_header(buf)? {
+            Some((len, header_len)) => {
+                if buf.remaining() < header_len + len {
+                    return Err(DecodeError::Incomplete);
+                }
+                buf.advance(header_len);
+                len
+            }
+            None => {
+                let len = read_varint(buf)?;
+                if len < 0 {
+                    return Err(DecodeError::invalid(format!("不正な文字列長: {}", len)));
+                }
+                if buf.remaining() < len as usize {
+                    return Err(DecodeError::Incomplete);
+                }
+                len as usize
+            }
+        };
+
+        // 中間バッファを作らず、結果の String の領域に直接コピーする
+        let mut bytes = vec![0u8; len];
+        buf.copy_to_slice(&mut bytes);
+        String::from_utf8(bytes).map_err(|_| DecodeError::invalid("不正なUTF-8シーケンス"))
+    }
+
+    /// スライスから文字列を借用して読む。コピーもアロケーションもしない
+    pub fn read_str<'a>(buf: &mut &'a [u8]) -> DecodeResult<&'a str> {
+        let (len, header_len) = peek_varint(buf)?;
+        if len < 0 {
+            return Err(DecodeError::invalid(format!("不正な文字列長: {}", len)));
+        }
+        let len = len as usize;
+        if buf.len() < header_len + len {
+            return Err(DecodeError::Incomplete);
+        }
+        let (bytes, rest) = buf[header_len..].split_at(len);
+        let value = std::str::from_utf8(bytes).map_err(|_| DecodeError::invalid("不正なUTF-8シーケンス"))?;
+        *buf = rest;
+        Ok(value)
+    }
+
+    /// `io::Read` から VarInt を1つ読む
+    pub fn read_varint_from<R: io::Read + ?Sized>(reader: &mut R) -> io::Result<i32> {
+        let mut head = [0u8; MAX_VARINT_LENGTH];
+        for i in 0..MAX_VARINT_LENGTH {
+            reader.read_exact(&mut head[i..=i])?;
+            if head[i] & CONTINUE_BIT == 0 {
+                return Ok(peek_varint(&head[..=i])?.0);
+            }
+        }
+        Err(DecodeError::invalid("VarInt が長すぎます").into())
+    }
+
+    /// `io::Write` に VarInt を書き、書いたバイト数を返す
+    pub fn write_varint_to<W: io::Write + ?Sized>(writer: &mut W, value: i32) -> io::Result<usize> {
+        let mut head = [0u8; MAX_VARINT_LENGTH];
+        let mut out = &mut head[..];
+        write_varint(&mut out, value)?;
+        let len = MAX_VARINT_LENGTH - out.len();
+        writer.write_all(&head[..len])?;
+        Ok(len)
+    }
+
+    /// `io::Read` から VarInt 長さ付きの文字列を読む。`max_length` は UTF-16 のコード単位で数えた最大長で、
+    /// 長さの接頭辞がその3倍のバイト数を超えていれば確保する前に弾く
+    pub fn read_string_from<R: io::Read + ?Sized>(reader: &mut R, max_length: usize) -> io::Result<String> {
+        let len = read_varint_from(reader)?;
+        if len < 0 {
+            return Err(DecodeError::invalid(format!("不正な文字列長: {}", len)).into());
+        }
+        // UTF-8 では1コード単位が最大3バイトになる
+        if len as usize > max_length.saturating_mul(3) {
+            return Err(DecodeError::invalid(format!("文字列が長すぎます: {} バイト", len)).into());
         }
+        let mut bytes = vec![0u8; len as usize];
+        reader.read_exact(&mut bytes)?;
+        let value = String::from_utf8(bytes).map_err(|_| io::Error::from(DecodeError::invalid("不正なUTF-8シーケンス")))?;
+        if value.encode_utf16().count() > max_length {
+            return Err(DecodeError::invalid(format!("文字列が長すぎます: 最大 {} 文字", max_length)).into());
+        }
+        Ok(value)
+    }
 
-        let bytes = buf.split_to(len);
-        String::from_utf8(bytes.to_vec()).ok()
+    /// `io::Write` に VarInt 長さ付きの文字列を書く
+    pub fn write_string_to<W: io::Write + ?Sized>(writer: &mut W, value: &str) -> io::Result<()> {
+        if value.len() > i32::MAX as usize {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "String too large"));
+        }
+        write_varint_to(writer, value.len() as i32)?;
+        writer.write_all(value.as_bytes())
     }
 }
 
@@ -130,29 +343,70 @@ pub enum VarIntError {
     Io(#[from] std::io::Error),
 }
 
+/// デコード時のエラー。バイトが足りないだけなのか、データが壊れているのかを区別する
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum DecodeError {
+    /// まだバイトが揃っていない。続きを受信してから読み直せばよい
+    #[error("Incomplete data")]
+    Incomplete,
+
+    /// データが不正。接続を切るべき
+    #[error("Invalid data: {reason}")]
+    Invalid { reason: String },
+}
+
+impl DecodeError {
+    pub fn invalid(reason: impl Into<String>) -> Self {
+        DecodeError::Invalid { reason: reason.into() }
+    }
+
+    pub fn is_incomplete(&self) -> bool {
+        matches!(self, DecodeError::Incomplete)
+    }
+}
+
+impl From<DecodeError> for io::Error {
+    fn from(error: DecodeError) -> Self {
+        match error {
+            DecodeError::Incomplete => io::Error::new(io::ErrorKind::UnexpectedEof, error),
+            DecodeError::Invalid { .. } => io::Error::new(io::ErrorKind::InvalidData, error),
+        }
+    }
+}
+
+impl From<DecodeError> for VarIntError {
+    fn from(error: DecodeError) -> Self {
+        match error {
+            DecodeError::Incomplete => VarIntError::BufferUnderflow,
+            DecodeError::Invalid { .. } => VarIntError::InvalidEncoding,
+        }
+    }
+}
+
 // 定数定義
 pub const MAX_VARINT_LENGTH: usize = 5;
+pub const MAX_VARLONG_LENGTH: usize = 10;
 pub const SEGMENT_BITS: u8 = 0x7F;
 pub const CONTINUE_BIT: u8 = 0x80;
 
 // 型エイリアス
 pub type Result<T> = std::result::Result<T, VarIntError>;
+pub type DecodeResult<T> = std::result::Result<T, DecodeError>;
 
 // プリミティブ型に対する拡張トレイト
 pub trait VarIntPrimitive: Sized {
-    fn to_varint(self, buf: &mut BytesMut) -> Result<()>;
-    fn from_varint(buf: &mut BytesMut) -> Option<Self>;
+    fn to_varint<B: BufMut + ?Sized>(self, buf: &mut B) -> Result<()>;
+    fn from_varint<B: Buf + ?Sized>(buf: &mut B) -> DecodeResult<Self>;
 }
 
 impl VarIntPrimitive for i32 {
     #[inline]
-    fn to_varint(self, buf: &mut BytesMut) -> Result<()> {
-        GLOBAL_VARINT.write_varint(buf, self);
-        Ok(())
+    fn to_varint<B: BufMut + ?Sized>(self, buf: &mut B) -> Result<()> {
+        GLOBAL_VARINT.write_varint(buf, self)
     }
 
     #[inline]
-    fn from_varint(buf: &mut BytesMut) -> Option<Self> {
+    fn from_varint<B: Buf + ?Sized>(buf: &mut B) -> DecodeResult<Self> {
         GLOBAL_VARINT.read_varint(buf)
     }
 }
@@ -166,7 +420,7 @@ mod tests {
     fn test_varint_encoding() {
         let mut buf = BytesMut::new();
         GLOBAL_VARINT.write_varint(&mut buf, 300);
-        assert_eq!(GLOBAL_VARINT.read_varint(&mut buf), Some(300));
+        assert_eq!(GLOBAL_VARINT.read_varint(&mut buf), Ok(300));
     }
 
     #[test]
@@ -176,9 +430,98 @@ mod tests {
         utils::write_varint_batch(&values, &mut output);
 
         let mut decoded = Vec::new();
-        while let Some(value) = GLOBAL_VARINT.read_varint(&mut output) {
+        while let Ok(value) = GLOBAL_VARINT.read_varint(&mut output) {
             decoded.push(value);
         }
         assert_eq!(values, decoded);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_incomplete_and_invalid() {
+        // 途中で切れた VarInt は消費されない
+        let mut buf = BytesMut::from(&[0xAC][..]);
+        assert_eq!(utils::read_varint(&mut buf), Err(DecodeError::Incomplete));
+        assert_eq!(buf.len(), 1);
+        buf.extend_from_slice(&[0x02]);
+        assert_eq!(utils::read_varint(&mut buf), Ok(300));
+
+        // 6バイト目まで続く VarInt は不正
+        let mut buf = BytesMut::from(&[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01][..]);
+        assert!(matches!(utils::read_varint(&mut buf), Err(DecodeError::Invalid { .. })));
+
+        // 文字列は本体が揃うまで消費しない
+        let mut buf = BytesMut::from(&[0x03, b'a', b'b'][..]);
+        assert_eq!(utils::read_string(&mut buf), Err(DecodeError::Incomplete));
+        assert_eq!(buf.len(), 3);
+
+        let mut buf = BytesMut::from(&[0x02, 0xC3, 0x28][..]);
+        assert!(matches!(utils::read_string(&mut buf), Err(DecodeError::Invalid { .. })));
+
+        assert!(matches!(VarIntError::from(DecodeError::Incomplete), VarIntError::BufferUnderflow));
+        assert!(matches!(VarIntError::from(DecodeError::invalid("x")), VarIntError::InvalidEncoding));
+    }
+
+    #[test]
+    fn test_generic_buffers_and_io() {
+        let mut encoded = BytesMut::new();
+        utils::write_varint(&mut encoded, -1).unwrap();
+        utils::write_string(&mut encoded, "héllo").unwrap();
+        utils::write_varlong(&mut encoded, i64::MIN).unwrap();
+        assert_eq!(&encoded[..5], &[0xFF, 0xFF, 0xFF, 0xFF, 0x0F]);
+
+        // スライスからはコピーせずに読める
+        let mut slice = &encoded[..];
+        assert_eq!(utils::read_varint(&mut slice), Ok(-1));
+        assert_eq!(utils::read_str(&mut slice), Ok("héllo"));
+        assert_eq!(utils::read_varlong(&mut slice), Ok(i64::MIN));
+        assert!(slice.is_empty());
+
+        // チャンクをまたぐ VarInt と文字列
+        let (head, tail) = encoded.split_at(3);
+        let mut chained = head.chain(tail);
+        assert_eq!(utils::read_varint(&mut chained), Ok(-1));
+        assert_eq!(utils::read_string(&mut chained).as_deref(), Ok("héllo"));
+
+        // チャンクをまたいで途切れている場合は消費しない
+        let (head, tail) = encoded[..4].split_at(2);
+        let mut chained = head.chain(tail);
+        assert_eq!(utils::read_varint(&mut chained), Err(DecodeError::Incomplete));
+        assert_eq!(chained.remaining(), 4);
+
+        // 全チャンクを覗けないバッファでは、途切れていても読んだ分は消費される
+        struct ChunkOnly<B>(B);
+        impl<B: Buf> Buf for ChunkOnly<B> {
+            fn remaining(&self) -> usize {
+                self.0.remaining()
+            }
+            fn chunk(&self) -> &[u8] {
+                self.0.chunk()
+            }
+            fn advance(&mut self, cnt: usize) {
+                self.0.advance(cnt)
+            }
+        }
+        let mut opaque = ChunkOnly(head.chain(tail));
+        assert_eq!(utils::read_varint(&mut opaque), Err(DecodeError::Incomplete));
+        assert_eq!(opaque.remaining(), 0);
+
+        // io::Read / io::Write アダプタ
+        let mut out = Vec::new();
+        assert_eq!(utils::write_varint_to(&mut out, 300).unwrap(), 2);
+        utils::write_string_to(&mut out, "abc").unwrap();
+        let mut reader = io::Cursor::new(out);
+        assert_eq!(utils::read_varint_from(&mut reader).unwrap(), 300);
+        assert_eq!(utils::read_string_from(&mut reader, 16).unwrap(), "abc");
+        let err = utils::read_varint_from(&mut reader).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        // 巨大な長さの接頭辞は確保する前に弾く
+        let mut huge = Vec::new();
+        utils::write_varint_to(&mut huge, i32::MAX).unwrap();
+        let err = utils::read_string_from(&mut io::Cursor::new(huge), 32767).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        let mut long = Vec::new();
+        utils::write_string_to(&mut long, "abcd").unwrap();
+        assert!(utils::read_string_from(&mut io::Cursor::new(long), 3).is_err());
+    }
+}
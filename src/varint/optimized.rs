@@ -1,180 +1,261 @@
 use crate::utils::config::VarIntConfig;
 use crate::varint::Result;
-use bytes::BytesMut;
-use parking_lot::RwLock;
-use std::sync::Arc;
+use bytes::{Buf, BufMut};
 
-use crate::varint::{VarIntEncoder, VarIntError};
+use crate::varint::{utils, DecodeError, DecodeResult, VarIntEncoder, VarIntError, MAX_VARINT_LENGTH};
 
-pub struct OptimizedVarInt {
-    config: Arc<VarIntConfig>,
-    encode_cache: Arc<RwLock<Vec<BytesMut>>>,
-    quick_lookup: Arc<Vec<BytesMut>>,
+/// エンコード表の上限。3バイトで表せる範囲までにとどめる
+const MAX_LOOKUP_SIZE: usize = 1 << 21;
+/// 各バイトの継続ビット
+const CONTINUE_MASK: u64 = 0x8080_8080_8080_8080;
+/// VarInt が取りうる先頭5バイトの継続ビット
+const VARINT_CONTINUE_MASK: u64 = 0x80_8080_8080;
+
+/// 7ビットずつ各バイトに広げ、最後のバイト以外に継続ビットを立てる (リトルエンディアン)
+#[inline]
+fn spread(value: i32, len: usize) -> u64 {
+    let v = value as u32 as u64;
+    let groups = (v & 0x7F)
+        | ((v & 0x3F80) << 1)
+        | ((v & 0x1F_C000) << 2)
+        | ((v & 0xFE0_0000) << 3)
+        | ((v & 0xF000_0000) << 4);
+    groups | (CONTINUE_MASK & ((1u64 << (8 * (len - 1))) - 1))
 }
 
-impl OptimizedVarInt {
-    fn init_varint(buf: &mut BytesMut, mut value: i32) -> Result<()> {
-        loop {
-            let mut temp: u8 = (value & 0b0111_1111) as u8;
-            value >>= 7;
+/// 先頭8バイトを1ワードとして読み、VarInt を1つ取り出す
+#[inline]
+fn decode_word(word: u64) -> DecodeResult<(i32, usize)> {
+    let stops = !word & VARINT_CONTINUE_MASK;
+    if stops == 0 {
+        return Err(DecodeError::invalid("VarInt が長すぎます"));
+    }
+    let len = stops.trailing_zeros() as usize / 8 + 1;
+    let m = word & ((1u64 << (8 * len)) - 1);
+    let value = (m & 0x7F)
+        | ((m >> 1) & 0x3F80)
+        | ((m >> 2) & 0x1F_C000)
+        | ((m >> 3) & 0xFE0_0000)
+        | ((m >> 4) & 0xF000_0000);
+    Ok((value as u32 as i32, len))
+}
 
-            if value != 0 {
-                temp |= 0b1000_0000;
-            }
+/// 継続ビットが立っていない (1バイトで完結する) 値が先頭から何バイト続くか
+#[cfg(not(all(feature = "simd", target_arch = "x86_64")))]
+fn single_byte_run(bytes: &[u8]) -> usize {
+    let mut run = 0;
+    let mut words = bytes.chunks_exact(8);
+    for word in words.by_ref() {
+        let continuation = u64::from_le_bytes(word.try_into().unwrap()) & CONTINUE_MASK;
+        if continuation != 0 {
+            return run + continuation.trailing_zeros() as usize / 8;
+        }
+        run += 8;
+    }
+    run + words.remainder().iter().take_while(|&&b| b & 0x80 == 0).count()
+}
 
-            buf.extend_from_slice(&[temp]);
+/// 16バイトずつ継続ビットを movemask で調べる
+#[cfg(all(feature = "simd", target_arch = "x86_64"))]
+fn single_byte_run(bytes: &[u8]) -> usize {
+    use std::arch::x86_64::{_mm_loadu_si128, _mm_movemask_epi8};
 
-            if value == 0 {
-                break;
-            }
+    let mut run = 0;
+    let mut blocks = bytes.chunks_exact(16);
+    for block in blocks.by_ref() {
+        // SAFETY: x86_64 では SSE2 が常に使え、block はちょうど16バイト
+        let continuation = unsafe { _mm_movemask_epi8(_mm_loadu_si128(block.as_ptr().cast())) } as u32;
+        if continuation != 0 {
+            return run + continuation.trailing_zeros() as usize;
         }
-        Ok(())
+        run += 16;
     }
+    run + blocks.remainder().iter().take_while(|&&b| b & 0x80 == 0).count()
+}
 
-    pub fn new(config: VarIntConfig) -> Self {
-        let config = Arc::new(config);
+pub struct OptimizedVarInt {
+    config: VarIntConfig,
+    /// `0..len` の値のエンコード結果。下位5バイトに本体、最上位バイトに長さ
+    lookup: Box<[u64]>,
+}
 
+impl OptimizedVarInt {
+    #[inline]
+    fn encode(value: i32) -> u64 {
+        let len = utils::varint_len(value);
+        spread(value, len) | ((len as u64) << 56)
+    }
+
+    pub fn new(config: VarIntConfig) -> Self {
         // クイックルックアップテーブルの初期化
-        let mut quick_lookup = Vec::with_capacity(256); // デフォルトサイズを使用
-        for i in 0..256 {
-            let mut buf = BytesMut::new();
-            Self::init_varint(&mut buf, i as i32);
-            quick_lookup.push(buf);
-        }
+        let size = config.quick_lookup_size.min(config.cache_size).min(MAX_LOOKUP_SIZE);
+        let lookup = (0..size as i32).map(Self::encode).collect();
 
-        // キャッシュの初期化
-        let encode_cache = Arc::new(RwLock::new(
-            Vec::with_capacity(config.cache_size)
-        ));
+        OptimizedVarInt { config, lookup }
+    }
 
-        OptimizedVarInt {
-            config,
-            encode_cache,
-            quick_lookup: Arc::new(quick_lookup),
-        }
+    pub fn config(&self) -> &VarIntConfig {
+        &self.config
     }
 }
 
 impl VarIntEncoder for OptimizedVarInt {
-    fn write_varint(&self, buf: &mut BytesMut, value: i32) -> Result<()> {
-        if value >= 0 && value < 256 {
-            buf.extend_from_slice(&self.quick_lookup[value as usize]);
-            Ok(())
-        }else {Self::init_varint(buf, value);
-            Ok(())
-        }
+    #[inline]
+    fn write_varint<B: BufMut + ?Sized>(&self, buf: &mut B, value: i32) -> Result<()> {
+        let encoded = match self.lookup.get(value as u32 as usize) {
+            Some(&encoded) => encoded,
+            None => Self::encode(value),
+        };
+        let len = (encoded >> 56) as usize;
+        buf.put_slice(&encoded.to_le_bytes()[..len]);
+        Ok(())
     }
 
-    fn read_varint(&self, buf: &mut BytesMut) -> Option<i32> {
-        // 既存のコードをそのまま使用
-        let mut result = 0;
-        let mut shift = 0;
-
-        loop {
-            if buf.is_empty() {
-                return None;
-            }
-            let byte = buf.split_to(1)[0];
-            result |= ((byte & 0b0111_1111) as i32) << shift;
-            shift += 7;
-            if byte & 0b1000_0000 == 0 {
-                break;
-            }
-            if shift >= 32 {
-                return None;
+    #[inline]
+    fn read_varint<B: Buf + ?Sized>(&self, buf: &mut B) -> DecodeResult<i32> {
+        let chunk = buf.chunk();
+        // 1バイトで完結する値が最も多い
+        if let Some(&byte) = chunk.first() {
+            if byte & 0x80 == 0 {
+                buf.advance(1);
+                return Ok(byte as i32);
             }
         }
-        Some(result)
+        // 5バイト以上連続して残っていれば1ワードにまとめて読む
+        let word = if let Some(head) = chunk.first_chunk::<8>() {
+            u64::from_le_bytes(*head)
+        } else if chunk.len() >= MAX_VARINT_LENGTH {
+            let mut head = [0u8; 8];
+            head[..chunk.len()].copy_from_slice(chunk);
+            u64::from_le_bytes(head)
+        } else {
+            return utils::read_varint(buf);
+        };
+        let (value, len) = decode_word(word)?;
+        buf.advance(len);
+        Ok(value)
     }
 
-    fn write_varint_batch(&self, values: &[i32], buf: &mut BytesMut) -> Result<()> {
+    fn write_varint_batch<B: BufMut + ?Sized>(&self, values: &[i32], buf: &mut B) -> Result<()> {
         for &value in values {
             self.write_varint(buf, value)?;
         }
         Ok(())
     }
 
-    fn write_i64(&self, buf: &mut BytesMut, mut value: i64) -> Result<()> {
-        loop {
-            let mut temp: u8 = (value & 0b0111_1111) as u8;
-            value >>= 7;
-            if value != 0 {
-                temp |= 0b1000_0000;
-            }
-            buf.extend_from_slice(&[temp]);
-            if value == 0 {
-                break;
+    fn read_varint_batch<B: Buf + ?Sized>(&self, buf: &mut B, count: usize, out: &mut Vec<i32>) -> DecodeResult<()> {
+        // 要素数は信用できないので、一度に確保するのは batch_size まで
+        out.reserve(count.min(self.config.batch_size.max(1)));
+        let mut remaining = count;
+        while remaining > 0 {
+            // パレットのように1バイトの値が続く区間はまとめて取り出す
+            let chunk = buf.chunk();
+            let run = match chunk.first() {
+                Some(&byte) if byte & 0x80 == 0 => single_byte_run(&chunk[..chunk.len().min(remaining)]),
+                _ => 0,
+            };
+            if run > 0 {
+                out.extend(chunk[..run].iter().map(|&b| b as i32));
+                buf.advance(run);
+                remaining -= run;
+                continue;
             }
+            out.push(self.read_varint(buf)?);
+            remaining -= 1;
         }
         Ok(())
     }
 
-    fn read_i64(&self, buf: &mut BytesMut) -> Option<i64> {
-        // 既存のコードをそのまま使用
-        let mut result: i64 = 0;
-        let mut shift = 0;
+    fn write_i64<B: BufMut + ?Sized>(&self, buf: &mut B, value: i64) -> Result<()> {
+        utils::write_varlong(buf, value)?;
+        Ok(())
+    }
 
-        loop {
-            if buf.is_empty() {
-                return None;
-            }
-            let byte = buf.split_to(1)[0];
-            result |= ((byte & 0b0111_1111) as i64) << shift;
-            shift += 7;
-            if byte & 0b1000_0000 == 0 {
-                break;
-            }
-            if shift >= 64 {
-                return None;
-            }
-        }
-        Some(result)
+    fn read_i64<B: Buf + ?Sized>(&self, buf: &mut B) -> DecodeResult<i64> {
+        utils::read_varlong(buf)
     }
 
-    fn write_string(&self, buf: &mut BytesMut, value: &str) -> Result<()> {
+    fn write_string<B: BufMut + ?Sized>(&self, buf: &mut B, value: &str) -> Result<()> {
         if value.len() > i32::MAX as usize {
             return Err(VarIntError::ValueTooLarge);
         }
         self.write_varint(buf, value.len() as i32)?;
-        buf.extend_from_slice(value.as_bytes());
+        buf.put_slice(value.as_bytes());
         Ok(())
     }
-    fn read_string(&self, buf: &mut BytesMut) -> Option<String> {
-        let len = self.read_varint(buf)? as usize;
-        if buf.len() < len {
-            return None;
-        }
-        let bytes = buf.split_to(len);
-        String::from_utf8(bytes.to_vec()).ok()
+
+    fn read_string<B: Buf + ?Sized>(&self, buf: &mut B) -> DecodeResult<String> {
+        utils::read_string(buf)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use bytes::BytesMut;
 
     #[test]
     fn test_optimized_varint() {
         let config = VarIntConfig {
             cache_size: 1024,
             batch_size: 100,
+            quick_lookup_size: 256,
         };
         let varint = OptimizedVarInt::new(config);
 
         // 基本的なエンコード/デコードテスト
         let mut buf = BytesMut::new();
         varint.write_varint(&mut buf, 300);
-        assert_eq!(varint.read_varint(&mut buf), Some(300));
+        assert_eq!(varint.read_varint(&mut buf), Ok(300));
 
         // クイックルックアップテーブルのテスト
         let mut buf = BytesMut::new();
         varint.write_varint(&mut buf, 255);
-        assert_eq!(varint.read_varint(&mut buf), Some(255));
+        assert_eq!(varint.read_varint(&mut buf), Ok(255));
 
         // 大きな値のテスト
         let mut buf = BytesMut::new();
         varint.write_varint(&mut buf, i32::MAX);
-        assert_eq!(varint.read_varint(&mut buf), Some(i32::MAX));
+        assert_eq!(varint.read_varint(&mut buf), Ok(i32::MAX));
+    }
+
+    #[test]
+    fn test_matches_reference_implementation() {
+        let varint = OptimizedVarInt::new(VarIntConfig::default());
+        let values = [0, 1, 127, 128, 255, 300, 16_383, 16_384, 2_097_151, 2_097_152, i32::MAX, -1, i32::MIN];
+
+        for &value in &values {
+            let mut expected = BytesMut::new();
+            utils::write_varint(&mut expected, value).unwrap();
+            let mut encoded = BytesMut::new();
+            varint.write_varint(&mut encoded, value).unwrap();
+            assert_eq!(encoded, expected, "{}", value);
+            assert_eq!(encoded.len(), utils::varint_len(value));
+
+            // 後ろにデータが続いていてもワード単位の高速パスで読める
+            encoded.extend_from_slice(&[0xFF; 8]);
+            assert_eq!(varint.read_varint(&mut encoded), Ok(value));
+            assert_eq!(encoded.len(), 8);
+        }
+
+        let mut overlong = BytesMut::from(&[0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01, 0x00, 0x00][..]);
+        assert!(matches!(varint.read_varint(&mut overlong), Err(DecodeError::Invalid { .. })));
+    }
+
+    #[test]
+    fn test_read_varint_batch() {
+        let varint = OptimizedVarInt::new(VarIntConfig::default());
+        let values: Vec<i32> = (0..100).map(|i| if i % 37 == 0 { i * 1000 } else { i % 16 }).collect();
+        let mut buf = BytesMut::new();
+        varint.write_varint_batch(&values, &mut buf).unwrap();
+        buf.extend_from_slice(&[0x05]);
+
+        let mut decoded = Vec::new();
+        varint.read_varint_batch(&mut buf, values.len(), &mut decoded).unwrap();
+        assert_eq!(decoded, values);
+        assert_eq!(&buf[..], &[0x05]);
+
+        let mut short = BytesMut::from(&[0x01, 0x02][..]);
+        assert_eq!(varint.read_varint_batch(&mut short, 3, &mut Vec::new()), Err(DecodeError::Incomplete));
     }
-    //コミット用コメント
 }
\ No newline at end of file